@@ -0,0 +1,10 @@
+#![no_main]
+
+use gwydd::{diagnostics::Diagnostics, omni::Omni, text::Text};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(omni) = Omni::parse_bytes(data) {
+        let _ = Text::from_omni(&omni, &Diagnostics::new());
+    }
+});
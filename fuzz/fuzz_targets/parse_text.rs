@@ -0,0 +1,8 @@
+#![no_main]
+
+use gwydd::text::Text;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &str| {
+    let _ = Text::parse_str(data);
+});
@@ -0,0 +1,119 @@
+/*
+ * C interface to gwydd, built with the `ffi` feature:
+ *
+ *     cargo rustc --lib --release --features ffi --crate-type cdylib
+ *
+ * Every string and payload belongs to the GwOmni it came from, and stays
+ * valid until that's passed to gw_omni_free.
+ */
+
+#ifndef GWYDD_H
+#define GWYDD_H
+
+/* Generated by cbindgen from src/ffi.rs, so don't change it by hand. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/*
+ What [`GwObject::parent`] is for objects that aren't part of an action.
+ */
+#define GW_NO_PARENT UINT32_MAX
+
+/*
+ A parsed file, with every object's data read in.
+ */
+typedef struct GwOmni GwOmni;
+
+/*
+ An object's details, filled in by [`gw_omni_object`].
+ */
+typedef struct {
+  uint32_t id;
+  /*
+   ID of the action it's part of, or `GW_NO_PARENT`
+   */
+  uint32_t parent;
+  const char *name;
+  /*
+   Type of object, such as `sound` or `world`
+   */
+  const char *kind;
+  /*
+   Name of the file it stores, or null if it doesn't store one
+   */
+  const char *file_name;
+  /*
+   Size of the stored file
+   */
+  size_t size;
+} GwObject;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Parses the Omni file at `path`, returning null on failure.
+
+ # Safety
+
+ `path` must be a valid null-terminated string.
+ */
+GwOmni *gw_omni_open(const char *path);
+
+/*
+ Message for the last call on this thread that failed, or null if none
+ has. It stays valid until the next call that fails.
+ */
+const char *gw_last_error(void);
+
+/*
+ Number of objects in the file, counting those inside actions.
+
+ # Safety
+
+ `omni` must be null or have come from [`gw_omni_open`].
+ */
+size_t gw_omni_object_count(const GwOmni *omni);
+
+/*
+ Fills in `out` with the details of object number `index`, returning
+ whether there is one. Objects come in file order, each followed by the
+ objects inside it.
+
+ # Safety
+
+ `omni` must be null or have come from [`gw_omni_open`], and `out` must be
+ null or point to a `GwObject`.
+ */
+bool gw_omni_object(const GwOmni *omni, size_t index, GwObject *out);
+
+/*
+ Points `data` at the stored file of object number `index` and sets `len`
+ to its size, returning whether there is an object with that index.
+ Objects without a stored file have a size of 0.
+
+ # Safety
+
+ `omni` must be null or have come from [`gw_omni_open`], and `data` and
+ `len` must be null or point to somewhere to write to.
+ */
+bool gw_omni_payload(const GwOmni *omni, size_t index, const uint8_t **data, size_t *len);
+
+/*
+ Frees a file and everything that came from it.
+
+ # Safety
+
+ `omni` must be null or have come from [`gw_omni_open`], and not have been
+ freed already.
+ */
+void gw_omni_free(GwOmni *omni);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* GWYDD_H */
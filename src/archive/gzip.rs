@@ -0,0 +1,21 @@
+use std::io::Write;
+
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+
+use super::{is_gzip, unpack, ArchiveError, Result};
+
+/// Unpacks a gzip file, which can be several gzip files one after another.
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>> {
+    if !is_gzip(data) {
+        return Err(ArchiveError::Invalid("gzip", "no gzip header"));
+    }
+
+    unpack(MultiGzDecoder::new(data))
+}
+
+/// Packs `data` as a gzip file.
+pub fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
@@ -0,0 +1,183 @@
+//! Reading Omni files out of the gzip and zip files they're often passed
+//! around in, and gzipping scripts, with flate2 and zip.
+
+#[cfg(feature = "archives")]
+use std::io::Read;
+
+use thiserror::Error;
+
+#[cfg(feature = "archives")]
+mod gzip;
+#[cfg(feature = "archives")]
+mod zip;
+
+#[cfg(feature = "archives")]
+pub use gzip::{gunzip, gzip};
+#[cfg(feature = "archives")]
+pub use zip::list_entries;
+
+#[derive(Error, Debug)]
+pub enum ArchiveError {
+    #[error("Invalid {0} file: {1}")]
+    Invalid(&'static str, &'static str),
+
+    #[error("Unsupported {0}: {1}")]
+    Unsupported(&'static str, String),
+
+    #[error("Unpacks to more than {} MB", MAX_UNPACKED >> 20)]
+    TooBig,
+
+    #[error("{0} files need gwŷdd to be built with the \"archives\" feature")]
+    NeedsArchives(&'static str),
+
+    #[error("Couldn't unpack: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[cfg(feature = "archives")]
+    #[error("Invalid zip file: {0}")]
+    Zip(::zip::result::ZipError),
+}
+
+pub type Result<T> = std::result::Result<T, ArchiveError>;
+
+/// Most bytes anything is unpacked to, so that a broken or hostile file
+/// can't fill memory.
+const MAX_UNPACKED: usize = 1 << 31;
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&[0x1F, 0x8B])
+}
+
+pub fn is_zip(data: &[u8]) -> bool {
+    data.starts_with(b"PK\x03\x04")
+}
+
+/// A file stored in a zip file.
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    /// Path inside the zip file, separated by `/`
+    pub path: String,
+    /// Size once it's unpacked
+    pub len: u64,
+    /// Where it is in the zip file's directory
+    #[cfg_attr(not(feature = "archives"), allow(dead_code))]
+    index: usize,
+}
+
+/// Reads everything that `reader` unpacks to, as long as it isn't more
+/// than [`MAX_UNPACKED`].
+#[cfg(feature = "archives")]
+fn unpack(reader: impl Read) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    reader.take(MAX_UNPACKED as u64 + 1).read_to_end(&mut out)?;
+
+    match out.len() > MAX_UNPACKED {
+        true => Err(ArchiveError::TooBig),
+        false => Ok(out),
+    }
+}
+
+// stand-ins for the archive formats when they aren't built in
+
+#[cfg(not(feature = "archives"))]
+pub fn gunzip(_: &[u8]) -> Result<Vec<u8>> {
+    Err(ArchiveError::NeedsArchives("gzip"))
+}
+
+#[cfg(not(feature = "archives"))]
+pub fn gzip(_: &[u8]) -> Result<Vec<u8>> {
+    Err(ArchiveError::NeedsArchives("gzip"))
+}
+
+#[cfg(not(feature = "archives"))]
+pub fn list_entries(_: &[u8]) -> Result<Vec<ZipEntry>> {
+    Err(ArchiveError::NeedsArchives("zip"))
+}
+
+#[cfg(not(feature = "archives"))]
+impl ZipEntry {
+    pub fn read(&self, _: &[u8]) -> Result<Vec<u8>> {
+        Err(ArchiveError::NeedsArchives("zip"))
+    }
+}
+
+#[cfg(all(test, feature = "archives"))]
+mod tests {
+    use super::*;
+
+    /// `printf 'one\n' | gzip -n` and `printf 'two\n' | gzip -n -9`, one
+    /// after the other.
+    const GZIP: [u8; 48] = [
+        0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xCB, 0xCF, 0x4B, 0xE5, 0x02,
+        0x00, 0x9F, 0xA8, 0x17, 0xF8, 0x04, 0x00, 0x00, 0x00, 0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x02, 0x03, 0x2B, 0x29, 0xCF, 0xE7, 0x02, 0x00, 0x74, 0x08, 0x17, 0x96, 0x04,
+        0x00, 0x00, 0x00,
+    ];
+
+    /// `zip -X -r` of a folder with a deflated `a.si` and a stored `b.txt`
+    /// in it.
+    const ZIP: [u8; 297] = [
+        0x50, 0x4B, 0x03, 0x04, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x58, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+        0x64, 0x2F, 0x50, 0x4B, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x21,
+        0x58, 0xDE, 0xF0, 0xAF, 0xB8, 0x0E, 0x00, 0x00, 0x00, 0x2D, 0x00, 0x00, 0x00, 0x06, 0x00,
+        0x00, 0x00, 0x64, 0x2F, 0x61, 0x2E, 0x73, 0x69, 0x0B, 0xF2, 0x74, 0x73, 0x53, 0xC8, 0xCF,
+        0xCD, 0xCB, 0x24, 0x82, 0xE0, 0x02, 0x00, 0x50, 0x4B, 0x03, 0x04, 0x0A, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x21, 0x58, 0x7A, 0x7A, 0x6F, 0xED, 0x03, 0x00, 0x00, 0x00, 0x03,
+        0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x64, 0x2F, 0x62, 0x2E, 0x74, 0x78, 0x74, 0x68,
+        0x69, 0x0A, 0x50, 0x4B, 0x01, 0x02, 0x1E, 0x03, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x21, 0x58, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0xED, 0x41, 0x00,
+        0x00, 0x00, 0x00, 0x64, 0x2F, 0x50, 0x4B, 0x01, 0x02, 0x1E, 0x03, 0x14, 0x00, 0x00, 0x00,
+        0x08, 0x00, 0x00, 0x00, 0x21, 0x58, 0xDE, 0xF0, 0xAF, 0xB8, 0x0E, 0x00, 0x00, 0x00, 0x2D,
+        0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+        0xA4, 0x81, 0x20, 0x00, 0x00, 0x00, 0x64, 0x2F, 0x61, 0x2E, 0x73, 0x69, 0x50, 0x4B, 0x01,
+        0x02, 0x1E, 0x03, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x58, 0x7A, 0x7A,
+        0x6F, 0xED, 0x03, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xA4, 0x81, 0x52, 0x00, 0x00, 0x00, 0x64, 0x2F,
+        0x62, 0x2E, 0x74, 0x78, 0x74, 0x50, 0x4B, 0x05, 0x06, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00,
+        0x03, 0x00, 0x99, 0x00, 0x00, 0x00, 0x7A, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn gzip_files_are_read() {
+        assert_eq!(gunzip(&GZIP).unwrap(), b"one\ntwo\n");
+    }
+
+    #[test]
+    fn gzip_round_trips() {
+        let data = b"defineSettings S { bufferSizeKB = 64; buffersNum = 2; }\n".repeat(100);
+        let packed = gzip(&data).unwrap();
+
+        assert!(is_gzip(&packed) && packed.len() < data.len());
+        assert_eq!(gunzip(&packed).unwrap(), data);
+    }
+
+    #[test]
+    fn zip_files_are_read() {
+        assert!(is_zip(&ZIP));
+
+        let entries = list_entries(&ZIP).unwrap();
+        let paths = entries.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, ["d/a.si", "d/b.txt"]);
+
+        assert_eq!(
+            entries[0].read(&ZIP).unwrap(),
+            b"RIFF omni omni omni omni omni omni omni omni\n"
+        );
+        assert_eq!(entries[1].read(&ZIP).unwrap(), b"hi\n");
+        assert_eq!(entries[1].len, 3);
+    }
+
+    #[test]
+    fn broken_files_are_errors() {
+        let mut zip = ZIP;
+        // flip a bit in a.si's deflated data
+        let at = ZIP.windows(6).position(|w| w == b"d/a.si").unwrap() + 6;
+        zip[at] ^= 1;
+
+        assert!(list_entries(&zip).unwrap()[0].read(&zip).is_err());
+        assert!(gunzip(&GZIP[..30]).is_err());
+        assert!(gunzip(b"not gzip").is_err());
+    }
+}
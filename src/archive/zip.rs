@@ -0,0 +1,42 @@
+use std::io::Cursor;
+
+use zip::{result::ZipError, ZipArchive};
+
+use super::{unpack, ArchiveError, Result, ZipEntry};
+
+impl From<ZipError> for ArchiveError {
+    fn from(e: ZipError) -> Self {
+        match e {
+            ZipError::Io(e) => Self::Io(e),
+            ZipError::UnsupportedArchive(what) => Self::Unsupported("zip file", what.into()),
+            e => Self::Zip(e),
+        }
+    }
+}
+
+impl ZipEntry {
+    /// Unpacks this file from the zip file it was listed from.
+    pub fn read(&self, zip: &[u8]) -> Result<Vec<u8>> {
+        let mut archive = ZipArchive::new(Cursor::new(zip))?;
+        let file = archive.by_index(self.index)?;
+
+        unpack(file)
+    }
+}
+
+/// Lists every file in a zip file, leaving out folders.
+pub fn list_entries(zip: &[u8]) -> Result<Vec<ZipEntry>> {
+    let mut archive = ZipArchive::new(Cursor::new(zip))?;
+
+    (0..archive.len())
+        .filter_map(|index| match archive.by_index_raw(index) {
+            Ok(file) if file.is_dir() => None,
+            Ok(file) => Some(Ok(ZipEntry {
+                path: file.name().into(),
+                len: file.size(),
+                index,
+            })),
+            Err(e) => Some(Err(e.into())),
+        })
+        .collect()
+}
@@ -0,0 +1,94 @@
+use std::io::{Cursor, ErrorKind};
+
+use symphonia::{
+    core::{
+        audio::SampleBuffer,
+        codecs::{DecoderOptions, CODEC_TYPE_FLAC, CODEC_TYPE_OPUS, CODEC_TYPE_VORBIS},
+        errors::Error,
+        formats::FormatOptions,
+        io::MediaSourceStream,
+        meta::MetadataOptions,
+        probe::Hint,
+    },
+    default::{get_codecs, get_probe},
+};
+
+use super::{AudioError, Pcm, Result};
+
+impl Pcm {
+    pub fn from_flac(data: &[u8]) -> Result<Self> {
+        Ok(decode(data)?.1)
+    }
+
+    /// Decodes FLAC or Vorbis in an Ogg container, returning the name of
+    /// its codec along with its samples.
+    pub fn from_ogg(data: &[u8]) -> Result<(&'static str, Self)> {
+        decode(data)
+    }
+}
+
+/// Decodes the first track of a FLAC or Ogg file.
+fn decode(data: &[u8]) -> Result<(&'static str, Pcm)> {
+    let source = MediaSourceStream::new(Box::new(Cursor::new(data.to_vec())), Default::default());
+    let mut format = get_probe()
+        .format(
+            &Hint::new(),
+            source,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?
+        .format;
+
+    let track = format
+        .default_track()
+        .ok_or(AudioError::Invalid("Ogg", "No streams"))?;
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+
+    let codec = match (params.codec, data.starts_with(b"OggS")) {
+        (CODEC_TYPE_FLAC, false) => "FLAC",
+        (CODEC_TYPE_FLAC, true) => "Ogg FLAC",
+        (CODEC_TYPE_VORBIS, _) => "Ogg Vorbis",
+        (CODEC_TYPE_OPUS, _) => return Err(AudioError::UnsupportedCodec("Opus")),
+        _ => return Err(AudioError::UnsupportedCodec("this kind of Ogg file")),
+    };
+
+    let mut decoder = get_codecs().make(&params, &DecoderOptions::default())?;
+
+    // kept in a size that WAV files can hold, which for Vorbis, with no
+    // size of its own, is 16 bits
+    let bits = params.bits_per_sample.map_or(16, |b| b.next_multiple_of(8));
+
+    let mut pcm = Pcm {
+        channels: params.channels.map_or(0, |c| c.count() as u16),
+        sample_rate: params.sample_rate.unwrap_or_default(),
+        bits: bits as u16,
+        samples: vec![],
+    };
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = decoder.decode(&packet)?;
+        let spec = *decoded.spec();
+
+        pcm.channels = spec.channels.count() as u16;
+        pcm.sample_rate = spec.rate;
+
+        // samples come out scaled to fill 32 bits
+        let mut buffer = SampleBuffer::<i32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        pcm.samples
+            .extend(buffer.samples().iter().map(|&s| s >> (32 - bits)));
+    }
+
+    Ok((codec, pcm))
+}
@@ -0,0 +1,322 @@
+use super::{AudioError, Pcm, Result};
+
+/// Samples in each channel of a frame.
+const BLOCK_SIZE: usize = 4096;
+
+/// Highest order of the fixed predictors.
+const MAX_ORDER: usize = 4;
+
+/// Largest Rice parameter that can be written, since 15 means the residual
+/// isn't Rice-coded.
+const MAX_RICE_PARAMETER: u32 = 14;
+
+pub(super) const STREAMINFO: u8 = 0;
+const VORBIS_COMMENT: u8 = 4;
+
+/// Writes values most significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// Bits that don't make a whole byte yet
+    pending: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    /// Writes the lowest `bits` bits of `value`, up to 32 at a time.
+    fn write(&mut self, value: u64, bits: u32) {
+        self.pending = (self.pending << bits) | (value & ((1 << bits) - 1));
+        self.len += bits;
+
+        while self.len >= 8 {
+            self.len -= 8;
+            self.bytes.push((self.pending >> self.len) as u8);
+        }
+
+        self.pending &= (1 << self.len) - 1;
+    }
+
+    /// Writes `n` zeros and then a one.
+    fn unary(&mut self, mut n: u64) {
+        while n >= 32 {
+            self.write(0, 32);
+            n -= 32;
+        }
+
+        self.write(1, n as u32 + 1);
+    }
+
+    /// Writes zeros up to the next byte.
+    fn align(&mut self) {
+        if self.len != 0 {
+            self.write(0, 8 - self.len);
+        }
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, &b| {
+        (0..8).fold(crc ^ b, |crc, _| match crc & 0x80 {
+            0 => crc << 1,
+            _ => crc << 1 ^ 0x07,
+        })
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &b| {
+        (0..8).fold(crc ^ (b as u16) << 8, |crc, _| match crc & 0x8000 {
+            0 => crc << 1,
+            _ => crc << 1 ^ 0x8005,
+        })
+    })
+}
+
+/// Frame numbers are coded the same way as characters in UTF-8, extended
+/// to fit 36 bits.
+fn write_utf8(w: &mut BitWriter, n: u64) {
+    if n < 0x80 {
+        w.write(n, 8);
+        return;
+    }
+
+    let mut bytes = 2;
+    while n >= 1 << (5 * bytes + 1) {
+        bytes += 1;
+    }
+
+    w.write((0xFF00 >> bytes) & 0xFF | n >> (6 * (bytes - 1)), 8);
+
+    for i in (0..bytes - 1).rev() {
+        w.write(0x80 | (n >> (6 * i)) & 0x3F, 8);
+    }
+}
+
+/// What's left of `samples` after predicting each one from the `order`
+/// before it with a fixed polynomial.
+fn residuals(samples: &[i64], order: usize) -> Vec<i64> {
+    (order..samples.len())
+        .map(|i| {
+            let s = |back: usize| samples[i - back];
+
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+/// Residuals as unsigned numbers, with negatives at the odd ones.
+fn fold(residual: i64) -> u64 {
+    ((residual << 1) ^ (residual >> 63)) as u64
+}
+
+fn rice_size(residuals: &[i64], parameter: u32) -> u64 {
+    residuals
+        .iter()
+        .map(|&r| (fold(r) >> parameter) + 1 + parameter as u64)
+        .sum()
+}
+
+/// The cheapest way to code `residuals`, as its Rice parameter and size in
+/// bits.
+fn best_rice_parameter(residuals: &[i64]) -> (u32, u64) {
+    (0..=MAX_RICE_PARAMETER)
+        .map(|p| (p, rice_size(residuals, p)))
+        .min_by_key(|&(_, size)| size)
+        .unwrap_or((0, 0))
+}
+
+const CONSTANT: u64 = 0b000000;
+const VERBATIM: u64 = 0b000001;
+const FIXED: u64 = 0b001000;
+
+fn write_subframe_header(w: &mut BitWriter, kind: u64) {
+    w.write(0, 1);
+    w.write(kind, 6);
+    // no wasted bits
+    w.write(0, 1);
+}
+
+fn write_subframe(w: &mut BitWriter, samples: &[i64], bits: u32) {
+    let write_sample = |w: &mut BitWriter, s: i64| w.write(s as u64, bits);
+
+    if samples.iter().all(|&s| s == samples[0]) {
+        write_subframe_header(w, CONSTANT);
+        write_sample(w, samples[0]);
+        return;
+    }
+
+    let verbatim = samples.len() as u64 * bits as u64;
+
+    let best = (0..=MAX_ORDER.min(samples.len() - 1))
+        .map(|order| {
+            let residuals = residuals(samples, order);
+            let (parameter, size) = best_rice_parameter(&residuals);
+            (
+                order,
+                residuals,
+                parameter,
+                order as u64 * bits as u64 + 10 + size,
+            )
+        })
+        .min_by_key(|&(.., size)| size)
+        .filter(|&(.., size)| size < verbatim);
+
+    let Some((order, residuals, parameter, _)) = best else {
+        write_subframe_header(w, VERBATIM);
+        for &s in samples {
+            write_sample(w, s);
+        }
+        return;
+    };
+
+    write_subframe_header(w, FIXED | order as u64);
+
+    for &s in &samples[..order] {
+        write_sample(w, s);
+    }
+
+    // Rice coding with 4-bit parameters, in one partition
+    w.write(0b00, 2);
+    w.write(0, 4);
+    w.write(parameter as u64, 4);
+
+    for r in residuals {
+        let r = fold(r);
+        w.unary(r >> parameter);
+        w.write(r, parameter);
+    }
+}
+
+/// Code in a frame header for the size of its samples.
+fn sample_size_code(bits: u16) -> u64 {
+    match bits {
+        8 => 0b001,
+        16 => 0b100,
+        24 => 0b110,
+        // given in STREAMINFO
+        _ => 0b000,
+    }
+}
+
+/// A FLAC stream's parts, for putting in a file or an Ogg container.
+pub(super) struct FlacStream {
+    pub streaminfo: Vec<u8>,
+    /// Each frame, with how many samples each channel has up to the end of
+    /// it
+    pub frames: Vec<(Vec<u8>, u64)>,
+}
+
+/// Header of a metadata block.
+pub(super) fn block_header(kind: u8, last: bool, len: usize) -> [u8; 4] {
+    let [_, a, b, c] = (len as u32).to_be_bytes();
+    [kind | if last { 0x80 } else { 0 }, a, b, c]
+}
+
+/// A VORBIS_COMMENT block with no comments, which Ogg FLAC needs.
+pub(super) fn vorbis_comment(last: bool) -> Vec<u8> {
+    let vendor = concat!("gwydd ", env!("CARGO_PKG_VERSION"));
+
+    let mut body = (vendor.len() as u32).to_le_bytes().to_vec();
+    body.extend(vendor.as_bytes());
+    body.extend(0u32.to_le_bytes());
+
+    let mut block = block_header(VORBIS_COMMENT, last, body.len()).to_vec();
+    block.extend(body);
+    block
+}
+
+impl Pcm {
+    pub(super) fn flac_stream(&self) -> Result<FlacStream> {
+        if !matches!(self.bits, 8 | 16 | 24) {
+            return Err(AudioError::UnsupportedBits(self.bits));
+        }
+
+        if !(1..=8).contains(&self.channels) {
+            return Err(AudioError::UnsupportedChannels(self.channels));
+        }
+
+        let channels = self.channels as usize;
+        let bits = self.bits as u32;
+
+        let mut info = BitWriter::default();
+        info.write(BLOCK_SIZE as u64, 16);
+        info.write(BLOCK_SIZE as u64, 16);
+        // smallest and largest frame sizes aren't known
+        info.write(0, 24);
+        info.write(0, 24);
+        info.write(self.sample_rate as u64, 20);
+        info.write(channels as u64 - 1, 3);
+        info.write(bits as u64 - 1, 5);
+        info.write(self.frames() as u64 >> 32, 4);
+        info.write(self.frames() as u64 & 0xFFFF_FFFF, 32);
+        // nor is the MD5 of the samples
+        info.bytes.extend([0; 16]);
+
+        let mut frames = vec![];
+        let mut done = 0;
+
+        for (number, block) in self.samples.chunks(BLOCK_SIZE * channels).enumerate() {
+            let len = block.len() / channels;
+
+            let mut w = BitWriter::default();
+            w.write(0b11111111111110, 14);
+            w.write(0, 2);
+            // block size as a 16-bit number after the header, sample rate
+            // from STREAMINFO
+            w.write(0b0111, 4);
+            w.write(0b0000, 4);
+            w.write(channels as u64 - 1, 4);
+            w.write(sample_size_code(self.bits), 3);
+            w.write(0, 1);
+            write_utf8(&mut w, number as u64);
+            w.write(len as u64 - 1, 16);
+
+            let crc = crc8(&w.bytes);
+            w.write(crc as u64, 8);
+
+            for channel in 0..channels {
+                let samples = block
+                    .iter()
+                    .skip(channel)
+                    .step_by(channels)
+                    .map(|&s| s as i64)
+                    .collect::<Vec<_>>();
+
+                write_subframe(&mut w, &samples, bits);
+            }
+
+            w.align();
+            let crc = crc16(&w.bytes);
+            w.write(crc as u64, 16);
+
+            done += len as u64;
+            frames.push((w.bytes, done));
+        }
+
+        Ok(FlacStream {
+            streaminfo: info.bytes,
+            frames,
+        })
+    }
+
+    pub fn to_flac(&self) -> Result<Vec<u8>> {
+        let stream = self.flac_stream()?;
+
+        let mut flac = b"fLaC".to_vec();
+        flac.extend(block_header(STREAMINFO, false, stream.streaminfo.len()));
+        flac.extend(stream.streaminfo);
+        flac.extend(vorbis_comment(true));
+
+        for (frame, _) in stream.frames {
+            flac.extend(frame);
+        }
+
+        Ok(flac)
+    }
+}
@@ -0,0 +1,387 @@
+//! Converting the sounds stored in Omni files to and from other formats.
+
+use std::{fmt::Display, str::FromStr};
+
+use thiserror::Error;
+
+#[cfg(feature = "audio-codecs")]
+mod decode;
+#[cfg(feature = "audio-codecs")]
+mod flac;
+#[cfg(feature = "audio-codecs")]
+mod ogg;
+mod wav;
+
+#[derive(Error, Debug)]
+pub enum AudioError {
+    #[error("Not a WAV file")]
+    NotWav,
+
+    #[error("WAV file has no \"{0}\" chunk")]
+    MissingChunk(&'static str),
+
+    #[error("Unsupported WAV format {0:#06X} (only PCM is supported)")]
+    UnsupportedFormat(u16),
+
+    #[error("Unsupported sample size of {0} bits")]
+    UnsupportedBits(u16),
+
+    #[error("Unsupported number of channels ({0})")]
+    UnsupportedChannels(u16),
+
+    #[error("Can't decode {0}, so it has to be converted to WAV or FLAC first")]
+    UnsupportedCodec(&'static str),
+
+    #[error("Invalid {0} file: {1}")]
+    Invalid(&'static str, &'static str),
+
+    #[error("{0} needs gwŷdd to be built with the \"audio-codecs\" feature")]
+    NeedsCodecs(&'static str),
+
+    #[cfg(feature = "audio-codecs")]
+    #[error("Couldn't decode: {0}")]
+    Decode(#[from] symphonia::core::errors::Error),
+
+    #[cfg(feature = "audio-codecs")]
+    #[error("Couldn't write Ogg file: {0}")]
+    OggWrite(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, AudioError>;
+
+/// Sample rates the engine's sounds are recorded at.
+const ENGINE_RATES: [u32; 3] = [11025, 22050, 44100];
+
+/// Uncompressed audio.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pcm {
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Bits per sample: 8, 16, 24 or 32
+    pub bits: u16,
+    /// Signed samples with each channel's interleaved; 8-bit samples, which
+    /// WAV files store unsigned, are centred on 0 too
+    pub samples: Vec<i32>,
+}
+
+impl Pcm {
+    /// Number of samples in each channel.
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    /// The nearest format to this one that the engine plays properly: 8 or
+    /// 16-bit, at one of [`ENGINE_RATES`], with at most `max_channels`.
+    pub fn engine_format(&self, max_channels: u16) -> (u16, u32, u16) {
+        let rate = ENGINE_RATES
+            .into_iter()
+            .find(|&r| r >= self.sample_rate)
+            .unwrap_or(ENGINE_RATES[ENGINE_RATES.len() - 1]);
+
+        let bits = self.bits.min(16);
+
+        (self.channels.min(max_channels), rate, bits)
+    }
+
+    /// Mixes down to `channels`, resamples linearly to `sample_rate` and
+    /// changes the sample size to `bits`.
+    pub fn convert(&self, channels: u16, sample_rate: u32, bits: u16) -> Self {
+        let from = self.channels.max(1) as usize;
+        let to = channels.max(1) as usize;
+
+        // each output channel is the average of the input channels that
+        // are mixed into it
+        let mixed = self
+            .samples
+            .chunks_exact(from)
+            .flat_map(|frame| {
+                (0..to).map(move |c| {
+                    let inputs = (c % from..from).step_by(to).map(|i| frame[i] as i64);
+                    let count = inputs.clone().count().max(1) as i64;
+                    inputs.sum::<i64>() / count
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let frames = mixed.len() / to;
+        let resampled_frames =
+            (frames as u64 * sample_rate as u64).div_ceil(self.sample_rate.max(1) as u64) as usize;
+        let step = self.sample_rate as f64 / sample_rate.max(1) as f64;
+
+        let samples = (0..resampled_frames)
+            .flat_map(|i| {
+                let pos = i as f64 * step;
+                let before = (pos as usize).min(frames.saturating_sub(1));
+                let after = (before + 1).min(frames.saturating_sub(1));
+                let t = pos - before as f64;
+                let mixed = &mixed;
+
+                (0..to).map(move |c| {
+                    let a = mixed[before * to + c] as f64;
+                    let b = mixed[after * to + c] as f64;
+                    let sample = (a + (b - a) * t).round() as i64;
+
+                    match bits.cmp(&self.bits) {
+                        std::cmp::Ordering::Less => sample >> (self.bits - bits),
+                        _ => sample << (bits - self.bits),
+                    }
+                })
+            })
+            .map(|s| s as i32)
+            .collect();
+
+        Self {
+            channels: to as u16,
+            sample_rate,
+            bits,
+            samples,
+        }
+    }
+
+    pub fn encode(&self, format: AudioFormat) -> Result<Vec<u8>> {
+        match format {
+            AudioFormat::Wav => Ok(self.to_wav()),
+            AudioFormat::Flac => self.to_flac(),
+            AudioFormat::OggFlac => self.to_ogg_flac(),
+        }
+    }
+}
+
+/// Stand-ins for the codecs when they aren't built in.
+#[cfg(not(feature = "audio-codecs"))]
+impl Pcm {
+    pub fn from_flac(_: &[u8]) -> Result<Self> {
+        Err(AudioError::NeedsCodecs("FLAC"))
+    }
+
+    pub fn to_flac(&self) -> Result<Vec<u8>> {
+        Err(AudioError::NeedsCodecs("FLAC"))
+    }
+
+    pub fn from_ogg(_: &[u8]) -> Result<(&'static str, Self)> {
+        Err(AudioError::NeedsCodecs("Ogg"))
+    }
+
+    pub fn to_ogg_flac(&self) -> Result<Vec<u8>> {
+        Err(AudioError::NeedsCodecs("Ogg FLAC"))
+    }
+}
+
+impl Display for Pcm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let channels = match self.channels {
+            1 => "mono".into(),
+            2 => "stereo".into(),
+            n => format!("{n}-channel"),
+        };
+
+        write!(f, "{}-bit {channels} at {} Hz", self.bits, self.sample_rate)
+    }
+}
+
+/// What to convert stored sounds to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    Wav,
+    Flac,
+    /// FLAC in an Ogg container, so still lossless
+    OggFlac,
+}
+
+impl AudioFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Wav => "wav",
+            Self::Flac => "flac",
+            Self::OggFlac => "oga",
+        }
+    }
+}
+
+impl FromStr for AudioFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "wav" => Ok(Self::Wav),
+            "flac" => Ok(Self::Flac),
+            "ogg-flac" => Ok(Self::OggFlac),
+            _ => Err(format!(
+                "Expected \"wav\", \"flac\" or \"ogg-flac\", found \"{s}\""
+            )),
+        }
+    }
+}
+
+/// A sound that was converted so the engine could play it.
+#[derive(Debug, Clone)]
+pub struct Conversion {
+    /// Codec it was in
+    pub codec: &'static str,
+    pub from: String,
+    pub to: String,
+}
+
+impl Display for Conversion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} to WAV {}", self.codec, self.from, self.to)
+    }
+}
+
+/// Decodes a WAV, FLAC, Ogg FLAC or Ogg Vorbis file, returning the name of
+/// its codec along with its samples. MP3 and Opus aren't supported.
+pub fn decode(data: &[u8]) -> Result<(&'static str, Pcm)> {
+    if data.starts_with(b"fLaC") {
+        Ok(("FLAC", Pcm::from_flac(data)?))
+    } else if data.starts_with(b"OggS") {
+        Pcm::from_ogg(data)
+    } else if data.starts_with(b"ID3") || matches!(data, [0xFF, b, ..] if b & 0xE0 == 0xE0) {
+        Err(AudioError::UnsupportedCodec("MP3"))
+    } else {
+        Ok(("WAV", Pcm::from_wav(data)?))
+    }
+}
+
+/// Converts a sound to a WAV file the engine can play through a presenter
+/// that takes at most `max_channels`, or returns `None` if it already can.
+pub fn convert_for_engine(data: &[u8], max_channels: u16) -> Result<Option<(Vec<u8>, Conversion)>> {
+    let (codec, pcm) = decode(data)?;
+    let (channels, sample_rate, bits) = pcm.engine_format(max_channels);
+
+    if codec == "WAV" && (pcm.channels, pcm.sample_rate, pcm.bits) == (channels, sample_rate, bits)
+    {
+        return Ok(None);
+    }
+
+    let converted = pcm.convert(channels, sample_rate, bits);
+
+    let conversion = Conversion {
+        codec,
+        from: pcm.to_string(),
+        to: converted.to_string(),
+    };
+
+    Ok(Some((converted.to_wav(), conversion)))
+}
+
+/// Converts a stored sound to `format`.
+pub fn transcode(wav: &[u8], format: AudioFormat) -> Result<Vec<u8>> {
+    Pcm::from_wav(wav)?.encode(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ramp, a stretch of silence and some noise in each channel, so the
+    /// FLAC encoder uses each kind of subframe.
+    fn pcm(channels: u16, bits: u16) -> Pcm {
+        let max = 1i64 << (bits - 1);
+        let mut noise = 0x1234_5678u32;
+
+        let samples = (0..5000 * channels as i64)
+            .map(|i| {
+                noise = noise.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let sample = match i / channels as i64 {
+                    0..=1999 => i * 7 + (i % channels as i64) * 100,
+                    2000..=2999 => 0,
+                    _ => (noise >> 8) as i64,
+                };
+                (sample.rem_euclid(2 * max) - max) as i32
+            })
+            .collect();
+
+        Pcm {
+            channels,
+            sample_rate: 22050,
+            bits,
+            samples,
+        }
+    }
+
+    #[test]
+    fn wav_files_are_read() {
+        let mut wav = b"RIFF\x2C\0\0\0WAVEfmt \x10\0\0\0".to_vec();
+        // PCM, stereo, 44100 Hz, 176400 bytes a second, 4 bytes a frame,
+        // 16-bit
+        wav.extend([1, 0, 2, 0, 0x44, 0xAC, 0, 0, 0x10, 0xB1, 2, 0, 4, 0, 16, 0]);
+        wav.extend(b"data\x08\0\0\0");
+        wav.extend([0x01, 0x00, 0xFF, 0xFF, 0x00, 0x80, 0xFF, 0x7F]);
+
+        let pcm = Pcm::from_wav(&wav).unwrap();
+        assert_eq!((pcm.channels, pcm.sample_rate, pcm.bits), (2, 44100, 16));
+        assert_eq!(pcm.samples, [1, -1, -32768, 32767]);
+        assert_eq!(pcm.to_wav(), wav);
+    }
+
+    #[test]
+    fn wav_round_trips() {
+        for bits in [8, 16, 24, 32] {
+            let pcm = pcm(2, bits);
+            assert_eq!(Pcm::from_wav(&pcm.to_wav()).unwrap(), pcm, "{bits}-bit");
+        }
+    }
+
+    #[cfg(feature = "audio-codecs")]
+    #[test]
+    fn flac_from_the_spec_is_read() {
+        // the first decoding example in RFC 9639
+        let flac = [
+            0x66, 0x4C, 0x61, 0x43, 0x80, 0x00, 0x00, 0x22, 0x10, 0x00, 0x10, 0x00, 0x00, 0x00,
+            0x0F, 0x00, 0x00, 0x0F, 0x0A, 0xC4, 0x42, 0xF0, 0x00, 0x00, 0x00, 0x01, 0x3E, 0x84,
+            0xB4, 0x18, 0x07, 0xDC, 0x69, 0x03, 0x07, 0x58, 0x6A, 0x3D, 0xAD, 0x1A, 0x2E, 0x0F,
+            0xFF, 0xF8, 0x69, 0x18, 0x00, 0x00, 0xBF, 0x03, 0x58, 0xFD, 0x03, 0x12, 0x8B, 0xAA,
+            0x9A,
+        ];
+
+        let (codec, pcm) = decode(&flac).unwrap();
+        assert_eq!(codec, "FLAC");
+        assert_eq!(
+            pcm,
+            Pcm {
+                channels: 2,
+                sample_rate: 44100,
+                bits: 16,
+                samples: vec![25588, 10416],
+            }
+        );
+    }
+
+    #[cfg(feature = "audio-codecs")]
+    #[test]
+    fn flac_round_trips() {
+        for (channels, bits) in [(1, 8), (2, 16), (3, 24)] {
+            let pcm = pcm(channels, bits);
+
+            for (format, codec) in [
+                (AudioFormat::Flac, "FLAC"),
+                (AudioFormat::OggFlac, "Ogg FLAC"),
+            ] {
+                let encoded = pcm.encode(format).unwrap();
+                assert_eq!(
+                    decode(&encoded).unwrap(),
+                    (codec, pcm.clone()),
+                    "{codec} {pcm}"
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "audio-codecs")]
+    #[test]
+    fn ogg_vorbis_goes_to_the_vorbis_decoder() {
+        let mut ogg = ::ogg::PacketWriter::new(vec![]);
+        ogg.write_packet(
+            b"\x01vorbis and nothing else".to_vec().into(),
+            1,
+            ::ogg::PacketWriteEndInfo::EndStream,
+            0,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            decode(&ogg.into_inner()),
+            Err(AudioError::Decode(_))
+        ));
+    }
+}
@@ -0,0 +1,49 @@
+use ogg::{PacketWriteEndInfo, PacketWriter};
+
+use super::{
+    flac::{block_header, vorbis_comment, STREAMINFO},
+    Pcm, Result,
+};
+
+/// Stream serial number; the files only ever have one stream.
+const SERIAL: u32 = 0x6777_7964;
+
+impl Pcm {
+    /// Encodes as FLAC in an Ogg container.
+    pub fn to_ogg_flac(&self) -> Result<Vec<u8>> {
+        let stream = self.flac_stream()?;
+
+        // the mapping's header, then the stream's first metadata block
+        let mut first = vec![0x7F];
+        first.extend(b"FLAC");
+        first.extend([1, 0]);
+        // header packets after this one
+        first.extend(1u16.to_be_bytes());
+        first.extend(b"fLaC");
+        first.extend(block_header(STREAMINFO, false, stream.streaminfo.len()));
+        first.extend(stream.streaminfo);
+
+        // each packet gets a page of its own, and the first page can't
+        // have anything else on it
+        let end = |last| match last {
+            true => PacketWriteEndInfo::EndStream,
+            false => PacketWriteEndInfo::EndPage,
+        };
+
+        let mut ogg = PacketWriter::new(vec![]);
+        ogg.write_packet(first.into(), SERIAL, end(false), 0)?;
+        ogg.write_packet(
+            vorbis_comment(true).into(),
+            SERIAL,
+            end(stream.frames.is_empty()),
+            0,
+        )?;
+
+        let count = stream.frames.len();
+        for (i, (frame, granule)) in stream.frames.into_iter().enumerate() {
+            ogg.write_packet(frame.into(), SERIAL, end(i == count - 1), granule)?;
+        }
+
+        Ok(ogg.into_inner())
+    }
+}
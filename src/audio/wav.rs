@@ -0,0 +1,144 @@
+use super::{AudioError, Pcm, Result};
+
+const PCM: u16 = 1;
+const EXTENSIBLE: u16 = 0xFFFE;
+
+/// Size of the format the engine stores at the start of a sound: a
+/// PCMWAVEFORMAT, followed by the size of the data and some flags.
+const ENGINE_HEADER_SIZE: usize = 24;
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// The `fmt ` chunk's format, channels, sample rate and bits per sample.
+fn parse_format(fmt: &[u8]) -> Result<(u16, u32, u16)> {
+    let short = || AudioError::MissingChunk("fmt ");
+
+    let mut tag = u16_at(fmt, 0).ok_or_else(short)?;
+    let channels = u16_at(fmt, 2).ok_or_else(short)?;
+    let sample_rate = u32_at(fmt, 4).ok_or_else(short)?;
+    let bits = u16_at(fmt, 14).ok_or_else(short)?;
+
+    // the real format is the first two bytes of the sub-format GUID
+    if tag == EXTENSIBLE {
+        tag = u16_at(fmt, 24).unwrap_or(EXTENSIBLE);
+    }
+
+    if tag != PCM {
+        return Err(AudioError::UnsupportedFormat(tag));
+    }
+
+    if !matches!(bits, 8 | 16 | 24 | 32) {
+        return Err(AudioError::UnsupportedBits(bits));
+    }
+
+    if channels == 0 {
+        return Err(AudioError::UnsupportedChannels(channels));
+    }
+
+    Ok((channels, sample_rate, bits))
+}
+
+fn decode_samples(data: &[u8], bits: u16) -> Vec<i32> {
+    let width = bits as usize / 8;
+
+    data.chunks_exact(width)
+        .map(|s| match s {
+            [a] => *a as i32 - 128,
+            [a, b] => i16::from_le_bytes([*a, *b]) as i32,
+            [a, b, c] => i32::from_le_bytes([0, *a, *b, *c]) >> 8,
+            [a, b, c, d] => i32::from_le_bytes([*a, *b, *c, *d]),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+impl Pcm {
+    /// Reads a WAV file, or a sound as the engine stores it: the format,
+    /// the size of the data and some flags, followed by the samples.
+    pub fn from_wav(data: &[u8]) -> Result<Self> {
+        let (format, samples) = if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WAVE") {
+            let mut format = None;
+            let mut samples = None;
+            let mut at = 12;
+
+            while let (Some(id), Some(size)) = (data.get(at..at + 4), u32_at(data, at + 4)) {
+                let start = at + 8;
+                let end = (start + size as usize).min(data.len());
+
+                match id {
+                    b"fmt " => format = Some(&data[start..end]),
+                    b"data" => samples = Some(&data[start..end]),
+                    _ => {}
+                }
+
+                // chunks are padded to an even size
+                at = start + size as usize + (size as usize & 1);
+            }
+
+            (
+                format.ok_or(AudioError::MissingChunk("fmt "))?,
+                samples.ok_or(AudioError::MissingChunk("data"))?,
+            )
+        } else if u16_at(data, 0) == Some(PCM) && data.len() >= ENGINE_HEADER_SIZE {
+            (&data[..16], &data[ENGINE_HEADER_SIZE..])
+        } else {
+            return Err(AudioError::NotWav);
+        };
+
+        let (channels, sample_rate, bits) = parse_format(format)?;
+
+        let mut samples = decode_samples(samples, bits);
+        // a last frame with only some of its channels can't be played
+        samples.truncate(samples.len() / channels as usize * channels as usize);
+
+        Ok(Self {
+            channels,
+            sample_rate,
+            bits,
+            samples,
+        })
+    }
+
+    pub fn to_wav(&self) -> Vec<u8> {
+        let width = self.bits as u32 / 8;
+        let block_align = self.channels as u32 * width;
+        let data_size = self.samples.len() as u32 * width;
+
+        let mut wav = Vec::with_capacity(44 + data_size as usize);
+
+        wav.extend(b"RIFF");
+        wav.extend((36 + data_size + (data_size & 1)).to_le_bytes());
+        wav.extend(b"WAVE");
+
+        wav.extend(b"fmt ");
+        wav.extend(16u32.to_le_bytes());
+        wav.extend(PCM.to_le_bytes());
+        wav.extend(self.channels.to_le_bytes());
+        wav.extend(self.sample_rate.to_le_bytes());
+        wav.extend((self.sample_rate * block_align).to_le_bytes());
+        wav.extend((block_align as u16).to_le_bytes());
+        wav.extend(self.bits.to_le_bytes());
+
+        wav.extend(b"data");
+        wav.extend(data_size.to_le_bytes());
+
+        for &sample in &self.samples {
+            match self.bits {
+                8 => wav.push((sample + 128) as u8),
+                _ => wav.extend(&sample.to_le_bytes()[..width as usize]),
+            }
+        }
+
+        if data_size & 1 != 0 {
+            wav.push(0);
+        }
+
+        wav
+    }
+}
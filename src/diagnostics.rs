@@ -0,0 +1,128 @@
+//! Problems found while reading, decompiling or compiling that don't stop
+//! it, gathered up so that they can be shown together at the end.
+
+use crate::text::Span;
+use std::{fmt::Display, sync::Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Worth knowing about, but not a problem
+    Note,
+    /// Works, but probably isn't what was meant
+    Warning,
+    /// Won't work strictly, and otherwise only works by getting around it
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Note => "note",
+                Self::Warning => "warning",
+                Self::Error => "error",
+            }
+        )
+    }
+}
+
+/// Where a problem was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    /// A file, by its path
+    File(String),
+    /// A byte offset in the file being read
+    Offset(u64),
+    /// An object, by ID and name
+    Object(u32, String),
+    /// A line and column in a script
+    Script(Span),
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{path}"),
+            Self::Offset(pos) => write!(f, "{pos:#X}"),
+            Self::Object(id, name) if name.is_empty() => write!(f, "object {id}"),
+            Self::Object(id, name) => write!(f, "{name} ({id})"),
+            Self::Script(span) => write!(f, "{span}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, location: Option<Location>, message: impl Display) -> Self {
+        Self {
+            severity,
+            location,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "{}: {location}: {}", self.severity, self.message),
+            None => write!(f, "{}: {}", self.severity, self.message),
+        }
+    }
+}
+
+/// Gathers [`Diagnostic`]s, from any number of threads, in the order they're
+/// found.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    found: Mutex<Vec<Diagnostic>>,
+}
+
+impl Diagnostics {
+    pub const fn new() -> Self {
+        Self {
+            found: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn push(&self, diagnostic: impl Into<Diagnostic>) {
+        self.found.lock().unwrap().push(diagnostic.into());
+    }
+
+    pub fn extend<D: Into<Diagnostic>>(&self, diagnostics: impl IntoIterator<Item = D>) {
+        self.found
+            .lock()
+            .unwrap()
+            .extend(diagnostics.into_iter().map(Into::into));
+    }
+
+    pub fn note(&self, location: Option<Location>, message: impl Display) {
+        self.push(Diagnostic::new(Severity::Note, location, message));
+    }
+
+    pub fn warn(&self, location: Option<Location>, message: impl Display) {
+        self.push(Diagnostic::new(Severity::Warning, location, message));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.found.lock().unwrap().is_empty()
+    }
+
+    /// The worst severity found, if anything was.
+    pub fn worst(&self) -> Option<Severity> {
+        self.found.lock().unwrap().iter().map(|d| d.severity).max()
+    }
+
+    /// Takes everything found so far, leaving none.
+    pub fn take(&self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.found.lock().unwrap())
+    }
+}
@@ -0,0 +1,279 @@
+//! C interface for reading Omni files, so that projects written in other
+//! languages can use this parser. The declarations are in `include/gwydd.h`.
+//!
+//! Every string and payload belongs to the [`GwOmni`] it came from, and
+//! stays valid until that's passed to [`gw_omni_free`].
+//!
+//! Cargo can't make a crate type depend on a feature, so the shared library
+//! is built with `cargo rustc --lib --release --features ffi --crate-type
+//! cdylib`. The header is generated from this file with `cbindgen --config
+//! cbindgen.toml --output include/gwydd.h src/ffi.rs`, which should be run
+//! again whenever anything here changes.
+
+use crate::{omni::Omni, types::ObjectFilter};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::{c_char, CStr, CString},
+    io::Cursor,
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(error: impl ToString) {
+    let message = CString::new(error.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, returning `failed` instead if it panics, with the panic's
+/// message left for [`gw_last_error`]. Unwinding into C is undefined
+/// behaviour, so every exported function goes through this.
+fn guard<T>(failed: T, f: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown cause".into());
+        set_error(format!("Panicked: {message}"));
+        failed
+    })
+}
+
+fn c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', " ")).unwrap_or_default()
+}
+
+struct Object {
+    id: u32,
+    parent: Option<u32>,
+    name: CString,
+    kind: CString,
+    file_name: Option<CString>,
+    data: Vec<u8>,
+}
+
+/// A parsed file, with every object's data read in.
+pub struct GwOmni {
+    objects: Vec<Object>,
+}
+
+/// An object's details, filled in by [`gw_omni_object`].
+#[repr(C)]
+pub struct GwObject {
+    pub id: u32,
+    /// ID of the action it's part of, or `GW_NO_PARENT`
+    pub parent: u32,
+    pub name: *const c_char,
+    /// Type of object, such as `sound` or `world`
+    pub kind: *const c_char,
+    /// Name of the file it stores, or null if it doesn't store one
+    pub file_name: *const c_char,
+    /// Size of the stored file
+    pub size: usize,
+}
+
+/// What [`GwObject::parent`] is for objects that aren't part of an action.
+pub const GW_NO_PARENT: u32 = u32::MAX;
+
+impl GwOmni {
+    fn new(omni: &Omni) -> std::io::Result<Self> {
+        let mut data = HashMap::new();
+
+        // nothing is left in the source, so there's nothing to read from
+        for resource in omni.extract(&ObjectFilter::all()) {
+            data.insert(resource.id, resource.read(&mut Cursor::new([]))?);
+        }
+
+        let objects = omni
+            .objects()
+            .into_iter()
+            .map(|o| Object {
+                id: o.id,
+                parent: o.parent,
+                name: c_string(&o.name),
+                kind: c_string(o.kind),
+                file_name: o.file_name.as_deref().map(c_string),
+                data: data.remove(&o.id).unwrap_or_default(),
+            })
+            .collect();
+
+        Ok(Self { objects })
+    }
+}
+
+/// Parses the Omni file at `path`, returning null on failure.
+///
+/// # Safety
+///
+/// `path` must be a valid null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn gw_omni_open(path: *const c_char) -> *mut GwOmni {
+    guard(ptr::null_mut(), || {
+        if path.is_null() {
+            set_error("No path given");
+            return ptr::null_mut();
+        }
+
+        let path = match CStr::from_ptr(path).to_str() {
+            Ok(path) => path,
+            Err(e) => {
+                set_error(e);
+                return ptr::null_mut();
+            }
+        };
+
+        let omni = Omni::load(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|omni| Ok(GwOmni::new(&omni)?));
+
+        match omni {
+            Ok(omni) => Box::into_raw(Box::new(omni)),
+            Err(e) => {
+                set_error(format!("{e:#}"));
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Message for the last call on this thread that failed, or null if none
+/// has. It stays valid until the next call that fails.
+#[no_mangle]
+pub extern "C" fn gw_last_error() -> *const c_char {
+    guard(ptr::null(), || {
+        LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+    })
+}
+
+/// Number of objects in the file, counting those inside actions.
+///
+/// # Safety
+///
+/// `omni` must be null or have come from [`gw_omni_open`].
+#[no_mangle]
+pub unsafe extern "C" fn gw_omni_object_count(omni: *const GwOmni) -> usize {
+    guard(0, || omni.as_ref().map_or(0, |o| o.objects.len()))
+}
+
+/// Fills in `out` with the details of object number `index`, returning
+/// whether there is one. Objects come in file order, each followed by the
+/// objects inside it.
+///
+/// # Safety
+///
+/// `omni` must be null or have come from [`gw_omni_open`], and `out` must be
+/// null or point to a `GwObject`.
+#[no_mangle]
+pub unsafe extern "C" fn gw_omni_object(
+    omni: *const GwOmni,
+    index: usize,
+    out: *mut GwObject,
+) -> bool {
+    guard(false, || {
+        let (Some(omni), Some(out)) = (omni.as_ref(), out.as_mut()) else {
+            set_error("No file or object given");
+            return false;
+        };
+
+        let Some(object) = omni.objects.get(index) else {
+            set_error(format!("No object {index}"));
+            return false;
+        };
+
+        *out = GwObject {
+            id: object.id,
+            parent: object.parent.unwrap_or(GW_NO_PARENT),
+            name: object.name.as_ptr(),
+            kind: object.kind.as_ptr(),
+            file_name: object
+                .file_name
+                .as_ref()
+                .map_or(ptr::null(), |f| f.as_ptr()),
+            size: object.data.len(),
+        };
+
+        true
+    })
+}
+
+/// Points `data` at the stored file of object number `index` and sets `len`
+/// to its size, returning whether there is an object with that index.
+/// Objects without a stored file have a size of 0.
+///
+/// # Safety
+///
+/// `omni` must be null or have come from [`gw_omni_open`], and `data` and
+/// `len` must be null or point to somewhere to write to.
+#[no_mangle]
+pub unsafe extern "C" fn gw_omni_payload(
+    omni: *const GwOmni,
+    index: usize,
+    data: *mut *const u8,
+    len: *mut usize,
+) -> bool {
+    guard(false, || {
+        let (Some(omni), Some(data), Some(len)) = (omni.as_ref(), data.as_mut(), len.as_mut())
+        else {
+            set_error("No file or payload given");
+            return false;
+        };
+
+        let Some(object) = omni.objects.get(index) else {
+            set_error(format!("No object {index}"));
+            return false;
+        };
+
+        *data = object.data.as_ptr();
+        *len = object.data.len();
+
+        true
+    })
+}
+
+/// Frees a file and everything that came from it.
+///
+/// # Safety
+///
+/// `omni` must be null or have come from [`gw_omni_open`], and not have been
+/// freed already.
+#[no_mangle]
+pub unsafe extern "C" fn gw_omni_free(omni: *mut GwOmni) {
+    guard((), || {
+        if !omni.is_null() {
+            drop(Box::from_raw(omni));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(gw_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn failures_leave_a_message() {
+        let path = c_string("no such file.si");
+        assert!(unsafe { gw_omni_open(path.as_ptr()) }.is_null());
+        assert!(!last_error().is_empty());
+
+        assert!(!unsafe { gw_omni_object(ptr::null(), 0, ptr::null_mut()) });
+        assert_eq!(last_error(), "No file or object given");
+    }
+
+    #[test]
+    fn panics_stop_at_the_boundary() {
+        let result = guard(false, || panic!("bad index {}", 3));
+        assert!(!result);
+        assert_eq!(last_error(), "Panicked: bad index 3");
+    }
+}
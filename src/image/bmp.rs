@@ -0,0 +1,168 @@
+use super::{u16_at, u32_at, Image, ImageError, Result, MAX_SIZE};
+
+/// Size of the BMP file header, which stills are usually stored without.
+const FILE_HEADER: usize = 14;
+
+/// Size of a BITMAPINFOHEADER; later versions are bigger, but start the
+/// same way.
+const INFO_HEADER: u32 = 40;
+
+const BI_RGB: u32 = 0;
+const BI_RLE8: u32 = 1;
+
+fn invalid(reason: &'static str) -> ImageError {
+    ImageError::Invalid("BMP", reason)
+}
+
+/// Decodes RLE8 pixels, which go from the bottom row up.
+fn decode_rle8(data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut indices = vec![0; width * height];
+    let (mut x, mut y) = (0, 0);
+    let mut at = 0;
+
+    let mut put = |x: &mut usize, y: usize, i: u8| {
+        if *x < width && y < height {
+            indices[y * width + *x] = i;
+        }
+        *x += 1;
+    };
+
+    while let (Some(&count), Some(&value)) = (data.get(at), data.get(at + 1)) {
+        at += 2;
+
+        match (count, value) {
+            (0, 0) => (x, y) = (0, y + 1),
+            (0, 1) => break,
+            (0, 2) => {
+                let (Some(&dx), Some(&dy)) = (data.get(at), data.get(at + 1)) else {
+                    break;
+                };
+                at += 2;
+                (x, y) = (x + dx as usize, y + dy as usize);
+            }
+            // a run of pixels as they are, padded to an even length
+            (0, n) => {
+                for &i in data.get(at..at + n as usize).unwrap_or_default() {
+                    put(&mut x, y, i);
+                }
+                at += (n as usize).next_multiple_of(2);
+            }
+            (n, i) => (0..n).for_each(|_| put(&mut x, y, i)),
+        }
+    }
+
+    indices
+}
+
+pub fn decode(data: &[u8]) -> Result<Image> {
+    let (dib, file_offset) = match data.starts_with(b"BM") {
+        true => (
+            data.get(FILE_HEADER..).ok_or(invalid("no header"))?,
+            u32_at(data, 10).map(|o| (o as usize).saturating_sub(FILE_HEADER)),
+        ),
+        false => (data, None),
+    };
+
+    let header_size = u32_at(dib, 0).ok_or(invalid("no header"))?;
+    if header_size < INFO_HEADER {
+        return Err(ImageError::Unsupported(
+            "BMP header",
+            format!("{header_size} bytes"),
+        ));
+    }
+
+    let field = |at| u32_at(dib, at).ok_or(invalid("header cut short"));
+    let width = field(4)? as i32;
+    let height = field(8)? as i32;
+    let bit_count = u16_at(dib, 14).ok_or(invalid("header cut short"))?;
+    let compression = field(16)?;
+    let colours = match field(32)? {
+        0 if bit_count <= 8 => 1 << bit_count,
+        n => n.min(256),
+    };
+
+    // rows go from the bottom up, unless the height is negative
+    let bottom_up = height > 0;
+    let (width, height) = (width.unsigned_abs(), height.unsigned_abs());
+
+    if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+        return Err(invalid("impossible size"));
+    }
+
+    let mut palette = [[0; 3]; 256];
+    if bit_count <= 8 {
+        let table = dib
+            .get(header_size as usize..)
+            .and_then(|t| t.get(..colours as usize * 4))
+            .ok_or(invalid("palette cut short"))?;
+
+        for (entry, bgrx) in palette.iter_mut().zip(table.chunks(4)) {
+            *entry = [bgrx[2], bgrx[1], bgrx[0]];
+        }
+    }
+
+    let pixels_at = file_offset.unwrap_or(match bit_count <= 8 {
+        true => header_size as usize + colours as usize * 4,
+        false => header_size as usize,
+    });
+    let pixels = dib.get(pixels_at..).ok_or(invalid("no pixels"))?;
+
+    let (w, h) = (width as usize, height as usize);
+    let row = |y: usize| match bottom_up {
+        true => h - 1 - y,
+        false => y,
+    };
+
+    match (compression, bit_count) {
+        (BI_RLE8, 8) => {
+            let indices = decode_rle8(pixels, w, h);
+            let flipped = (0..h)
+                .flat_map(|y| &indices[row(y) * w..][..w])
+                .copied()
+                .collect::<Vec<_>>();
+
+            Ok(Image::from_indices(width, height, &flipped, w, &palette))
+        }
+        (BI_RGB, 1 | 4 | 8 | 16 | 24 | 32) => {
+            let stride = (w * bit_count as usize).div_ceil(32) * 4;
+            if pixels.len() < stride * h {
+                return Err(invalid("pixels cut short"));
+            }
+
+            let pixel = |x: usize, y: usize| -> [u8; 3] {
+                let line = &pixels[row(y) * stride..][..stride];
+
+                match bit_count {
+                    1 | 4 | 8 => {
+                        let bits = bit_count as usize;
+                        let byte = line[x * bits / 8];
+                        let shift = 8 - bits - (x * bits % 8);
+                        palette[(byte >> shift) as usize & ((1 << bits) - 1)]
+                    }
+                    // 5 bits each of red, green and blue
+                    16 => {
+                        let p = u16::from_le_bytes([line[x * 2], line[x * 2 + 1]]);
+                        [10, 5, 0].map(|s| ((p >> s & 0x1F) * 255 / 31) as u8)
+                    }
+                    _ => {
+                        let at = x * bit_count as usize / 8;
+                        [line[at + 2], line[at + 1], line[at]]
+                    }
+                }
+            };
+
+            Ok(Image {
+                width,
+                height,
+                pixels: (0..h)
+                    .flat_map(|y| (0..w).map(move |x| (x, y)))
+                    .map(|(x, y)| pixel(x, y))
+                    .collect(),
+            })
+        }
+        _ => Err(ImageError::Unsupported(
+            "BMP format",
+            format!("{bit_count}-bit with compression {compression}"),
+        )),
+    }
+}
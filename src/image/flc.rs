@@ -0,0 +1,259 @@
+use super::{u16_at, u32_at, Image, ImageError, Result, FLC_MAGIC, MAX_SIZE};
+const FLC_HEADER: usize = 128;
+const FLC_FRAME: u16 = 0xF1FA;
+
+/// Size of a frame's header, before its chunks.
+const FRAME_HEADER: usize = 16;
+
+/// Size of a chunk's header, before its data.
+const CHUNK_HEADER: usize = 6;
+
+const COLOR_256: u16 = 4;
+const DELTA_FLC: u16 = 7;
+const COLOR_64: u16 = 11;
+const DELTA_FLI: u16 = 12;
+const BLACK: u16 = 13;
+const BYTE_RUN: u16 = 15;
+const FLI_COPY: u16 = 16;
+
+fn invalid(reason: &'static str) -> ImageError {
+    ImageError::Invalid("FLC", reason)
+}
+
+/// Reads bytes through a chunk, failing at its end.
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn u8(&mut self) -> Result<u8> {
+        let b = *self.data.get(self.at).ok_or(invalid("chunk cut short"))?;
+        self.at += 1;
+        Ok(b)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&[u8]> {
+        let bytes = self
+            .data
+            .get(self.at..self.at + n)
+            .ok_or(invalid("chunk cut short"))?;
+        self.at += n;
+        Ok(bytes)
+    }
+}
+
+/// Copies `bytes` into `line` from `x`, leaving off whatever runs past the
+/// end.
+fn put(line: &mut [u8], x: usize, bytes: impl IntoIterator<Item = u8>) {
+    for (pixel, b) in line.iter_mut().skip(x).zip(bytes) {
+        *pixel = b;
+    }
+}
+
+fn read_palette(r: &mut Reader, palette: &mut [[u8; 3]; 256], six_bit: bool) -> Result<()> {
+    let mut index = 0;
+
+    for _ in 0..r.u16()? {
+        index += r.u8()? as usize;
+        let count = match r.u8()? {
+            0 => 256,
+            n => n as usize,
+        };
+
+        for _ in 0..count {
+            let rgb = r.bytes(3)?;
+            if let Some(entry) = palette.get_mut(index) {
+                *entry = [rgb[0], rgb[1], rgb[2]].map(|c| match six_bit {
+                    true => ((c as u32 & 0x3F) * 255 / 63) as u8,
+                    false => c,
+                });
+            }
+            index += 1;
+        }
+    }
+
+    Ok(())
+}
+
+fn byte_run(r: &mut Reader, pixels: &mut [u8], width: usize) -> Result<()> {
+    for line in pixels.chunks_mut(width) {
+        // the packet count is only a hint; lines are decoded to their width
+        r.u8()?;
+        let mut x = 0;
+
+        while x < width {
+            match r.u8()? as i8 {
+                n if n >= 0 => {
+                    let b = r.u8()?;
+                    put(line, x, std::iter::repeat_n(b, n as usize));
+                    x += n as usize;
+                }
+                n => {
+                    let n = n.unsigned_abs() as usize;
+                    put(line, x, r.bytes(n)?.iter().copied());
+                    x += n;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn delta_fli(r: &mut Reader, pixels: &mut [u8], width: usize) -> Result<()> {
+    let skip = r.u16()? as usize;
+    let lines = r.u16()? as usize;
+
+    for line in pixels.chunks_mut(width).skip(skip).take(lines) {
+        let mut x = 0;
+
+        for _ in 0..r.u8()? {
+            x += r.u8()? as usize;
+
+            match r.u8()? as i8 {
+                n if n >= 0 => {
+                    put(line, x, r.bytes(n as usize)?.iter().copied());
+                    x += n as usize;
+                }
+                n => {
+                    let n = n.unsigned_abs() as usize;
+                    let b = r.u8()?;
+                    put(line, x, std::iter::repeat_n(b, n));
+                    x += n;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn delta_flc(r: &mut Reader, pixels: &mut [u8], width: usize) -> Result<()> {
+    let mut lines = pixels.chunks_mut(width);
+
+    for _ in 0..r.u16()? {
+        // words before the packet count skip lines or set the last pixel
+        let mut last = None;
+        let packets = loop {
+            let word = r.u16()?;
+            match word >> 14 {
+                0b11 => {
+                    let skip = word.wrapping_neg() as usize;
+                    (&mut lines).take(skip).for_each(drop);
+                }
+                0b10 => last = Some(word as u8),
+                0b00 => break word,
+                _ => return Err(invalid("bad line in delta chunk")),
+            }
+        };
+
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let mut x = 0;
+
+        for _ in 0..packets {
+            x += r.u8()? as usize;
+
+            match r.u8()? as i8 {
+                n if n >= 0 => {
+                    let words = r.bytes(n as usize * 2)?;
+                    put(line, x, words.iter().copied());
+                    x += words.len();
+                }
+                n => {
+                    let n = n.unsigned_abs() as usize;
+                    let word = r.bytes(2)?;
+                    put(line, x, word.iter().copied().cycle().take(n * 2));
+                    x += n * 2;
+                }
+            }
+        }
+
+        if let (Some(b), Some(end)) = (last, line.last_mut()) {
+            *end = b;
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes the first frame of an FLI or FLC file.
+pub fn first_frame(data: &[u8]) -> Result<Image> {
+    let width = u16_at(data, 8).ok_or(invalid("no header"))? as u32;
+    let height = u16_at(data, 10).ok_or(invalid("no header"))? as u32;
+    let depth = u16_at(data, 12).ok_or(invalid("no header"))?;
+
+    if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+        return Err(invalid("impossible size"));
+    }
+    if depth != 8 && depth != 0 {
+        return Err(ImageError::Unsupported(
+            "FLC depth",
+            format!("{depth} bits"),
+        ));
+    }
+
+    // FLC files say where the first frame is; prefix chunks can come first
+    let mut at = match u16_at(data, 4) {
+        Some(FLC_MAGIC) => u32_at(data, 80)
+            .filter(|&o| o != 0)
+            .unwrap_or(FLC_HEADER as u32) as usize,
+        _ => FLC_HEADER,
+    };
+
+    let frame = loop {
+        let size = u32_at(data, at).ok_or(invalid("no frames"))? as usize;
+        let kind = u16_at(data, at + 4).ok_or(invalid("no frames"))?;
+
+        if kind == FLC_FRAME {
+            break data.get(at..at + size).ok_or(invalid("frame cut short"))?;
+        }
+        if size < CHUNK_HEADER {
+            return Err(invalid("no frames"));
+        }
+        at += size;
+    };
+
+    let (w, h) = (width as usize, height as usize);
+    let mut pixels = vec![0; w * h];
+    let mut palette = [[0; 3]; 256];
+
+    let chunks = u16_at(frame, 6).ok_or(invalid("frame cut short"))?;
+    let mut at = FRAME_HEADER;
+
+    for _ in 0..chunks {
+        let size = u32_at(frame, at).ok_or(invalid("frame cut short"))? as usize;
+        let kind = u16_at(frame, at + 4).ok_or(invalid("frame cut short"))?;
+        let mut r = Reader {
+            data: frame
+                .get(at + CHUNK_HEADER..at + size.max(CHUNK_HEADER))
+                .ok_or(invalid("chunk cut short"))?,
+            at: 0,
+        };
+
+        match kind {
+            COLOR_256 => read_palette(&mut r, &mut palette, false)?,
+            COLOR_64 => read_palette(&mut r, &mut palette, true)?,
+            BLACK => pixels.fill(0),
+            BYTE_RUN => byte_run(&mut r, &mut pixels, w)?,
+            FLI_COPY => put(&mut pixels, 0, r.bytes(w * h)?.iter().copied()),
+            DELTA_FLI => delta_fli(&mut r, &mut pixels, w)?,
+            DELTA_FLC => delta_flc(&mut r, &mut pixels, w)?,
+            // postage stamps and anything else don't change the frame
+            _ => {}
+        }
+
+        if size < CHUNK_HEADER {
+            break;
+        }
+        at += size;
+    }
+
+    Ok(Image::from_indices(width, height, &pixels, w, &palette))
+}
@@ -0,0 +1,382 @@
+//! Decoding the pictures stored in Omni files, to preview them.
+
+use thiserror::Error;
+
+#[cfg(feature = "images")]
+mod bmp;
+#[cfg(feature = "images")]
+mod flc;
+#[cfg(feature = "images")]
+mod smk;
+
+#[derive(Error, Debug)]
+pub enum ImageError {
+    #[error("Not a still or a video that can be previewed")]
+    Unknown,
+
+    #[error("Unsupported {0}: {1}")]
+    Unsupported(&'static str, String),
+
+    #[error("Invalid {0} file: {1}")]
+    Invalid(&'static str, &'static str),
+
+    #[error("Previews need gwŷdd to be built with the \"images\" feature")]
+    NeedsImages,
+
+    #[cfg(feature = "images")]
+    #[error("Couldn't write PNG: {0}")]
+    Png(#[from] png::EncodingError),
+}
+
+pub type Result<T> = std::result::Result<T, ImageError>;
+
+/// Widest or tallest a thumbnail is, in pixels.
+pub const THUMBNAIL_SIZE: u32 = 160;
+
+/// Widest or tallest a picture that's decoded can be, so that a broken
+/// header can't ask for gigabytes.
+#[cfg(feature = "images")]
+const MAX_SIZE: u32 = 4096;
+
+/// Sizes of the BITMAPINFOHEADER and the versions after it.
+const BMP_HEADER_SIZES: [u32; 5] = [40, 52, 56, 108, 124];
+
+const FLI_MAGIC: u16 = 0xAF11;
+const FLC_MAGIC: u16 = 0xAF12;
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Whether `data` is a BMP file, or the bitmap from one without the file
+/// header.
+pub fn is_bmp(data: &[u8]) -> bool {
+    data.starts_with(b"BM") || u32_at(data, 0).is_some_and(|size| BMP_HEADER_SIZES.contains(&size))
+}
+
+pub fn is_flc(data: &[u8]) -> bool {
+    !data.starts_with(b"RIFF") && matches!(u16_at(data, 4), Some(FLI_MAGIC | FLC_MAGIC))
+}
+
+pub fn is_smk(data: &[u8]) -> bool {
+    data.starts_with(b"SMK2") || data.starts_with(b"SMK4")
+}
+
+/// A picture, as RGB pixels row by row from the top.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[u8; 3]>,
+}
+
+impl Image {
+    /// An image of `width` by `height` from palette indices, with each row
+    /// `stride` indices apart.
+    #[cfg(feature = "images")]
+    fn from_indices(
+        width: u32,
+        height: u32,
+        indices: &[u8],
+        stride: usize,
+        palette: &[[u8; 3]; 256],
+    ) -> Self {
+        let pixels = (0..height as usize)
+            .flat_map(|y| &indices[y * stride..y * stride + width as usize])
+            .map(|&i| palette[i as usize])
+            .collect();
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Scaled down to fit in `size` by `size`, each pixel the average of
+    /// the ones it covers. Images that already fit are left as they are.
+    pub fn fit(&self, size: u32) -> Self {
+        let largest = self.width.max(self.height);
+        if largest <= size {
+            return self.clone();
+        }
+
+        let scale = |n: u32| ((n as u64 * size as u64).div_ceil(largest as u64) as u32).max(1);
+        let (width, height) = (scale(self.width), scale(self.height));
+
+        // the pixels from `to * from / to` up to where the next one starts
+        let span = |i: u32, to: u32, from: u32| {
+            let start = (i as u64 * from as u64 / to as u64) as usize;
+            let end = ((i as u64 + 1) * from as u64 / to as u64) as usize;
+            start..end.max(start + 1)
+        };
+
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+
+        for y in 0..height {
+            let rows = span(y, height, self.height);
+
+            for x in 0..width {
+                let columns = span(x, width, self.width);
+
+                let mut sum = [0u32; 3];
+                for row in rows.clone() {
+                    for pixel in &self.pixels[row * self.width as usize..][columns.clone()] {
+                        for (s, &c) in sum.iter_mut().zip(pixel) {
+                            *s += c as u32;
+                        }
+                    }
+                }
+
+                let count = (rows.len() * columns.len()) as u32;
+                pixels.push(sum.map(|s| ((s + count / 2) / count) as u8));
+            }
+        }
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// The image as an 8-bit RGB PNG file.
+    #[cfg(feature = "images")]
+    pub fn to_png(&self) -> Result<Vec<u8>> {
+        let mut png = vec![];
+
+        let mut encoder = png::Encoder::new(&mut png, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(self.pixels.as_flattened())?;
+        writer.finish()?;
+
+        Ok(png)
+    }
+
+    #[cfg(not(feature = "images"))]
+    pub fn to_png(&self) -> Result<Vec<u8>> {
+        Err(ImageError::NeedsImages)
+    }
+}
+
+/// The picture in a still, or the first frame of an FLC or Smacker video.
+#[cfg(feature = "images")]
+pub fn decode(data: &[u8]) -> Result<Image> {
+    if is_smk(data) {
+        smk::first_frame(data)
+    } else if is_flc(data) {
+        flc::first_frame(data)
+    } else if is_bmp(data) {
+        bmp::decode(data)
+    } else {
+        Err(ImageError::Unknown)
+    }
+}
+
+#[cfg(not(feature = "images"))]
+pub fn decode(data: &[u8]) -> Result<Image> {
+    match is_smk(data) || is_flc(data) || is_bmp(data) {
+        true => Err(ImageError::NeedsImages),
+        false => Err(ImageError::Unknown),
+    }
+}
+
+/// A PNG thumbnail of a still or video, at most [`THUMBNAIL_SIZE`] pixels
+/// across.
+pub fn thumbnail(data: &[u8]) -> Result<Vec<u8>> {
+    decode(data)?.fit(THUMBNAIL_SIZE).to_png()
+}
+
+#[cfg(all(test, feature = "images"))]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+
+    fn u16s(values: &[u16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    /// Packs `(value, bits)` pairs least significant bit first, the way
+    /// Smacker files are read.
+    fn pack(fields: &[(u32, usize)]) -> Vec<u8> {
+        let mut out: Vec<u8> = vec![];
+        let mut len = 0;
+
+        for &(value, bits) in fields {
+            for i in 0..bits {
+                if len % 8 == 0 {
+                    out.push(0);
+                }
+                *out.last_mut().unwrap() |= ((value >> i & 1) as u8) << (len % 8);
+                len += 1;
+            }
+        }
+
+        out
+    }
+
+    #[test]
+    fn bmp_stills_are_read() {
+        // 2 by 2, 8-bit with two colours, stored without the file header
+        // and from the bottom row up, each row padded to 4 bytes
+        let mut still = u32s(&[40, 2, 2]);
+        still.extend(u16s(&[1, 8]));
+        still.extend(u32s(&[0, 0, 0, 0, 2, 0]));
+        still.extend([0, 0, 255, 0, 255, 0, 0, 0]);
+        still.extend([1, 0, 0, 0, 0, 1, 0, 0]);
+
+        assert!(is_bmp(&still));
+        assert_eq!(
+            decode(&still).unwrap(),
+            Image {
+                width: 2,
+                height: 2,
+                pixels: vec![RED, BLUE, BLUE, RED],
+            }
+        );
+
+        // 2 by 1, 24-bit, with the file header and a negative height for
+        // rows from the top down
+        let mut bmp = b"BM".to_vec();
+        bmp.extend(u32s(&[62, 0, 54, 40, 2, -1i32 as u32]));
+        bmp.extend(u16s(&[1, 24]));
+        bmp.extend(u32s(&[0, 0, 0, 0, 0, 0]));
+        bmp.extend([3, 2, 1, 6, 5, 4, 0, 0]);
+
+        assert_eq!(decode(&bmp).unwrap().pixels, [[1, 2, 3], [4, 5, 6]]);
+    }
+
+    #[test]
+    fn flc_first_frames_are_read() {
+        // a 4 by 2 FLC with one frame
+        let mut flc = u32s(&[0]);
+        flc.extend(u16s(&[FLC_MAGIC, 1, 4, 2, 8]));
+        flc.resize(80, 0);
+        flc.extend(u32s(&[128]));
+        flc.resize(128, 0);
+
+        // a frame with a palette of two colours, then a byte run of each
+        // line: two 1s, then a 0 and a 1 as they are, and then four 0s
+        flc.extend(u32s(&[16 + 16 + 16]));
+        flc.extend(u16s(&[0xF1FA, 2, 0, 0, 0, 0]));
+        flc.extend(u32s(&[16]));
+        flc.extend(u16s(&[4, 1]));
+        flc.extend([0, 2, 10, 20, 30, 200, 100, 0]);
+        flc.extend(u32s(&[16]));
+        flc.extend(u16s(&[15]));
+        flc.extend([2, 2, 1, 0xFE, 0, 1, 1, 4, 0, 0]);
+
+        let (a, b) = ([10, 20, 30], [200, 100, 0]);
+
+        assert!(is_flc(&flc));
+        assert_eq!(
+            decode(&flc).unwrap(),
+            Image {
+                width: 4,
+                height: 2,
+                pixels: vec![b, b, a, b, a, a, a, a],
+            }
+        );
+    }
+
+    #[test]
+    fn smk_first_frames_are_read() {
+        // no mono or full block trees, and a block type tree where 0 is a
+        // solid block of colour 5 and 1 one of colour 9
+        let trees = pack(&[
+            (0, 1),
+            (0, 1),
+            (0, 1),
+            (1, 1),
+            // the low bytes' tree, with 3 at both leaves
+            (1, 1),
+            (1, 1),
+            (0, 1),
+            (3, 8),
+            (0, 1),
+            (3, 8),
+            (0, 1),
+            // the high bytes', with 5 and 9
+            (1, 1),
+            (1, 1),
+            (0, 1),
+            (5, 8),
+            (0, 1),
+            (9, 8),
+            (0, 1),
+            // values that stand for recent ones, which aren't used
+            (0xFFFF, 16),
+            (0xFFFF, 16),
+            (0xFFFF, 16),
+            // the tree itself, each leaf picking its low and high bytes
+            (1, 1),
+            (0, 1),
+            (0, 1),
+            (0, 1),
+            (0, 1),
+            (0, 1),
+            (1, 1),
+            (0, 1),
+        ]);
+
+        // a palette that skips to colour 5 and sets it to red, then skips
+        // to 9 for a teal, and skips the rest; then the two blocks
+        let mut frame = vec![3, 0x84, 0x3F, 0, 0, 0x82, 0, 0x3F, 0x20, 0xFF, 0xF5, 0];
+        frame.extend(pack(&[(0, 1), (1, 1)]));
+        frame.resize(16, 0);
+
+        // 8 by 4, one frame
+        let mut smk = b"SMK2".to_vec();
+        smk.extend(u32s(&[8, 4, 1, 100, 0]));
+        smk.resize(0x34, 0);
+        smk.extend(u32s(&[trees.len() as u32]));
+        smk.resize(0x68, 0);
+        smk.extend(u32s(&[frame.len() as u32]));
+        smk.push(1);
+        smk.extend(trees);
+        smk.extend(frame);
+
+        let teal = [0, 255, 130];
+
+        assert!(is_smk(&smk));
+        assert_eq!(
+            decode(&smk).unwrap().pixels,
+            [[RED; 4], [teal; 4]].concat().repeat(4)
+        );
+    }
+
+    #[test]
+    fn thumbnails_are_pngs() {
+        let image = Image {
+            width: 4,
+            height: 2,
+            pixels: vec![[0, 0, 0], [255, 255, 255], RED, RED, BLUE, BLUE, RED, BLUE],
+        };
+
+        let small = image.fit(2);
+        assert_eq!((small.width, small.height), (2, 1));
+        assert_eq!(small.pixels, [[64, 64, 191], [191, 0, 64]]);
+
+        let png = small.to_png().unwrap();
+        let mut reader = png::Decoder::new(&png[..]).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+
+        assert_eq!((info.width, info.height), (2, 1));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        assert_eq!(pixels, small.pixels.as_flattened());
+    }
+}
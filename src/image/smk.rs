@@ -0,0 +1,401 @@
+use super::{u32_at, Image, ImageError, Result, MAX_SIZE};
+
+/// Offset of the frame sizes in a Smacker header.
+const FRAME_SIZES: usize = 0x68;
+
+/// Offset of the size of the Huffman trees.
+const TREES_SIZE: usize = 0x34;
+
+/// Set in the flags when there's a ring frame after the others.
+const RING_FRAME: u32 = 1;
+/// Set in the flags when every other line is left out.
+const Y_INTERLACED: u32 = 2;
+/// Set in the flags when every line is shown twice.
+const Y_DOUBLED: u32 = 4;
+
+/// Set in a frame's type when it starts with a palette.
+const FRAME_PALETTE: u8 = 1;
+
+/// How many blocks each run length code stands for.
+const BLOCK_RUNS: [usize; 64] = [
+    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26,
+    27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50,
+    51, 52, 53, 54, 55, 56, 57, 58, 59, 128, 256, 512, 1024, 2048,
+];
+
+const BLOCK_MONO: u16 = 0;
+const BLOCK_FULL: u16 = 1;
+const BLOCK_VOID: u16 = 2;
+
+/// Deepest a tree can go before it's taken to be broken.
+const MAX_DEPTH: usize = 32;
+
+fn invalid(reason: &'static str) -> ImageError {
+    ImageError::Invalid("Smacker", reason)
+}
+
+/// Reads bits from the least significant end of each byte first.
+struct Bits<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl Bits<'_> {
+    fn bit(&mut self) -> Result<bool> {
+        let byte = self
+            .data
+            .get(self.at / 8)
+            .ok_or(invalid("bitstream cut short"))?;
+        let bit = byte >> (self.at % 8) & 1 == 1;
+        self.at += 1;
+        Ok(bit)
+    }
+
+    fn bits(&mut self, n: usize) -> Result<u32> {
+        (0..n).try_fold(0, |v, i| Ok(v | (self.bit()? as u32) << i))
+    }
+}
+
+/// A Huffman tree, where a 0 bit goes to the first child and a 1 to the
+/// second.
+#[derive(Debug, Default)]
+struct Tree {
+    /// Each node's children, or its leaf's index in `values`
+    nodes: Vec<std::result::Result<(usize, usize), usize>>,
+}
+
+impl Tree {
+    /// The leaf that the next code in `bits` leads to, or `None` if the tree
+    /// is empty, which stands for a value of 0 without reading anything.
+    fn leaf(&self, bits: &mut Bits) -> Result<Option<usize>> {
+        let Some(mut node) = self.nodes.first() else {
+            return Ok(None);
+        };
+
+        loop {
+            match node {
+                Ok((zero, one)) => node = &self.nodes[if bits.bit()? { *one } else { *zero }],
+                Err(leaf) => return Ok(Some(*leaf)),
+            }
+        }
+    }
+
+    /// Reads a tree's shape, calling `leaf` to read each leaf in turn.
+    fn read(bits: &mut Bits, leaf: &mut impl FnMut(&mut Bits) -> Result<usize>) -> Result<Self> {
+        fn node(
+            tree: &mut Tree,
+            bits: &mut Bits,
+            leaf: &mut impl FnMut(&mut Bits) -> Result<usize>,
+            depth: usize,
+        ) -> Result<usize> {
+            if depth > MAX_DEPTH {
+                return Err(invalid("tree too deep"));
+            }
+
+            let index = tree.nodes.len();
+
+            if bits.bit()? {
+                tree.nodes.push(Ok((0, 0)));
+                let zero = node(tree, bits, leaf, depth + 1)?;
+                let one = node(tree, bits, leaf, depth + 1)?;
+                tree.nodes[index] = Ok((zero, one));
+            } else {
+                let value = leaf(bits)?;
+                tree.nodes.push(Err(value));
+            }
+
+            Ok(index)
+        }
+
+        let mut tree = Self::default();
+        node(&mut tree, bits, leaf, 0)?;
+        Ok(tree)
+    }
+}
+
+/// A tree of bytes, used to read the leaves of a [`BigTree`].
+struct ByteTree {
+    tree: Tree,
+    values: Vec<u8>,
+}
+
+impl ByteTree {
+    fn read(bits: &mut Bits) -> Result<Self> {
+        let mut values = vec![];
+
+        let tree = match bits.bit()? {
+            false => Tree::default(),
+            true => {
+                let tree = Tree::read(bits, &mut |bits| {
+                    values.push(bits.bits(8)? as u8);
+                    Ok(values.len() - 1)
+                })?;
+                // ends with a 0 bit
+                bits.bit()?;
+                tree
+            }
+        };
+
+        Ok(Self { tree, values })
+    }
+
+    fn get(&self, bits: &mut Bits) -> Result<u8> {
+        Ok(self.tree.leaf(bits)?.map_or(0, |leaf| self.values[leaf]))
+    }
+}
+
+/// A tree of 16-bit values. Three of its leaves stand for whichever values
+/// were read most recently instead of values of their own.
+struct BigTree {
+    tree: Tree,
+    values: Vec<u16>,
+    /// The leaves that stand for recent values, most recent first
+    recent: [usize; 3],
+}
+
+impl BigTree {
+    fn read(bits: &mut Bits) -> Result<Self> {
+        let mut values = vec![];
+        let mut recent = [None; 3];
+
+        let tree = match bits.bit()? {
+            false => Tree::default(),
+            true => {
+                let low = ByteTree::read(bits)?;
+                let high = ByteTree::read(bits)?;
+                let escapes = [bits.bits(16)?, bits.bits(16)?, bits.bits(16)?];
+
+                let tree = Tree::read(bits, &mut |bits| {
+                    let value = low.get(bits)? as u16 | (high.get(bits)? as u16) << 8;
+
+                    match escapes.iter().position(|&e| e == value as u32) {
+                        Some(i) if recent[i].is_none() => {
+                            recent[i] = Some(values.len());
+                            values.push(0);
+                        }
+                        _ => values.push(value),
+                    }
+
+                    Ok(values.len() - 1)
+                })?;
+                bits.bit()?;
+                tree
+            }
+        };
+
+        // ones the tree doesn't have are given places that can't be reached
+        let recent = recent.map(|r| {
+            r.unwrap_or_else(|| {
+                values.push(0);
+                values.len() - 1
+            })
+        });
+
+        Ok(Self {
+            tree,
+            values,
+            recent,
+        })
+    }
+
+    fn get(&mut self, bits: &mut Bits) -> Result<u16> {
+        let value = match self.tree.leaf(bits)? {
+            Some(leaf) => self.values[leaf],
+            None => return Ok(0),
+        };
+
+        let [first, second, third] = self.recent;
+        if value != self.values[first] {
+            self.values[third] = self.values[second];
+            self.values[second] = self.values[first];
+            self.values[first] = value;
+        }
+
+        Ok(value)
+    }
+}
+
+/// Reads the palette at the start of a frame, which is given in terms of the
+/// one before it.
+fn read_palette(data: &[u8], palette: &mut [[u8; 3]; 256]) -> Result<()> {
+    // 6-bit components spread out to 8 bits
+    let expand = |c: u8| (c & 0x3F) * 4 + (c & 0x3F) / 16;
+
+    let old = *palette;
+    let mut bytes = data.iter().copied();
+    let mut next = || bytes.next().ok_or(invalid("palette cut short"));
+    let mut index = 0;
+
+    while index < 256 {
+        let b = next()?;
+
+        if b & 0x80 != 0 {
+            index += (b & 0x7F) as usize + 1;
+        } else if b & 0x40 != 0 {
+            let count = (b & 0x3F) as usize + 1;
+            let from = next()? as usize;
+
+            for i in 0..count.min(256 - index) {
+                palette[index] = *old.get(from + i).ok_or(invalid("palette out of range"))?;
+                index += 1;
+            }
+        } else {
+            palette[index] = [b, next()?, next()?].map(expand);
+            index += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes the first frame of a Smacker video.
+pub fn first_frame(data: &[u8]) -> Result<Image> {
+    let header = |at| u32_at(data, at).ok_or(invalid("header cut short"));
+    let (width, height) = (header(4)?, header(8)?);
+    let frames = header(12)? as usize;
+    let flags = header(20)?;
+    let trees_size = header(TREES_SIZE)? as usize;
+
+    if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+        return Err(invalid("impossible size"));
+    }
+    if frames == 0 {
+        return Err(invalid("no frames"));
+    }
+
+    let count = frames + (flags & RING_FRAME) as usize;
+    let types = FRAME_SIZES + count * 4;
+    let trees_at = types + count;
+
+    let mut bits = Bits {
+        data: data
+            .get(trees_at..trees_at + trees_size)
+            .ok_or(invalid("trees cut short"))?,
+        at: 0,
+    };
+    let mut mono_map = BigTree::read(&mut bits)?;
+    let mut mono_colours = BigTree::read(&mut bits)?;
+    let mut full = BigTree::read(&mut bits)?;
+    let mut block_types = BigTree::read(&mut bits)?;
+
+    // the bottom two bits of each size are flags
+    let frame_at = trees_at + trees_size;
+    let frame_size = (header(FRAME_SIZES)? & !3) as usize;
+    let frame_type = *data.get(types).ok_or(invalid("header cut short"))?;
+    let frame = data
+        .get(frame_at..frame_at + frame_size)
+        .ok_or(invalid("frame cut short"))?;
+
+    let mut palette = [[0; 3]; 256];
+    let mut at = 0;
+
+    if frame_type & FRAME_PALETTE != 0 {
+        let size = *frame.first().ok_or(invalid("frame cut short"))? as usize * 4;
+        read_palette(frame.get(1..size).unwrap_or_default(), &mut palette)?;
+        at = size;
+    }
+
+    // audio comes next, each track with its size first
+    for track in 0..7 {
+        if frame_type & 2 << track != 0 {
+            at += u32_at(frame, at).ok_or(invalid("audio cut short"))? as usize;
+        }
+    }
+
+    let mut bits = Bits {
+        data: frame.get(at..).ok_or(invalid("frame cut short"))?,
+        at: 0,
+    };
+
+    let (w, h) = (width as usize, height as usize);
+    let mut pixels = vec![0; w * h];
+    let (across, blocks) = (w / 4, (w / 4) * (h / 4));
+    let mut block = 0;
+
+    let v4 = data.starts_with(b"SMK4");
+
+    while block < blocks {
+        let kind = block_types.get(&mut bits)?;
+        let run = BLOCK_RUNS[(kind >> 2 & 0x3F) as usize];
+
+        // full blocks in version 4 files can be at half resolution
+        let mode = match kind & 3 == BLOCK_FULL && v4 {
+            true if bits.bit()? => 1,
+            true if bits.bit()? => 2,
+            _ => 0,
+        };
+
+        for _ in 0..run.min(blocks - block) {
+            let (x, y) = (block % across * 4, block / across * 4);
+            let mut row = |i: usize, values: [u8; 4]| {
+                pixels[(y + i) * w + x..][..4].copy_from_slice(&values);
+            };
+
+            match kind & 3 {
+                BLOCK_MONO => {
+                    let [low, high] = mono_colours.get(&mut bits)?.to_le_bytes();
+                    let mut map = mono_map.get(&mut bits)?;
+
+                    for i in 0..4 {
+                        row(
+                            i,
+                            [0; 4].map(|_| {
+                                let pixel = if map & 1 != 0 { high } else { low };
+                                map >>= 1;
+                                pixel
+                            }),
+                        );
+                    }
+                }
+                BLOCK_FULL => match mode {
+                    0 => {
+                        for i in 0..4 {
+                            let [c, d] = full.get(&mut bits)?.to_le_bytes();
+                            let [a, b] = full.get(&mut bits)?.to_le_bytes();
+                            row(i, [a, b, c, d]);
+                        }
+                    }
+                    1 => {
+                        for i in [0, 2] {
+                            let [a, b] = full.get(&mut bits)?.to_le_bytes();
+                            row(i, [a, a, b, b]);
+                            row(i + 1, [a, a, b, b]);
+                        }
+                    }
+                    _ => {
+                        for i in [0, 2] {
+                            let [c, d] = full.get(&mut bits)?.to_le_bytes();
+                            let [a, b] = full.get(&mut bits)?.to_le_bytes();
+                            row(i, [a, b, c, d]);
+                            row(i + 1, [a, b, c, d]);
+                        }
+                    }
+                },
+                // left as it was in the frame before, which there isn't one of
+                BLOCK_VOID => {}
+                _ => {
+                    let colour = (kind >> 8) as u8;
+                    (0..4).for_each(|i| row(i, [colour; 4]));
+                }
+            }
+
+            block += 1;
+        }
+    }
+
+    let mut image = Image::from_indices(width, height, &pixels, w, &palette);
+
+    // lines that are left out or doubled are shown twice
+    if flags & (Y_INTERLACED | Y_DOUBLED) != 0 {
+        image.pixels = image
+            .pixels
+            .chunks(w)
+            .flat_map(|line| [line, line])
+            .flatten()
+            .copied()
+            .collect();
+        image.height *= 2;
+    }
+
+    Ok(image)
+}
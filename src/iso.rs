@@ -0,0 +1,246 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use thiserror::Error;
+
+/// Offset of the first volume descriptor; everything before it is the
+/// system area.
+const DESCRIPTORS_START: u64 = 16 * 2048;
+
+/// Descriptors are always 2048 bytes, whatever the logical block size.
+const DESCRIPTOR_SIZE: u64 = 2048;
+
+const PRIMARY_DESCRIPTOR: u8 = 1;
+const TERMINATOR: u8 = 255;
+
+/// Most volume descriptors that will be looked through for the primary one.
+const MAX_DESCRIPTORS: u64 = 64;
+
+/// ISO9660 allows 8 levels of directories; anything much deeper is a loop
+/// in a corrupted image.
+const MAX_DEPTH: u32 = 64;
+
+const DIRECTORY_FLAG: u8 = 0x02;
+
+#[derive(Error, Debug)]
+pub enum IsoError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("Not an ISO9660 image (no primary volume descriptor)")]
+    NoPrimaryDescriptor,
+
+    #[error("Directory \"{0}\" is nested too deeply")]
+    TooDeep(String),
+}
+
+pub type Result<T> = std::result::Result<T, IsoError>;
+
+/// A file stored in an image.
+#[derive(Debug, Clone)]
+pub struct IsoEntry {
+    /// Path from the root of the image, separated by `/`, without the
+    /// `;1` version suffix
+    pub path: String,
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl IsoEntry {
+    /// Gives a reader over just this file, from a reader over the image.
+    pub fn open<R: Read + Seek>(&self, image: R) -> Window<R> {
+        Window::new(image, self.offset, self.len)
+    }
+}
+
+/// A reader over part of another reader, seen as if it were a whole file.
+pub struct Window<R> {
+    inner: R,
+    start: u64,
+    len: u64,
+    pos: u64,
+    /// Whether `inner` is at `pos`, so reads can carry on without a seek
+    /// (which would throw away a `BufReader`'s buffer)
+    synced: bool,
+}
+
+impl<R> Window<R> {
+    /// Gives a reader over the `len` bytes of `inner` starting at `start`.
+    pub fn new(inner: R, start: u64, len: u64) -> Self {
+        Self {
+            inner,
+            start,
+            len,
+            pos: 0,
+            synced: false,
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for Window<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.len.saturating_sub(self.pos);
+        let want = buf.len().min(left.try_into().unwrap_or(usize::MAX));
+
+        if want == 0 {
+            return Ok(0);
+        }
+
+        if !self.synced {
+            self.inner.seek(SeekFrom::Start(self.start + self.pos))?;
+            self.synced = true;
+        }
+
+        let read = self.inner.read(&mut buf[..want])?;
+        self.pos += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for Window<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => self.len.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        };
+
+        let new = new.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Seek before start of file")
+        })?;
+
+        if new != self.pos {
+            self.pos = new;
+            self.synced = false;
+        }
+
+        Ok(self.pos)
+    }
+}
+
+/// A directory record, as found in the volume descriptor and directories.
+struct Record {
+    extent: u32,
+    len: u32,
+    is_dir: bool,
+    name: String,
+}
+
+impl Record {
+    /// Parses the record at the start of `buf`, which must be at least as
+    /// long as the record's own length byte says.
+    fn parse(buf: &[u8]) -> Option<Self> {
+        let name_len = *buf.get(32)? as usize;
+
+        Some(Self {
+            extent: u32::from_le_bytes(buf.get(2..6)?.try_into().ok()?),
+            len: u32::from_le_bytes(buf.get(10..14)?.try_into().ok()?),
+            is_dir: buf.get(25)? & DIRECTORY_FLAG != 0,
+            name: String::from_utf8_lossy(buf.get(33..33 + name_len)?).into_owned(),
+        })
+    }
+}
+
+/// Lists every file in an ISO9660 image (such as a dump of a game CD).
+///
+/// Only images made of plain 2048-byte sectors are understood, so raw
+/// `.bin` dumps have to be converted first. Names come from the primary
+/// volume descriptor, which is all the original discs have.
+pub fn list_files<R: Read + Seek>(image: &mut R) -> Result<Vec<IsoEntry>> {
+    let (root, block_size) = read_primary_descriptor(image)?;
+
+    let mut files = vec![];
+    read_directory(image, block_size, &root, "", 0, &mut files)?;
+
+    Ok(files)
+}
+
+fn read_primary_descriptor<R: Read + Seek>(image: &mut R) -> Result<(Record, u64)> {
+    let mut buf = [0; DESCRIPTOR_SIZE as usize];
+
+    for i in 0..MAX_DESCRIPTORS {
+        image.seek(SeekFrom::Start(DESCRIPTORS_START + i * DESCRIPTOR_SIZE))?;
+
+        if image.read_exact(&mut buf).is_err() || &buf[1..6] != b"CD001" {
+            break;
+        }
+
+        match buf[0] {
+            PRIMARY_DESCRIPTOR => {
+                let block_size = u16::from_le_bytes([buf[128], buf[129]]) as u64;
+                let root = Record::parse(&buf[156..190]).ok_or(IsoError::NoPrimaryDescriptor)?;
+
+                if block_size == 0 {
+                    break;
+                }
+
+                return Ok((root, block_size));
+            }
+            TERMINATOR => break,
+            _ => {}
+        }
+    }
+
+    Err(IsoError::NoPrimaryDescriptor)
+}
+
+fn read_directory<R: Read + Seek>(
+    image: &mut R,
+    block_size: u64,
+    dir: &Record,
+    path: &str,
+    depth: u32,
+    files: &mut Vec<IsoEntry>,
+) -> Result<()> {
+    if depth > MAX_DEPTH {
+        return Err(IsoError::TooDeep(path.into()));
+    }
+
+    image.seek(SeekFrom::Start(dir.extent as u64 * block_size))?;
+
+    // a directory's length is trusted no further than the image goes
+    let mut buf = vec![];
+    image.by_ref().take(dir.len as u64).read_to_end(&mut buf)?;
+
+    let mut pos = 0;
+
+    while pos < buf.len() {
+        let len = buf[pos] as usize;
+
+        // records never cross a block, so the rest of this one is padding
+        if len == 0 {
+            pos = (pos / block_size as usize + 1) * block_size as usize;
+            continue;
+        }
+
+        let Some(record) = buf.get(pos..pos + len).and_then(Record::parse) else {
+            break;
+        };
+        pos += len;
+
+        // the first two records are the directory itself and its parent
+        if record.name == "\0" || record.name == "\x01" {
+            continue;
+        }
+
+        let name = record.name.split(';').next().unwrap_or_default();
+        let name = name.strip_suffix('.').unwrap_or(name);
+        let full = if path.is_empty() {
+            name.to_string()
+        } else {
+            format!("{path}/{name}")
+        };
+
+        if record.is_dir {
+            read_directory(image, block_size, &record, &full, depth + 1, files)?;
+        } else {
+            files.push(IsoEntry {
+                path: full,
+                offset: record.extent as u64 * block_size,
+                len: record.len as u64,
+            });
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,12 @@
+pub mod archive;
+pub mod audio;
+pub mod diagnostics;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod image;
+pub mod iso;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod omni;
+pub mod text;
+pub mod types;
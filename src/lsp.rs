@@ -0,0 +1,558 @@
+//! A language server for scripts, spoken over stdin and stdout, with
+//! diagnostics, go-to-definition for object names, hover text for fields
+//! and values, and completion.
+
+use crate::text::{preprocessor::Preprocessor, schema, BlockType, Severity, Text};
+use anyhow::Result;
+use chumsky::Parser;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+        Notification as NotificationTrait, PublishDiagnostics,
+    },
+    request::{Completion, GotoDefinition, HoverRequest, Request as RequestTrait},
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents,
+    HoverParams, HoverProviderCapability, Location, MarkupContent, MarkupKind, NumberOrString,
+    OneOf, Position, PublishDiagnosticsParams, Range, ServerCapabilities,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+use std::{collections::HashMap, fs::read_to_string, ops::Range as Span};
+
+/// What each named value means.
+const VALUES: &[(&str, &str)] = &[
+    ("CACHE", "Loop by keeping the object's data in memory"),
+    ("STREAM", "Loop by streaming the object's data again"),
+    (
+        "NONE",
+        "Don't loop (or, for palettes, don't manage the palette)",
+    ),
+    ("INDEFINITE", "Play until stopped"),
+    ("YES", "The image has transparent parts"),
+    (
+        "FAST",
+        "Fast transparency, which has no known flag bit and isn't stored",
+    ),
+];
+
+const BLOCK_TYPE_NAMES: &[BlockType] = &[
+    BlockType::DefineSettings,
+    BlockType::DefineObject,
+    BlockType::DefineSound,
+    BlockType::DefineEvent,
+    BlockType::DefineAnim,
+    BlockType::DefineStill,
+    BlockType::SerialAction,
+    BlockType::ParallelAction,
+];
+
+/// What each kind of block defines.
+const BLOCK_TYPES: &[(&str, &str)] = &[
+    ("defineSettings", "Buffer settings for the whole file"),
+    (
+        "defineObject",
+        "An object with data of its own, such as a model",
+    ),
+    ("defineSound", "A sound, played from a WAV file"),
+    ("defineEvent", "An event, sent to a presenter at a set time"),
+    ("defineAnim", "A video, played from an FLC or Smacker file"),
+    ("defineStill", "A still image, shown from a bitmap"),
+    (
+        "serialAction",
+        "An action that plays its children one after another",
+    ),
+    (
+        "parallelAction",
+        "An action that plays its children all at once",
+    ),
+    ("external", "An object in another Omni file"),
+];
+
+fn describe(word: &str) -> Option<&'static str> {
+    if let Some(field) = schema::field(word) {
+        return Some(field.description);
+    }
+
+    VALUES
+        .iter()
+        .chain(BLOCK_TYPES)
+        .find(|(name, _)| *name == word)
+        .map(|(_, description)| *description)
+}
+
+/// The type of the block that `offset` is inside, if it's inside one.
+fn enclosing_block_type(text: &str, offset: usize) -> Option<BlockType> {
+    let before = &text[..offset.min(text.len())];
+    let open = before.rfind('{')?;
+
+    if before[open..].contains('}') {
+        return None;
+    }
+
+    let header = before[..open].rsplit(['}', ';']).next()?;
+    let word = header.split_whitespace().next()?;
+
+    BLOCK_TYPE_NAMES
+        .iter()
+        .copied()
+        .find(|t| t.to_string() == word)
+}
+
+/// Byte offset of an LSP position, which counts UTF-16 code units.
+fn offset(text: &str, position: Position) -> usize {
+    let line_start = text
+        .split_inclusive('\n')
+        .take(position.line as usize)
+        .map(str::len)
+        .sum::<usize>();
+
+    let mut units = 0;
+
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= position.character as usize || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+
+    text.len()
+}
+
+fn position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+    Position {
+        line: before.matches('\n').count() as u32,
+        character: before[line_start..].encode_utf16().count() as u32,
+    }
+}
+
+fn range(text: &str, span: Span<usize>) -> Range {
+    Range {
+        start: position(text, span.start),
+        end: position(text, span.end),
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The words in a script, leaving out comments and strings.
+fn words(text: &str) -> Vec<(Span<usize>, &str)> {
+    let mut words = vec![];
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '"' | '\n' => break,
+                        '\\' if matches!(chars.peek(), Some((_, '"' | '\\'))) => {
+                            chars.next();
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            '/' if matches!(chars.peek(), Some((_, '/'))) => {
+                for (_, c) in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if matches!(chars.peek(), Some((_, '*'))) => {
+                chars.next();
+                let mut star = false;
+                for (_, c) in chars.by_ref() {
+                    if star && c == '/' {
+                        break;
+                    }
+                    star = c == '*';
+                }
+            }
+            c if is_word(c) => {
+                let mut end = i + c.len_utf8();
+                while let Some(&(j, c)) = chars.peek() {
+                    if !is_word(c) {
+                        break;
+                    }
+                    end = j + c.len_utf8();
+                    chars.next();
+                }
+                words.push((i..end, &text[i..end]));
+            }
+            _ => {}
+        }
+    }
+
+    words
+}
+
+/// Where each block (or external object) is defined, with the words that
+/// introduce it.
+fn definitions(text: &str) -> Vec<(Span<usize>, &str, &str)> {
+    let words = words(text);
+
+    words
+        .windows(2)
+        .filter(|w| BLOCK_TYPES.iter().any(|(t, _)| *t == w[0].1))
+        .map(|w| (w[1].0.clone(), w[1].1, w[0].1))
+        .collect()
+}
+
+/// The word under the cursor, if there is one.
+fn word_at(text: &str, offset: usize) -> Option<&str> {
+    words(text)
+        .into_iter()
+        .find(|(span, _)| span.start <= offset && offset <= span.end)
+        .map(|(_, word)| word)
+}
+
+/// Files a script `#include`s, as written.
+fn includes(text: &str) -> Vec<&str> {
+    text.lines()
+        .filter_map(|l| l.trim_start().strip_prefix("#include"))
+        .map(|l| l.trim().trim_matches(['"', '<', '>']))
+        .collect()
+}
+
+fn diagnostic(text: &str, span: Span<usize>, message: String) -> Diagnostic {
+    Diagnostic {
+        range: range(text, span),
+        severity: Some(DiagnosticSeverity::ERROR),
+        source: Some("gwydd".into()),
+        message,
+        ..Default::default()
+    }
+}
+
+/// Problems with a script. Included files aren't read, since they're
+/// checked when they're opened themselves.
+fn diagnostics(text: &str) -> Vec<Diagnostic> {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.skip_includes();
+
+    let preprocessed = match preprocessor.preprocess(text) {
+        Ok(p) => p,
+        Err(e) => {
+            let start = e.position().map_or(0, |(line, column)| {
+                offset(
+                    text,
+                    Position {
+                        line: line as u32,
+                        character: column as u32,
+                    },
+                )
+            });
+
+            return vec![diagnostic(text, start..start, e.to_string())];
+        }
+    };
+
+    // warnings and lints have lines where they were in the script, and
+    // columns that are close enough
+    let start = |line: usize, column: usize| {
+        let line_start = text
+            .split_inclusive('\n')
+            .take(line)
+            .map(str::len)
+            .sum::<usize>();
+
+        (line_start + column).min(text.len())
+    };
+
+    let warnings = preprocessor.warnings().iter().map(|w| {
+        let start = start(w.site().line, w.site().column);
+
+        Diagnostic {
+            severity: Some(DiagnosticSeverity::WARNING),
+            ..diagnostic(text, start..start, w.message())
+        }
+    });
+
+    // the preprocessor keeps every line where it was, so spans in its
+    // output are close enough to the script's
+    let (parsed, errors) = Text::parser().parse(&preprocessed).into_output_errors();
+
+    let Some(mut parsed) = parsed.filter(|_| errors.is_empty()) else {
+        let errors = errors
+            .into_iter()
+            .map(|e| diagnostic(&preprocessed, e.span().start..e.span().end, e.to_string()));
+
+        return warnings.chain(errors).collect();
+    };
+
+    parsed.locate(&preprocessed, &preprocessor);
+
+    let lints = parsed.lint().into_iter().map(|lint| {
+        let start = lint.span.as_ref().map_or(0, |s| start(s.line, s.column));
+
+        Diagnostic {
+            severity: Some(match lint.severity {
+                Severity::Note => DiagnosticSeverity::INFORMATION,
+                Severity::Warning => DiagnosticSeverity::WARNING,
+                Severity::Error => DiagnosticSeverity::ERROR,
+            }),
+            code: Some(NumberOrString::String(lint.rule.into())),
+            ..diagnostic(text, start..start, lint.message)
+        }
+    });
+
+    warnings.chain(lints).collect()
+}
+
+struct Server {
+    documents: HashMap<Url, String>,
+}
+
+impl Server {
+    fn document(&self, uri: &Url) -> &str {
+        self.documents.get(uri).map_or("", String::as_str)
+    }
+
+    /// Looks for where `name` is defined, first in the document and then in
+    /// what it includes (from the editor if they're open, otherwise from
+    /// disk).
+    fn find_definition(&self, uri: &Url, name: &str) -> Option<Location> {
+        let mut pending = vec![uri.clone()];
+        let mut seen = vec![];
+
+        while let Some(uri) = pending.pop() {
+            if seen.contains(&uri) {
+                continue;
+            }
+            seen.push(uri.clone());
+
+            let text = match self.documents.get(&uri) {
+                Some(text) => text.clone(),
+                None => read_to_string(uri.to_file_path().ok()?).ok()?,
+            };
+
+            if let Some((span, _, _)) = definitions(&text).into_iter().find(|d| d.1 == name) {
+                return Some(Location {
+                    uri,
+                    range: range(&text, span),
+                });
+            }
+
+            pending.extend(
+                includes(&text)
+                    .into_iter()
+                    .filter_map(|i| uri.join(&i.replace('\\', "/")).ok()),
+            );
+        }
+
+        None
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let uri = &params.text_document_position_params.text_document.uri;
+        let text = self.document(uri);
+        let offset = offset(text, params.text_document_position_params.position);
+        let word = word_at(text, offset)?;
+
+        let value = match describe(word) {
+            Some(description) => {
+                // what the field is left at in the block being edited
+                let default = enclosing_block_type(text, offset)
+                    .zip(schema::field(word))
+                    .and_then(|(block_type, field)| field.default(block_type))
+                    .map(|value| format!("\n\nDefaults to `{value}`"))
+                    .unwrap_or_default();
+                format!("**{word}**\n\n{description}{default}")
+            }
+            None => {
+                let (_, name, block_type) = definitions(text)
+                    .into_iter()
+                    .find(|(_, name, _)| *name == word)?;
+                format!("```\n{block_type} {name}\n```")
+            }
+        };
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: None,
+        })
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let uri = &params.text_document_position_params.text_document.uri;
+        let text = self.document(uri);
+        let offset = offset(text, params.text_document_position_params.position);
+        let word = word_at(text, offset)?;
+
+        self.find_definition(uri, word)
+            .map(GotoDefinitionResponse::Scalar)
+    }
+
+    fn completion(&self, params: CompletionParams) -> CompletionResponse {
+        let text = self.document(&params.text_document_position.text_document.uri);
+
+        let item = |label: &str, kind, detail: Option<&str>| CompletionItem {
+            label: label.into(),
+            kind: Some(kind),
+            detail: detail.map(str::to_string),
+            ..Default::default()
+        };
+
+        let offset = offset(text, params.text_document_position.position);
+
+        // inside a block, only the fields that do something there
+        let block_type = enclosing_block_type(text, offset);
+        let fields = schema::FIELDS
+            .iter()
+            .filter(|f| block_type.is_none_or(|t| f.used_by.contains(&t)));
+
+        let mut items = vec![];
+        items.extend(fields.map(|f| item(f.name, CompletionItemKind::FIELD, Some(f.description))));
+        items.extend(
+            VALUES
+                .iter()
+                .map(|(n, d)| item(n, CompletionItemKind::ENUM_MEMBER, Some(d))),
+        );
+        items.extend(
+            BLOCK_TYPES
+                .iter()
+                .map(|(n, d)| item(n, CompletionItemKind::KEYWORD, Some(d))),
+        );
+        items.extend(
+            definitions(text)
+                .into_iter()
+                .map(|(_, n, t)| item(n, CompletionItemKind::CLASS, Some(t))),
+        );
+
+        CompletionResponse::Array(items)
+    }
+
+    fn handle_request(&self, request: Request) -> Response {
+        let id = request.id.clone();
+        let params = request.params;
+
+        let result = match request.method.as_str() {
+            HoverRequest::METHOD => {
+                serde_json::from_value(params).and_then(|p| serde_json::to_value(self.hover(p)))
+            }
+            GotoDefinition::METHOD => serde_json::from_value(params)
+                .and_then(|p| serde_json::to_value(self.definition(p))),
+            Completion::METHOD => serde_json::from_value(params)
+                .and_then(|p| serde_json::to_value(self.completion(p))),
+            method => {
+                return Response::new_err(
+                    id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("Unsupported request \"{method}\""),
+                )
+            }
+        };
+
+        match result {
+            Ok(result) => Response::new_ok(id, result),
+            Err(e) => Response::new_err(id, ErrorCode::InvalidParams as i32, e.to_string()),
+        }
+    }
+
+    /// Keeps track of open documents, returning the document whose
+    /// diagnostics need to be sent again. Notifications that can't be read
+    /// are ignored, since there's no way to answer them.
+    fn handle_notification(&mut self, notification: Notification) -> Option<Url> {
+        let params = notification.params;
+
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams = serde_json::from_value(params).ok()?;
+                let uri = params.text_document.uri;
+                self.documents
+                    .insert(uri.clone(), params.text_document.text);
+                Some(uri)
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams = serde_json::from_value(params).ok()?;
+                let uri = params.text_document.uri;
+                // synced in full, so the last change is the whole document
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.documents.insert(uri.clone(), change.text);
+                }
+                Some(uri)
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = serde_json::from_value(params).ok()?;
+                self.documents.remove(&params.text_document.uri);
+                Some(params.text_document.uri)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Runs the language server until the editor shuts it down.
+pub fn run() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        ..Default::default()
+    };
+
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut server = Server {
+        documents: HashMap::new(),
+    };
+
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+
+                let response = server.handle_request(request);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                let Some(uri) = server.handle_notification(notification) else {
+                    continue;
+                };
+
+                // closed documents get their diagnostics cleared
+                let diagnostics = server
+                    .documents
+                    .get(&uri)
+                    .map(|text| diagnostics(text))
+                    .unwrap_or_default();
+
+                let params = PublishDiagnosticsParams {
+                    uri,
+                    diagnostics,
+                    version: None,
+                };
+
+                connection
+                    .sender
+                    .send(Message::Notification(Notification::new(
+                        PublishDiagnostics::METHOD.into(),
+                        params,
+                    )))?;
+            }
+            Message::Response(_) => {}
+        }
+    }
+
+    // the writer thread only finishes once the connection's gone
+    drop(connection);
+    io_threads.join()?;
+
+    Ok(())
+}
@@ -1,85 +1,2632 @@
 use anyhow::{anyhow, Result};
+use binrw::Endian;
 use clap::Parser;
-use omni::Omni;
+use gwydd::{
+    archive::{self, ArchiveError, ZipEntry},
+    audio::{self, AudioError, AudioFormat},
+    diagnostics::{Diagnostics, Location},
+    image::{self, ImageError},
+    iso::{self, IsoEntry, IsoError, Window},
+    omni::{
+        self, codec_for, read_resources, safe_file_name, safe_relative_path, CompileError,
+        CompileOptions, ExtractNaming, ExtractPaths, ObjectInfo, Omni, OmniParseError, OmniVersion,
+        OmniWriteError, PadPolicy, ParseOptions, Patch, PatchError, PathRewrite, ProgressHook,
+        RemapError, RenameError, Stats,
+    },
+    text::{
+        preprocessor::{PreprocessError, Preprocessor},
+        schema, FormatOptions, Indent, Lint, Query, Severity, StatementOrder, SyntaxError, Text,
+        VolumeUnit,
+    },
+    types::{wildcard_match, Encoding, ObjectFilter},
+};
+use human_bytes::human_bytes;
+use indicatif::{ProgressBar, ProgressStyle};
+use regex::Regex;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 use std::{
-    fs::{read, read_to_string, write},
-    io::Cursor,
-    path::PathBuf,
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs::{create_dir_all, metadata, read, read_dir, read_to_string, write},
+    io::{Cursor, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
 };
-use text::Text;
+use thiserror::Error;
 
-mod omni;
-mod text;
-mod types;
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  Success
+  1  Failure not covered below
+  2  Bad arguments
+  3  Success, with warnings
+  4  An Omni file, script or disc image couldn't be parsed
+  5  A script has mistakes, or can't be compiled as it is
+  6  A file couldn't be read or written
+  7  Something that isn't supported (yet)";
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, after_help = EXIT_CODES)]
 #[clap(group(
     clap::ArgGroup::new("command").required(false)
 ))]
 struct Args {
-    /// Input file
+    /// Input file, or a directory or wildcard pattern (e.g. `disc/*.si`) to
+    /// decompile or extract every Omni file in, or an ISO9660 disc image
     #[arg(short, long)]
-    infile: PathBuf,
+    infile: Option<PathBuf>,
 
-    /// Output file
+    /// Output file (or folder, when given several input files)
     #[arg(short, long)]
-    outfile: PathBuf,
+    outfile: Option<PathBuf>,
 
     /// Resource folder
     #[arg(short, long)]
     resources: Option<PathBuf>,
 
-    /// Prefix for stored paths (case-insensitive)
+    /// Prefix for stored paths (case-insensitive), such as \lego\scripts,
+    /// that's left out of the paths of files extracted or looked up in the
+    /// resource folder, and put in front of file names given relative to it
+    /// when compiling
     #[arg(short, long)]
     prefix: Option<PathBuf>,
 
-    /// Decompile given file
+    /// Decompile given file (to its syntax tree as JSON if the output ends
+    /// in .json)
     #[arg(short, long, group = "command", action)]
     decompile: bool,
 
-    /// Compile given file
+    /// Compile given file (a script, or its syntax tree as JSON if it ends
+    /// in .json)
     #[arg(short, long, group = "command", action)]
     compile: bool,
 
+    /// Extract stored files from given file into the output folder
+    #[arg(short = 'x', long, group = "command", action)]
+    extract: bool,
+
+    /// Write a table of every MxCh chunk's object, time, size and flags,
+    /// with whether it ends its object's data or runs into the next buffer
+    /// (tab-separated if the output ends in .tsv, otherwise CSV)
+    #[arg(long, group = "command", action)]
+    timeline: bool,
+
+    /// Write the text and timing of every event chunk as subtitles (if the
+    /// output ends in .srt) or an Audacity label track
+    #[arg(long, group = "command", action)]
+    cues: bool,
+
+    /// Write the object hierarchy as a Graphviz DOT graph
+    #[arg(long, group = "command", action)]
+    dot: bool,
+
+    /// Write an HTML page summarising the file, with its objects, when
+    /// their chunks are streamed, how full the buffers are and its stills
+    /// (or previews of its stills and videos, with --thumbnails)
+    #[arg(long, group = "command", action)]
+    report: bool,
+
+    /// Make small PNG previews of stills and the first frames of videos:
+    /// next to each file when extracting (as NAME.EXT.png), and in place of
+    /// the stills in a report. Needs the `images` feature
+    #[arg(long)]
+    thumbnails: bool,
+
+    /// Print every object in given file with its ID, type and stored file,
+    /// which is quicker than decompiling since no data is read
+    #[arg(long, group = "command", action)]
+    list: bool,
+
+    /// Print how much data each object streams and how full the buffers are
+    #[arg(long, group = "command", action)]
+    stats: bool,
+
+    /// Print the SHA-256 of each stored file as it would be extracted, to
+    /// tell whether files with different layouts hold the same data
+    #[arg(long, group = "command", action)]
+    checksums: bool,
+
+    /// Print what a selector picks out of given file or script, such as
+    /// `defineSound[name~="*laugh*"].fileName` for the file of every sound
+    /// with "laugh" in its name
+    #[arg(long, group = "command")]
+    query: Option<Query>,
+
+    /// List where Omni files start inside the input file, which can be
+    /// anything (an EXE, a disc image, a memory dump...)
+    #[arg(long, group = "command", action)]
+    scan: bool,
+
+    /// Read the Omni file starting at this offset in the input file (as
+    /// listed by --scan)
+    #[arg(long, value_parser = parse_offset)]
+    offset: Option<u64>,
+
+    /// Merge these Omni files into the input file, renumbering objects whose
+    /// IDs are already used
+    #[arg(long, group = "command", num_args = 1..)]
+    merge: Vec<PathBuf>,
+
+    /// Give an object in the input file another ID (OLD=NEW, repeatable),
+    /// along with the chunks holding its data, such as to keep its IDs
+    /// from clashing with another file's before merging them
+    #[arg(long, group = "command", value_parser = parse_renumber)]
+    renumber: Vec<(u32, u32)>,
+
+    /// Renumber every object in the input file in order, starting from
+    /// this ID
+    #[arg(long, group = "command")]
+    renumber_from: Option<u32>,
+
+    /// Write each top-level object into its own Omni file in the output
+    /// folder
+    #[arg(long, group = "command", action)]
+    split: bool,
+
+    /// Write a patch that turns the input file into this one, holding only
+    /// the top-level objects that were added or changed
+    #[arg(long, group = "command")]
+    make_patch: Option<PathBuf>,
+
+    /// Apply this patch (made with --make-patch) to the input file
+    #[arg(long, group = "command")]
+    apply_patch: Option<PathBuf>,
+
+    /// Compile the blocks in this script snippet and put them in place of
+    /// the objects with the same names in the input file, which is
+    /// otherwise left as it is. Objects keep their children, and the data
+    /// they have unless their files are found in the resource folder
+    #[arg(long, group = "command")]
+    replace: Option<PathBuf>,
+
+    /// Replace the start of stored paths in the input file (OLD=NEW,
+    /// case-insensitive, repeatable), such as `\lego\scripts=\mod\scripts`
+    #[arg(long, value_parser = parse_rename)]
+    rewrite_prefix: Vec<(String, String)>,
+
+    /// Replace every match of a regex in stored paths in the input file
+    /// (PATTERN=REPLACEMENT, repeatable), with `$1` and so on standing for
+    /// its groups; rewrites are made in the order given, prefixes first
+    #[arg(long, value_parser = parse_rewrite_regex)]
+    rewrite_regex: Vec<(Regex, String)>,
+
+    /// Run a language server for scripts over stdin and stdout
+    #[cfg(feature = "lsp")]
+    #[arg(long, group = "command", action)]
+    lsp: bool,
+
+    /// Check given script for mistakes, failing if any would stop it
+    /// compiling
+    #[arg(long, group = "command", action)]
+    lint: bool,
+
+    /// Reformat given script into the output file, or convert it to or
+    /// from its syntax tree as JSON if either file ends in .json. Scripts
+    /// with comments, #defines or #includes can only be converted to JSON,
+    /// which expands them
+    #[arg(long, group = "command", action)]
+    fmt: bool,
+
+    /// Write the script given to --fmt over itself
+    #[arg(long, requires = "fmt", conflicts_with = "outfile")]
+    in_place: bool,
+
+    /// Expand the #defines and #includes in given script, with #line
+    /// markers saying which file and line each part came from
+    #[arg(long, group = "command", action)]
+    preprocess: bool,
+
+    /// Indentation when formatting or decompiling: "tab" or a number of
+    /// spaces
+    #[arg(long, default_value = "tab")]
+    indent: Indent,
+
+    /// Where declarations go when formatting: "keep", "declarations-first"
+    /// or "declarations-last"
+    #[arg(long, default_value = "keep")]
+    order: StatementOrder,
+
+    /// Use CRLF line endings when formatting or decompiling
+    #[arg(long)]
+    crlf: bool,
+
+    /// Comment each decompiled object with its ID and file offset
+    #[arg(long)]
+    annotate: bool,
+
+    /// Comment each decompiled object whose flags aren't all accounted for
+    /// by loopingMethod and transparency with its flags as they're stored
+    #[arg(long)]
+    annotate_unknown: bool,
+
+    /// Decompile each top-level weave into its own file in this folder, with
+    /// the output file #including them all
+    #[arg(long)]
+    split_output: Option<PathBuf>,
+
+    /// Write out every value when decompiling, even ones left at their
+    /// defaults
+    #[arg(long)]
+    keep_defaults: bool,
+
+    /// Print the value that each type of object gets for each field its
+    /// block leaves out, which is what decompiling leaves out
+    #[arg(long, group = "command", action)]
+    defaults: bool,
+
+    /// What volumes are written in when decompiling: "level", as they're
+    /// stored, or "percent" or "db" relative to the default level
+    #[arg(long, default_value = "level")]
+    volume_unit: VolumeUnit,
+
+    /// File in the disc image or zip file to use, or a wildcard pattern for
+    /// several (e.g. `SCRIPTS/*`). If left out, a disc image's Omni files
+    /// are listed, and every Omni file in a zip file is used
+    #[arg(long)]
+    entry: Option<String>,
+
+    /// Compress the decompiled script with gzip, which is also done if the
+    /// output file ends in .gz
+    #[arg(long)]
+    gzip: bool,
+
+    /// Rename an object and every reference to it (OLD=NEW, repeatable):
+    /// in the script when compiling or decompiling, or in the input file
+    /// when the output is an Omni file too
+    #[arg(long, value_parser = parse_rename)]
+    rename: Vec<(String, String)>,
+
+    /// Only extract or decompile these objects (comma-separated IDs or name globs)
+    #[arg(long)]
+    object: Option<ObjectFilter>,
+
+    /// Decompile only the objects picked by --object and the ones they
+    /// declare, without the settings, for pasting into another script; the
+    /// objects they're declared in are named in a comment. Printed if
+    /// there's no output file
+    #[arg(long, requires = "object")]
+    snippet: bool,
+
+    /// Number of files to extract at once (defaults to the number of CPUs)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    /// Convert sounds to this format when extracting: "wav", "flac" or
+    /// "ogg-flac" (FLAC in an Ogg container); the last two need the
+    /// `audio-codecs` feature
+    #[arg(long)]
+    audio_format: Option<AudioFormat>,
+
+    /// When extracting, write each object's chunk data as it's stored, as
+    /// NAME.bin, with NAME.csv listing where each chunk starts and when it's
+    /// streamed, instead of putting the stored files back together
+    #[arg(long)]
+    raw: bool,
+
+    /// What to call extracted files: "path", as they're stored, "name",
+    /// after their objects, or "id-name", as ID_NAME. Files that would end
+    /// up in the same place (ignoring case) get their object's ID added
+    #[arg(long, default_value = "path")]
+    naming: ExtractNaming,
+
+    /// Put every extracted file straight in the output folder, leaving out
+    /// the folders in stored paths
+    #[arg(long)]
+    flatten: bool,
+
+    /// When extracting, also write the data of chunks that belong to no
+    /// object into this folder, as ID.bin
+    #[arg(long)]
+    orphans: Option<PathBuf>,
+
+    /// When extracting, also decompile the file into the output folder (as
+    /// FOLDER.ss, after the folder) and write the manifest there (as
+    /// manifest.json, unless --manifest says where), so that --rebuild can
+    /// put the file back together from the folder
+    #[arg(long, conflicts_with = "raw")]
+    with_script: bool,
+
+    /// Compile the script in this folder, extracted with --with-script,
+    /// back into an Omni file, along with the files its manifest lists.
+    /// --infile, --manifest and --resources say where they are instead,
+    /// and the file is written next to the folder as FOLDER.si unless
+    /// --outfile says where
+    #[arg(long, group = "command")]
+    rebuild: Option<PathBuf>,
+
+    /// Salvage what can be read from truncated or corrupted files
+    #[arg(long)]
+    lenient: bool,
+
+    /// Byte order of Omni files: "auto", to go by whether they're RIFF
+    /// (little-endian) or RIFX (big-endian) files, "little" or "big".
+    /// Compiled files are little-endian unless it's "big"
+    #[arg(long, default_value = "auto")]
+    endian: ByteOrder,
+
+    /// Code page of names and file names stored in Omni files:
+    /// "windows-1252" (as in the original game) or "utf-8"
+    #[arg(long, default_value = "windows-1252")]
+    encoding: Encoding,
+
+    /// How to write errors, warnings, lints and stats: "text" or "json"
+    /// (one JSON object per line)
+    #[arg(long, default_value = "text")]
+    format: OutputFormat,
+
+    /// Don't show progress bars
+    #[arg(short, long, action)]
+    quiet: bool,
+
     /// Dump AST to file
     #[arg(long)]
     dump_ast: Option<PathBuf>,
+
+    /// Buffer size in KB when compiling (overrides bufferSizeKB), and to
+    /// read Omni files with until their MxHd chunk gives one (which is
+    /// otherwise looked for before reading)
+    #[arg(long)]
+    buffer_size: Option<i32>,
+
+    /// Number of buffers when compiling (overrides buffersNum)
+    #[arg(long)]
+    buffer_count: Option<i32>,
+
+    /// Format version to compile to (e.g. 2.2, which LEGO Island uses)
+    #[arg(long, default_value = "2.2")]
+    target_version: OmniVersion,
+
+    /// Store sounds as they are when compiling, instead of converting FLAC
+    /// files, and WAV files the engine can't play properly, to WAV files
+    /// it can
+    #[arg(long)]
+    keep_audio: bool,
+
+    /// Leave out blocks that aren't weaves and aren't declared by any other
+    /// block when compiling, instead of storing them as top-level objects
+    #[arg(long)]
+    strip_unused: bool,
+
+    /// Fail to compile on unknown fields, unknown values and missing
+    /// required fields, instead of warning about them and keeping unknown
+    /// fields in the extra data
+    #[arg(long)]
+    strict: bool,
+
+    /// File listing where to find each stored file when compiling (one
+    /// NAME = PATH per line, or a JSON manifest written when extracting);
+    /// when extracting, where to write a JSON manifest of what was extracted
+    /// and which files had to be renamed to be safe to write
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+
+    /// Write a Makefile rule listing every script and resource the compiled
+    /// file was built from
+    #[arg(long)]
+    depfile: Option<PathBuf>,
+
+    /// Compile without writing anything, and report how big the file would
+    /// be, how full its buffers would be and any chunks too big for them
+    /// (or, when rewriting paths, only list what would change)
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Byte to fill new padding with when compiling, merging or splitting
+    #[arg(long, value_parser = parse_byte, default_value = "0")]
+    pad_fill: u8,
+
+    /// Don't pad chunks to line them up with buffers when compiling,
+    /// merging or splitting
+    #[arg(long)]
+    no_pad: bool,
+
+    /// Keep the contents of the original padding when merging or splitting,
+    /// wherever the same padding is needed
+    #[arg(long)]
+    keep_pads: bool,
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+/// What the process exits with, so that scripts can tell kinds of failure
+/// apart (see [`EXIT_CODES`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    Failure = 1,
+    Warnings = 3,
+    Parse = 4,
+    Invalid = 5,
+    Io = 6,
+    Unsupported = 7,
+}
 
-    if args.compile {
-        let file = read_to_string(args.infile)?;
+impl Exit {
+    /// Works out what kind of failure `error` is from the first cause that
+    /// says.
+    fn of(error: &anyhow::Error) -> Self {
+        error
+            .chain()
+            .find_map(|cause| {
+                if let Some(e) = cause.downcast_ref::<Failed>() {
+                    return Some(e.exit());
+                }
+                if let Some(e) = cause.downcast_ref::<OmniParseError>() {
+                    return Some(match e {
+                        OmniParseError::Io(_) => Self::Io,
+                        OmniParseError::UnsupportedObject(..) => Self::Unsupported,
+                        OmniParseError::Cancelled => Self::Failure,
+                        _ => Self::Parse,
+                    });
+                }
+                if let Some(mut e) = cause.downcast_ref::<CompileError>() {
+                    // where it happened doesn't change what kind of error it is
+                    while let CompileError::At(_, inner) = e {
+                        e = inner;
+                    }
+
+                    return Some(match e {
+                        CompileError::Resource(..) | CompileError::BinRW(_) => Self::Io,
+                        CompileError::UnknownFileType(..)
+                        | CompileError::Audio(
+                            _,
+                            AudioError::UnsupportedCodec(_)
+                            | AudioError::NeedsCodecs(_)
+                            | AudioError::UnsupportedFormat(_)
+                            | AudioError::UnsupportedBits(_)
+                            | AudioError::UnsupportedChannels(_),
+                        ) => Self::Unsupported,
+                        CompileError::Cancelled => Self::Failure,
+                        _ => Self::Invalid,
+                    });
+                }
+                if let Some(e) = cause.downcast_ref::<PatchError>() {
+                    return Some(match e {
+                        PatchError::Parse(OmniParseError::Io(_)) => Self::Io,
+                        PatchError::Parse(_) | PatchError::NotPatch => Self::Parse,
+                        PatchError::Missing(_) | PatchError::Mismatch(_) => Self::Invalid,
+                        PatchError::BinRW(_) | PatchError::Write(_) => Self::Io,
+                    });
+                }
+                if cause.is::<RemapError>() || cause.is::<RenameError>() {
+                    return Some(Self::Invalid);
+                }
+                if let Some(e) = cause.downcast_ref::<IsoError>() {
+                    return Some(match e {
+                        IsoError::Io(_) => Self::Io,
+                        _ => Self::Parse,
+                    });
+                }
+                if let Some(e) = cause.downcast_ref::<ArchiveError>() {
+                    return Some(match e {
+                        ArchiveError::Unsupported(..) | ArchiveError::NeedsArchives(_) => {
+                            Self::Unsupported
+                        }
+                        ArchiveError::Io(_) => Self::Io,
+                        _ => Self::Parse,
+                    });
+                }
+                if let Some(e) = cause.downcast_ref::<PreprocessError>() {
+                    return Some(match e {
+                        PreprocessError::Include(..) => Self::Io,
+                        _ => Self::Parse,
+                    });
+                }
+                if cause.is::<SyntaxError>() {
+                    return Some(Self::Parse);
+                }
+                if cause.is::<OmniWriteError>() || cause.is::<std::io::Error>() {
+                    return Some(Self::Io);
+                }
+                None
+            })
+            .unwrap_or(Self::Failure)
+    }
+}
+
+/// Failures found by the tool itself rather than the library.
+#[derive(Debug, Error)]
+enum Failed {
+    #[error("{0} has errors")]
+    Lint(String),
+
+    #[error("{0} of {1} files failed")]
+    Batch(usize, usize, Exit),
+}
+
+impl Failed {
+    fn exit(&self) -> Exit {
+        match self {
+            Self::Lint(_) => Exit::Invalid,
+            Self::Batch(_, _, exit) => *exit,
+        }
+    }
+}
+
+/// Whether any warnings have been printed, for [`Exit::Warnings`].
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Warnings found along the way, printed once everything's done.
+static DIAGNOSTICS: Diagnostics = Diagnostics::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Expected \"text\" or \"json\", found \"{s}\"")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteOrder {
+    Auto,
+    Little,
+    Big,
+}
+
+impl FromStr for ByteOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "little" => Ok(Self::Little),
+            "big" => Ok(Self::Big),
+            _ => Err(format!(
+                "Expected \"auto\", \"little\" or \"big\", found \"{s}\""
+            )),
+        }
+    }
+}
+
+const NO_INFILE: &str = "No input file given (use --infile)";
+const NO_OUTFILE: &str = "No output file given (use --outfile)";
+
+/// What the manifest written with --with-script is called.
+const MANIFEST_NAME: &str = "manifest.json";
+
+impl Args {
+    fn json(&self) -> bool {
+        self.format == OutputFormat::Json
+    }
+
+    /// Buffer size in bytes to start reading Omni files with.
+    fn read_buffer_size(&self) -> Option<i32> {
+        self.buffer_size.map(|kb| kb.saturating_mul(1024))
+    }
+
+    /// Byte order to read and write Omni files in, if it isn't worked out
+    /// from each file.
+    fn endian(&self) -> Option<Endian> {
+        match self.endian {
+            ByteOrder::Auto => None,
+            ByteOrder::Little => Some(Endian::Little),
+            ByteOrder::Big => Some(Endian::Big),
+        }
+    }
+
+    /// Number of files to read at once.
+    fn jobs(&self) -> usize {
+        self.jobs
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    /// Adds a warning to be printed at the end, from `file` if it's known.
+    fn warn(&self, file: Option<&dyn Display>, message: impl Display) {
+        let location = file.map(|f| Location::File(f.to_string()));
+        DIAGNOSTICS.warn(location, message);
+    }
+
+    /// Prints everything in [`DIAGNOSTICS`] to stderr.
+    fn print_diagnostics(&self) {
+        for diagnostic in DIAGNOSTICS.take() {
+            if diagnostic.severity >= Severity::Warning {
+                WARNED.store(true, Ordering::Relaxed);
+            }
+
+            if !self.json() {
+                eprintln!("{diagnostic}");
+                continue;
+            }
+
+            let mut json = json!({ diagnostic.severity.to_string(): diagnostic.message });
+            match diagnostic.location {
+                Some(Location::File(file)) => json["file"] = json!(file),
+                Some(Location::Offset(pos)) => json["offset"] = json!(pos),
+                Some(Location::Object(id, name)) => {
+                    json["object"] = json!(id);
+                    json["name"] = json!(name);
+                }
+                Some(Location::Script(span)) => {
+                    json["file"] = json!(span.file.map(|f| f.display().to_string()));
+                    json["line"] = json!(span.line + 1);
+                    json["column"] = json!(span.column + 1);
+                }
+                None => {}
+            }
+            eprintln!("{json}");
+        }
+    }
+
+    /// Prints an error to stderr, from `file` if it's known.
+    fn error(&self, file: Option<&dyn Display>, error: &anyhow::Error) {
+        match (self.json(), file) {
+            (true, Some(file)) => eprintln!(
+                "{}",
+                json!({ "error": format!("{error:#}"), "file": file.to_string() })
+            ),
+            (true, None) => eprintln!("{}", json!({ "error": format!("{error:#}") })),
+            (false, Some(file)) => eprintln!("error: {file}: {error}"),
+            // as returning it from main would
+            (false, None) => eprintln!("Error: {error:?}"),
+        }
+    }
+
+    fn infile(&self) -> Result<&Path> {
+        self.infile.as_deref().ok_or(anyhow!(NO_INFILE))
+    }
+
+    fn outfile(&self) -> Result<&Path> {
+        self.outfile.as_deref().ok_or(anyhow!(NO_OUTFILE))
+    }
+
+    fn prefix(&self) -> Option<String> {
+        self.prefix.as_ref().map(|p| p.to_string_lossy().into())
+    }
+
+    fn format_options(&self) -> FormatOptions {
+        FormatOptions {
+            indent: self.indent,
+            order: self.order,
+            crlf: self.crlf,
+            annotate: self.annotate,
+            annotate_unknown: self.annotate_unknown,
+        }
+    }
+
+    fn pads(&self) -> PadPolicy {
+        PadPolicy {
+            fill: self.pad_fill,
+            pack: self.no_pad,
+            keep_original: self.keep_pads,
+        }
+    }
+}
+
+fn parse_rename(s: &str) -> Result<(String, String)> {
+    let (from, to) = s
+        .split_once('=')
+        .ok_or(anyhow!("Expected OLD=NEW, found \"{s}\""))?;
+
+    Ok((from.into(), to.into()))
+}
+
+fn parse_renumber(s: &str) -> Result<(u32, u32)> {
+    let (old, new) = s
+        .split_once('=')
+        .ok_or(anyhow!("Expected OLD=NEW, found \"{s}\""))?;
+
+    Ok((old.trim().parse()?, new.trim().parse()?))
+}
 
-        let text = Text::parse(&file)?;
+fn parse_rewrite_regex(s: &str) -> Result<(Regex, String)> {
+    let (pattern, replacement) = s
+        .split_once('=')
+        .ok_or(anyhow!("Expected PATTERN=REPLACEMENT, found \"{s}\""))?;
 
-        if let Some(path) = args.dump_ast {
-            write(path, format!("{:#?}", text))?;
+    Ok((Regex::new(pattern)?, replacement.into()))
+}
+
+fn parse_byte(s: &str) -> Result<u8> {
+    Ok(parse_offset(s)?.try_into()?)
+}
+
+fn parse_offset(s: &str) -> Result<u64> {
+    Ok(match s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16)?,
+        None => s.parse()?,
+    })
+}
+
+/// Extensions of the files picked up when given a directory.
+const OMNI_EXTENSIONS: [&str; 2] = ["si", "omni"];
+
+/// Extensions of the compressed files that Omni files are read out of.
+const PACKED_EXTENSIONS: [&str; 2] = ["gz", "zip"];
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .is_some_and(|ext| extensions.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
+
+/// An Omni file to decompile or extract.
+enum Input {
+    File(PathBuf),
+    /// A file inside a disc image
+    Iso {
+        image: PathBuf,
+        entry: IsoEntry,
+    },
+    /// An Omni file inside some other file, running from `offset` to the
+    /// end
+    Embedded {
+        path: PathBuf,
+        offset: u64,
+    },
+    /// A file inside a zip file
+    Zip {
+        archive: PathBuf,
+        entry: ZipEntry,
+    },
+    /// A gzipped file
+    Gzip(PathBuf),
+}
+
+impl Display for Input {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Input::File(path) => write!(f, "{}", path.display()),
+            Input::Iso { image, entry } => write!(f, "{}:{}", image.display(), entry.path),
+            Input::Embedded { path, offset } => write!(f, "{}@{offset:#X}", path.display()),
+            Input::Zip { archive, entry } => write!(f, "{}:{}", archive.display(), entry.path),
+            Input::Gzip(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+enum Inputs {
+    Single(Input),
+    /// Several files, each with the path its output should go to, relative
+    /// to the output folder
+    Batch(Vec<(PathBuf, Input)>),
+}
+
+/// Works out which files `infile` covers: just itself, or everything in
+/// it if it's a directory or a wildcard pattern.
+fn find_inputs(infile: &Path) -> Result<Inputs> {
+    fn walk(base: &Path, rel: &Path, found: &mut Vec<PathBuf>) -> Result<()> {
+        for entry in read_dir(base.join(rel))? {
+            let entry = entry?;
+            let path = rel.join(entry.file_name());
+
+            if entry.file_type()?.is_dir() {
+                walk(base, &path, found)?;
+            } else if has_extension(&path, &OMNI_EXTENSIONS) {
+                found.push(path);
+            }
         }
+
+        Ok(())
+    }
+
+    let mut found = vec![];
+
+    let base = if infile.is_dir() {
+        walk(infile, Path::new(""), &mut found)?;
+        infile.to_path_buf()
     } else {
-        let file = read(args.infile)?;
-        let mut cursor = Cursor::new(&file);
+        let pattern = infile
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default();
+
+        if !pattern.contains(['*', '?']) {
+            return Ok(Inputs::Single(Input::File(infile.into())));
+        }
 
-        let omni = Omni::parse(&mut cursor)?;
+        let base = match infile.parent() {
+            Some(p) if p != Path::new("") => p.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
 
-        if let Some(path) = args.dump_ast {
-            write(
-                path,
-                format!(
-                    "{:#?}\n\n({}) {:X?}\n\n{:#?}",
-                    omni.header,
-                    omni.offsets.objects.len(),
-                    omni.offsets,
-                    omni.streams
-                ),
-            )?;
+        for entry in read_dir(&base)? {
+            let entry = entry?;
+
+            if entry.file_type()?.is_file()
+                && wildcard_match(&pattern, &entry.file_name().to_string_lossy())
+            {
+                found.push(entry.file_name().into());
+            }
+        }
+
+        base
+    };
+
+    found.sort();
+
+    Ok(Inputs::Batch(
+        found
+            .into_iter()
+            .map(|rel| (rel.clone(), Input::File(base.join(rel))))
+            .collect(),
+    ))
+}
+
+/// Picks out the files in a disc image matching `pattern`, which is matched
+/// against the whole path inside the image.
+fn find_iso_inputs(image: &Path, pattern: &str) -> Result<Inputs> {
+    let entries = iso::list_files(&mut omni::open(image)?)?;
+    let pattern = pattern.trim_start_matches(['\\', '/']).replace('\\', "/");
+
+    let mut found = entries
+        .into_iter()
+        .filter(|e| wildcard_match(&pattern, &e.path))
+        .map(|entry| {
+            (
+                safe_relative_path(&entry.path),
+                Input::Iso {
+                    image: image.into(),
+                    entry,
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if pattern.contains(['*', '?']) {
+        return Ok(Inputs::Batch(found));
+    }
+
+    match found.pop() {
+        Some((_, input)) => Ok(Inputs::Single(input)),
+        None => Err(anyhow!("\"{pattern}\" not found in {}", image.display())),
+    }
+}
+
+/// Picks out the Omni files in a zip file, or the ones matching `pattern`
+/// if there is one, which is matched against the whole path inside it.
+fn find_zip_inputs(zip: &Path, pattern: Option<&str>) -> Result<Inputs> {
+    let entries = archive::list_entries(&read(zip)?)?;
+    let pattern = pattern.map(|p| p.trim_start_matches(['\\', '/']).replace('\\', "/"));
+
+    let mut found = entries
+        .into_iter()
+        .filter(|e| match &pattern {
+            Some(pattern) => wildcard_match(pattern, &e.path),
+            None => has_extension(Path::new(&e.path), &OMNI_EXTENSIONS),
+        })
+        .map(|entry| {
+            (
+                safe_relative_path(&entry.path),
+                Input::Zip {
+                    archive: zip.into(),
+                    entry,
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let several = match &pattern {
+        Some(pattern) => pattern.contains(['*', '?']),
+        None => found.len() != 1,
+    };
+
+    if several && !found.is_empty() {
+        return Ok(Inputs::Batch(found));
+    }
+
+    match found.pop() {
+        Some((_, input)) => Ok(Inputs::Single(input)),
+        None => Err(anyhow!(
+            "\"{}\" not found in {}",
+            pattern.as_deref().unwrap_or("*.si"),
+            zip.display()
+        )),
+    }
+}
+
+/// Unpacks the Omni file in a gzip or zip file. A zip file has to have just
+/// one, unless --entry picks one out.
+fn unpack(args: &Args, path: &Path) -> Result<Vec<u8>> {
+    if has_extension(path, &["gz"]) {
+        return Ok(archive::gunzip(&read(path)?)?);
+    }
+
+    match find_zip_inputs(path, args.entry.as_deref())? {
+        Inputs::Single(Input::Zip { archive, entry }) => Ok(entry.read(&read(archive)?)?),
+        _ => Err(anyhow!(
+            "{} has several Omni files in it (pick one with --entry)",
+            path.display()
+        )),
+    }
+}
+
+fn scan(args: &Args) -> Result<()> {
+    let found = omni::scan(&mut omni::open(args.infile()?)?)?;
+
+    for embedded in &found {
+        println!(
+            "{:#010X} ({})",
+            embedded.offset,
+            human_bytes(embedded.len as f64)
+        );
+    }
+
+    if found.is_empty() {
+        return Err(anyhow!("No Omni files found"));
+    }
+
+    Ok(())
+}
+
+fn list_iso(image: &Path) -> Result<()> {
+    for entry in iso::list_files(&mut omni::open(image)?)? {
+        if has_extension(Path::new(&entry.path), &OMNI_EXTENSIONS) {
+            println!("{} ({})", entry.path, human_bytes(entry.len as f64));
+        }
+    }
+
+    Ok(())
+}
+
+/// A progress bar on stderr for a long operation, or none with --quiet.
+/// It's only drawn if stderr is a terminal.
+fn progress_bar(args: &Args, what: &'static str) -> Option<ProgressBar> {
+    if args.quiet {
+        return None;
+    }
+
+    let bar = ProgressBar::new(0).with_prefix(what);
+    bar.set_style(
+        ProgressStyle::with_template(
+            "{prefix} [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta}) {wide_msg}",
+        )
+        .unwrap()
+        .progress_chars("=> "),
+    );
+
+    Some(bar)
+}
+
+/// A hook that moves `bar` along, showing the object being worked on.
+fn progress_hook(bar: &ProgressBar) -> ProgressHook {
+    let bar = bar.clone();
+
+    ProgressHook::new(move |done, total, object: &str| {
+        // compiling doesn't know how much there is to do
+        if total != 0 {
+            bar.set_length(total);
+        } else if bar.length() != Some(done) {
+            bar.set_length(done);
+        }
+
+        bar.set_position(done);
+        bar.set_message(object.to_string());
+    })
+}
+
+/// Prints a line to stdout without it getting mixed up with `bar`.
+fn print_above(bar: &Option<ProgressBar>, line: String) {
+    match bar {
+        Some(bar) => bar.suspend(|| println!("{line}")),
+        None => println!("{line}"),
+    }
+}
+
+/// SHA-256 of `data`, in hex.
+fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Escapes a path for a Makefile rule.
+fn make_escape(path: &Path) -> String {
+    path.display().to_string().replace(' ', "\\ ")
+}
+
+fn compile(args: &Args) -> Result<()> {
+    compile_script(
+        args,
+        args.infile()?,
+        args.outfile()?,
+        args.resources.as_deref(),
+        args.manifest.as_deref(),
+    )
+}
+
+fn compile_script(
+    args: &Args,
+    script: &Path,
+    outfile: &Path,
+    resources: Option<&Path>,
+    manifest: Option<&Path>,
+) -> Result<()> {
+    let mut text = read_script(args, script)?;
+
+    // unused blocks are stored at the top level unless they're stripped
+    let reported = |l: &&Lint| match l.rule {
+        "handler-class" | "sound-3d" => true,
+        "unused-block" => !args.strip_unused,
+        _ => false,
+    };
+
+    DIAGNOSTICS.extend(text.lint().iter().filter(reported));
+
+    if args.strip_unused {
+        for block in text.unused() {
+            DIAGNOSTICS.note(
+                block.span.clone().map(Location::Script),
+                format_args!("{} left out, as nothing uses it", block.name),
+            );
         }
+    }
+
+    for (from, to) in &args.rename {
+        text.rename(from, to)?;
+    }
+
+    text.assign_ids()?;
+
+    if let Some(path) = &args.dump_ast {
+        write(path, format!("{:#?}", text))?;
+    }
 
-        let text = Text::from_omni(&omni)?;
+    let mut options = CompileOptions {
+        resources: resources.map(Path::to_path_buf),
+        buffer_size: args.buffer_size,
+        buffer_count: args.buffer_count,
+        target_version: args.target_version,
+        keep_audio: args.keep_audio,
+        strip_unused: args.strip_unused,
+        strict: args.strict,
+        pads: args.pads(),
+        prefix: args.prefix(),
+        endian: args.endian(),
+        ..Default::default()
+    };
+
+    if let Some(manifest) = manifest {
+        options.load_manifest(manifest)?;
+    }
+
+    let bar = progress_bar(args, "Compiling");
+    options.progress = bar.as_ref().map(progress_hook);
+
+    let compiled = Omni::compile(&text, &options)?;
+    let (omni, resources) = (compiled.omni, compiled.resources);
+
+    if let Some(bar) = bar {
+        bar.finish_and_clear();
+    }
+
+    for converted in &compiled.conversions {
+        match args.format {
+            OutputFormat::Text => println!(
+                "{}: converted {}, stored as {}",
+                converted.file_name, converted.conversion, converted.stored_name
+            ),
+            OutputFormat::Json => println!(
+                "{}",
+                json!({
+                    "converted": converted.file_name,
+                    "storedAs": converted.stored_name,
+                    "codec": converted.conversion.codec,
+                    "from": converted.conversion.from,
+                    "to": converted.conversion.to,
+                })
+            ),
+        }
+    }
+
+    DIAGNOSTICS.extend(compiled.warnings.clone());
+    DIAGNOSTICS.extend(&compiled.problems);
+
+    if args.dry_run {
+        return dry_run(args, &omni);
+    }
+
+    let mut cursor = Cursor::new(vec![]);
+    omni.write(&mut cursor)?;
+
+    write(outfile, cursor.into_inner())?;
+
+    if let Some(depfile) = &args.depfile {
+        let deps = text
+            .sources()
+            .iter()
+            .map(PathBuf::as_path)
+            .chain(manifest)
+            .chain(resources.iter().map(PathBuf::as_path))
+            .map(make_escape)
+            .collect::<Vec<_>>();
+
+        write(
+            depfile,
+            format!("{}: {}\n", make_escape(outfile), deps.join(" \\\n  ")),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The script that extracting with --with-script writes into `folder`.
+fn rebuild_script(folder: &Path) -> PathBuf {
+    let name = std::path::absolute(folder)
+        .ok()
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "script".into());
+
+    folder.join(format!("{name}.ss"))
+}
+
+fn rebuild(args: &Args, folder: &Path) -> Result<()> {
+    let script = match &args.infile {
+        Some(script) => script.clone(),
+        None => {
+            let script = rebuild_script(folder);
+
+            // the folder could have been renamed since
+            let found = match script.is_file() {
+                true => vec![script],
+                false => read_dir(folder)?
+                    .filter_map(|e| e.ok().map(|e| e.path()))
+                    .filter(|p| p.is_file() && has_extension(p, &["ss"]))
+                    .collect(),
+            };
+
+            match <[_; 1]>::try_from(found) {
+                Ok([script]) => script,
+                Err(found) if found.is_empty() => {
+                    return Err(anyhow!(
+                        "No script in {} (give one with --infile)",
+                        folder.display()
+                    ))
+                }
+                Err(_) => {
+                    return Err(anyhow!(
+                        "More than one script in {} (pick one with --infile)",
+                        folder.display()
+                    ))
+                }
+            }
+        }
+    };
+
+    let manifest = match &args.manifest {
+        Some(manifest) => Some(manifest.clone()),
+        None => Some(folder.join(MANIFEST_NAME)).filter(|m| m.is_file()),
+    };
+
+    let outfile = match &args.outfile {
+        Some(outfile) => outfile.clone(),
+        None => folder.with_extension("si"),
+    };
+
+    compile_script(
+        args,
+        &script,
+        &outfile,
+        Some(args.resources.as_deref().unwrap_or(folder)),
+        manifest.as_deref(),
+    )
+}
+
+/// Counts what's written to it, so that the size of a file can be found
+/// without keeping it.
+#[derive(Default)]
+struct SizeCounter {
+    pos: u64,
+    len: u64,
+}
+
+impl Write for SizeCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.pos += buf.len() as u64;
+        self.len = self.len.max(self.pos);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for SizeCounter {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+        };
+
+        self.pos = new.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Seek to before the start of the stream",
+            )
+        })?;
+
+        Ok(self.pos)
+    }
+}
+
+/// Reports what compiling would write, instead of writing it.
+fn dry_run(args: &Args, omni: &Omni) -> Result<()> {
+    let mut counter = SizeCounter::default();
+    omni.write(&mut counter)?;
+
+    let stats = omni.stats();
+    let buffer_size = stats.buffer_size;
+
+    match args.format {
+        OutputFormat::Text => {
+            println!(
+                "Would write {} ({} bytes)",
+                human_bytes(counter.len as f64),
+                counter.len
+            );
+            print_stats(&stats);
+        }
+        OutputFormat::Json => {
+            let mut value = stats_json(&stats);
+            value["size"] = counter.len.into();
+            println!("{value}");
+        }
+    }
+
+    if buffer_size == 0 {
+        return Ok(());
+    }
+
+    let names = stats
+        .objects
+        .iter()
+        .map(|o| (o.id, o.name.as_str()))
+        .collect::<HashMap<_, _>>();
+
+    for placement in omni.placements()? {
+        let first = placement.offset / buffer_size;
+        let last = (placement.offset + placement.len - 1) / buffer_size;
+
+        if first == last {
+            continue;
+        }
+
+        let object = match names.get(&placement.object) {
+            Some(name) => format!("{name} ({})", placement.object),
+            None => format!("object {}", placement.object),
+        };
+
+        if placement.len > buffer_size {
+            args.warn(
+                None,
+                format!(
+                    "chunk of {object} at {:#X} is {} bytes, more than a {}-byte buffer",
+                    placement.offset, placement.len, buffer_size
+                ),
+            );
+        } else {
+            args.warn(
+                None,
+                format!(
+                    "chunk of {object} at {:#X} runs over from buffer #{first} into #{last}",
+                    placement.offset
+                ),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads an Omni file in the byte order given by `--endian`.
+fn load(args: &Args, path: impl AsRef<Path>) -> Result<Omni> {
+    let options = ParseOptions {
+        endian: args.endian(),
+        buffer_size: args.read_buffer_size(),
+        ..Default::default()
+    };
+
+    let path = path.as_ref();
+
+    if has_extension(path, &PACKED_EXTENSIONS) {
+        let data = unpack(args, path)?;
+        return Ok(Omni::parse_shared(data.into(), &options)?.0);
+    }
+
+    Ok(Omni::load_with_options(path, &options)?.0)
+}
+
+fn merge(args: &Args) -> Result<()> {
+    let mut omni = load(args, args.infile()?)?;
+
+    for path in &args.merge {
+        let other = load(args, path)?;
+
+        for (old, new) in omni.merge(other) {
+            println!("{}: object {old} is now {new}", path.display());
+        }
+    }
+
+    omni.interleave_with(&args.pads())?;
+
+    let mut cursor = Cursor::new(vec![]);
+    omni.write(&mut cursor)?;
+
+    write(args.outfile()?, cursor.into_inner())?;
+
+    Ok(())
+}
+
+fn renumber(args: &Args) -> Result<()> {
+    let mut omni = load(args, args.infile()?)?;
+
+    let renumbered = match args.renumber_from {
+        Some(first) => omni.renumber_from(first)?,
+        None => {
+            omni.remap_ids(&args.renumber.iter().copied().collect())?;
+            args.renumber.clone()
+        }
+    };
+
+    for (old, new) in renumbered {
+        println!("object {old} is now {new}");
+    }
+
+    omni.interleave_with(&args.pads())?;
+
+    let mut cursor = Cursor::new(vec![]);
+    omni.write(&mut cursor)?;
+
+    write(args.outfile()?, cursor.into_inner())?;
+
+    Ok(())
+}
+
+fn split(args: &Args) -> Result<()> {
+    let omni = load(args, args.infile()?)?;
+    let folder = args.outfile()?;
+
+    create_dir_all(folder)?;
+
+    let mut used = HashSet::new();
+
+    for (name, omni) in omni.split_with(&args.pads())? {
+        // objects in different files can have the same name, which merged
+        // files keep
+        let safe_name = safe_file_name(&name);
+        let file_name = (1..)
+            .map(|i| match i {
+                1 => format!("{safe_name}.si"),
+                _ => format!("{safe_name}_{i}.si"),
+            })
+            .find(|f| used.insert(f.to_lowercase()))
+            .unwrap();
+        let path = folder.join(file_name);
+
+        let mut cursor = Cursor::new(vec![]);
+        omni.write(&mut cursor)?;
+        write(&path, cursor.into_inner())?;
+
+        println!("{name} -> {}", path.display());
+    }
+
+    Ok(())
+}
+
+fn make_patch(args: &Args, modified: &Path) -> Result<()> {
+    let original = load(args, args.infile()?)?;
+    let modified = load(args, modified)?;
+
+    let patch = Patch::make(&original, &modified)?;
+
+    for id in patch.changed() {
+        println!("object {id} added or changed");
+    }
+
+    let mut cursor = Cursor::new(vec![]);
+    patch.write(&mut cursor)?;
+
+    write(args.outfile()?, cursor.into_inner())?;
+
+    Ok(())
+}
+
+fn apply_patch(args: &Args, patch: &Path) -> Result<()> {
+    let original = load(args, args.infile()?)?;
+    let patch = Patch::read(&mut omni::open(patch)?)?;
+
+    let mut omni = patch.apply(&original)?;
+    omni.interleave_with(&args.pads())?;
+
+    let mut cursor = Cursor::new(vec![]);
+    omni.write(&mut cursor)?;
+
+    write(args.outfile()?, cursor.into_inner())?;
+
+    Ok(())
+}
+
+fn replace(args: &Args, snippet: &Path) -> Result<()> {
+    let mut omni = load(args, args.infile()?)?;
+    let mut text = Text::from_omni(&omni, &DIAGNOSTICS)?;
+
+    let blocks = Text::parse_snippet_file(snippet)?;
+    let names = blocks.iter().map(|b| b.name.clone()).collect::<Vec<_>>();
+    let ids = text.replace_blocks(blocks)?;
+
+    let mut options = CompileOptions {
+        resources: args.resources.clone(),
+        keep_audio: args.keep_audio,
+        prefix: args.prefix(),
+        strict: args.strict,
+        ..Default::default()
+    };
+
+    if let Some(manifest) = &args.manifest {
+        options.load_manifest(manifest)?;
+    }
+
+    DIAGNOSTICS.extend(&omni.recompile(&text, &ids, &options)?);
+
+    for (name, id) in names.iter().zip(&ids) {
+        println!("{name} ({id}) replaced");
+    }
+
+    omni.interleave_with(&args.pads())?;
+
+    let mut cursor = Cursor::new(vec![]);
+    omni.write(&mut cursor)?;
+
+    write(args.outfile()?, cursor.into_inner())?;
+
+    Ok(())
+}
+
+fn rewrite(args: &Args) -> Result<()> {
+    let mut omni = load(args, args.infile()?)?;
+
+    for (from, to) in &args.rename {
+        let id = omni.rename_object(from, to)?;
+        println!("{from} ({id}) -> {to}");
+    }
+
+    let rules = args
+        .rewrite_prefix
+        .iter()
+        .map(|(from, to)| PathRewrite::Prefix(from.clone(), to.clone()))
+        .chain(
+            args.rewrite_regex
+                .iter()
+                .map(|(regex, to)| PathRewrite::Regex(regex.clone(), to.clone())),
+        )
+        .collect::<Vec<_>>();
+
+    for change in omni.rewrite_paths(&rules)? {
+        println!(
+            "{} ({}): {} -> {}",
+            change.name, change.id, change.from, change.to
+        );
+    }
+
+    if args.dry_run {
+        return Ok(());
+    }
+
+    omni.interleave_with(&args.pads())?;
+
+    let mut cursor = Cursor::new(vec![]);
+    omni.write(&mut cursor)?;
+
+    write(args.outfile()?, cursor.into_inner())?;
+
+    Ok(())
+}
+
+/// Longest a cue is shown for, in milliseconds, when exporting events.
+const CUE_LENGTH: u32 = 2000;
+
+fn srt_time(ms: u32) -> String {
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+fn lint(args: &Args) -> Result<()> {
+    let text = read_script(args, args.infile()?)?;
+    let lints = text.lint();
+
+    let errors = lints
+        .iter()
+        .filter(|l| l.severity == Severity::Error)
+        .count();
+    let warnings = lints.len() - errors;
+
+    match args.format {
+        OutputFormat::Text => {
+            for lint in &lints {
+                println!("{lint}");
+            }
+
+            println!("{errors} error(s), {warnings} warning(s)");
+        }
+        OutputFormat::Json => println!(
+            "{}",
+            json!({
+                "lints": lints.iter().map(lint_json).collect::<Vec<_>>(),
+                "errors": errors,
+                "warnings": warnings,
+            })
+        ),
+    }
+
+    if warnings > 0 {
+        WARNED.store(true, Ordering::Relaxed);
+    }
+
+    if errors > 0 {
+        return Err(Failed::Lint(args.infile()?.display().to_string()).into());
+    }
+
+    Ok(())
+}
+
+fn lint_json(lint: &Lint) -> Value {
+    json!({
+        "rule": lint.rule,
+        "severity": lint.severity.to_string(),
+        "block": lint.block,
+        "message": lint.message,
+        "file": lint.span.as_ref().and_then(|s| s.file.as_ref()).map(|f| f.display().to_string()),
+        "line": lint.span.as_ref().map(|s| s.line + 1),
+        "column": lint.span.as_ref().map(|s| s.column + 1),
+    })
+}
+
+fn print_query(args: &Args, text: &Text, query: &Query) -> Result<()> {
+    for found in text.query(query) {
+        match args.format {
+            OutputFormat::Text => println!("{}", found.value),
+            OutputFormat::Json => println!(
+                "{}",
+                json!({
+                    "block": found.block,
+                    "value": found.value,
+                })
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+fn preprocess(args: &Args) -> Result<()> {
+    let mut preprocessor = Preprocessor::new();
+    let preprocessed = preprocessor.preprocess_file(args.infile()?)?;
+
+    for warning in preprocessor.warnings() {
+        args.warn(None, warning);
+    }
+
+    let marked = preprocessor.mark_lines(&preprocessed);
+
+    match &args.outfile {
+        Some(path) => write(path, marked)?,
+        None => print!("{marked}"),
+    }
+
+    Ok(())
+}
+
+fn format(args: &Args) -> Result<()> {
+    let infile = args.infile()?;
+    let outfile = match (&args.outfile, args.in_place) {
+        (Some(outfile), _) => outfile.as_path(),
+        (None, true) => infile,
+        (None, false) => {
+            return Err(anyhow!(
+                "No output file (give one with --outfile, or use --in-place)"
+            ))
+        }
+    };
+
+    let text = read_script(args, infile)?;
+
+    // the formatted script is made from what's left once they're expanded
+    if text.stripped() && !has_extension(outfile, &["json"]) {
+        return Err(anyhow!(
+            "{} has comments, #defines or #includes, which formatting would lose",
+            infile.display()
+        ));
+    }
+
+    let options = FormatOptions {
+        indent: args.indent,
+        order: args.order,
+        crlf: args.crlf,
+        annotate: false,
+        annotate_unknown: false,
+    };
+
+    write_script(args, outfile, &text, &options)?;
+
+    Ok(())
+}
+
+/// Reads a script, or its syntax tree as JSON if the file ends in .json.
+fn read_script(args: &Args, path: &Path) -> Result<Text> {
+    let text = match has_extension(path, &["json"]) {
+        true => serde_json::from_str(&read_to_string(path)?)?,
+        false => Text::parse_file(path)?,
+    };
+
+    for warning in text.preprocess_warnings() {
+        args.warn(None, warning);
+    }
+
+    Ok(text)
+}
+
+/// Writes a script, or its syntax tree as JSON if the file ends in .json,
+/// gzipped with --gzip or if it ends in .gz.
+fn write_script(args: &Args, path: &Path, text: &Text, options: &FormatOptions) -> Result<()> {
+    let gzip = args.gzip || has_extension(path, &["gz"]);
+    // so that NAME.json.gz is still JSON
+    let inner = match has_extension(path, &["gz"]) {
+        true => Path::new(path.file_stem().unwrap_or_default()),
+        false => path,
+    };
+
+    let script = match has_extension(inner, &["json"]) {
+        true => serde_json::to_string_pretty(text)?,
+        false => text.format(options),
+    };
+
+    match gzip {
+        true => write(path, archive::gzip(script.as_bytes())?)?,
+        false => write(path, script)?,
+    }
+
+    Ok(())
+}
+
+fn print_defaults(args: &Args) {
+    for &block_type in schema::OBJECTS {
+        let defaults = schema::defaults(block_type);
+
+        match args.format {
+            OutputFormat::Text => {
+                println!("{block_type} {{");
+                for (name, value) in defaults {
+                    println!("\t{name} = {value};");
+                }
+                println!("}}");
+            }
+            OutputFormat::Json => println!(
+                "{}",
+                json!({
+                    "type": block_type.to_string(),
+                    "defaults": defaults
+                        .into_iter()
+                        .map(|(name, value)| (name.to_string(), json!(value)))
+                        .collect::<serde_json::Map<_, _>>(),
+                })
+            ),
+        }
+    }
+}
+
+fn print_objects(args: &Args, objects: &[ObjectInfo]) {
+    // children come after their parents
+    let mut depths = HashMap::new();
+
+    for object in objects {
+        match args.format {
+            OutputFormat::Text => {
+                let depth = object
+                    .parent
+                    .and_then(|p| depths.get(&p))
+                    .map_or(0, |d| d + 1);
+                depths.insert(object.id, depth);
+
+                let file = object
+                    .file_name
+                    .as_ref()
+                    .map_or(String::new(), |f| format!(" ({f})"));
+                println!(
+                    "{:>5}  {}{} {}{file}",
+                    object.id,
+                    "  ".repeat(depth),
+                    object.kind,
+                    object.name
+                );
+            }
+            OutputFormat::Json => println!(
+                "{}",
+                json!({
+                    "id": object.id,
+                    "parent": object.parent,
+                    "type": object.kind,
+                    "name": object.name,
+                    "fileName": object.file_name,
+                })
+            ),
+        }
+    }
+}
+
+fn stats_json(stats: &Stats) -> Value {
+    json!({
+        "bufferSize": stats.buffer_size,
+        "buffers": stats.buffers,
+        "objects": stats.objects.iter().map(|o| json!({
+            "id": o.id,
+            "type": o.kind,
+            "name": o.name,
+            "orphaned": o.kind.is_empty(),
+            "chunks": o.chunks,
+            "bytes": o.bytes,
+            "firstTime": o.first_time,
+            "lastTime": o.last_time,
+            "averageRate": o.average_rate(),
+            "peakRate": o.peak_rate,
+        })).collect::<Vec<_>>(),
+    })
+}
+
+fn print_stats(stats: &Stats) {
+    if let Some(buffers) = &stats.buffers {
+        let used = |b: u64| b as f64 * 100.0 / stats.buffer_size as f64;
+
+        let average = buffers.iter().sum::<u64>() as f64 * 100.0
+            / (buffers.len() as u64 * stats.buffer_size).max(1) as f64;
+
+        print!(
+            "{} buffers of {}, {average:.1}% chunk data",
+            buffers.len(),
+            human_bytes(stats.buffer_size as f64)
+        );
+
+        // the last buffer is only as full as the file is long
+        match buffers
+            .iter()
+            .enumerate()
+            .take(buffers.len().saturating_sub(1))
+            .min_by_key(|&(_, &b)| b)
+        {
+            Some((i, &b)) => println!(", emptiest is #{i} at {:.1}%", used(b)),
+            None => println!(),
+        }
+    }
+
+    println!(
+        "\n{:>6}  {:<10} {:<24} {:>7} {:>11} {:>10} {:>11} {:>11}",
+        "ID", "Type", "Name", "Chunks", "Bytes", "Duration", "Average/s", "Peak/s"
+    );
+
+    for object in &stats.objects {
+        println!(
+            "{:>6}  {:<10} {:<24} {:>7} {:>11} {:>9.3}s {:>11} {:>11}",
+            object.id,
+            if object.kind.is_empty() {
+                "orphaned"
+            } else {
+                object.kind
+            },
+            object.name,
+            object.chunks,
+            human_bytes(object.bytes as f64),
+            object.duration() as f64 / 1000.0,
+            object.average_rate().map_or("-".into(), human_bytes),
+            human_bytes(object.peak_rate as f64)
+        );
+    }
+}
+
+/// Decompiles `input`, or extracts its stored files, writes its timeline,
+/// event cues, object graph or report, or prints its stats or what a query
+/// picks out if asked to.
+fn decompile(
+    args: &Args,
+    input: &Input,
+    outfile: Option<&Path>,
+    dump_ast: Option<&Path>,
+) -> Result<()> {
+    match input {
+        Input::File(path) => decompile_from(args, || omni::open(path), outfile, dump_ast),
+        Input::Iso { image, entry } => decompile_from(
+            args,
+            || Ok(entry.open(omni::open(image)?)),
+            outfile,
+            dump_ast,
+        ),
+        Input::Embedded { path, offset } => {
+            let len = metadata(path)?.len().saturating_sub(*offset);
+
+            decompile_from(
+                args,
+                || Ok(Window::new(omni::open(path)?, *offset, len)),
+                outfile,
+                dump_ast,
+            )
+        }
+        // unpacked once, then read from memory
+        Input::Zip { archive, entry } => {
+            let data = entry.read(&read(archive)?)?;
+            decompile_from(args, || Ok(Cursor::new(&data[..])), outfile, dump_ast)
+        }
+        Input::Gzip(path) => {
+            let data = archive::gunzip(&read(path)?)?;
+            decompile_from(args, || Ok(Cursor::new(&data[..])), outfile, dump_ast)
+        }
+    }
+}
+
+/// Decompiles or extracts the file that `open` gives a reader for. Each
+/// extraction thread opens its own reader.
+fn decompile_from<R, O>(
+    args: &Args,
+    open: O,
+    outfile: Option<&Path>,
+    dump_ast: Option<&Path>,
+) -> Result<()>
+where
+    R: Read + Seek,
+    O: Fn() -> std::io::Result<R> + Sync,
+{
+    // chunk data is only read when it's extracted, so large files never
+    // have to fit in memory
+    let mut file = open()?;
+
+    // only the objects are needed, which can be picked out without parsing
+    // the whole file
+    if args.list {
+        let options = ParseOptions {
+            endian: args.endian(),
+            buffer_size: args.read_buffer_size(),
+            ..Default::default()
+        };
+        print_objects(args, &Omni::objects_in(file, &options)?);
+        return Ok(());
+    }
+
+    let bar = progress_bar(args, "Reading");
+
+    let options = ParseOptions {
+        lenient: args.lenient,
+        lazy: true,
+        progress: bar.as_ref().map(progress_hook),
+        endian: args.endian(),
+        buffer_size: args.read_buffer_size(),
+    };
+
+    let (segments, warnings) = Omni::parse_segments(&mut file, &options)?;
+
+    if let Some(bar) = bar {
+        bar.finish_and_clear();
+    }
+
+    DIAGNOSTICS.extend(warnings);
+
+    if segments.len() == 1 {
+        return decompile_segment(args, &segments[0], &mut file, &open, outfile, dump_ast);
+    }
+
+    // each segment is as good as its own file, so it gets its own output
+    for (i, omni) in segments.iter().enumerate() {
+        let path = |p: &Path| segment_path(p, i + 1);
+
+        if args.stats || args.checksums {
+            println!("Segment {}:", i + 1);
+        }
+
+        decompile_segment(
+            args,
+            omni,
+            &mut file,
+            &open,
+            outfile.map(path).as_deref(),
+            dump_ast.map(path).as_deref(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// `path` with `.N` put before its extension, for the output of the Nth
+/// RIFF segment of a file that has several.
+fn segment_path(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    path.with_file_name(match path.extension() {
+        Some(ext) => format!("{stem}.{n}.{}", ext.to_string_lossy()),
+        None => format!("{stem}.{n}"),
+    })
+}
+
+/// Decompiles or extracts one RIFF segment of the file that `open` gives a
+/// reader for, with `file` being the reader it was parsed from.
+fn decompile_segment<R, O>(
+    args: &Args,
+    omni: &Omni,
+    file: &mut R,
+    open: &O,
+    outfile: Option<&Path>,
+    dump_ast: Option<&Path>,
+) -> Result<()>
+where
+    R: Read + Seek,
+    O: Fn() -> std::io::Result<R> + Sync,
+{
+    let orphans = omni.orphans();
+
+    if !orphans.is_empty() {
+        let ids = orphans
+            .iter()
+            .map(|r| r.id.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        args.warn(
+            None,
+            format_args!("Data chunks for objects that don't exist: {ids}"),
+        );
+    }
+
+    if let Some(path) = dump_ast {
+        write(
+            path,
+            format!(
+                "{:#?}\n\n({}) {:X?}\n\n{:#?}",
+                omni.header,
+                omni.offsets.objects.len(),
+                omni.offsets,
+                omni.streams
+            ),
+        )?;
+    }
+
+    if args.stats {
+        match args.format {
+            OutputFormat::Text => print_stats(&omni.stats()),
+            OutputFormat::Json => println!("{}", stats_json(&omni.stats())),
+        }
+        return Ok(());
+    }
+
+    if args.checksums {
+        let filter = args.object.clone().unwrap_or_else(ObjectFilter::all);
+        let resources = omni.extract(&filter);
+        let sums = Mutex::new(HashMap::new());
+
+        read_resources(&resources, args.jobs(), open, None, |resource, data| {
+            sums.lock().unwrap().insert(resource.id, sha256(&data));
+            Ok(())
+        })?;
+
+        let sums = sums.into_inner().unwrap();
+
+        for resource in &resources {
+            let sum = &sums[&resource.id];
+
+            match args.format {
+                OutputFormat::Text => println!(
+                    "{sum}  {} ({}) {}",
+                    resource.name, resource.id, resource.file_name
+                ),
+                OutputFormat::Json => println!(
+                    "{}",
+                    json!({
+                        "id": resource.id,
+                        "name": resource.name,
+                        "fileName": resource.file_name,
+                        "sha256": sum,
+                    })
+                ),
+            }
+        }
+
+        return Ok(());
+    }
+
+    if let Some(query) = &args.query {
+        let mut text = Text::from_omni(omni, &DIAGNOSTICS)?;
+
+        if args.keep_defaults {
+            text.add_defaults();
+        }
+        text.set_volume_unit(args.volume_unit);
+
+        return print_query(args, &text, query);
+    }
+
+    if args.snippet {
+        let mut text = Text::from_omni(omni, &DIAGNOSTICS)?;
+
+        for (from, to) in &args.rename {
+            text.rename(from, to)?;
+        }
+
+        if args.keep_defaults {
+            text.add_defaults();
+        }
+        text.set_volume_unit(args.volume_unit);
+
+        let snippet = text.format_snippet(args.object.as_ref().unwrap(), &args.format_options());
+
+        match outfile {
+            Some(path) => write(path, snippet)?,
+            None => print!("{snippet}"),
+        }
+
+        return Ok(());
+    }
+
+    let outfile = outfile.ok_or(anyhow!(NO_OUTFILE))?;
+
+    if args.extract {
+        let filter = args.object.clone().unwrap_or_else(ObjectFilter::all);
+
+        let jobs = args.jobs();
+
+        let bar = progress_bar(args, "Extracting");
+        let hook = bar.as_ref().map(progress_hook);
+        let progress = hook.as_ref().map(|h| &*h.0);
+
+        if args.raw {
+            let (resources, indices): (Vec<_>, HashMap<_, _>) = omni
+                .extract_raw(&filter)
+                .into_iter()
+                .map(|(resource, chunks)| {
+                    let id = resource.id;
+                    (resource, (id, chunks))
+                })
+                .unzip();
+
+            create_dir_all(outfile)?;
+
+            read_resources(&resources, jobs, open, progress, |resource, data| {
+                let path = outfile.join(&resource.file_name);
+
+                let mut index = ["offset", "size", "time", "flags"].join(",") + "\n";
+                for chunk in &indices[&resource.id] {
+                    index += &format!(
+                        "{},{},{},{:#06X}\n",
+                        chunk.offset, chunk.size, chunk.time, chunk.flags
+                    );
+                }
+
+                print_above(
+                    &bar,
+                    format!("{} ({}) -> {}", resource.name, resource.id, path.display()),
+                );
+
+                write(path.with_extension("csv"), index)?;
+                write(path, data)
+            })?;
+        } else {
+            let manifest = match args.with_script {
+                true => Some(
+                    args.manifest
+                        .clone()
+                        .unwrap_or_else(|| outfile.join(MANIFEST_NAME)),
+                ),
+                false => args.manifest.clone(),
+            };
+
+            // only the manifest needs the times, which take a while to work
+            // out for big files
+            let times = match manifest {
+                Some(_) => omni
+                    .stats()
+                    .objects
+                    .into_iter()
+                    .map(|o| (o.id, (o.first_time, o.last_time)))
+                    .collect::<HashMap<_, _>>(),
+                None => HashMap::new(),
+            };
+            let manifest_dir = manifest
+                .as_deref()
+                .and_then(Path::parent)
+                .unwrap_or(Path::new(""));
+            let entries = Mutex::new(vec![]);
+
+            let resources = omni.extract(&filter);
+            let mut paths = ExtractPaths::new(args.naming, args.flatten);
+            let paths = resources
+                .iter()
+                .map(|r| {
+                    let path = paths.path(r.id, &r.name, &r.file_name, args.prefix().as_deref());
+                    (r.id, path)
+                })
+                .collect::<HashMap<_, _>>();
+
+            read_resources(&resources, jobs, open, progress, |resource, data| {
+                let (path, renamed) = &paths[&resource.id];
+                let path = outfile.join(path);
+
+                // in the form its codec writes it in
+                let file = codec_for(&data).and_then(|codec| match codec.extract(&data) {
+                    Ok(Cow::Owned(file)) => Some(file),
+                    Ok(Cow::Borrowed(_)) => None,
+                    Err(e) => {
+                        args.warn(
+                            Some(&path.display()),
+                            format_args!("Couldn't convert, so left as it is: {e}"),
+                        );
+                        None
+                    }
+                });
+                let data = file.unwrap_or(data);
+
+                let (path, data) = match args.audio_format {
+                    Some(format) if has_extension(&path, &["wav"]) => {
+                        match audio::transcode(&data, format) {
+                            Ok(converted) => (path.with_extension(format.extension()), converted),
+                            Err(e) => {
+                                args.warn(
+                                    Some(&path.display()),
+                                    format_args!("Couldn't convert, so left as it is: {e}"),
+                                );
+                                (path, data)
+                            }
+                        }
+                    }
+                    _ => (path, data),
+                };
+
+                if let Some(parent) = path.parent() {
+                    create_dir_all(parent)?;
+                }
+
+                print_above(
+                    &bar,
+                    format!("{} ({}) -> {}", resource.name, resource.id, path.display()),
+                );
+
+                if manifest.is_some() {
+                    // paths are taken from the manifest's folder when
+                    // compiling
+                    let listed = match path.strip_prefix(manifest_dir) {
+                        Ok(rel) => rel.to_path_buf(),
+                        Err(_) => std::path::absolute(&path)?,
+                    };
+                    let (first_time, last_time) =
+                        times.get(&resource.id).copied().unwrap_or_default();
+
+                    entries.lock().unwrap().push(json!({
+                        "id": resource.id,
+                        "name": resource.name,
+                        "fileName": resource.file_name,
+                        "path": listed.to_string_lossy().replace('\\', "/"),
+                        "renamed": renamed,
+                        "size": data.len(),
+                        "sha256": sha256(&data),
+                        "firstTime": first_time,
+                        "lastTime": last_time,
+                    }));
+                }
+
+                if args.thumbnails {
+                    let mut thumbnail = path.clone().into_os_string();
+                    thumbnail.push(".png");
+
+                    match image::thumbnail(&data) {
+                        Ok(png) => write(thumbnail, png)?,
+                        // not a still or a video
+                        Err(ImageError::Unknown) => {}
+                        Err(e) => args.warn(
+                            Some(&path.display()),
+                            format_args!("Couldn't make a thumbnail: {e}"),
+                        ),
+                    }
+                }
+
+                write(path, data)
+            })?;
+
+            if let Some(path) = &manifest {
+                let mut resources = entries.into_inner().unwrap();
+                resources.sort_by_key(|r| r["id"].as_u64());
+
+                write(
+                    path,
+                    serde_json::to_string_pretty(&json!({ "resources": resources }))? + "\n",
+                )?;
+            }
+
+            if args.with_script {
+                let mut text = Text::from_omni(omni, &DIAGNOSTICS)?;
+
+                for (from, to) in &args.rename {
+                    text.rename(from, to)?;
+                }
+
+                if let Some(filter) = &args.object {
+                    text.select(filter);
+                }
+
+                if args.keep_defaults {
+                    text.add_defaults();
+                }
+                text.set_volume_unit(args.volume_unit);
+
+                // scripts can't be read back gzipped
+                write(rebuild_script(outfile), text.format(&args.format_options()))?;
+            }
+        }
+
+        if let Some(folder) = &args.orphans {
+            create_dir_all(folder)?;
+
+            read_resources(&orphans, jobs, open, progress, |resource, data| {
+                let path = folder.join(&resource.file_name);
+                print_above(
+                    &bar,
+                    format!("orphaned ({}) -> {}", resource.id, path.display()),
+                );
+                write(path, data)
+            })?;
+        }
+
+        if let Some(bar) = bar {
+            bar.finish_and_clear();
+        }
+    } else if args.timeline {
+        let filter = args.object.clone().unwrap_or_else(ObjectFilter::all);
+        let separator = if has_extension(outfile, &["tsv"]) {
+            "\t"
+        } else {
+            ","
+        };
+
+        let mut table =
+            ["object", "name", "time", "size", "flags", "end", "split"].join(separator) + "\n";
+
+        for entry in omni.timeline() {
+            if !filter.matches(entry.object, &entry.name) {
+                continue;
+            }
+
+            // names are the only field that can have a separator in them
+            let name = if entry.name.contains([',', '\t', '"', '\n']) {
+                format!("\"{}\"", entry.name.replace('"', "\"\""))
+            } else {
+                entry.name
+            };
+
+            table += &[
+                entry.object.to_string(),
+                name,
+                entry.time.to_string(),
+                entry.size.to_string(),
+                format!("{:#06X}", entry.flags),
+                entry.end.to_string(),
+                entry.split.to_string(),
+            ]
+            .join(separator);
+            table.push('\n');
+        }
+
+        write(outfile, table)?;
+    } else if args.report {
+        let title = outfile.file_stem().unwrap_or_default().to_string_lossy();
+        write(outfile, omni.report(file, &title, args.thumbnails)?)?;
+    } else if args.cues {
+        let filter = args.object.clone().unwrap_or_else(ObjectFilter::all);
+
+        let events = omni
+            .timeline()
+            .into_iter()
+            .filter(|e| e.kind == "event" && !e.data.is_empty())
+            .filter(|e| filter.matches(e.object, &e.name))
+            .collect::<Vec<_>>();
+
+        let mut cues = vec![];
+
+        for (i, event) in events.iter().enumerate() {
+            // a cue lasts until the next one starts, but not forever
+            let end = events[i + 1..]
+                .iter()
+                .map(|e| e.time)
+                .find(|&t| t > event.time)
+                .map_or(event.time + CUE_LENGTH, |t| t.min(event.time + CUE_LENGTH));
+
+            let data = event.data.load(file)?;
+            let text = String::from_utf8_lossy(&data);
+            let text = text.trim_end_matches('\0').trim();
+
+            cues.push((
+                event.time,
+                end,
+                if text.is_empty() {
+                    event.name.clone()
+                } else {
+                    text.to_string()
+                },
+            ));
+        }
+
+        let out = if has_extension(outfile, &["srt"]) {
+            cues.iter()
+                .enumerate()
+                .map(|(i, (start, end, text))| {
+                    format!(
+                        "{}\n{} --> {}\n{}\n\n",
+                        i + 1,
+                        srt_time(*start),
+                        srt_time(*end),
+                        text
+                    )
+                })
+                .collect::<String>()
+        } else {
+            // Audacity labels can't hold newlines or tabs
+            cues.iter()
+                .map(|(start, end, text)| {
+                    format!(
+                        "{:.3}\t{:.3}\t{}\n",
+                        *start as f64 / 1000.0,
+                        *end as f64 / 1000.0,
+                        text.replace(['\t', '\n', '\r'], " ")
+                    )
+                })
+                .collect::<String>()
+        };
+
+        write(outfile, out)?;
+    } else {
+        let mut text = Text::from_omni(omni, &DIAGNOSTICS)?;
+
+        for (from, to) in &args.rename {
+            text.rename(from, to)?;
+        }
+
+        if let Some(filter) = &args.object {
+            text.select(filter);
+        }
+
+        if args.keep_defaults {
+            text.add_defaults();
+        }
+        text.set_volume_unit(args.volume_unit);
+
+        if args.dot {
+            write(outfile, text.to_dot())?;
+        } else {
+            let options = args.format_options();
+
+            match &args.split_output {
+                Some(dir) => {
+                    // includes are found relative to the file they're in
+                    let include_dir = match outfile.parent().map(|p| dir.strip_prefix(p)) {
+                        Some(Ok(rel)) => rel,
+                        _ => dir,
+                    };
+                    let include_dir = match include_dir.to_string_lossy().replace('\\', "/") {
+                        d if d.is_empty() || d.ends_with('/') => d,
+                        d => d + "/",
+                    };
+
+                    let split = text.format_split(&options, &include_dir);
+
+                    create_dir_all(dir)?;
+
+                    for (name, contents) in split.files {
+                        write(dir.join(name), contents)?;
+                    }
+
+                    write(outfile, split.master)?;
+                }
+                None => write_script(args, outfile, &text, &options)?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let result = omni::with_encoding(args.encoding, || run(&args));
+    args.print_diagnostics();
+
+    match result {
+        Ok(()) if WARNED.load(Ordering::Relaxed) => ExitCode::from(Exit::Warnings as u8),
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            args.error(None, &e);
+            ExitCode::from(Exit::of(&e) as u8)
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<()> {
+    #[cfg(feature = "lsp")]
+    if args.lsp {
+        return gwydd::lsp::run();
+    }
+
+    if args.defaults {
+        print_defaults(args);
+        return Ok(());
+    }
+
+    if args.compile {
+        return compile(args);
+    }
+
+    if let Some(folder) = &args.rebuild {
+        return rebuild(args, folder);
+    }
+
+    if args.fmt {
+        return format(args);
+    }
+
+    if args.lint {
+        return lint(args);
+    }
+
+    if args.preprocess {
+        return preprocess(args);
+    }
+
+    if !args.merge.is_empty() {
+        return merge(args);
+    }
+
+    if !args.renumber.is_empty() || args.renumber_from.is_some() {
+        return renumber(args);
+    }
+
+    if args.split {
+        return split(args);
+    }
+
+    if let Some(modified) = &args.make_patch {
+        return make_patch(args, modified);
+    }
+
+    if let Some(patch) = &args.apply_patch {
+        return apply_patch(args, patch);
+    }
+
+    if let Some(snippet) = &args.replace {
+        return replace(args, snippet);
+    }
+
+    let omni_to_omni = !args.rename.is_empty()
+        && [&args.infile, &args.outfile].iter().all(|p| {
+            p.as_deref()
+                .is_some_and(|p| has_extension(p, &OMNI_EXTENSIONS))
+        });
+
+    if !args.rewrite_prefix.is_empty() || !args.rewrite_regex.is_empty() || omni_to_omni {
+        return rewrite(args);
+    }
+
+    if args.scan {
+        return scan(args);
+    }
+
+    let infile = args.infile()?;
+
+    if let Some(query) = &args.query {
+        // anything that isn't an Omni file, disc image or compressed file
+        // is a script
+        let is_script = infile.is_file()
+            && args.offset.is_none()
+            && !has_extension(infile, &OMNI_EXTENSIONS)
+            && !has_extension(infile, &PACKED_EXTENSIONS)
+            && !has_extension(infile, &["iso"]);
+
+        if is_script {
+            return print_query(args, &read_script(args, infile)?, query);
+        }
+    }
+
+    let inputs = if let Some(offset) = args.offset {
+        Inputs::Single(Input::Embedded {
+            path: infile.into(),
+            offset,
+        })
+    } else if has_extension(infile, &["iso"]) {
+        let Some(pattern) = &args.entry else {
+            return list_iso(infile);
+        };
+
+        find_iso_inputs(infile, pattern)?
+    } else if has_extension(infile, &["zip"]) {
+        find_zip_inputs(infile, args.entry.as_deref())?
+    } else if args.entry.is_some() {
+        return Err(anyhow!("--entry only works with disc images and zip files"));
+    } else if has_extension(infile, &["gz"]) {
+        Inputs::Single(Input::Gzip(infile.into()))
+    } else {
+        find_inputs(infile)?
+    };
+
+    let inputs = match inputs {
+        Inputs::Single(input) => {
+            return decompile(
+                args,
+                &input,
+                args.outfile.as_deref(),
+                args.dump_ast.as_deref(),
+            )
+        }
+        Inputs::Batch(inputs) => inputs,
+    };
+
+    if args.dump_ast.is_some() {
+        return Err(anyhow!("--dump-ast only works on a single file"));
+    }
+
+    if args.split_output.is_some() {
+        return Err(anyhow!("--split-output only works on a single file"));
+    }
+
+    // one bad file shouldn't stop the rest of an install from being done
+    let mut failed = 0;
+    // what the first failure was, which the whole batch exits with
+    let mut exit = None;
+
+    for (rel, input) in &inputs {
+        if args.list || args.stats || args.checksums || args.query.is_some() {
+            if !args.json() {
+                println!("{input}:");
+            }
+
+            if let Err(e) = decompile(args, input, None, None) {
+                args.error(Some(input), &e);
+                exit = exit.or(Some(Exit::of(&e)));
+                failed += 1;
+            }
+
+            continue;
+        }
+
+        // scripts go next to where their file would be, and stored files
+        // into a folder named after it
+        let outfile = if args.extract {
+            args.outfile()?.join(rel.with_extension(""))
+        } else if args.timeline {
+            args.outfile()?.join(rel.with_extension("csv"))
+        } else if args.cues {
+            args.outfile()?.join(rel.with_extension("srt"))
+        } else if args.dot {
+            args.outfile()?.join(rel.with_extension("dot"))
+        } else if args.report {
+            args.outfile()?.join(rel.with_extension("html"))
+        } else {
+            args.outfile()?.join(rel.with_extension("ss"))
+        };
+
+        if let Some(parent) = outfile.parent() {
+            create_dir_all(parent)?;
+        }
+
+        println!("{input} -> {}", outfile.display());
+
+        if let Err(e) = decompile(args, input, Some(&outfile), None) {
+            args.error(Some(input), &e);
+            exit = exit.or(Some(Exit::of(&e)));
+            failed += 1;
+        }
+    }
 
-        write(args.outfile, text.to_string())?;
+    if failed != 0 {
+        return Err(Failed::Batch(failed, inputs.len(), exit.unwrap_or(Exit::Failure)).into());
     }
 
     Ok(())
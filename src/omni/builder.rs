@@ -0,0 +1,236 @@
+use super::{
+    compile::{CompileError, CompileOptions, Result, DEFAULT_BUFFER_COUNT, DEFAULT_BUFFER_SIZE_KB},
+    Omni,
+};
+use crate::{
+    text::{Block, BlockType, Definition, LoopingMethod, RValue, Statement, Text},
+    types::Vec3,
+};
+use std::{collections::HashSet, path::Path};
+
+/// Optional properties of an object added to an [`OmniBuilder`]. Anything
+/// left as `None` gets the same default as in a script.
+#[derive(Debug, Clone, Default)]
+pub struct ObjectOptions {
+    /// File name stored in the object; defaults to `\` followed by the name
+    /// of the local file
+    pub stored_name: Option<String>,
+    pub handler_class: Option<String>,
+    pub location: Option<Vec3>,
+    pub direction: Option<Vec3>,
+    pub up: Option<Vec3>,
+    pub start_time: Option<i32>,
+    pub duration: Option<i32>,
+    pub loop_count: Option<i32>,
+    pub looping_method: Option<LoopingMethod>,
+    pub volume: Option<i32>,
+    pub extra: Option<String>,
+}
+
+impl ObjectOptions {
+    fn statements(&self) -> Vec<Statement> {
+        let mut statements = vec![];
+        let mut assign = |name: &str, value| {
+            statements.push(Statement::Assignment(name.into(), value));
+        };
+
+        if let Some(s) = &self.handler_class {
+            assign("handlerClass", RValue::String(s.clone()));
+        }
+        if let Some(v) = self.location {
+            assign("location", RValue::Vec3(v));
+        }
+        if let Some(v) = self.direction {
+            assign("direction", RValue::Vec3(v));
+        }
+        if let Some(v) = self.up {
+            assign("up", RValue::Vec3(v));
+        }
+        if let Some(i) = self.start_time {
+            assign("startTime", RValue::Integer(i));
+        }
+        if let Some(i) = self.duration {
+            assign("duration", RValue::Integer(i));
+        }
+        if let Some(i) = self.loop_count {
+            assign("loopCount", RValue::Integer(i));
+        }
+        if let Some(l) = &self.looping_method {
+            assign(
+                "loopingMethod",
+                RValue::Definition(Definition::LoopingMethod(l.clone())),
+            );
+        }
+        if let Some(i) = self.volume {
+            assign("volume", RValue::Integer(i));
+        }
+        if let Some(s) = &self.extra {
+            assign("extra", RValue::String(s.clone()));
+        }
+
+        statements
+    }
+}
+
+/// Puts together an Omni file in code, without going through a script.
+///
+/// Objects that no action refers to get a stream of their own; everything
+/// else is stored inside the action that refers to it.
+pub struct OmniBuilder {
+    buffer_size: i32,
+    buffer_count: i32,
+    blocks: Vec<Block>,
+    options: CompileOptions,
+}
+
+impl Default for OmniBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OmniBuilder {
+    pub fn new() -> Self {
+        Self {
+            buffer_size: DEFAULT_BUFFER_SIZE_KB,
+            buffer_count: DEFAULT_BUFFER_COUNT,
+            blocks: vec![],
+            options: CompileOptions::default(),
+        }
+    }
+
+    /// Sets the buffer size (in KB) and number of buffers.
+    pub fn settings(mut self, buffer_size: i32, buffer_count: i32) -> Self {
+        self.buffer_size = buffer_size;
+        self.buffer_count = buffer_count;
+        self
+    }
+
+    fn add_file(
+        mut self,
+        block_type: BlockType,
+        name: &str,
+        path: impl AsRef<Path>,
+        opts: ObjectOptions,
+        suffix: &str,
+    ) -> Self {
+        let path = path.as_ref();
+
+        // events have their extension added back when they're compiled
+        let stored_name = opts.stored_name.clone().unwrap_or_else(|| {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            format!("\\{}", file_name.strip_suffix(suffix).unwrap_or(&file_name))
+        });
+
+        self.options
+            .files
+            .insert(stored_name.clone() + suffix, path.to_path_buf());
+
+        let mut statements = vec![Statement::Assignment(
+            "fileName".into(),
+            RValue::String(stored_name),
+        )];
+        statements.extend(opts.statements());
+
+        self.blocks.push(Block {
+            id: 0,
+            block_type,
+            name: name.into(),
+            is_weave: false,
+            statements,
+            span: None,
+            statement_spans: vec![],
+        });
+        self
+    }
+
+    pub fn add_sound(self, name: &str, path: impl AsRef<Path>, opts: ObjectOptions) -> Self {
+        self.add_file(BlockType::DefineSound, name, path, opts, "")
+    }
+
+    pub fn add_still(self, name: &str, path: impl AsRef<Path>, opts: ObjectOptions) -> Self {
+        self.add_file(BlockType::DefineStill, name, path, opts, "")
+    }
+
+    pub fn add_anim(self, name: &str, path: impl AsRef<Path>, opts: ObjectOptions) -> Self {
+        self.add_file(BlockType::DefineAnim, name, path, opts, "")
+    }
+
+    pub fn add_object(self, name: &str, path: impl AsRef<Path>, opts: ObjectOptions) -> Self {
+        self.add_file(BlockType::DefineObject, name, path, opts, "")
+    }
+
+    pub fn add_event(self, name: &str, path: impl AsRef<Path>, opts: ObjectOptions) -> Self {
+        self.add_file(BlockType::DefineEvent, name, path, opts, ".evt")
+    }
+
+    fn add_action(
+        mut self,
+        block_type: BlockType,
+        name: &str,
+        children: &[&str],
+        opts: ObjectOptions,
+    ) -> Self {
+        let mut statements = children
+            .iter()
+            .map(|c| Statement::Declaration(c.to_string()))
+            .collect::<Vec<_>>();
+        statements.extend(opts.statements());
+
+        self.blocks.push(Block {
+            id: 0,
+            block_type,
+            name: name.into(),
+            is_weave: false,
+            statements,
+            span: None,
+            statement_spans: vec![],
+        });
+        self
+    }
+
+    /// Adds an action that plays its children all at once.
+    pub fn add_parallel(self, name: &str, children: &[&str], opts: ObjectOptions) -> Self {
+        self.add_action(BlockType::ParallelAction, name, children, opts)
+    }
+
+    /// Adds an action that plays its children one after another.
+    pub fn add_serial(self, name: &str, children: &[&str], opts: ObjectOptions) -> Self {
+        self.add_action(BlockType::SerialAction, name, children, opts)
+    }
+
+    pub fn build(mut self) -> Result<Omni> {
+        let declared = self
+            .blocks
+            .iter()
+            .flat_map(|b| &b.statements)
+            .filter_map(|s| match s {
+                Statement::Declaration(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        for block in &mut self.blocks {
+            block.is_weave = !declared.contains(&block.name);
+        }
+
+        let settings = Block {
+            id: u32::MAX,
+            block_type: BlockType::DefineSettings,
+            name: "Configuration".into(),
+            is_weave: false,
+            statements: vec![
+                Statement::Assignment("bufferSizeKB".into(), RValue::Integer(self.buffer_size)),
+                Statement::Assignment("buffersNum".into(), RValue::Integer(self.buffer_count)),
+            ],
+            span: None,
+            statement_spans: vec![],
+        };
+
+        let mut text = Text::new(settings, self.blocks);
+        text.assign_ids()
+            .map_err(|e| CompileError::Ids(e.to_string()))?;
+
+        Omni::from_text(&text, &self.options)
+    }
+}
@@ -0,0 +1,196 @@
+use super::timing::MediaClock;
+use crate::image::{is_bmp, is_flc, is_smk};
+use std::{
+    borrow::Cow,
+    ops::Range,
+    sync::{Arc, RwLock},
+};
+
+static CODECS: RwLock<Vec<Arc<dyn ResourceCodec>>> = RwLock::new(vec![]);
+
+/// How a data file is split into data chunks for its object's stream.
+#[derive(Debug, Clone, Default)]
+pub struct Chunking {
+    /// Each chunk's part of the file, along with the time in ms from the
+    /// start of playback that it's needed
+    pub chunks: Vec<(Range<usize>, u32)>,
+    /// When the file finishes playing, which the chunk after the last one
+    /// is given
+    pub end: u32,
+}
+
+impl Chunking {
+    /// Splits `len` bytes into chunks of at most `max` bytes, all needed
+    /// straight away.
+    pub fn untimed(len: usize, max: usize) -> Self {
+        Self::timed(len, max, |_| 0, 0)
+    }
+
+    /// Splits `len` bytes into chunks of at most `max` bytes, each needed
+    /// at `time_at` its first byte.
+    pub fn timed(len: usize, max: usize, time_at: impl Fn(usize) -> u32, end: u32) -> Self {
+        let chunks = (0..len)
+            .step_by(max.max(1))
+            .map(|start| (start..(start + max).min(len), time_at(start)))
+            .collect();
+
+        Self { chunks, end }
+    }
+
+    fn clocked(clock: Option<MediaClock>, len: usize, max: usize) -> Self {
+        match clock {
+            Some(clock) => Self::timed(len, max, |at| clock.time_at(at), clock.duration(len)),
+            None => Self::untimed(len, max),
+        }
+    }
+}
+
+/// A type of data file that objects store, such as sounds or videos: how
+/// it's recognised, turned into the file written when extracting, stored
+/// when compiling and split into timed data chunks.
+///
+/// The built-in codecs are [`WavCodec`], [`FlcCodec`], [`SmkCodec`] and
+/// [`StlCodec`], and more can be added with [`register_codec`].
+pub trait ResourceCodec: Send + Sync {
+    /// Short name it's picked by, such as `wav`.
+    fn name(&self) -> &str;
+
+    /// Whether `data` is a file of this type, either as it's stored or as
+    /// it's given to be compiled.
+    fn detect(&self, data: &[u8]) -> bool;
+
+    /// The file to write when extracting an object that stores `stored`,
+    /// which is the data as it's stored by default.
+    fn extract<'a>(&self, stored: &'a [u8]) -> Result<Cow<'a, [u8]>, String> {
+        Ok(Cow::Borrowed(stored))
+    }
+
+    /// The data to store for `file` when compiling, which is the file as it
+    /// is by default.
+    fn assemble(&self, file: Vec<u8>) -> Result<Vec<u8>, String> {
+        Ok(file)
+    }
+
+    /// Splits data to be stored into chunks of at most `max` bytes. By
+    /// default, they're all needed straight away.
+    fn chunk(&self, data: &[u8], max: usize) -> Chunking {
+        Chunking::untimed(data.len(), max)
+    }
+}
+
+/// Sounds, either as WAV files or as the engine's own form of them, with
+/// each chunk timed by when its samples are played.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WavCodec;
+
+impl ResourceCodec for WavCodec {
+    fn name(&self) -> &str {
+        "wav"
+    }
+
+    fn detect(&self, data: &[u8]) -> bool {
+        MediaClock::wav(data).is_some()
+    }
+
+    fn chunk(&self, data: &[u8], max: usize) -> Chunking {
+        Chunking::clocked(MediaClock::wav(data), data.len(), max)
+    }
+}
+
+/// FLI and FLC animations, with each chunk timed by the frame it's in.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FlcCodec;
+
+impl ResourceCodec for FlcCodec {
+    fn name(&self) -> &str {
+        "flc"
+    }
+
+    fn detect(&self, data: &[u8]) -> bool {
+        is_flc(data)
+    }
+
+    fn chunk(&self, data: &[u8], max: usize) -> Chunking {
+        Chunking::clocked(MediaClock::flc(data), data.len(), max)
+    }
+}
+
+/// Smacker videos, with each chunk timed by the frame it's in.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SmkCodec;
+
+impl ResourceCodec for SmkCodec {
+    fn name(&self) -> &str {
+        "smk"
+    }
+
+    fn detect(&self, data: &[u8]) -> bool {
+        is_smk(data)
+    }
+
+    fn chunk(&self, data: &[u8], max: usize) -> Chunking {
+        Chunking::clocked(MediaClock::smk(data), data.len(), max)
+    }
+}
+
+/// Stills, which are bitmaps, usually without the BMP file header. They're
+/// needed straight away.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StlCodec;
+
+impl ResourceCodec for StlCodec {
+    fn name(&self) -> &str {
+        "stl"
+    }
+
+    fn detect(&self, data: &[u8]) -> bool {
+        is_bmp(data)
+    }
+}
+
+/// The built-in codecs, in the order they're tried. Videos come first,
+/// since the start of one can pass for the engine's form of a WAV file.
+pub fn builtin_codecs() -> [Arc<dyn ResourceCodec>; 4] {
+    [
+        Arc::new(SmkCodec),
+        Arc::new(FlcCodec),
+        Arc::new(WavCodec),
+        Arc::new(StlCodec),
+    ]
+}
+
+/// Has `codec` used for the files it detects from now on, before any
+/// codec registered earlier and the built-in ones. A codec with the same
+/// name as one registered earlier takes its place, and one named like a
+/// built-in codec is used instead of it.
+pub fn register_codec(codec: impl ResourceCodec + 'static) {
+    let mut codecs = CODECS.write().unwrap_or_else(|e| e.into_inner());
+    codecs.retain(|other| other.name() != codec.name());
+    codecs.insert(0, Arc::new(codec));
+}
+
+/// Every codec, in the order they're tried: the registered ones from the
+/// latest, then the built-in ones that haven't been replaced.
+pub fn codecs() -> Vec<Arc<dyn ResourceCodec>> {
+    let mut codecs = CODECS.read().unwrap_or_else(|e| e.into_inner()).clone();
+
+    for builtin in builtin_codecs() {
+        if !codecs.iter().any(|c| c.name() == builtin.name()) {
+            codecs.push(builtin);
+        }
+    }
+
+    codecs
+}
+
+/// The first codec that detects `data`.
+pub fn codec_for(data: &[u8]) -> Option<Arc<dyn ResourceCodec>> {
+    codecs().into_iter().find(|c| c.detect(data))
+}
+
+/// The codec called `name`, ignoring case.
+pub fn codec_named(name: &str) -> Option<Arc<dyn ResourceCodec>> {
+    codecs()
+        .into_iter()
+        .find(|c| c.name().eq_ignore_ascii_case(name))
+}
@@ -0,0 +1,691 @@
+use super::{
+    codec::{codec_for, Chunking},
+    looping,
+    paths::{disk_to_stored, find_ignoring_case, stored_to_disk},
+    riff::{
+        with_version, LISTType, List, MxCh, MxHd, MxOb, MxObType, MxOf, MxSt, OmniVersion,
+        RiffChunk, MXST_ID, OMNI_ID,
+    },
+    Omni, PadPolicy, ProgressHook, RateWarning,
+};
+use crate::{
+    audio::{self, AudioError, Conversion},
+    diagnostics::{Diagnostic, Severity},
+    text::{Block, BlockType, External, RValue, Span, Statement, Text},
+};
+use binrw::{BinResult, Endian};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{read, read_to_string},
+    mem::{replace, swap, take},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CompileError {
+    #[error(transparent)]
+    BinRW(#[from] binrw::Error),
+
+    #[error("Couldn't read resource \"{0}\": {1}")]
+    Resource(PathBuf, std::io::Error),
+
+    #[error("Couldn't convert sound \"{0}\": {1}")]
+    Audio(PathBuf, AudioError),
+
+    #[error("Couldn't store \"{0}\": {1}")]
+    Codec(PathBuf, String),
+
+    #[error("Block \"{0}\" is missing required field \"{1}\"")]
+    MissingField(String, &'static str),
+
+    #[error("Field \"{1}\" of block \"{0}\" has the wrong type")]
+    WrongType(String, String),
+
+    #[error("Unknown field \"{1}\" in block \"{0}\"")]
+    UnknownField(String, String),
+
+    #[error("Field \"{1}\" of block \"{0}\" can't be {2}")]
+    UnknownValue(String, String, String),
+
+    #[error("Block \"{0}\" is declared but never defined")]
+    UnknownBlock(String),
+
+    #[error("Block \"{0}\" ({1}) can't be used as an object")]
+    NotAnObject(String, BlockType),
+
+    #[error("Can't tell the file type of \"{1}\" in block \"{0}\"")]
+    UnknownFileType(String, String),
+
+    #[error("Invalid buffer geometry ({0} KB x {1})")]
+    BadBufferGeometry(i32, i32),
+
+    #[error("Buffer size {0} KB is too large")]
+    BufferTooLarge(i32),
+
+    #[error("Couldn't assign object IDs: {0}")]
+    Ids(String),
+
+    #[error("\"{0}\" has characters that can't be stored in the chosen encoding")]
+    Encoding(String),
+
+    #[error("Line {1} of manifest \"{0}\" isn't of the form NAME = PATH")]
+    Manifest(PathBuf, usize),
+
+    #[error("Manifest \"{0}\" isn't valid JSON: {1}")]
+    ManifestJson(PathBuf, serde_json::Error),
+
+    #[error("Resource {1} in manifest \"{0}\" has no fileName or path")]
+    ManifestEntry(PathBuf, usize),
+
+    #[error("There's no object \"{0}\" in the file to replace")]
+    NotInFile(String),
+
+    #[error("Cancelled")]
+    Cancelled,
+
+    #[error("{0}: {1}")]
+    At(Span, Box<CompileError>),
+}
+
+/// A mistake that was got around, from [`Compiled::problems`].
+impl From<&CompileError> for Diagnostic {
+    fn from(error: &CompileError) -> Self {
+        Diagnostic::new(Severity::Error, None, error)
+    }
+}
+
+impl CompileError {
+    /// Points the error at where it happened in `block`'s script: the field
+    /// it's about, or else the block itself.
+    pub fn at(self, block: &Block) -> Self {
+        let span = match &self {
+            Self::At(..) => None,
+            Self::WrongType(_, field)
+            | Self::UnknownField(_, field)
+            | Self::UnknownValue(_, field, _) => block.field_span(field),
+            _ => block.span.as_ref(),
+        };
+
+        match span {
+            Some(span) => Self::At(span.clone(), Box::new(self)),
+            None => self,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, CompileError>;
+
+pub trait FromBlock: Sized {
+    fn from_block(block: &Block, compiler: &mut Compiler) -> Result<Self>;
+}
+
+/// Buffer size (in KB) used when a script doesn't give one, if the compile
+/// isn't strict.
+pub(crate) const DEFAULT_BUFFER_SIZE_KB: i32 = 64;
+/// Number of buffers used when a script doesn't give one, if the compile
+/// isn't strict.
+pub(crate) const DEFAULT_BUFFER_COUNT: i32 = 2;
+
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    /// Folder that stored file names are looked up in
+    pub resources: Option<PathBuf>,
+    /// Overrides `bufferSizeKB` from the settings block
+    pub buffer_size: Option<i32>,
+    /// Overrides `buffersNum` from the settings block
+    pub buffer_count: Option<i32>,
+    /// Local files for particular stored file names, used instead of
+    /// looking them up in the resource folder
+    pub files: HashMap<String, PathBuf>,
+    /// Format version to write, which decides the layout of the objects
+    pub target_version: OmniVersion,
+    /// Told about each resource as it's read, and asked whether to stop
+    pub progress: Option<ProgressHook>,
+    /// Store sounds as they are, instead of converting any the engine can't
+    /// play properly to a WAV file it can
+    pub keep_audio: bool,
+    /// How the file is padded to line chunks up with its buffers
+    pub pads: PadPolicy,
+    /// Start of stored paths (case-insensitive) that's left out when
+    /// looking them up in the resource folder, and put in front of file
+    /// names given relative to it
+    pub prefix: Option<String>,
+    /// Leave out blocks that aren't weaves and aren't declared by any other
+    /// block, instead of storing them as top-level objects
+    pub strip_unused: bool,
+    /// Fail on unknown fields, unknown values and missing required fields,
+    /// instead of getting by without them (see [`Compiled::problems`])
+    pub strict: bool,
+    /// Byte order to write, as a RIFX file if it's big-endian. Files are
+    /// little-endian RIFF files if not given
+    pub endian: Option<Endian>,
+}
+
+/// A sound that was converted while compiling.
+#[derive(Debug, Clone)]
+pub struct AudioConversion {
+    /// File name the script gave
+    pub file_name: String,
+    /// File name it was stored under, which ends in `.wav`
+    pub stored_name: String,
+    pub conversion: Conversion,
+}
+
+/// The result of [`Omni::compile`].
+pub struct Compiled {
+    pub omni: Omni,
+    /// Resource files that went into it
+    pub resources: Vec<PathBuf>,
+    pub conversions: Vec<AudioConversion>,
+    /// Objects streamed faster than the buffer layout can keep up with
+    pub warnings: Vec<RateWarning>,
+    /// Mistakes in the script that were got around, because the compile
+    /// wasn't strict. Unknown fields are kept in the extra data, fields
+    /// with unknown values are left out, and missing fields get defaults.
+    pub problems: Vec<CompileError>,
+}
+
+impl CompileOptions {
+    /// Adds the files listed in a manifest to [`CompileOptions::files`].
+    ///
+    /// Each line is a stored file name (as the script gives it) and a local
+    /// path, separated by `=`; relative paths are taken from the manifest's
+    /// folder. Blank lines and lines starting with `#` are skipped.
+    ///
+    /// The JSON manifests written when extracting can be given too, which
+    /// list the same things as the `fileName` and `path` of each of their
+    /// `resources`.
+    pub fn load_manifest(&mut self, path: &Path) -> Result<()> {
+        let manifest = read_to_string(path).map_err(|e| CompileError::Resource(path.into(), e))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        if manifest.trim_start().starts_with('{') {
+            let json = serde_json::from_str::<serde_json::Value>(&manifest)
+                .map_err(|e| CompileError::ManifestJson(path.into(), e))?;
+
+            let resources = json["resources"].as_array().map_or(&[][..], |r| r);
+
+            for (i, resource) in resources.iter().enumerate() {
+                let (Some(name), Some(file)) =
+                    (resource["fileName"].as_str(), resource["path"].as_str())
+                else {
+                    return Err(CompileError::ManifestEntry(path.into(), i));
+                };
+
+                self.files.insert(name.into(), dir.join(file));
+            }
+
+            return Ok(());
+        }
+
+        for (i, line) in manifest.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((name, file)) = line.split_once('=') else {
+                return Err(CompileError::Manifest(path.into(), i + 1));
+            };
+
+            let name = name.trim().trim_matches('"');
+            let file = file.trim().trim_matches('"');
+
+            self.files.insert(name.into(), dir.join(file));
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Compiler<'a> {
+    options: &'a CompileOptions,
+    blocks: HashMap<&'a str, &'a Block>,
+    externals: HashMap<&'a str, &'a External>,
+    max_payload: usize,
+    chunks: Vec<RiffChunk>,
+    resources: Vec<PathBuf>,
+    conversions: Vec<AudioConversion>,
+    /// Bytes of resources read so far
+    loaded: u64,
+    problems: Vec<CompileError>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(text: &'a Text, options: &'a CompileOptions) -> Self {
+        Self {
+            options,
+            blocks: text.blocks().map(|b| (b.name.as_str(), b)).collect(),
+            externals: text
+                .externals()
+                .iter()
+                .map(|e| (e.name.as_str(), e))
+                .collect(),
+            max_payload: 0,
+            chunks: vec![],
+            resources: vec![],
+            conversions: vec![],
+            loaded: 0,
+            problems: vec![],
+        }
+    }
+
+    pub fn options(&self) -> &CompileOptions {
+        self.options
+    }
+
+    /// The path to store for a file name from a script. File names given
+    /// relative to the resource folder have the prefix put in front of them.
+    pub fn stored_name(&self, file_name: &str) -> String {
+        match &self.options.prefix {
+            Some(prefix) if !file_name.starts_with(['\\', '/']) => {
+                disk_to_stored(Path::new(file_name), Some(prefix))
+            }
+            _ => file_name.into(),
+        }
+    }
+
+    /// Fails with `error` if the compile is strict, and otherwise notes it
+    /// down and carries on.
+    pub fn tolerate(&mut self, error: CompileError) -> Result<()> {
+        match self.options.strict {
+            true => Err(error),
+            false => {
+                self.problems.push(error);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn lookup(&self, name: &str) -> Result<&'a Block> {
+        self.blocks
+            .get(name)
+            .copied()
+            .ok_or_else(|| CompileError::UnknownBlock(name.into()))
+    }
+
+    /// The object in another file that `name` refers to, if it's one.
+    pub fn external(&self, name: &str) -> Option<&'a External> {
+        self.externals.get(name).copied()
+    }
+
+    /// Reads a resource from the resource folder (or the file given for it),
+    /// returning it along with where it was read from. Without either, or
+    /// without a file name, there's nothing to read.
+    fn read_resource(
+        &mut self,
+        block: &Block,
+        file_name: &str,
+    ) -> Result<Option<(Vec<u8>, PathBuf)>> {
+        let progress = self.options.progress.as_ref().map(|p| &p.0);
+
+        if progress.is_some_and(|p| p.cancelled()) {
+            return Err(CompileError::Cancelled);
+        }
+
+        let path = match (self.options.files.get(file_name), &self.options.resources) {
+            (Some(path), _) => path.clone(),
+            (None, Some(resources)) => find_ignoring_case(
+                resources,
+                &stored_to_disk(file_name, self.options.prefix.as_deref()),
+            ),
+            _ if file_name.is_empty() => return Ok(None),
+            (None, None) => return Ok(None),
+        };
+        let data = read(&path).map_err(|e| CompileError::Resource(path.clone(), e))?;
+
+        self.loaded += data.len() as u64;
+        if let Some(progress) = progress {
+            progress.update(self.loaded, 0, &block.name);
+        }
+
+        if !self.resources.contains(&path) {
+            self.resources.push(path.clone());
+        }
+
+        // in the form it's stored in
+        let data = match codec_for(&data) {
+            Some(codec) => codec
+                .assemble(data)
+                .map_err(|e| CompileError::Codec(path.clone(), e))?,
+            None => data,
+        };
+
+        Ok(Some((data, path)))
+    }
+
+    /// Splits a resource into data chunks for the stream currently being
+    /// built, as the codec that detects it says. Sounds and animations have
+    /// each chunk timed for when its data is played, so that the engine
+    /// streams it in on schedule; anything else is needed straight away.
+    fn push_resource(&mut self, block: &Block, data: &[u8]) {
+        let chunking = match codec_for(data) {
+            Some(codec) => codec.chunk(data, self.max_payload),
+            None => Chunking::untimed(data.len(), self.max_payload),
+        };
+
+        for (range, time) in chunking.chunks {
+            self.chunks.push(RiffChunk::MxCh(MxCh::new(
+                block.id,
+                time,
+                data[range].to_vec(),
+            )));
+        }
+
+        self.chunks
+            .push(RiffChunk::MxCh(MxCh::end_of_stream(block.id, chunking.end)));
+    }
+
+    /// Reads a resource from the resource folder (or the file given for it)
+    /// and splits it into data chunks for the stream currently being built.
+    /// Without either, only the object structure is compiled.
+    pub fn load_resource(&mut self, block: &Block, file_name: &str) -> Result<()> {
+        if let Some((data, _)) = self.read_resource(block, file_name)? {
+            self.push_resource(block, &data);
+        }
+
+        Ok(())
+    }
+
+    /// Loads a sound like [`Compiler::load_resource`], first converting it
+    /// to a WAV file if it's in a format the engine can't play properly
+    /// through the block's handler class, then storing its samples
+    /// `repeats` times if it loops by streaming. Returns the file name to
+    /// store, which is changed to end in `.wav` if it was converted.
+    pub fn load_sound(&mut self, block: &Block, file_name: &str, repeats: u32) -> Result<String> {
+        let Some((data, path)) = self.read_resource(block, file_name)? else {
+            return Ok(file_name.into());
+        };
+
+        let handler = block.statements.iter().find_map(|s| match s {
+            Statement::Assignment(n, RValue::String(class)) if n == "handlerClass" => {
+                Some(class.as_str())
+            }
+            _ => None,
+        });
+
+        // 3D sounds are played through DirectSound 3D buffers, which have
+        // to be mono
+        let max_channels = match handler {
+            Some("Lego3DWavePresenter") => 1,
+            _ => 2,
+        };
+
+        let converted = if self.options.keep_audio {
+            None
+        } else {
+            match audio::convert_for_engine(&data, max_channels) {
+                Ok(converted) => converted,
+                // not a sound that can be decoded, so it's left for the engine
+                Err(AudioError::NotWav) => None,
+                Err(e) => return Err(CompileError::Audio(path, e)),
+            }
+        };
+
+        let (data, stored_name) = match converted {
+            Some((data, conversion)) => {
+                let stored_name = match file_name.rsplit_once('.') {
+                    Some((stem, _)) => format!("{stem}.wav"),
+                    None => format!("{file_name}.wav"),
+                };
+
+                self.conversions.push(AudioConversion {
+                    file_name: file_name.into(),
+                    stored_name: stored_name.clone(),
+                    conversion,
+                });
+
+                (data, stored_name)
+            }
+            None => (data, file_name.into()),
+        };
+
+        let data = match repeats {
+            0 | 1 => data,
+            n => looping::expand(&data, n).unwrap_or(data),
+        };
+
+        self.push_resource(block, &data);
+
+        Ok(stored_name)
+    }
+
+    /// Builds a block as an MxSt, with its own data list holding the chunks
+    /// of the block and any children that don't have streams of their own.
+    pub fn compile_stream(&mut self, block: &Block) -> Result<MxSt> {
+        let outer = take(&mut self.chunks);
+        let obj = MxOb::from_block(block, self)?;
+        let chunks = replace(&mut self.chunks, outer);
+
+        Ok(MxSt::new(obj, chunks)?)
+    }
+}
+
+impl Omni {
+    /// Builds an Omni file from a script. Object IDs are taken from the
+    /// blocks as they are, so they should be given out with
+    /// [`Text::assign_ids`] first.
+    pub fn from_text(text: &Text, options: &CompileOptions) -> Result<Self> {
+        Self::from_text_with_resources(text, options).map(|(omni, _)| omni)
+    }
+
+    /// Builds an Omni file from a script like [`Omni::from_text`], also
+    /// returning the resource files that went into it, for build systems
+    /// to track along with [`Text::sources`].
+    pub fn from_text_with_resources(
+        text: &Text,
+        options: &CompileOptions,
+    ) -> Result<(Self, Vec<PathBuf>)> {
+        Self::compile(text, options).map(|c| (c.omni, c.resources))
+    }
+
+    /// Builds an Omni file from a script like [`Omni::from_text`], also
+    /// returning the resource files that went into it and the sounds that
+    /// had to be converted.
+    pub fn compile(text: &Text, options: &CompileOptions) -> Result<Compiled> {
+        with_version(options.target_version, || Self::build(text, options))
+    }
+
+    fn build(text: &Text, options: &CompileOptions) -> Result<Compiled> {
+        let mut compiler = Compiler::new(text, options);
+
+        let header =
+            MxHd::from_block(text.settings(), &mut compiler).map_err(|e| e.at(text.settings()))?;
+
+        // each data chunk has to fit in a buffer along with its headers
+        compiler.max_payload = (header.buffer_size.0 as usize - 8 - MxCh::HEADER_SIZE) & !1;
+
+        // weaves declared by another block get nested inside it instead,
+        // and blocks nothing declares are stored at the top level so that
+        // they can still be started by ID
+        let declared = text
+            .blocks()
+            .flat_map(|b| &b.statements)
+            .filter_map(|s| match s {
+                Statement::Declaration(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        let mut subchunks = vec![];
+
+        for block in text
+            .blocks()
+            .filter(|b| !declared.contains(b.name.as_str()))
+            .filter(|b| b.is_weave || !options.strip_unused)
+        {
+            let st = compiler.compile_stream(block)?;
+            subchunks.push(RiffChunk::MxSt(Box::new(st)));
+        }
+
+        let mut omni = Self {
+            endian: options.endian.unwrap_or(Endian::Little),
+            container_type: OMNI_ID,
+            header,
+            offsets: MxOf::from_offsets(vec![]),
+            streams: List::new(LISTType::Other(MXST_ID), subchunks)?,
+            extra: vec![],
+        };
+
+        omni.interleave_with(&options.pads)?;
+
+        let warnings = omni.stats().rate_warnings();
+
+        Ok(Compiled {
+            omni,
+            resources: compiler.resources,
+            conversions: compiler.conversions,
+            warnings,
+            problems: compiler.problems,
+        })
+    }
+}
+
+/// Takes the data chunks of object `id` out of a stream's data list, and
+/// puts `data` where the first of them was.
+fn replace_data(list: &mut Vec<RiffChunk>, id: u32, data: Vec<RiffChunk>) {
+    let is_object = |c: &RiffChunk| matches!(c, RiffChunk::MxCh(ch) if ch.object == id);
+    let index = list.iter().position(is_object).unwrap_or(list.len());
+
+    list.retain(|c| !is_object(c));
+    list.splice(index..index, data);
+}
+
+/// Puts `obj` in place of object `id` if it's `ob` or one of its children,
+/// keeping the children it had. Returns whether it was found.
+fn splice_object(
+    ob: &mut MxOb,
+    id: u32,
+    obj: &mut Option<MxObType>,
+    data: &mut Option<Vec<RiffChunk>>,
+) -> BinResult<bool> {
+    if ob.obj.get_id() == id {
+        let Some(mut new) = obj.take() else {
+            return Ok(false);
+        };
+
+        if let (Some(old), Some(list)) = (ob.obj.get_list_mut(), new.get_list_mut()) {
+            swap(old, list);
+        }
+        ob.obj = new;
+    } else {
+        let Some(list) = ob.obj.get_list_mut() else {
+            return Ok(false);
+        };
+
+        if !splice_chunks(&mut list.subchunks, id, obj, data)? {
+            return Ok(false);
+        }
+        list.update_size()?;
+    }
+
+    ob.update_size()?;
+    Ok(true)
+}
+
+/// Finds object `id` in `chunks` and puts `obj` in place of it, along with
+/// `data` in place of its data chunks in the stream it's in, if there's any
+/// new data. Returns whether it was found.
+fn splice_chunks(
+    chunks: &mut [RiffChunk],
+    id: u32,
+    obj: &mut Option<MxObType>,
+    data: &mut Option<Vec<RiffChunk>>,
+) -> BinResult<bool> {
+    for chunk in chunks {
+        let found = match chunk {
+            RiffChunk::MxSt(st) => {
+                let found = splice_object(&mut st.obj, id, obj, data)?;
+
+                // the object's data is in the innermost stream it's in
+                if let (true, Some(data)) = (found, data.take()) {
+                    replace_data(&mut st.list.subchunks, id, data);
+                    st.list.update_size()?;
+                }
+
+                if found {
+                    st.update_size()?;
+                }
+                found
+            }
+            RiffChunk::MxOb(ob) => splice_object(ob, id, obj, data)?,
+            RiffChunk::List(list) => {
+                let found = splice_chunks(&mut list.subchunks, id, obj, data)?;
+                if found {
+                    list.update_size()?;
+                }
+                found
+            }
+            _ => false,
+        };
+
+        if found {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+impl Omni {
+    /// Compiles the blocks in `text` with the IDs in `ids` and puts them in
+    /// place of those objects, leaving the rest of the file as it is.
+    /// `text` should be this file decompiled, with the blocks to change
+    /// edited.
+    ///
+    /// Each object keeps the children it has here, so objects can't be
+    /// added or taken away this way. Objects whose resources can't be found
+    /// (or that are compiled without a resource folder) keep the data they
+    /// have here.
+    ///
+    /// Objects are replaced where they are, so [`Omni::interleave`] should
+    /// be called before writing the file. Returns the mistakes got around,
+    /// as in [`Compiled::problems`].
+    pub fn recompile(
+        &mut self,
+        text: &Text,
+        ids: &[u32],
+        options: &CompileOptions,
+    ) -> Result<Vec<CompileError>> {
+        with_version(self.header.version, || {
+            let mut compiler = Compiler::new(text, options);
+            compiler.max_payload =
+                (self.header.buffer_size.0 as usize - 8 - MxCh::HEADER_SIZE) & !1;
+
+            for &id in ids {
+                let block = text
+                    .blocks()
+                    .find(|b| b.id == id)
+                    .ok_or_else(|| CompileError::NotInFile(id.to_string()))?;
+
+                // the children are kept as they are, so they're left out
+                // rather than compiled along with it
+                let block = Block {
+                    statements: block
+                        .statements
+                        .iter()
+                        .filter(|s| !matches!(s, Statement::Declaration(_)))
+                        .cloned()
+                        .collect(),
+                    ..block.clone()
+                };
+
+                let ob = MxOb::from_block(&block, &mut compiler)?;
+                let data = take(&mut compiler.chunks);
+
+                let mut obj = Some(ob.obj);
+                let mut data = (!data.is_empty()).then_some(data);
+
+                if !splice_chunks(&mut self.streams.subchunks, id, &mut obj, &mut data)? {
+                    return Err(CompileError::NotInFile(block.name));
+                }
+            }
+
+            self.streams.update_size()?;
+
+            Ok(compiler.problems)
+        })
+    }
+}
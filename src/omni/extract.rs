@@ -0,0 +1,370 @@
+use super::{
+    looping,
+    paths::safe_file_name,
+    peek_buffer_size,
+    readahead::ReadAhead,
+    riff::{Payload, RiffChunk, MXDA_ID, MXOB_ID},
+    ChunkIter, Omni, OmniParseError, ParseOptions, Progress,
+};
+use crate::types::ObjectFilter;
+use std::{
+    collections::{HashMap, HashSet},
+    io::{ErrorKind, Read, Seek},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    thread,
+};
+
+/// A stored file, along with the chunks it's split across.
+pub struct Resource<'a> {
+    pub id: u32,
+    pub name: String,
+    pub file_name: String,
+    chunks: Vec<&'a Payload>,
+    /// Times a sound that loops by streaming has its samples stored
+    loops: u32,
+}
+
+impl Resource<'_> {
+    /// Size of the stored file.
+    pub fn len(&self) -> u64 {
+        self.chunks.iter().map(|c| c.len() as u64).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Puts the file back together, reading any data that wasn't loaded
+    /// from `source`. Sounds that loop by streaming have their samples
+    /// stored once for each loop, which are put back together only once.
+    pub fn read<R: Read + Seek>(&self, source: &mut R) -> std::io::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(self.len() as usize);
+
+        for chunk in &self.chunks {
+            data.extend_from_slice(&chunk.load(source)?);
+        }
+
+        if self.loops > 1 {
+            if let Some(once) = looping::collapse(&data, self.loops) {
+                return Ok(once);
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+/// Reads `resources` across `jobs` threads, passing each one's data to
+/// `f` as soon as it's been put together. Every thread gets its own reader
+/// from `open`, since chunks are read by seeking around the source.
+///
+/// `progress` is told about each resource as it's read, with the bytes read
+/// so far, and can stop the rest from being read.
+///
+/// Stops handing out resources after the first error, which is returned
+/// once the threads already running have finished.
+pub fn read_resources<R, O, F>(
+    resources: &[Resource<'_>],
+    jobs: usize,
+    open: O,
+    progress: Option<&dyn Progress>,
+    f: F,
+) -> std::io::Result<()>
+where
+    R: Read + Seek,
+    O: Fn() -> std::io::Result<R> + Sync,
+    F: Fn(&Resource<'_>, Vec<u8>) -> std::io::Result<()> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let total = resources.iter().map(Resource::len).sum();
+    let done = AtomicU64::new(0);
+
+    let work = || -> std::io::Result<()> {
+        let mut source = open()?;
+
+        while let Some(resource) = resources.get(next.fetch_add(1, Ordering::Relaxed)) {
+            if progress.is_some_and(|p| p.cancelled()) {
+                return Err(std::io::Error::new(ErrorKind::Interrupted, "Cancelled"));
+            }
+
+            f(resource, resource.read(&mut source)?)?;
+
+            if let Some(progress) = progress {
+                let done = done.fetch_add(resource.len(), Ordering::Relaxed) + resource.len();
+                progress.update(done, total, &resource.name);
+            }
+        }
+
+        Ok(())
+    };
+
+    let stop = || next.store(resources.len(), Ordering::Relaxed);
+
+    thread::scope(|s| {
+        let workers = (0..jobs.clamp(1, resources.len().max(1)))
+            .map(|_| {
+                s.spawn(|| {
+                    let result = work();
+                    if result.is_err() {
+                        stop();
+                    }
+                    result
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers
+            .into_iter()
+            .try_for_each(|w| w.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+    })
+}
+
+/// One chunk of a resource extracted by [`Omni::extract_raw`].
+#[derive(Debug, Clone, Copy)]
+pub struct RawChunk {
+    /// Where its data starts in the resource
+    pub offset: u64,
+    pub size: u32,
+    /// Time in milliseconds from the start of the object
+    pub time: u32,
+    pub flags: u16,
+}
+
+/// An object in the file, as listed by [`Omni::objects`].
+#[derive(Debug, Clone)]
+pub struct ObjectInfo {
+    pub id: u32,
+    /// ID of the action it's part of, if any
+    pub parent: Option<u32>,
+    pub name: String,
+    /// Type of object, such as `sound` or `world`
+    pub kind: &'static str,
+    /// Name of the file it stores, if it stores one
+    pub file_name: Option<String>,
+}
+
+fn list_objects(chunk: &RiffChunk, parent: Option<u32>, found: &mut Vec<ObjectInfo>) {
+    let (obj, children) = match chunk {
+        RiffChunk::MxOb(x) => (&x.obj, &[][..]),
+        RiffChunk::MxSt(x) => (&x.obj.obj, &x.list.subchunks[..]),
+        RiffChunk::List(x) => {
+            for child in &x.subchunks {
+                list_objects(child, parent, found);
+            }
+            return;
+        }
+        _ => return,
+    };
+
+    found.push(ObjectInfo {
+        id: obj.get_id(),
+        parent,
+        name: obj.get_name(),
+        kind: obj.kind(),
+        file_name: obj.get_filename(),
+    });
+
+    for child in obj.get_children().iter().chain(children) {
+        list_objects(child, Some(obj.get_id()), found);
+    }
+}
+
+fn find_objects<'a>(chunk: &'a RiffChunk, filter: &ObjectFilter, found: &mut Vec<Resource<'a>>) {
+    let (obj, children) = match chunk {
+        RiffChunk::MxOb(x) => (&x.obj, &[][..]),
+        RiffChunk::MxSt(x) => (&x.obj.obj, &x.list.subchunks[..]),
+        RiffChunk::List(x) => {
+            for child in &x.subchunks {
+                find_objects(child, filter, found);
+            }
+            return;
+        }
+        _ => return,
+    };
+
+    if let Some(file_name) = obj.get_filename() {
+        if filter.matches(obj.get_id(), &obj.get_name()) {
+            found.push(Resource {
+                id: obj.get_id(),
+                name: obj.get_name(),
+                file_name,
+                chunks: vec![],
+                loops: obj.streamed_loops(),
+            });
+        }
+    }
+
+    for child in obj.get_children().iter().chain(children) {
+        find_objects(child, filter, found);
+    }
+}
+
+fn collect_chunks<'a>(chunk: &'a RiffChunk, chunks: &mut HashMap<u32, &mut Vec<&'a Payload>>) {
+    match chunk {
+        RiffChunk::MxCh(x) => {
+            if let Some(list) = chunks.get_mut(&x.object) {
+                list.push(&x.data);
+            }
+        }
+        RiffChunk::MxSt(x) => {
+            for child in x.obj.obj.get_children().iter().chain(&x.list.subchunks) {
+                collect_chunks(child, chunks);
+            }
+        }
+        RiffChunk::MxOb(x) => {
+            for child in x.obj.get_children() {
+                collect_chunks(child, chunks);
+            }
+        }
+        RiffChunk::List(x) => {
+            for child in &x.subchunks {
+                collect_chunks(child, chunks);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Omni {
+    /// Lists every object in the file, each followed by the objects inside
+    /// it.
+    pub fn objects(&self) -> Vec<ObjectInfo> {
+        let mut found = vec![];
+
+        for chunk in &self.streams.subchunks {
+            list_objects(chunk, None, &mut found);
+        }
+
+        found
+    }
+
+    /// Lists every object in the Omni file at the source's position, like
+    /// [`Omni::objects`], without reading the rest of the file. Only the
+    /// objects are read, and the chunks holding their data are skipped, so
+    /// it's much quicker than parsing big files.
+    pub fn objects_in<R: Read + Seek>(
+        mut source: R,
+        options: &ParseOptions,
+    ) -> Result<Vec<ObjectInfo>, OmniParseError> {
+        // the chunks are skipped over in small steps, which shouldn't each
+        // be a seek on the source
+        let buf_size = peek_buffer_size(&mut source, options)?;
+        let mut reader = ReadAhead::new(&mut source, buf_size as usize)?;
+        let mut chunks = ChunkIter::with_options(&mut reader, options)?;
+        let mut found = vec![];
+
+        while let Some(header) = chunks.next() {
+            let header = header?;
+
+            if header.id == MXOB_ID {
+                // along with the objects inside it
+                let chunk = header.read(chunks.get_mut())?;
+                list_objects(&chunk, None, &mut found);
+            } else if header.list_type == Some(MXDA_ID) {
+                chunks.skip_children();
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Finds the stored files of the objects picked by `filter`. Nothing is
+    /// read until [`Resource::read`] is called, so a lazily-parsed file only
+    /// ever has one resource in memory at a time.
+    pub fn extract(&self, filter: &ObjectFilter) -> Vec<Resource<'_>> {
+        let mut found = vec![];
+
+        for chunk in &self.streams.subchunks {
+            find_objects(chunk, filter, &mut found);
+        }
+
+        let mut chunks = found
+            .iter_mut()
+            .map(|r| (r.id, &mut r.chunks))
+            .collect::<HashMap<_, _>>();
+
+        for chunk in &self.streams.subchunks {
+            collect_chunks(chunk, &mut chunks);
+        }
+
+        found
+    }
+
+    /// Gathers the chunk data of every object picked by `filter` that has
+    /// any, as it's stored, into one resource per object named `NAME.bin`
+    /// (or `NAME_ID.bin` if the name is taken, or `ID.bin` if it has none).
+    /// Each comes with where its chunks start in it and when they're
+    /// streamed, for formats that can't be put back together properly.
+    pub fn extract_raw(&self, filter: &ObjectFilter) -> Vec<(Resource<'_>, Vec<RawChunk>)> {
+        let mut found: Vec<(Resource<'_>, Vec<RawChunk>)> = vec![];
+        let mut indices = HashMap::new();
+        let mut used = HashSet::new();
+
+        for entry in self.timeline() {
+            if entry.kind.is_empty() || !filter.matches(entry.object, &entry.name) {
+                continue;
+            }
+
+            let index = match indices.get(&entry.object) {
+                Some(&index) => index,
+                None => {
+                    let name = safe_file_name(&entry.name);
+                    let file_name = [
+                        format!("{name}.bin"),
+                        format!("{name}_{}.bin", entry.object),
+                    ]
+                    .into_iter()
+                    .filter(|_| !entry.name.is_empty())
+                    .chain([format!("{}.bin", entry.object)])
+                    .find(|f| used.insert(f.to_lowercase()))
+                    .unwrap_or_default();
+
+                    found.push((
+                        Resource {
+                            id: entry.object,
+                            name: entry.name.clone(),
+                            file_name,
+                            chunks: vec![],
+                            loops: 1,
+                        },
+                        vec![],
+                    ));
+                    indices.insert(entry.object, found.len() - 1);
+                    found.len() - 1
+                }
+            };
+
+            let (resource, chunks) = &mut found[index];
+            chunks.push(RawChunk {
+                offset: chunks.last().map_or(0, |c| c.offset + c.size as u64),
+                size: entry.size,
+                time: entry.time,
+                flags: entry.flags,
+            });
+            resource.chunks.push(entry.data);
+        }
+
+        found
+    }
+
+    /// Gathers the chunks whose object ID doesn't belong to any object,
+    /// which the engine never reads, as one resource per ID named `ID.bin`.
+    pub fn orphans(&self) -> Vec<Resource<'_>> {
+        let mut found: Vec<Resource<'_>> = vec![];
+
+        for entry in self.timeline().into_iter().filter(|e| e.kind.is_empty()) {
+            match found.iter_mut().find(|r| r.id == entry.object) {
+                Some(resource) => resource.chunks.push(entry.data),
+                None => found.push(Resource {
+                    id: entry.object,
+                    name: String::new(),
+                    file_name: format!("{}.bin", entry.object),
+                    chunks: vec![entry.data],
+                    loops: 1,
+                }),
+            }
+        }
+
+        found
+    }
+}
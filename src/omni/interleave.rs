@@ -0,0 +1,383 @@
+use super::{
+    riff::{serialized_size, with_version, MxCh, MxOb, MxOf, MxSt, Pad, RiffChunk},
+    Omni,
+};
+use ::bytes::Bytes;
+use binrw::BinResult;
+use std::{
+    collections::VecDeque,
+    mem::{replace, take},
+};
+
+/// Size of a chunk ID and size field.
+const CHUNK_HEADER: u64 = 8;
+
+/// Size of a LIST chunk's header and type.
+const LIST_HEADER: u64 = CHUNK_HEADER + 4;
+
+/// Distance from the start of an MxOb chunk to the first of its children,
+/// if it has any.
+fn object_head(ob: &MxOb) -> BinResult<Option<u64>> {
+    let Some(list) = ob.obj.get_list() else {
+        return Ok(None);
+    };
+
+    // the child list is the last thing in the object
+    let fields = serialized_size(&ob.obj)? as u64 - CHUNK_HEADER - list.header.size as u64;
+
+    Ok(Some(
+        CHUNK_HEADER + fields + CHUNK_HEADER + list.list_type.size() as u64,
+    ))
+}
+
+/// Distance from the start of a container chunk to the first chunk of the
+/// first list inside it, which is the first place padding can go.
+fn head(chunk: &RiffChunk) -> BinResult<Option<u64>> {
+    Ok(match chunk {
+        RiffChunk::MxSt(st) => Some(
+            CHUNK_HEADER
+                + match object_head(&st.obj)? {
+                    Some(head) => head,
+                    None => CHUNK_HEADER + st.obj.header.size as u64 + LIST_HEADER,
+                },
+        ),
+        RiffChunk::MxOb(ob) => object_head(ob)?,
+        _ => None,
+    })
+}
+
+/// How padding is made when laying a file out.
+#[derive(Debug, Clone, Default)]
+pub struct PadPolicy {
+    /// Byte that new padding is filled with
+    pub fill: u8,
+    /// Don't pad anything, leaving chunks to straddle buffer boundaries
+    /// wherever they fall instead of lining them up like the original
+    /// interleaver does
+    pub pack: bool,
+    /// Reuse what was in the padding of a parsed file, wherever a pad of the
+    /// same size is needed in the same list, so that files laid out as they
+    /// were come out the same
+    pub keep_original: bool,
+}
+
+struct Layout<'a> {
+    buf_size: u64,
+    pos: u64,
+    policy: &'a PadPolicy,
+    /// What was in the pads of the list being laid out, in order
+    originals: VecDeque<Bytes>,
+}
+
+impl Layout<'_> {
+    fn remaining(&self, pos: u64) -> u64 {
+        self.buf_size - pos % self.buf_size
+    }
+
+    /// Whether another chunk could start at `pos`, either because there's
+    /// room for its header or because it's the start of a buffer.
+    fn can_follow(&self, pos: u64) -> bool {
+        self.remaining(pos) >= CHUNK_HEADER || pos.is_multiple_of(self.buf_size)
+    }
+
+    /// Whether `len` bytes at `pos` stay inside one buffer, leaving either
+    /// nothing or enough room for another chunk header behind them.
+    fn fits(&self, pos: u64, len: u64) -> bool {
+        let remaining = self.remaining(pos);
+        len == remaining || len + CHUNK_HEADER <= remaining
+    }
+
+    /// Pads can only be made in even sizes of at least a chunk header, and
+    /// only if padding is wanted at all.
+    fn can_pad(&self, len: u64) -> bool {
+        !self.policy.pack && len >= CHUNK_HEADER && len.is_multiple_of(2)
+    }
+
+    fn pad(&mut self, out: &mut Vec<RiffChunk>, len: u64) {
+        let size = (len - CHUNK_HEADER) as usize;
+
+        let data = self
+            .originals
+            .pop_front()
+            .filter(|d| d.len() == size)
+            .unwrap_or_else(|| vec![self.policy.fill; size].into());
+
+        out.push(RiffChunk::Pad(Pad::new(data)));
+        self.pos += len;
+    }
+
+    fn place_list(&mut self, chunks: &mut Vec<RiffChunk>) -> BinResult<()> {
+        let originals = match self.policy.keep_original {
+            true => chunks
+                .iter()
+                .filter_map(|c| match c {
+                    RiffChunk::Pad(pad) => Some(pad.data.clone()),
+                    _ => None,
+                })
+                .collect(),
+            false => VecDeque::new(),
+        };
+        let outer = replace(&mut self.originals, originals);
+
+        for chunk in take(chunks) {
+            match chunk {
+                RiffChunk::Pad(_) => {}
+                c => match head(&c)? {
+                    Some(head) => self.place_container(chunks, c, head)?,
+                    None => self.place(chunks, c),
+                },
+            }
+        }
+
+        self.originals = outer;
+
+        Ok(())
+    }
+
+    fn place(&mut self, out: &mut Vec<RiffChunk>, mut chunk: RiffChunk) {
+        let len = CHUNK_HEADER + chunk.get_size() as u64;
+        let remaining = self.remaining(self.pos);
+
+        // oversized chunks can't be helped, so they just start a new buffer
+        if !self.fits(self.pos, len) && remaining != self.buf_size && self.can_pad(remaining) {
+            self.pad(out, remaining);
+        }
+
+        // the engine has to gather a data chunk that runs into the next
+        // buffer before it can use it
+        if let RiffChunk::MxCh(ch) = &mut chunk {
+            ch.flags.set_split(len > self.remaining(self.pos));
+        }
+
+        self.pos += len;
+        out.push(chunk);
+    }
+
+    fn place_container(
+        &mut self,
+        out: &mut Vec<RiffChunk>,
+        mut chunk: RiffChunk,
+        head: u64,
+    ) -> BinResult<()> {
+        // shift the container so that its header isn't split and its first
+        // list starts somewhere that padding can be inserted
+        let shift = (0..self.buf_size)
+            .step_by(2)
+            .filter(|&s| s == 0 || self.can_pad(s) && self.can_pad(self.remaining(self.pos)))
+            .find(|&s| {
+                let start = self.pos + s;
+                self.remaining(start) >= CHUNK_HEADER && self.can_follow(start + head)
+            })
+            .unwrap_or(0);
+
+        if shift != 0 {
+            self.pad(out, shift);
+        }
+
+        match &mut chunk {
+            RiffChunk::MxSt(st) => self.place_stream(st)?,
+            RiffChunk::MxOb(ob) => self.place_object(ob)?,
+            c => self.pos += CHUNK_HEADER + c.get_size() as u64,
+        }
+
+        out.push(chunk);
+
+        Ok(())
+    }
+
+    fn place_object(&mut self, ob: &mut MxOb) -> BinResult<()> {
+        let start = self.pos;
+
+        if let Some(head) = object_head(ob)? {
+            self.pos = start + head;
+
+            if let Some(list) = ob.obj.get_list_mut() {
+                self.place_list(&mut list.subchunks)?;
+                list.update_size()?;
+            }
+
+            ob.update_size()?;
+        }
+
+        self.pos = start + CHUNK_HEADER + ob.header.size as u64;
+
+        Ok(())
+    }
+
+    fn place_stream(&mut self, st: &mut MxSt) -> BinResult<()> {
+        let start = self.pos;
+
+        self.pos += CHUNK_HEADER;
+        self.place_object(&mut st.obj)?;
+        self.pos += LIST_HEADER;
+        self.place_list(&mut st.list.subchunks)?;
+
+        st.update_size()?;
+        self.pos = start + CHUNK_HEADER + st.header.size as u64;
+
+        Ok(())
+    }
+}
+
+/// Where an MxCh chunk goes when the file is written.
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    pub object: u32,
+    /// Offset of the chunk from the start of the file
+    pub offset: u64,
+    /// Size of the whole chunk, headers included
+    pub len: u64,
+    /// Offset of the chunk's data from the start of the file
+    pub data_offset: u64,
+    pub data_len: u64,
+}
+
+fn walk_object(ob: &MxOb, pos: u64, visit: &mut impl FnMut(&RiffChunk, u64)) -> BinResult<()> {
+    if let Some(head) = object_head(ob)? {
+        walk_chunks(ob.obj.get_children(), pos + head, visit)?;
+    }
+
+    Ok(())
+}
+
+/// Calls `visit` with each of `chunks`, which start at `pos`, and each
+/// chunk inside them, along with where it goes when the file is written.
+pub(super) fn walk_chunks(
+    chunks: &[RiffChunk],
+    mut pos: u64,
+    visit: &mut impl FnMut(&RiffChunk, u64),
+) -> BinResult<()> {
+    for chunk in chunks {
+        visit(chunk, pos);
+
+        match chunk {
+            RiffChunk::MxSt(st) => {
+                let ob = pos + CHUNK_HEADER;
+                walk_object(&st.obj, ob, visit)?;
+
+                let list = ob + CHUNK_HEADER + st.obj.header.size as u64 + LIST_HEADER;
+                walk_chunks(&st.list.subchunks, list, visit)?;
+            }
+            RiffChunk::MxOb(ob) => walk_object(ob, pos, visit)?,
+            _ => {}
+        }
+
+        pos += CHUNK_HEADER + chunk.get_size() as u64;
+    }
+
+    Ok(())
+}
+
+impl Omni {
+    /// Lays the streams out over the buffer geometry in the header, replacing
+    /// any existing padding so that no chunk header straddles a buffer
+    /// boundary and every data chunk is read in a single buffer. Data chunks
+    /// that still run past the end of their buffer are marked as split.
+    pub fn interleave(&mut self) -> BinResult<()> {
+        self.interleave_with(&PadPolicy::default())
+    }
+
+    /// Lays the streams out like [`Omni::interleave`], making padding as
+    /// `policy` says.
+    pub fn interleave_with(&mut self, policy: &PadPolicy) -> BinResult<()> {
+        with_version(self.header.version, || self.interleave_streams(policy))
+    }
+
+    /// Where the stream list's chunks start when the file is written: after
+    /// the RIFF header and type, MxHd, MxOf and any other chunks before the
+    /// stream list, then the LIST header and type.
+    fn streams_start(&self) -> BinResult<u64> {
+        let offsets = MxOf::from_offsets(vec![0; self.offset_table_len()?]);
+
+        let mut extra = 0;
+        for (_, chunk) in self.extra.iter().filter(|(at, _)| *at < 3) {
+            extra += serialized_size(chunk)? as u64;
+        }
+
+        Ok(LIST_HEADER
+            + CHUNK_HEADER
+            + self.header.header.size as u64
+            + CHUNK_HEADER
+            + offsets.header.size as u64
+            + extra
+            + LIST_HEADER)
+    }
+
+    fn interleave_streams(&mut self, policy: &PadPolicy) -> BinResult<()> {
+        self.offsets = MxOf::from_offsets(vec![0; self.offset_table_len()?]);
+
+        let mut layout = Layout {
+            buf_size: self.header.buffer_size.0 as u64,
+            pos: self.streams_start()?,
+            policy,
+            originals: VecDeque::new(),
+        };
+
+        layout.place_list(&mut self.streams.subchunks)?;
+
+        self.streams.update_size()
+    }
+
+    /// Where each MxCh chunk goes when the file is written, in file order.
+    pub fn placements(&self) -> BinResult<Vec<Placement>> {
+        with_version(self.header.version, || {
+            let mut placements = vec![];
+            walk_chunks(
+                &self.streams.subchunks,
+                self.streams_start()?,
+                &mut |chunk, pos| {
+                    if let RiffChunk::MxCh(ch) = chunk {
+                        placements.push(Placement {
+                            object: ch.object,
+                            offset: pos,
+                            len: CHUNK_HEADER + chunk.get_size() as u64,
+                            data_offset: pos + CHUNK_HEADER + MxCh::HEADER_SIZE as u64,
+                            data_len: ch.data.len() as u64,
+                        })
+                    }
+                },
+            )?;
+            Ok(placements)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::omni::{
+        testing::{compile_with, wav},
+        CompileOptions, Omni, PadPolicy,
+    };
+
+    fn splits(pack: bool) -> Vec<bool> {
+        let script = "defineSettings S { bufferSizeKB = 1; buffersNum = 2; }
+            defineSound A { fileName = \"a.wav\"; }";
+        let options = CompileOptions {
+            pads: PadPolicy {
+                pack,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let file = compile_with(script, &[("a.wav", &wav())], options).unwrap();
+        let omni = Omni::parse_bytes(&file).unwrap();
+
+        let placements = omni.placements().unwrap();
+        let timeline = omni.timeline();
+        assert_eq!(placements.len(), timeline.len());
+
+        for (placement, entry) in placements.iter().zip(&timeline) {
+            let first = placement.offset / 1024;
+            let last = (placement.offset + placement.len - 1) / 1024;
+            assert_eq!(entry.split, first != last, "{placement:?}");
+        }
+
+        timeline.iter().map(|e| e.split).collect()
+    }
+
+    #[test]
+    fn chunks_that_run_into_the_next_buffer_are_split() {
+        assert!(!splits(false).contains(&true));
+        assert!(splits(true).contains(&true));
+    }
+}
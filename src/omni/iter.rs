@@ -0,0 +1,324 @@
+use super::{
+    peek_buffer_size,
+    riff::{
+        padded, set_origin, with_parse_options, with_version, ChunkId, LISTType, OmniVersion,
+        Payload, RiffChunk, LIST_ID, MXCH_ID, MXHD_ID, MXST_ID,
+    },
+    root_endian, OmniParseError, ParseOptions, Result,
+};
+use binrw::{BinRead, Endian};
+use std::io::{Read, Seek, SeekFrom};
+
+/// Size of a chunk's ID and size.
+const HEADER_SIZE: u64 = 8;
+
+/// A chunk found by a [`ChunkIter`], before anything in it is read.
+#[derive(Debug, Clone)]
+pub struct ChunkHeader {
+    pub id: ChunkId,
+    /// Where the chunk starts in the source
+    pub offset: u64,
+    /// Size of what comes after the chunk's ID and size, rounded up to an
+    /// even number
+    pub size: u32,
+    /// Type of a RIFF or LIST chunk
+    pub list_type: Option<ChunkId>,
+    /// ID of the object that an MxCh chunk's data belongs to
+    pub object: Option<u32>,
+    /// How many chunks this one is inside
+    pub depth: usize,
+    endian: Endian,
+    buf_size: i32,
+    origin: u64,
+    version: OmniVersion,
+}
+
+impl ChunkHeader {
+    /// Whether the chunk holds other chunks, which the iterator goes
+    /// through next.
+    pub fn is_container(&self) -> bool {
+        self.list_type.is_some() || self.id == MXST_ID
+    }
+
+    /// Handle to everything in the chunk after its ID and size, to be read
+    /// from the source with [`Payload::load`] when it's wanted.
+    pub fn payload(&self) -> Payload {
+        Payload::Lazy {
+            offset: self.offset + HEADER_SIZE,
+            len: self.size,
+        }
+    }
+
+    /// Reads the whole chunk, and any chunks in it, from `source`.
+    pub fn read<R: Read + Seek>(&self, source: &mut R) -> Result<RiffChunk> {
+        let (chunk, _) = with_parse_options(&ParseOptions::default(), || {
+            with_version(self.version, || {
+                // buffers are counted from the start of the file
+                set_origin(self.origin);
+                source.seek(SeekFrom::Start(self.offset))?;
+                RiffChunk::read_options(source, self.endian, self.buf_size)
+            })
+        });
+
+        Ok(chunk?)
+    }
+}
+
+/// A chunk being gone through.
+struct Container {
+    end: u64,
+    /// Whether chunks are moved to the next buffer rather than being split
+    /// across two, which they are everywhere but in MxSt chunks
+    buffered: bool,
+}
+
+/// Goes through the chunks of an Omni file one at a time, in the order
+/// they're stored, reading only their headers. Nothing is kept from one
+/// chunk to the next, so files of any size can be walked through.
+///
+/// Several Omni files one after another are gone through in turn, like
+/// [`Omni::parse_segments`](super::Omni::parse_segments) does.
+pub struct ChunkIter<R> {
+    source: R,
+    options: ParseOptions,
+    endian: Endian,
+    buf_size: i32,
+    origin: u64,
+    version: OmniVersion,
+    /// The chunks that the next one is inside, innermost last
+    containers: Vec<Container>,
+    /// Where the next chunk starts
+    next: u64,
+    /// Whether the last chunk returned holds other chunks
+    entered: bool,
+    started: bool,
+    done: bool,
+}
+
+impl<R: Read + Seek> ChunkIter<R> {
+    /// Starts going through the Omni file at the source's position.
+    pub fn new(source: R) -> Result<Self> {
+        Self::with_options(source, &ParseOptions::default())
+    }
+
+    /// Starts going through the Omni file at the source's position, in the
+    /// byte order and with the buffer size given in `options`, if they are.
+    pub fn with_options(mut source: R, options: &ParseOptions) -> Result<Self> {
+        let next = source.stream_position()?;
+
+        Ok(Self {
+            source,
+            options: ParseOptions {
+                endian: options.endian,
+                buffer_size: options.buffer_size,
+                ..Default::default()
+            },
+            endian: Endian::Little,
+            buf_size: 0,
+            origin: next,
+            version: OmniVersion::default(),
+            containers: vec![],
+            next,
+            entered: false,
+            started: false,
+            done: false,
+        })
+    }
+
+    /// Skips the chunks inside the last chunk returned, if it holds any,
+    /// going on to whatever comes after it.
+    pub fn skip_children(&mut self) {
+        if self.entered {
+            if let Some(container) = self.containers.pop() {
+                self.next = container.end;
+            }
+            self.entered = false;
+        }
+    }
+
+    /// The source, such as to read the chunks found from with
+    /// [`ChunkHeader::read`] or [`Payload::load`].
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.source
+    }
+
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+    fn read_root(&mut self) -> Result<Option<ChunkHeader>> {
+        let start = self.next;
+        self.source.seek(SeekFrom::Start(start))?;
+
+        let id = match ChunkId::read_ne(&mut self.source) {
+            Ok(id) => id,
+            Err(e) if e.is_eof() && self.started => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        // anything after the last file is ignored
+        let Some(endian) = root_endian(id, self.options.endian) else {
+            return match self.started {
+                true => Ok(None),
+                false => Err(OmniParseError::NoRiffChunk),
+            };
+        };
+
+        self.source.seek(SeekFrom::Start(start))?;
+        self.buf_size = peek_buffer_size(&mut self.source, &self.options)?;
+        self.source
+            .seek(SeekFrom::Start(start + size_of::<ChunkId>() as u64))?;
+
+        let size = padded(u32::read_options(&mut self.source, endian, ())?);
+        let list_type = ChunkId::read_ne(&mut self.source)?;
+
+        self.endian = endian;
+        self.origin = start;
+        self.version = OmniVersion::default();
+        self.started = true;
+
+        self.containers.push(Container {
+            end: start + HEADER_SIZE + size as u64,
+            buffered: true,
+        });
+        self.next = start + HEADER_SIZE + size_of::<ChunkId>() as u64;
+
+        Ok(Some(self.header(id, start, size, Some(list_type), None, 0)))
+    }
+
+    fn read_next(&mut self) -> Result<Option<ChunkHeader>> {
+        self.entered = false;
+
+        loop {
+            let Some(container) = self.containers.last() else {
+                return self.read_root();
+            };
+
+            let at = self.next;
+            let end = container.end;
+
+            if at + HEADER_SIZE >= end {
+                self.containers.pop();
+                self.next = end;
+                continue;
+            }
+
+            // a chunk header that wouldn't fit in what's left of the buffer
+            // is in the next one, the same as when the file is parsed
+            if container.buffered {
+                let pos_in_buffer = ((at - self.origin) % self.buf_size as u64) as i32;
+                if pos_in_buffer + HEADER_SIZE as i32 > self.buf_size {
+                    self.next = at + (self.buf_size - pos_in_buffer) as u64;
+                    continue;
+                }
+            }
+
+            self.source.seek(SeekFrom::Start(at))?;
+            let (id, size) = <(ChunkId, u32)>::read_options(&mut self.source, self.endian, ())?;
+
+            if at + HEADER_SIZE + size as u64 > end {
+                return Err(binrw::Error::AssertFail {
+                    pos: at,
+                    message: format!("Chunk of {size:#X} bytes runs past the end of its parent"),
+                }
+                .into());
+            }
+
+            let size = padded(size);
+            let chunk_end = (at + HEADER_SIZE + size as u64).min(end);
+            let depth = self.containers.len();
+
+            let (list_type, object, children) = match id {
+                LIST_ID => {
+                    let list_type = LISTType::read_options(&mut self.source, self.endian, ())?;
+                    let children = at + HEADER_SIZE + list_type.size() as u64;
+
+                    let list_type = match list_type {
+                        LISTType::MxCh(_) => MXCH_ID,
+                        LISTType::Other(id) => id,
+                    };
+                    (Some(list_type), None, Some((children, true)))
+                }
+                MXST_ID => (None, None, Some((at + HEADER_SIZE, false))),
+                MXHD_ID => {
+                    // later chunks are read with the file's version and
+                    // buffer size
+                    self.version = OmniVersion::read_options(&mut self.source, self.endian, ())?;
+                    let buf_size = i32::read_options(&mut self.source, self.endian, ())?;
+                    if buf_size > 0 {
+                        self.buf_size = buf_size;
+                    }
+                    (None, None, None)
+                }
+                MXCH_ID => {
+                    // after the flags
+                    self.source.seek(SeekFrom::Current(2))?;
+                    let object = u32::read_options(&mut self.source, self.endian, ())?;
+                    (None, Some(object), None)
+                }
+                _ => (None, None, None),
+            };
+
+            match children {
+                Some((start, buffered)) => {
+                    self.containers.push(Container {
+                        end: chunk_end,
+                        buffered,
+                    });
+                    self.next = start;
+                    self.entered = true;
+                }
+                None => self.next = chunk_end,
+            }
+
+            return Ok(Some(self.header(id, at, size, list_type, object, depth)));
+        }
+    }
+
+    fn header(
+        &self,
+        id: ChunkId,
+        offset: u64,
+        size: u32,
+        list_type: Option<ChunkId>,
+        object: Option<u32>,
+        depth: usize,
+    ) -> ChunkHeader {
+        ChunkHeader {
+            id,
+            offset,
+            size,
+            list_type,
+            object,
+            depth,
+            endian: self.endian,
+            buf_size: self.buf_size,
+            origin: self.origin,
+            version: self.version,
+        }
+    }
+}
+
+impl<R: Read + Seek> Iterator for ChunkIter<R> {
+    type Item = Result<ChunkHeader>;
+
+    /// The next chunk, or an error if it can't be read, after which there
+    /// are no more.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.read_next() {
+            Ok(Some(header)) => Some(Ok(header)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
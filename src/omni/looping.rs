@@ -0,0 +1,60 @@
+//! Sounds that loop by streaming have their samples stored once for each
+//! time they're played, since the engine plays the chunks as they come.
+
+use super::timing::WavLayout;
+
+/// Where the samples of a sound start, and how long one pass of them is
+/// when they're stored `count` times.
+fn one_pass(data: &[u8], count: u32) -> Option<(usize, usize)> {
+    let wav = WavLayout::of(data)?;
+    let stored = data.len().checked_sub(wav.data_start)?;
+
+    // the engine's header doesn't say, so the rest is taken to be the loops
+    let len = match wav.data_len {
+        Some(len) => len.min(stored),
+        None if stored % count as usize == 0 => stored / count as usize,
+        None => return None,
+    };
+
+    Some((wav.data_start, len))
+}
+
+/// Stores the samples of a sound `count` times over, leaving its header
+/// describing one pass. Returns `None` if it isn't a WAV file.
+pub fn expand(data: &[u8], count: u32) -> Option<Vec<u8>> {
+    let (start, len) = one_pass(data, 1)?;
+    let samples = &data[start..start + len];
+
+    let mut out = Vec::with_capacity(data.len() + len * (count as usize).saturating_sub(1));
+    out.extend(&data[..start]);
+    for _ in 0..count {
+        out.extend(samples);
+    }
+    out.extend(&data[start + len..]);
+
+    Some(out)
+}
+
+/// Undoes [`expand`], returning the sound with its samples stored once if
+/// they're stored exactly `count` times.
+pub fn collapse(data: &[u8], count: u32) -> Option<Vec<u8>> {
+    let (start, len) = one_pass(data, count)?;
+    let end = start + len * count as usize;
+
+    if len == 0 || count < 2 || end > data.len() {
+        return None;
+    }
+
+    let samples = &data[start..start + len];
+    if !data[start..end]
+        .chunks_exact(len)
+        .all(|pass| pass == samples)
+    {
+        return None;
+    }
+
+    let mut out = data[..start + len].to_vec();
+    out.extend(&data[end..]);
+
+    Some(out)
+}
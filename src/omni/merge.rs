@@ -0,0 +1,86 @@
+use super::{
+    riff::{List, MxOf, RiffChunk},
+    Omni, PadPolicy,
+};
+use binrw::BinResult;
+use std::collections::{HashMap, HashSet};
+
+impl Omni {
+    /// Moves every stream in `other` into this file, after the ones already
+    /// here. Objects whose IDs are already taken are given the lowest free
+    /// ones instead, and the old and new IDs of each are returned.
+    ///
+    /// The streams keep the layout they had in their own files, so
+    /// [`Omni::interleave`] should be called once everything is merged.
+    pub fn merge(&mut self, other: Omni) -> Vec<(u32, u32)> {
+        let mut taken = self.object_ids().into_iter().collect::<HashSet<_>>();
+        let theirs = other.object_ids();
+
+        // IDs the other file uses can't be handed out either, or one of its
+        // objects could end up sharing an ID with another of its own
+        let mut reserved = taken.clone();
+        reserved.extend(&theirs);
+
+        let mut next = 0;
+        let mut map = HashMap::new();
+        let mut renumbered = vec![];
+
+        for id in theirs {
+            if !taken.insert(id) {
+                while reserved.contains(&next) {
+                    next += 1;
+                }
+
+                reserved.insert(next);
+                taken.insert(next);
+                map.insert(id, next);
+                renumbered.push((id, next));
+            }
+        }
+
+        for mut chunk in other.streams.subchunks {
+            chunk.remap_ids(&map);
+            self.streams.subchunks.push(chunk);
+        }
+
+        renumbered
+    }
+}
+
+impl Omni {
+    /// Splits the file up so that each top-level object, along with its
+    /// children and their data, is in a file of its own with the same
+    /// header. Each comes back with the name of its object.
+    pub fn split(self) -> BinResult<Vec<(String, Omni)>> {
+        self.split_with(&PadPolicy::default())
+    }
+
+    /// Splits the file up like [`Omni::split`], making padding as `policy`
+    /// says.
+    pub fn split_with(self, policy: &PadPolicy) -> BinResult<Vec<(String, Omni)>> {
+        let list_type = self.streams.list_type;
+        let mut files = vec![];
+
+        for chunk in self.streams.subchunks {
+            let RiffChunk::MxSt(st) = &chunk else {
+                continue;
+            };
+
+            let name = st.obj.obj.get_name();
+
+            let mut omni = Omni {
+                endian: self.endian,
+                container_type: self.container_type,
+                header: self.header.clone(),
+                offsets: MxOf::from_offsets(vec![]),
+                streams: List::new(list_type.clone(), vec![chunk])?,
+                extra: vec![],
+            };
+            omni.interleave_with(policy)?;
+
+            files.push((name, omni));
+        }
+
+        Ok(files)
+    }
+}
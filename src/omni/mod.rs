@@ -1,15 +1,110 @@
-use self::riff::{ChunkId, List, MxHd, MxOf, RiffChunk, OMNI_ID, RIFF_ID};
-use binrw::BinRead;
-use std::io::{Read, Seek};
+use self::compile::DEFAULT_BUFFER_SIZE_KB;
+use self::interleave::walk_chunks;
+use self::riff::{
+    begin_chunk, end_chunk, padded, set_origin, set_progress_total, warn, with_parse_options,
+    with_shared, write_sized, LISTType, List, MxHd, MxOf, Riff, LIST_ID, MXHD_ID, MXOF_ID, MXST_ID,
+    OMNI_ID, RIFF_ID, RIFX_ID,
+};
+use crate::diagnostics::{Diagnostic, Location, Severity};
+use binrw::{BinRead, BinResult, BinWrite, Endian};
+use bytes::Bytes;
+use readahead::ReadAhead;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::File,
+    io::{Cursor, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
 use thiserror::Error;
 
+mod builder;
+mod codec;
+mod compile;
+mod extract;
+mod interleave;
+mod iter;
+mod looping;
+mod merge;
+mod patch;
+mod paths;
+mod progress;
+mod readahead;
+mod remap;
+mod report;
+mod rewrite;
 mod riff;
+mod scan;
+mod stats;
+#[cfg(test)]
+mod testing;
+mod timeline;
+mod timing;
+
+pub use builder::{ObjectOptions, OmniBuilder};
+pub use codec::{
+    builtin_codecs, codec_for, codec_named, codecs, register_codec, Chunking, FlcCodec,
+    ResourceCodec, SmkCodec, StlCodec, WavCodec,
+};
+pub use compile::{AudioConversion, CompileError, CompileOptions, Compiled};
+pub use extract::{read_resources, ObjectInfo, RawChunk, Resource};
+pub use interleave::{PadPolicy, Placement};
+pub use iter::{ChunkHeader, ChunkIter};
+pub use patch::{Patch, PatchError};
+pub use paths::{
+    disk_to_stored, find_ignoring_case, replace_prefix, safe_file_name, safe_relative_path,
+    stored_to_disk, ExtractNaming, ExtractPaths,
+};
+pub use progress::{CancelToken, Progress, ProgressHook};
+pub use remap::RemapError;
+pub use rewrite::{PathChange, PathRewrite, RenameError};
+pub use riff::{
+    chunk_handler, encoding, register_chunk_handler, version, with_encoding, with_version,
+    ChunkHandler, ChunkId, CustomChunk, OmniVersion, Payload, RawHandler, RiffChunk,
+};
+pub use scan::{scan, Embedded};
+pub use stats::{ObjectStats, RateWarning, Stats, BUFFER_READS_PER_SECOND};
+pub use timeline::TimelineEntry;
+
+/// Reader for Omni files opened from disk. With the `mmap` feature the file
+/// is mapped into memory, so the seeks made while parsing and extracting
+/// are served from the page cache instead of refilling a buffer each time.
+#[cfg(feature = "mmap")]
+pub type Source = Cursor<memmap2::Mmap>;
+
+#[cfg(not(feature = "mmap"))]
+pub type Source = std::io::BufReader<File>;
+
+/// Opens a file for parsing, mapping it into memory if the `mmap` feature
+/// is enabled.
+pub fn open(path: impl AsRef<Path>) -> std::io::Result<Source> {
+    let file = File::open(path)?;
+
+    #[cfg(feature = "mmap")]
+    {
+        // SAFETY: the map is read-only; if the file is changed underneath
+        // us, parsing sees garbage (and fails) but nothing is unsound on
+        // our side
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(Cursor::new(map))
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    Ok(std::io::BufReader::new(file))
+}
 
 pub struct Omni {
+    /// Byte order of everything in the file: little-endian in RIFF files,
+    /// and big-endian in RIFX ones
+    pub endian: Endian,
     pub container_type: ChunkId,
     pub header: MxHd,
     pub offsets: MxOf,
     pub streams: List,
+    /// Other top-level chunks, each with how many of the header, offset
+    /// table and stream list came before it, so that they're written back
+    /// where they were
+    pub extra: Vec<(usize, RiffChunk)>,
 }
 
 #[derive(Error, Debug)]
@@ -17,29 +112,255 @@ pub enum OmniParseError {
     #[error(transparent)]
     BinRW(#[from] binrw::Error),
 
-    #[error("RIFF chunk not found at beginning of file")]
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("File is truncated (RIFF chunk is {0:#X} bytes, but only {1:#X} are left)")]
+    Truncated(u64, u64),
+
+    #[error("RIFF or RIFX chunk not found at beginning of file")]
     NoRiffChunk,
 
     #[error("Not an Omni file (RIFF chunk type \"{0}\", expected \"OMNI\" or \"MxSt\")")]
     NotOmni(ChunkId),
 
-    #[error("Unknown top-level chunk layout (expected a RIFF chunk with 3 children: MxHd, MxOf, LIST; try dumping the AST to inspect it)")]
+    #[error("Unknown top-level chunk layout (expected a RIFF chunk with MxHd, MxOf and LIST children; try dumping the AST to inspect it)")]
     UnknownLayout,
+
+    #[error("Unexpected {0} chunk in an object list")]
+    UnexpectedChunk(&'static str),
+
+    #[error("Object \"{0}\" is an {1}, which can't be decompiled yet")]
+    UnsupportedObject(String, &'static str),
+
+    #[error("Cancelled")]
+    Cancelled,
 }
 
 pub type Result<T> = std::result::Result<T, OmniParseError>;
 
+#[derive(Error, Debug)]
+pub enum OmniWriteError {
+    #[error(transparent)]
+    BinRW(#[from] binrw::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("Object {0} is stored at offset {1:#X}, which doesn't fit in the MxOf table")]
+    OffsetOverflow(u32, u64),
+}
+
+pub type WriteResult<T> = std::result::Result<T, OmniWriteError>;
+
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Keep whatever can be read from truncated or corrupted files instead
+    /// of failing, with a warning for each part that had to be dropped
+    pub lenient: bool,
+    /// Leave chunk data in the source stream instead of reading it in, so
+    /// that it can be loaded as needed with [`Payload::load`]
+    pub lazy: bool,
+    /// Told about each chunk as it's read, and asked whether to stop
+    pub progress: Option<ProgressHook>,
+    /// Byte order to read files in, whatever their RIFF or RIFX chunk says.
+    /// If not given, it's worked out from that
+    pub endian: Option<Endian>,
+    /// Buffer size, in bytes, to read chunks with until the MxHd chunk
+    /// gives one. If not given, the MxHd chunk is looked for among the
+    /// first few, and it's 64 KB if it isn't there
+    pub buffer_size: Option<i32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseWarning {
+    pub pos: u64,
+    pub message: String,
+}
+
+impl Display for ParseWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#X}: {}", self.pos, self.message)
+    }
+}
+
+impl From<ParseWarning> for Diagnostic {
+    fn from(warning: ParseWarning) -> Self {
+        Diagnostic::new(
+            Severity::Warning,
+            Some(Location::Offset(warning.pos)),
+            warning.message,
+        )
+    }
+}
+
+/// Byte order of a file that starts with a chunk called `id`: little-endian
+/// for RIFF and big-endian for RIFX, unless it's `forced`. Anything else
+/// isn't an Omni file.
+fn root_endian(id: ChunkId, forced: Option<Endian>) -> Option<Endian> {
+    match id {
+        RIFF_ID => Some(forced.unwrap_or(Endian::Little)),
+        RIFX_ID => Some(forced.unwrap_or(Endian::Big)),
+        _ => None,
+    }
+}
+
+/// Most chunks looked through for the MxHd chunk, which is usually the
+/// first one in the file.
+const MAX_PEEKED_CHUNKS: usize = 8;
+
+/// Most entries the MxOf table is written with, which is far more objects
+/// than any file has. The table has one for every ID up to the highest, so
+/// a stray huge ID would otherwise make it gigabytes long.
+const MAX_OFFSET_TABLE: usize = 0x100000;
+
+/// The buffer size in the MxHd chunk of the file at the stream's position,
+/// if it's among the first few chunks in it.
+fn find_buffer_size<T: Read + Seek>(
+    stream: &mut T,
+    forced: Option<Endian>,
+) -> binrw::BinResult<Option<i32>> {
+    let id = ChunkId::read_ne(stream)?;
+    let Some(endian) = root_endian(id, forced) else {
+        return Ok(None);
+    };
+
+    let size = u32::read_options(stream, endian, ())?;
+    let end = stream.stream_position()? + size as u64;
+    // the RIFF type, which chunks follow
+    stream.seek(SeekFrom::Current(size_of::<ChunkId>() as i64))?;
+
+    for _ in 0..MAX_PEEKED_CHUNKS {
+        let at = stream.stream_position()?;
+        if at + 8 > end {
+            break;
+        }
+
+        let (id, size) = <(ChunkId, u32)>::read_options(stream, endian, ())?;
+        if id == MXHD_ID {
+            // the buffer size comes after the version
+            stream.seek(SeekFrom::Current(size_of::<OmniVersion>() as i64))?;
+            let buffer_size = i32::read_options(stream, endian, ())?;
+            return Ok(Some(buffer_size).filter(|&s| s > 0));
+        }
+
+        stream.seek(SeekFrom::Start(at + 8 + padded(size) as u64))?;
+    }
+
+    Ok(None)
+}
+
+/// The buffer size to start reading the file at the stream's position with:
+/// the one given in `options`, or else the one in its MxHd chunk if that
+/// can be found, or else the usual 64 KB.
+fn peek_buffer_size<T: Read + Seek>(stream: &mut T, options: &ParseOptions) -> Result<i32> {
+    let start = stream.stream_position()?;
+    let found = find_buffer_size(stream, options.endian);
+    stream.seek(SeekFrom::Start(start))?;
+
+    // a file too broken to find it in will fail properly when it's read
+    Ok(options
+        .buffer_size
+        .filter(|&s| s > 0)
+        .or(found.ok().flatten())
+        .unwrap_or(DEFAULT_BUFFER_SIZE_KB * 1024))
+}
+
+/// Fails if parsing was stopped through [`ParseOptions::progress`], in
+/// which case whatever error it stopped with is beside the point.
+fn cancelled(options: &ParseOptions) -> Result<()> {
+    match &options.progress {
+        Some(progress) if progress.0.cancelled() => Err(OmniParseError::Cancelled),
+        _ => Ok(()),
+    }
+}
+
 impl Omni {
     pub fn parse<T: Read + Seek>(stream: &mut T) -> Result<Self> {
-        let riff_chunk = RiffChunk::read_args(stream, 0x10000)?;
+        Self::parse_with_options(stream, &ParseOptions::default()).map(|(omni, _)| omni)
+    }
+
+    /// Parses a file, also returning the warnings for anything that was
+    /// skipped in lenient mode.
+    pub fn parse_with_options<T: Read + Seek>(
+        stream: &mut T,
+        options: &ParseOptions,
+    ) -> Result<(Self, Vec<ParseWarning>)> {
+        let (omni, warnings) = with_parse_options(options, || -> Result<_> {
+            let buf_size = peek_buffer_size(stream, options)?;
+            let mut reader = ReadAhead::new(stream, buf_size as usize)?;
+            let omni = Self::parse_root(&mut reader, options);
+            reader.finish()?;
+            omni
+        });
+        cancelled(options)?;
+        Ok((omni?, warnings))
+    }
+
+    /// Parses every RIFF chunk in a file, for rips that have several Omni
+    /// files one after another. Anything after the last one is ignored,
+    /// like it is by [`Omni::parse`].
+    pub fn parse_segments<T: Read + Seek>(
+        stream: &mut T,
+        options: &ParseOptions,
+    ) -> Result<(Vec<Self>, Vec<ParseWarning>)> {
+        let (segments, warnings) = with_parse_options(options, || -> Result<_> {
+            let buf_size = peek_buffer_size(stream, options)?;
+            let mut reader = ReadAhead::new(stream, buf_size as usize)?;
+            let stream = &mut reader;
+            let mut segments = vec![];
+
+            loop {
+                let start = stream.stream_position()?;
+                let segment = Self::parse_root(stream, options)?;
+                let endian = segment.endian;
+                segments.push(segment);
+
+                stream.seek(SeekFrom::Start(start))?;
+                let (_, size) = <(ChunkId, u32)>::read_options(stream, endian, ())?;
+                let next = stream.seek(SeekFrom::Start(start + 8 + padded(size) as u64))?;
+
+                // each segment can be in either byte order
+                match ChunkId::read_ne(stream) {
+                    Ok(id) if root_endian(id, options.endian).is_some() => {
+                        stream.seek(SeekFrom::Start(next))?
+                    }
+                    _ => break,
+                };
+            }
+
+            reader.finish()?;
+            Ok(segments)
+        });
+        cancelled(options)?;
+        Ok((segments?, warnings))
+    }
+
+    fn parse_root<T: Read + Seek>(stream: &mut T, options: &ParseOptions) -> Result<Self> {
+        // nested chunks are checked against their parents as they're read,
+        // so the root just has to fit in what's left of the stream
+        let start = stream.stream_position()?;
+        let end = stream.seek(SeekFrom::End(0))?;
+        let len = end - start;
+        stream.seek(SeekFrom::Start(start))?;
+        set_progress_total(end);
+
+        // buffers are counted from the start of the file, which isn't the
+        // start of the stream for the later files in a rip
+        let buf_size = peek_buffer_size(stream, options)?;
+        set_origin(start);
+
+        // IDs are just bytes, so they read the same in either order
+        let id = ChunkId::read_ne(stream)?;
+        let endian = root_endian(id, options.endian).ok_or(OmniParseError::NoRiffChunk)?;
+        let size = u32::read_options(stream, endian, ())?;
+        stream.seek(SeekFrom::Start(start + size_of::<ChunkId>() as u64))?;
 
-        if !matches!(riff_chunk, RiffChunk::Riff(_)) {
-            return Err(OmniParseError::NoRiffChunk);
+        if size as u64 + 8 > len && !warn(start, OmniParseError::Truncated(size as u64 + 8, len)) {
+            return Err(OmniParseError::Truncated(size as u64 + 8, len));
         }
 
-        let RiffChunk::Riff(root) = riff_chunk else {
-            unreachable!()
-        };
+        let root = Riff::read_options(stream, endian, (buf_size,))?;
 
         /*if root.riff_type != OMNI_ID {
             return Err(OmniParseError::NotOmni(root.riff_type));
@@ -50,21 +371,470 @@ impl Omni {
             _ => return Err(OmniParseError::NotOmni(root.riff_type)),
         }
 
-        if root.subchunks.len() != 3 {
-            return Err(OmniParseError::UnknownLayout);
+        let mut header = None;
+        let mut offsets = None;
+        let mut streams = None;
+        let mut extra = vec![];
+
+        // the three chunks every file has are usually all there is, but
+        // anything else at the top level is kept where it was found
+        for chunk in root.subchunks {
+            let found = [header.is_some(), offsets.is_some(), streams.is_some()];
+
+            match chunk {
+                RiffChunk::MxHd(x) if header.is_none() => header = Some(x),
+                RiffChunk::MxOf(x) if offsets.is_none() => offsets = Some(x),
+                RiffChunk::List(x) if streams.is_none() => streams = Some(x),
+                chunk => extra.push((found.iter().filter(|&&f| f).count(), chunk)),
+            }
         }
 
-        let [RiffChunk::MxHd(header), RiffChunk::MxOf(offsets), RiffChunk::List(streams)]: [RiffChunk; 3] =
-            root.subchunks.try_into().unwrap()
-        else {
-            return Err(OmniParseError::UnknownLayout);
+        let (header, offsets, streams) = match (header, offsets, streams) {
+            (Some(header), Some(offsets), Some(streams)) => (header, offsets, streams),
+            // a truncated file can still be used as long as the header
+            // made it, with whatever else was read
+            (Some(header), offsets, None) if warn(start, "File ends before the stream list") => {
+                let offsets = offsets.unwrap_or_else(|| MxOf::from_offsets(vec![]));
+                let streams = List::new(LISTType::Other(MXST_ID), vec![])?;
+                (header, offsets, streams)
+            }
+            _ => return Err(OmniParseError::UnknownLayout),
         };
 
         Ok(Self {
+            endian,
             container_type: root.riff_type,
             header,
             offsets,
             streams,
+            extra,
         })
     }
+
+    /// Parses a whole file held in memory. Malformed input comes back as an
+    /// error rather than a panic, and no count is trusted beyond what the
+    /// input could actually hold.
+    pub fn parse_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::parse_shared(Bytes::copy_from_slice(bytes), &ParseOptions::default())
+            .map(|(omni, _)| omni)
+    }
+
+    /// Parses a whole file held in memory like [`Omni::parse_with_options`],
+    /// with chunk data kept as slices of `data` instead of being copied.
+    pub fn parse_shared(data: Bytes, options: &ParseOptions) -> Result<(Self, Vec<ParseWarning>)> {
+        with_shared(data.clone(), || {
+            Self::parse_with_options(&mut Cursor::new(data), options)
+        })
+    }
+
+    /// Reads a file into memory (or maps it, with the `mmap` feature) and
+    /// parses it with [`Omni::parse_shared`], so that its chunk data is only
+    /// ever held once.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::load_with_options(path, &ParseOptions::default()).map(|(omni, _)| omni)
+    }
+
+    /// Loads a file like [`Omni::load`], parsing it with `options`.
+    pub fn load_with_options(
+        path: impl AsRef<Path>,
+        options: &ParseOptions,
+    ) -> Result<(Self, Vec<ParseWarning>)> {
+        #[cfg(feature = "mmap")]
+        let data = {
+            let file = File::open(path)?;
+            // SAFETY: as in `open`
+            Bytes::from_owner(unsafe { memmap2::Mmap::map(&file)? })
+        };
+
+        #[cfg(not(feature = "mmap"))]
+        let data = Bytes::from(std::fs::read(path)?);
+
+        Self::parse_shared(data, options)
+    }
+
+    pub fn object_ids(&self) -> Vec<u32> {
+        let mut ids = vec![];
+
+        for chunk in &self.streams.subchunks {
+            chunk.collect_ids(&mut ids);
+        }
+
+        ids
+    }
+
+    /// How many entries the MxOf table is written with. It has one for every
+    /// object ID up to the highest, so that children only reachable through
+    /// their parent still get a (zero) slot.
+    fn offset_table_len(&self) -> BinResult<usize> {
+        let len = self
+            .object_ids()
+            .into_iter()
+            .max()
+            .map_or(0, |id| id as usize + 1);
+
+        if len > MAX_OFFSET_TABLE {
+            return Err(binrw::Error::AssertFail {
+                pos: 0,
+                message: format!("Object ID {:#X} is too high for the MxOf table", len - 1),
+            });
+        }
+
+        Ok(len)
+    }
+
+    /// Where each object's MxOb chunk was read from, by ID.
+    pub fn object_offsets(&self) -> HashMap<u32, u64> {
+        let mut offsets = HashMap::new();
+
+        for chunk in &self.streams.subchunks {
+            chunk.collect_offsets(&mut offsets);
+        }
+
+        offsets
+    }
+
+    /// Each object's flags as they're stored, by ID.
+    pub fn object_flags(&self) -> HashMap<u32, u32> {
+        let mut flags = HashMap::new();
+
+        for chunk in &self.streams.subchunks {
+            chunk.collect_flags(&mut flags);
+        }
+
+        flags
+    }
+
+    /// The extra top-level chunks that go after `n` of the standard ones.
+    fn extra_after(&self, n: usize) -> impl Iterator<Item = &RiffChunk> {
+        self.extra
+            .iter()
+            .filter(move |(at, _)| *at == n)
+            .map(|(_, c)| c)
+    }
+
+    pub fn write<T: Write + Seek>(&self, stream: &mut T) -> WriteResult<()> {
+        with_version(self.header.version, || self.write_root(stream))
+    }
+
+    fn write_root<T: Write + Seek>(&self, stream: &mut T) -> WriteResult<()> {
+        let endian = self.endian;
+        let root_id = match endian {
+            Endian::Little => RIFF_ID,
+            Endian::Big => RIFX_ID,
+        };
+
+        let riff_start = begin_chunk(stream, root_id)?;
+        self.container_type.write_options(stream, endian, ())?;
+
+        for chunk in self.extra_after(0) {
+            chunk.write_options(stream, endian, ())?;
+        }
+
+        MXHD_ID.write_options(stream, endian, ())?;
+        write_sized(&self.header, stream, endian, ())?;
+
+        for chunk in self.extra_after(1) {
+            chunk.write_options(stream, endian, ())?;
+        }
+
+        let mut objects = vec![0; self.offset_table_len()?];
+
+        let offsets_start = stream.stream_position()?;
+        MXOF_ID.write_options(stream, endian, ())?;
+        write_sized(&MxOf::from_offsets(objects.clone()), stream, endian, ())?;
+
+        for chunk in self.extra_after(2) {
+            chunk.write_options(stream, endian, ())?;
+        }
+
+        let list_start = begin_chunk(stream, LIST_ID)?;
+        self.streams.list_type.write_options(stream, endian, ())?;
+
+        for chunk in &self.streams.subchunks {
+            // streams nested in other objects' lists get an entry as well
+            let mut streams = vec![];
+            walk_chunks(
+                std::slice::from_ref(chunk),
+                stream.stream_position()? - riff_start,
+                &mut |chunk, pos| {
+                    if let RiffChunk::MxSt(st) = chunk {
+                        streams.push((st.obj.obj.get_id(), pos))
+                    }
+                },
+            )?;
+
+            for (id, pos) in streams {
+                objects[id as usize] = pos
+                    .try_into()
+                    .map_err(|_| OmniWriteError::OffsetOverflow(id, pos))?;
+            }
+
+            chunk.write_options(stream, endian, ())?;
+        }
+
+        end_chunk(stream, list_start, endian)?;
+
+        for chunk in self.extra_after(3) {
+            chunk.write_options(stream, endian, ())?;
+        }
+
+        end_chunk(stream, riff_start, endian)?;
+
+        let end = stream.stream_position()?;
+        stream.seek(SeekFrom::Start(offsets_start))?;
+        MXOF_ID.write_options(stream, endian, ())?;
+        write_sized(&MxOf::from_offsets(objects), stream, endian, ())?;
+        stream.seek(SeekFrom::Start(end))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        testing::{compile, compile_with, decompile, round_trip},
+        CompileOptions, Omni, ParseOptions, RiffChunk,
+    };
+    use binrw::Endian;
+    use std::io::Cursor;
+
+    const SETTINGS: &str = "defineSettings S { bufferSizeKB = 64; buffersNum = 2; }\n";
+
+    #[test]
+    fn nested_streams_are_in_the_offset_table() {
+        let files = [("e.evt", b"event".as_slice())];
+        let file = compile(
+            &format!(
+                "{SETTINGS}defineEvent E {{ fileName = \"e\"; }}
+                serialAction Inner Weave {{ E; }}
+                parallelAction P {{ Inner; }}
+                serialAction Outer Weave {{ P; }}"
+            ),
+            &files,
+        );
+
+        let omni = Omni::parse_bytes(&file).unwrap();
+        let top_level = omni.streams.subchunks.iter();
+        assert_eq!(
+            top_level
+                .filter(|c| matches!(c, RiffChunk::MxSt(_)))
+                .count(),
+            1
+        );
+
+        let objects = omni.object_ids();
+        let streams = objects
+            .iter()
+            .filter(|&&id| omni.offsets.objects[id as usize] != 0)
+            .count();
+        assert_eq!(streams, 2, "{:?}", omni.offsets.objects);
+
+        for offset in omni.offsets.objects.iter().filter(|&&o| o != 0) {
+            assert_eq!(&file[*offset as usize..][..4], b"MxSt");
+        }
+    }
+
+    #[test]
+    fn huge_ids_are_errors() {
+        let files = [("e.evt", b"event".as_slice())];
+        let script =
+            format!("{SETTINGS}defineEvent E Weave {{ fileName = \"e\"; stream = 2000000000; }}");
+
+        let error = compile_with(&script, &files, CompileOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("too high"), "{error}");
+    }
+
+    fn two_weaves() -> Vec<u8> {
+        let files = [("e.evt", b"event".as_slice())];
+        compile(
+            &format!(
+                "{SETTINGS}defineEvent A {{ fileName = \"e\"; }}
+                defineEvent B {{ fileName = \"e\"; }}
+                serialAction First Weave {{ A; }}
+                parallelAction Second Weave {{ B; }}"
+            ),
+            &files,
+        )
+    }
+
+    #[test]
+    fn offset_tables_cover_every_id() {
+        let file = two_weaves();
+        let omni = Omni::parse_bytes(&file).unwrap();
+        let offsets = &omni.offsets;
+
+        let highest = omni.object_ids().into_iter().max().unwrap();
+        assert_eq!(offsets.objects.len(), highest as usize + 1);
+        assert_eq!(
+            offsets.header.size as usize,
+            4 * (offsets.objects.len() + 1)
+        );
+
+        let streams: Vec<_> = offsets.objects.iter().filter(|&&o| o != 0).collect();
+        assert_eq!(offsets.offset_count as usize, streams.len());
+        assert_eq!(streams.len(), 2, "{:?}", offsets.objects);
+        for &&offset in &streams {
+            assert_eq!(&file[offset as usize..][..4], b"MxSt");
+        }
+    }
+
+    #[test]
+    fn offset_tables_are_rewritten_the_same() {
+        let file = two_weaves();
+        let omni = Omni::parse_bytes(&file).unwrap();
+
+        let mut cursor = Cursor::new(vec![]);
+        omni.write(&mut cursor).unwrap();
+        assert!(cursor.into_inner() == file);
+    }
+
+    #[test]
+    fn offset_tables_survive_decompiling() {
+        let files = [("e.evt", b"event".as_slice())];
+        let file = two_weaves();
+        round_trip(&file, &files);
+
+        let omni = Omni::parse_bytes(&file).unwrap();
+        assert_eq!(omni.offsets.offset_count, 2);
+    }
+
+    fn written(omni: &Omni) -> Vec<u8> {
+        let mut cursor = Cursor::new(vec![]);
+        omni.write(&mut cursor).unwrap();
+        cursor.into_inner()
+    }
+
+    #[test]
+    fn big_endian_files_round_trip() {
+        let files = [("e.evt", b"event".as_slice())];
+        let script = format!(
+            "{SETTINGS}defineEvent E {{ fileName = \"e\"; startTime = 20; }}
+            serialAction T Weave {{ E; }}"
+        );
+        let little = compile(&script, &files);
+        let big = compile_with(
+            &script,
+            &files,
+            CompileOptions {
+                endian: Some(Endian::Big),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(&little[..4], b"RIFF");
+        assert_eq!(&big[..4], b"RIFX");
+        assert_ne!(little, big);
+
+        let mut omni = Omni::parse_bytes(&big).unwrap();
+        assert_eq!(omni.endian, Endian::Big);
+        assert!(written(&omni) == big);
+        assert_eq!(decompile(&big).to_string(), decompile(&little).to_string());
+
+        omni.endian = Endian::Little;
+        assert!(written(&omni) == little);
+    }
+
+    #[test]
+    fn segments_round_trip() {
+        let files = [("e.evt", b"event".as_slice())];
+        let first = compile(
+            &format!(
+                "{SETTINGS}defineEvent E {{ fileName = \"e\"; }} serialAction T Weave {{ E; }}"
+            ),
+            &files,
+        );
+        let second = compile_with(
+            &format!(
+                "{SETTINGS}defineEvent F {{ fileName = \"e\"; }} parallelAction U Weave {{ F; }}"
+            ),
+            &files,
+            CompileOptions {
+                endian: Some(Endian::Big),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let rip = [first.as_slice(), &second, b"junk"].concat();
+        let (segments, _) =
+            Omni::parse_segments(&mut Cursor::new(rip), &ParseOptions::default()).unwrap();
+
+        assert_eq!(segments.len(), 2);
+        assert!(written(&segments[0]) == first);
+        assert!(written(&segments[1]) == second);
+    }
+
+    #[test]
+    fn merged_files_round_trip() {
+        let files = [("e.evt", b"event".as_slice())];
+        let first = compile(
+            &format!(
+                "{SETTINGS}defineEvent E {{ fileName = \"e\"; }} serialAction T Weave {{ E; }}"
+            ),
+            &files,
+        );
+        let second = compile(
+            &format!(
+                "{SETTINGS}defineEvent F {{ fileName = \"e\"; }} parallelAction U Weave {{ F; }}"
+            ),
+            &files,
+        );
+
+        let mut omni = Omni::parse_bytes(&first).unwrap();
+        let theirs = Omni::parse_bytes(&second).unwrap();
+        let clashing = theirs.object_ids().len();
+        assert_eq!(omni.merge(theirs).len(), clashing);
+        omni.interleave().unwrap();
+
+        let merged = written(&omni);
+        let omni = Omni::parse_bytes(&merged).unwrap();
+        let mut ids = omni.object_ids();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 4);
+        assert_eq!(omni.offsets.offset_count, 2);
+
+        let script = round_trip(&merged, &files);
+        for name in ["E", "F", "T", "U"] {
+            assert!(
+                script.contains(&format!(" {name} ")),
+                "no {name} in {script}"
+            );
+        }
+    }
+
+    #[test]
+    fn split_files_round_trip() {
+        let files = [("e.evt", b"event".as_slice())];
+        let file = compile(
+            &format!(
+                "{SETTINGS}defineEvent E {{ fileName = \"e\"; }}
+                defineEvent F {{ fileName = \"e\"; }}
+                serialAction T Weave {{ E; }}
+                parallelAction U Weave {{ F; }}"
+            ),
+            &files,
+        );
+
+        let split = Omni::parse_bytes(&file).unwrap().split().unwrap();
+        let names = split
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["T", "U"]);
+
+        let mut parts = split.iter().map(|(_, omni)| {
+            let part = written(omni);
+            let omni = Omni::parse_bytes(&part).unwrap();
+            assert_eq!(omni.offsets.offset_count, 1);
+            round_trip(&part, &files);
+            omni
+        });
+
+        let mut joined = parts.next().unwrap();
+        assert!(joined.merge(parts.next().unwrap()).is_empty());
+        joined.interleave().unwrap();
+        assert!(written(&joined) == file);
+    }
 }
@@ -0,0 +1,338 @@
+use super::{
+    riff::{with_version, List, MxOb, MxOf, RiffChunk},
+    Omni, OmniParseError, OmniWriteError, MAX_OFFSET_TABLE,
+};
+use binrw::{binrw, BinRead, BinResult, BinWrite};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read, Seek, Write},
+};
+use thiserror::Error;
+
+const PATCH_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum PatchError {
+    #[error(transparent)]
+    BinRW(#[from] binrw::Error),
+
+    #[error(transparent)]
+    Parse(#[from] OmniParseError),
+
+    #[error(transparent)]
+    Write(#[from] OmniWriteError),
+
+    #[error("Not a patch, or a version of one that isn't supported")]
+    NotPatch,
+
+    #[error("The original file has no object {0}, which the patch keeps")]
+    Missing(u32),
+
+    #[error("Object {0} in the original file isn't the one the patch was made from")]
+    Mismatch(u32),
+}
+
+pub type Result<T> = std::result::Result<T, PatchError>;
+
+/// A top-level object of the patched file.
+#[binrw]
+#[derive(Debug, Clone)]
+#[brw(little)]
+struct PatchEntry {
+    id: u32,
+    /// Whether it's in the patch, rather than taken from the original
+    #[br(map = |x: u8| x != 0)]
+    #[bw(map = |x: &bool| *x as u8)]
+    changed: bool,
+    /// Hash of the object as it's taken from the original
+    hash: [u8; 32],
+}
+
+#[binrw]
+#[derive(Debug, Clone)]
+#[brw(little, magic = b"GWPT")]
+struct PatchHeader {
+    version: u32,
+    #[br(assert(count as usize <= MAX_OFFSET_TABLE, "Patch has too many objects ({} entries)", count))]
+    #[bw(try_calc(entries.len().try_into()))]
+    count: u32,
+    #[br(count = count)]
+    entries: Vec<PatchEntry>,
+}
+
+/// What changed between two Omni files, a top-level object at a time. Only
+/// the objects that were added or changed are kept, so a patch can be
+/// handed out without the rest of the file it was made from.
+pub struct Patch {
+    header: PatchHeader,
+    /// The added and changed objects, in a file with the header of the
+    /// modified one
+    changes: Omni,
+}
+
+fn strip_object(ob: &mut MxOb) -> BinResult<()> {
+    if let Some(list) = ob.obj.get_list_mut() {
+        strip_layout(&mut list.subchunks)?;
+        list.update_size()?;
+    }
+
+    ob.update_size()
+}
+
+/// Takes out the padding and clears the split flags, which only depend on
+/// where things are laid out.
+fn strip_layout(chunks: &mut Vec<RiffChunk>) -> BinResult<()> {
+    chunks.retain(|c| !matches!(c, RiffChunk::Pad(_)));
+
+    for chunk in chunks {
+        match chunk {
+            RiffChunk::MxSt(st) => {
+                strip_object(&mut st.obj)?;
+                strip_layout(&mut st.list.subchunks)?;
+                st.update_size()?;
+            }
+            RiffChunk::MxOb(ob) => strip_object(ob)?,
+            RiffChunk::MxCh(ch) => ch.flags.set_split(false),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// The top-level objects of a file by ID, each with a hash of everything
+/// in it apart from its layout.
+fn hash_streams(omni: &Omni) -> BinResult<HashMap<u32, ([u8; 32], &RiffChunk)>> {
+    with_version(omni.header.version, || {
+        let mut streams = HashMap::new();
+
+        for chunk in &omni.streams.subchunks {
+            let RiffChunk::MxSt(st) = chunk else {
+                continue;
+            };
+
+            let mut stripped = vec![chunk.clone()];
+            strip_layout(&mut stripped)?;
+
+            // always hashed little-endian, so that files compare the same
+            // in either byte order
+            let mut data = Cursor::new(vec![]);
+            stripped[0].write_le(&mut data)?;
+
+            let hash = Sha256::digest(data.into_inner()).into();
+            streams.insert(st.obj.obj.get_id(), (hash, chunk));
+        }
+
+        Ok(streams)
+    })
+}
+
+fn top_level_ids(omni: &Omni) -> Vec<u32> {
+    omni.streams
+        .subchunks
+        .iter()
+        .filter_map(|c| match c {
+            RiffChunk::MxSt(st) => Some(st.obj.obj.get_id()),
+            _ => None,
+        })
+        .collect()
+}
+
+impl Patch {
+    /// Finds the top-level objects of `modified` that aren't the same in
+    /// `original`, along with the order they all go in. Objects are matched
+    /// up by ID, and padding and split flags are ignored, so files that only
+    /// differ in how they're laid out make empty patches.
+    pub fn make(original: &Omni, modified: &Omni) -> Result<Self> {
+        let before = hash_streams(original)?;
+        let after = hash_streams(modified)?;
+
+        let mut entries = vec![];
+        let mut changed = vec![];
+
+        for id in top_level_ids(modified) {
+            let (hash, chunk) = after[&id];
+            let same = before.get(&id).is_some_and(|(h, _)| *h == hash);
+
+            if !same {
+                changed.push(chunk.clone());
+            }
+
+            entries.push(PatchEntry {
+                id,
+                changed: !same,
+                hash,
+            });
+        }
+
+        let changes = Omni {
+            endian: modified.endian,
+            container_type: modified.container_type,
+            header: modified.header.clone(),
+            offsets: MxOf::from_offsets(vec![]),
+            streams: List::new(modified.streams.list_type.clone(), changed)?,
+            extra: vec![],
+        };
+
+        Ok(Self {
+            header: PatchHeader {
+                version: PATCH_VERSION,
+                entries,
+            },
+            changes,
+        })
+    }
+
+    /// IDs of the top-level objects that the patch adds or changes.
+    pub fn changed(&self) -> Vec<u32> {
+        top_level_ids(&self.changes)
+    }
+
+    /// IDs of the top-level objects that the patch takes from the original.
+    pub fn kept(&self) -> Vec<u32> {
+        self.header
+            .entries
+            .iter()
+            .filter(|e| !e.changed)
+            .map(|e| e.id)
+            .collect()
+    }
+
+    /// Builds the modified file from `original`, checking that every object
+    /// taken from it is the same as in the file the patch was made from.
+    /// Anything the modified file didn't have is left out.
+    ///
+    /// The result keeps the layout of the files its objects came from, so
+    /// [`Omni::interleave`] should be called before writing it.
+    pub fn apply(mut self, original: &Omni) -> Result<Omni> {
+        let before = hash_streams(original)?;
+        let mut changes = self
+            .changes
+            .streams
+            .subchunks
+            .drain(..)
+            .filter_map(|c| match &c {
+                RiffChunk::MxSt(st) => Some((st.obj.obj.get_id(), c)),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut streams = vec![];
+
+        for entry in &self.header.entries {
+            let chunk = match entry.changed {
+                true => changes.remove(&entry.id).ok_or(PatchError::NotPatch)?,
+                false => {
+                    let (hash, chunk) =
+                        before.get(&entry.id).ok_or(PatchError::Missing(entry.id))?;
+
+                    if *hash != entry.hash {
+                        return Err(PatchError::Mismatch(entry.id));
+                    }
+
+                    (*chunk).clone()
+                }
+            };
+
+            streams.push(chunk);
+        }
+
+        let mut patched = self.changes;
+        patched.streams.subchunks = streams;
+        patched.streams.update_size()?;
+
+        Ok(patched)
+    }
+
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let header = PatchHeader::read(reader).map_err(|_| PatchError::NotPatch)?;
+
+        if header.version != PATCH_VERSION {
+            return Err(PatchError::NotPatch);
+        }
+
+        let changes = Omni::parse(reader)?;
+
+        Ok(Self { header, changes })
+    }
+
+    pub fn write<W: Write + Seek>(&self, writer: &mut W) -> Result<()> {
+        self.header.write(writer)?;
+        self.changes.write(writer)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Patch;
+    use crate::omni::{
+        testing::{compile, compile_with, wav},
+        CompileOptions, Omni, PadPolicy,
+    };
+    use std::io::Cursor;
+
+    fn script(volume: i32) -> String {
+        format!(
+            "defineSettings S {{ bufferSizeKB = 1; buffersNum = 2; }}
+            defineSound A {{ fileName = \"a.wav\"; volume = {volume}; }}
+            defineEvent E {{ fileName = \"e\"; }}
+            serialAction T Weave {{ E; }}"
+        )
+    }
+
+    #[test]
+    fn patches_round_trip() {
+        let wav = wav();
+        let files = [("a.wav", wav.as_slice()), ("e.evt", b"event".as_slice())];
+
+        let original = Omni::parse_bytes(&compile(&script(0x40), &files)).unwrap();
+        let modified_file = compile(&script(0x20), &files);
+        let modified = Omni::parse_bytes(&modified_file).unwrap();
+
+        let patch = Patch::make(&original, &modified).unwrap();
+        assert_eq!(patch.changed().len(), 1);
+        assert_eq!(patch.kept().len(), 1);
+
+        let mut written = Cursor::new(vec![]);
+        patch.write(&mut written).unwrap();
+        written.set_position(0);
+
+        let mut patched = Patch::read(&mut written).unwrap().apply(&original).unwrap();
+        patched.interleave().unwrap();
+
+        let mut cursor = Cursor::new(vec![]);
+        patched.write(&mut cursor).unwrap();
+        assert!(cursor.into_inner() == modified_file);
+    }
+
+    #[test]
+    fn layout_only_changes_make_empty_patches() {
+        let wav = wav();
+        let files = [("a.wav", wav.as_slice()), ("e.evt", b"event".as_slice())];
+        let laid_out = |pack| {
+            let options = CompileOptions {
+                pads: PadPolicy {
+                    pack,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let file = compile_with(&script(0x40), &files, options).unwrap();
+            Omni::parse_bytes(&file).unwrap()
+        };
+
+        let patch = Patch::make(&laid_out(false), &laid_out(true)).unwrap();
+        assert!(patch.changed().is_empty());
+    }
+
+    #[test]
+    fn huge_counts_are_not_patches() {
+        let mut data = b"GWPT".to_vec();
+        data.extend(1u32.to_le_bytes());
+        data.extend(u32::MAX.to_le_bytes());
+
+        assert!(Patch::read(&mut Cursor::new(data)).is_err());
+    }
+}
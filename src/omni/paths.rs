@@ -0,0 +1,382 @@
+//! Mapping between the DOS-style paths stored in Omni files (such as
+//! `\lego\scripts\isle\isle.si`) and paths on disk.
+
+use std::{
+    collections::HashSet,
+    fs::read_dir,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split(['\\', '/']).filter(|c| !c.is_empty())
+}
+
+/// What's left of a stored path once `prefix` is taken off the start of it,
+/// ignoring case and which way the slashes go, or the whole path if it
+/// doesn't start with `prefix`.
+fn strip_prefix<'a>(stored: &'a str, prefix: Option<&str>) -> Vec<&'a str> {
+    let parts = components(stored).collect::<Vec<_>>();
+
+    let Some(prefix) = prefix else {
+        return parts;
+    };
+    let prefix = components(prefix).collect::<Vec<_>>();
+
+    let matches = parts.len() > prefix.len()
+        && parts
+            .iter()
+            .zip(&prefix)
+            .all(|(a, b)| a.eq_ignore_ascii_case(b));
+
+    match matches {
+        true => parts[prefix.len()..].to_vec(),
+        false => parts,
+    }
+}
+
+/// `stored` with `from` at the start of it (ignoring case and which way the
+/// slashes go) replaced by `to`, if it starts with `from`.
+pub fn replace_prefix(stored: &str, from: &str, to: &str) -> Option<String> {
+    let parts = strip_prefix(stored, Some(from));
+    if parts.len() == components(stored).count() {
+        return None;
+    }
+
+    let to = to.trim_end_matches(['\\', '/']);
+    Some(
+        parts
+            .into_iter()
+            .fold(to.into(), |path, part| path + "\\" + part),
+    )
+}
+
+/// Where a stored file goes on disk, relative to the folder files are
+/// extracted to or looked up in.
+pub fn stored_to_disk(stored: &str, prefix: Option<&str>) -> PathBuf {
+    strip_prefix(stored, prefix).into_iter().collect()
+}
+
+/// The stored path of a file on disk, given relative to the folder files
+/// are looked up in, with `prefix` in front of it.
+pub fn disk_to_stored(path: &Path, prefix: Option<&str>) -> String {
+    let parts = path.components().filter_map(|c| match c {
+        Component::Normal(part) => Some(part.to_string_lossy()),
+        _ => None,
+    });
+
+    prefix
+        .into_iter()
+        .flat_map(components)
+        .map(Into::into)
+        .chain(parts)
+        .fold(String::new(), |path, part| path + "\\" + &part)
+}
+
+/// Finds `rel` inside `base` as a case-insensitive file system would, for
+/// stored paths whose case doesn't match the files on disk. Files whose
+/// case does match are found without looking through any folders.
+pub fn find_ignoring_case(base: &Path, rel: &Path) -> PathBuf {
+    let exact = base.join(rel);
+    if exact.exists() {
+        return exact;
+    }
+
+    let mut found = base.to_path_buf();
+
+    for part in rel.components() {
+        let Component::Normal(part) = part else {
+            found.push(part);
+            continue;
+        };
+
+        let matching = read_dir(&found).ok().and_then(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.file_name())
+                .find(|name| {
+                    name.to_string_lossy()
+                        .eq_ignore_ascii_case(&part.to_string_lossy())
+                })
+        });
+
+        found.push(matching.as_deref().unwrap_or(part));
+    }
+
+    found
+}
+
+/// What extracted files are called.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExtractNaming {
+    /// The stored path
+    #[default]
+    Path,
+    /// The object's name, with the stored file's extension
+    Name,
+    /// `ID_name`, with the stored file's extension
+    IdName,
+}
+
+impl FromStr for ExtractNaming {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "path" => Ok(Self::Path),
+            "name" => Ok(Self::Name),
+            "id-name" | "id_name" => Ok(Self::IdName),
+            _ => Err(format!(
+                "Expected \"path\", \"name\" or \"id-name\", found \"{s}\""
+            )),
+        }
+    }
+}
+
+/// Names Windows keeps for devices, which files can't have even with an
+/// extension after them.
+const RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Longest an extracted file or folder's name can be, in bytes, leaving room
+/// under the 255 most file systems allow for an ID to be added to it.
+const MAX_NAME: usize = 240;
+
+/// Longest an extracted file's path can be inside the output folder before
+/// its folders are left out, so that it fits in the 260 characters Windows
+/// allows with room for the output folder.
+const MAX_PATH: usize = 200;
+
+/// `name` cut down to [`MAX_NAME`] bytes, keeping its extension.
+fn shorten(name: &str) -> String {
+    if name.len() <= MAX_NAME {
+        return name.into();
+    }
+
+    let extension = name
+        .rfind('.')
+        .map(|i| &name[i..])
+        .filter(|e| e.len() < MAX_NAME / 2)
+        .unwrap_or_default();
+
+    let mut end = MAX_NAME - extension.len();
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    format!("{}{extension}", &name[..end])
+}
+
+/// One part of a stored path made safe to write on any system, or `None` if
+/// it's `.`, `..` or a drive, which would go somewhere other than the folder
+/// files are extracted to.
+fn safe_part(part: &str) -> Option<String> {
+    let is_drive = part.len() == 2 && part.ends_with(':');
+    if part == "." || part == ".." || is_drive {
+        return None;
+    }
+
+    let part = part
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' | '\\' | '/' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+
+    // Windows takes dots and spaces off the end of names
+    let part = match part.trim_end_matches(['.', ' ']) {
+        "" => "_",
+        trimmed => trimmed,
+    };
+
+    let stem = part.split('.').next().unwrap_or_default().trim_end();
+    let part = match RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        true => format!("_{part}"),
+        false => part.into(),
+    };
+
+    Some(shorten(&part))
+}
+
+/// `name` made safe to use as a file name on any system, with anything that
+/// would make it a path changed to `_`.
+pub fn safe_file_name(name: &str) -> String {
+    safe_part(name).unwrap_or_else(|| "_".into())
+}
+
+/// A path inside an archive or disc image made safe to use under the folder
+/// files are written to, with each part made safe and any that would leave
+/// the folder left out.
+pub fn safe_relative_path(path: &str) -> PathBuf {
+    let parts = path
+        .split(['/', '\\'])
+        .filter(|part| !part.is_empty())
+        .filter_map(safe_part)
+        .collect::<PathBuf>();
+
+    match parts.as_os_str().is_empty() {
+        true => "_".into(),
+        false => parts,
+    }
+}
+
+/// Picks where extracted files go, so that no two end up at the same place
+/// on a case-insensitive file system and none end up outside the folder
+/// they're extracted to. A file whose place is taken gets its object's ID
+/// put on the end of its name, before the extension.
+#[derive(Debug, Default)]
+pub struct ExtractPaths {
+    naming: ExtractNaming,
+    /// Whether to leave out the folders in stored paths
+    flatten: bool,
+    used: HashSet<String>,
+}
+
+impl ExtractPaths {
+    pub fn new(naming: ExtractNaming, flatten: bool) -> Self {
+        Self {
+            naming,
+            flatten,
+            used: HashSet::new(),
+        }
+    }
+
+    /// Where the file stored at `stored` for object `id` called `name` goes,
+    /// relative to the folder files are extracted to, and whether it had to
+    /// be renamed to get there: because a part of its path wasn't safe to
+    /// write, its path was too long, or its place was taken.
+    pub fn path(
+        &mut self,
+        id: u32,
+        name: &str,
+        stored: &str,
+        prefix: Option<&str>,
+    ) -> (PathBuf, bool) {
+        let original = strip_prefix(stored, prefix);
+        let mut parts = original
+            .iter()
+            .filter_map(|part| safe_part(part))
+            .collect::<Vec<_>>();
+        let mut renamed = parts != original;
+
+        let file = parts.pop().unwrap_or_else(|| id.to_string());
+        let (stem, extension) = match file.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => (stem, Some(extension)),
+            _ => (file.as_str(), None),
+        };
+
+        let safe_name = safe_file_name(name);
+        if self.naming != ExtractNaming::Path && !name.is_empty() {
+            renamed |= safe_name != name;
+        }
+
+        let stem = match (self.naming, name.is_empty()) {
+            (ExtractNaming::Path, _) => stem.to_string(),
+            (ExtractNaming::Name, false) => safe_name,
+            (ExtractNaming::IdName, false) => format!("{id}_{safe_name}"),
+            (_, true) => id.to_string(),
+        };
+
+        // an extension too long to keep apart is cut down along with the
+        // rest of the name
+        let (stem, extension) = match extension {
+            Some(extension) if extension.len() < MAX_NAME / 2 => (stem, format!(".{extension}")),
+            Some(extension) => (format!("{stem}.{extension}"), String::new()),
+            None => (stem, String::new()),
+        };
+
+        // the stem is cut down first, so that what's added to it is kept
+        let with_suffix = |suffix: &str| {
+            let mut end = MAX_NAME
+                .saturating_sub(extension.len() + suffix.len())
+                .min(stem.len());
+            while !stem.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            format!("{}{suffix}{extension}", &stem[..end])
+        };
+
+        let too_long =
+            parts.iter().map(|p| p.len() + 1).sum::<usize>() + with_suffix("").len() > MAX_PATH;
+        if self.flatten || too_long {
+            parts.clear();
+            renamed |= too_long;
+        }
+
+        let mut candidates = [String::new(), format!("_{id}")]
+            .into_iter()
+            .chain((2..).map(|n| format!("_{id}_{n}")))
+            .map(|suffix| with_suffix(&suffix));
+
+        let first = candidates.next().unwrap_or_default();
+        let mut take = |file: &String| {
+            let path = parts
+                .iter()
+                .chain([file])
+                .fold(String::new(), |path, part| path + "\\" + part);
+            self.used.insert(path.to_lowercase())
+        };
+
+        let file = match take(&first) {
+            true => first,
+            false => {
+                renamed = true;
+                candidates.find(take).unwrap_or_default()
+            }
+        };
+
+        (parts.into_iter().chain([file]).collect(), renamed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_names_that_clash_get_ids() {
+        let name = "a".repeat(250);
+        let mut paths = ExtractPaths::new(ExtractNaming::Name, false);
+
+        let (first, _) = paths.path(1, &name, "\\sound\\x.wav", None);
+        let (second, renamed) = paths.path(2, &name, "\\sound\\y.wav", None);
+        let (third, _) = paths.path(2, &name, "\\sound\\z.wav", None);
+
+        let names = [&first, &second, &third].map(|p| p.to_string_lossy().into_owned());
+        assert!(renamed);
+        assert!(names
+            .iter()
+            .all(|n| n.len() <= MAX_NAME && n.ends_with(".wav")));
+        assert!(names[1].ends_with("_2.wav"));
+        assert!(names[2].ends_with("_2_2.wav"));
+        assert_eq!(names.iter().collect::<HashSet<_>>().len(), 3);
+    }
+
+    #[test]
+    fn paths_stay_inside_the_folder() {
+        let mut paths = ExtractPaths::new(ExtractNaming::Path, false);
+
+        let (path, renamed) = paths.path(1, "x", "C:\\..\\lego\\CON.wav", None);
+        assert!(renamed);
+        assert_eq!(path, Path::new("lego").join("_CON.wav"));
+        assert_eq!(safe_file_name("../a\\b"), ".._a_b");
+    }
+
+    #[test]
+    fn image_paths_stay_inside_the_folder() {
+        assert_eq!(
+            safe_relative_path("../../etc/passwd"),
+            Path::new("etc").join("passwd")
+        );
+        assert_eq!(
+            safe_relative_path("C:/LEGO/..\\SCRIPTS/NUL.SI"),
+            Path::new("LEGO").join("SCRIPTS").join("_NUL.SI")
+        );
+        assert_eq!(safe_relative_path("/.."), Path::new("_"));
+    }
+}
@@ -0,0 +1,60 @@
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// Something to tell about how a long operation (parsing, extracting or
+/// compiling) is going, such as a progress bar.
+pub trait Progress: Send + Sync {
+    /// Called as work gets done, with the bytes done so far out of `total`
+    /// (0 if that isn't known in advance) and the name of the object being
+    /// worked on, if there is one.
+    fn update(&self, done: u64, total: u64, object: &str);
+
+    /// Whether the operation should stop. It's checked between chunks and
+    /// objects, and stopping makes it fail with a "cancelled" error.
+    fn cancelled(&self) -> bool {
+        false
+    }
+}
+
+impl<F: Fn(u64, u64, &str) + Send + Sync> Progress for F {
+    fn update(&self, done: u64, total: u64, object: &str) {
+        self(done, total, object)
+    }
+}
+
+/// A [`Progress`] to give to [`ParseOptions`](super::ParseOptions) or
+/// [`CompileOptions`](super::CompileOptions).
+#[derive(Clone)]
+pub struct ProgressHook(pub Arc<dyn Progress>);
+
+impl ProgressHook {
+    pub fn new(progress: impl Progress + 'static) -> Self {
+        Self(Arc::new(progress))
+    }
+}
+
+impl Debug for ProgressHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ProgressHook")
+    }
+}
+
+/// A flag that can be set from another thread to stop an operation, for
+/// implementing [`Progress::cancelled`].
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,113 @@
+use std::io::{Read, Result, Seek, SeekFrom};
+
+/// Least that's read at a time. Files with small buffers have their window
+/// made up of several of them.
+const MIN_WINDOW: usize = 0x10000;
+
+/// Reads a stream a window at a time, with windows lined up with the
+/// file's buffers, since that's how the chunks in it are laid out.
+///
+/// Parsing reads a chunk header, seeks back and reads the chunk, and skips
+/// over data it doesn't load, which would otherwise be a read or seek on
+/// the stream each time. Here, seeking only moves the position, and reads
+/// inside the window don't touch the stream at all.
+pub struct ReadAhead<'a, R> {
+    inner: &'a mut R,
+    window: usize,
+    buf: Vec<u8>,
+    /// Where `buf` was read from
+    buf_start: u64,
+    pos: u64,
+    len: u64,
+}
+
+impl<'a, R: Read + Seek> ReadAhead<'a, R> {
+    /// Starts reading from where `inner` is, in windows of at least
+    /// `buf_size` bytes.
+    pub fn new(inner: &'a mut R, buf_size: usize) -> Result<Self> {
+        let pos = inner.stream_position()?;
+        let len = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(pos))?;
+
+        let buf_size = buf_size.max(1);
+
+        Ok(Self {
+            inner,
+            window: MIN_WINDOW.div_ceil(buf_size) * buf_size,
+            buf: vec![],
+            buf_start: 0,
+            pos,
+            len,
+        })
+    }
+
+    /// Leaves the stream where reading got to.
+    pub fn finish(self) -> Result<u64> {
+        self.inner.seek(SeekFrom::Start(self.pos))
+    }
+
+    fn buffered(&self) -> &[u8] {
+        match self.pos.checked_sub(self.buf_start) {
+            Some(offset) if offset < self.buf.len() as u64 => &self.buf[offset as usize..],
+            _ => &[],
+        }
+    }
+
+    fn fill(&mut self) -> Result<()> {
+        self.buf_start = self.pos - self.pos % self.window as u64;
+        self.inner.seek(SeekFrom::Start(self.buf_start))?;
+
+        self.buf.clear();
+        (&mut self.inner)
+            .take(self.window as u64)
+            .read_to_end(&mut self.buf)?;
+
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for ReadAhead<'_, R> {
+    fn read(&mut self, out: &mut [u8]) -> Result<usize> {
+        if self.buffered().is_empty() {
+            // anything as big as the window may as well go straight through
+            if out.len() >= self.window {
+                self.inner.seek(SeekFrom::Start(self.pos))?;
+                let n = self.inner.read(out)?;
+                self.pos += n as u64;
+                return Ok(n);
+            }
+
+            self.fill()?;
+        }
+
+        let buffered = self.buffered();
+        let n = buffered.len().min(out.len());
+        out[..n].copy_from_slice(&buffered[..n]);
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for ReadAhead<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let new = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+        };
+
+        self.pos = new.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Seek to before the start of the stream",
+            )
+        })?;
+
+        Ok(self.pos)
+    }
+
+    fn stream_position(&mut self) -> Result<u64> {
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,186 @@
+use super::{riff::MxOf, Omni, MAX_OFFSET_TABLE};
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RemapError {
+    #[error("There's no object {0} to renumber")]
+    Missing(u32),
+
+    #[error("Object {0} can't be renumbered to {1}, which is already used")]
+    Taken(u32, u32),
+
+    #[error("Object ID {0:#X} is too high for the MxOf table")]
+    TooHigh(u32),
+}
+
+impl Omni {
+    /// Gives the objects in `map` the IDs it maps them to, along with the
+    /// offset table entries and data chunks that refer to them, so that
+    /// clashing IDs can be moved out of the way before merging.
+    ///
+    /// Objects can swap IDs, but none can end up with the ID of an object
+    /// that keeps its own. Nothing is changed if the map can't be applied.
+    pub fn remap_ids(&mut self, map: &HashMap<u32, u32>) -> Result<(), RemapError> {
+        let ids = self.object_ids().into_iter().collect::<HashSet<_>>();
+        let mut used = HashMap::new();
+
+        for &old in map.keys() {
+            if !ids.contains(&old) {
+                return Err(RemapError::Missing(old));
+            }
+        }
+
+        for &id in &ids {
+            let new = map.get(&id).copied().unwrap_or(id);
+
+            if let Some(other) = used.insert(new, id) {
+                // name the object being moved, rather than the one staying
+                let old = if map.contains_key(&id) { id } else { other };
+                return Err(RemapError::Taken(old, new));
+            }
+        }
+
+        if let Some(&max) = used
+            .keys()
+            .filter(|&&id| id as usize >= MAX_OFFSET_TABLE)
+            .max()
+        {
+            return Err(RemapError::TooHigh(max));
+        }
+
+        for chunk in &mut self.streams.subchunks {
+            chunk.remap_ids(map);
+        }
+        for (_, chunk) in &mut self.extra {
+            chunk.remap_ids(map);
+        }
+
+        // the table is rebuilt when writing, but it still orders the blocks
+        // when decompiling straight away
+        let table = &self.offsets.objects;
+        let mut objects = vec![0; used.keys().max().map_or(0, |&id| id as usize + 1)];
+        for (&new, &old) in &used {
+            objects[new as usize] = table.get(old as usize).copied().unwrap_or_default();
+        }
+        self.offsets = MxOf::from_offsets(objects);
+
+        Ok(())
+    }
+
+    /// Renumbers every object in the order they're found, starting from
+    /// `first`, returning the old and new IDs of those that changed.
+    pub fn renumber_from(&mut self, first: u32) -> Result<Vec<(u32, u32)>, RemapError> {
+        let renumbered = self
+            .object_ids()
+            .into_iter()
+            .zip(first..)
+            .filter(|(old, new)| old != new)
+            .collect::<Vec<_>>();
+
+        self.remap_ids(&renumbered.iter().copied().collect())?;
+
+        Ok(renumbered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::omni::{
+        riff::RiffChunk,
+        testing::{compile, wav},
+        Omni,
+    };
+    use std::{collections::HashMap, io::Cursor};
+
+    fn file() -> Vec<u8> {
+        let wav = wav();
+        let files = [("a.wav", wav.as_slice()), ("e.evt", b"event".as_slice())];
+        compile(
+            "defineSettings S { bufferSizeKB = 64; buffersNum = 2; }
+            defineSound A { fileName = \"a.wav\"; }
+            defineSound B { fileName = \"a.wav\"; }
+            defineEvent E { fileName = \"e\"; }
+            serialAction T Weave { A; E; }
+            parallelAction W Weave { B; }",
+            &files,
+        )
+    }
+
+    fn data_chunk_objects(chunks: &[RiffChunk], objects: &mut Vec<u32>) {
+        for chunk in chunks {
+            match chunk {
+                RiffChunk::MxCh(ch) => objects.push(ch.object),
+                RiffChunk::List(list) => data_chunk_objects(&list.subchunks, objects),
+                _ => {}
+            }
+        }
+    }
+
+    /// Writes `omni` out and reads it back, checking that each data chunk
+    /// is in the stream of the object it belongs to, and that the offset
+    /// table points at each stream by its object's ID.
+    fn rewrite(omni: &Omni) -> Omni {
+        let mut cursor = Cursor::new(vec![]);
+        omni.write(&mut cursor).unwrap();
+        let file = cursor.into_inner();
+        let omni = Omni::parse_bytes(&file).unwrap();
+
+        let mut streams = HashMap::new();
+        for chunk in &omni.streams.subchunks {
+            let RiffChunk::MxSt(st) = chunk else {
+                continue;
+            };
+
+            let mut ids = vec![];
+            chunk.collect_ids(&mut ids);
+
+            let mut objects = vec![];
+            data_chunk_objects(&st.list.subchunks, &mut objects);
+            assert!(!objects.is_empty());
+            for object in objects {
+                assert!(ids.contains(&object), "{object} isn't in {ids:?}");
+            }
+
+            streams.insert(st.obj.obj.get_id(), ids);
+        }
+
+        let offsets = omni.object_offsets();
+        let table = &omni.offsets.objects;
+        assert_eq!(table.iter().filter(|&&o| o != 0).count(), streams.len());
+        for (id, &offset) in table.iter().enumerate().filter(|(_, &o)| o != 0) {
+            assert_eq!(&file[offset as usize..][..4], b"MxSt");
+            assert!(streams.contains_key(&(id as u32)), "{id} isn't a stream");
+            assert_eq!(offsets[&(id as u32)], offset as u64 + 8);
+        }
+
+        omni
+    }
+
+    #[test]
+    fn swapped_ids_stay_consistent() {
+        let mut omni = Omni::parse_bytes(&file()).unwrap();
+        let ids = omni.object_ids();
+        let (first, last) = (ids[0], *ids.last().unwrap());
+
+        omni.remap_ids(&HashMap::from([(first, last), (last, first)]))
+            .unwrap();
+
+        let mut swapped = ids.clone();
+        let len = swapped.len();
+        swapped.swap(0, len - 1);
+        assert_eq!(rewrite(&omni).object_ids(), swapped);
+    }
+
+    #[test]
+    fn renumbered_ids_stay_consistent() {
+        let mut omni = Omni::parse_bytes(&file()).unwrap();
+        let count = omni.object_ids().len() as u32;
+
+        omni.renumber_from(100).unwrap();
+        assert_eq!(
+            rewrite(&omni).object_ids(),
+            (100..100 + count).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use super::{ObjectInfo, Omni};
+use crate::{image, types::ObjectFilter};
+use human_bytes::human_bytes;
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    io::{Read, Seek},
+};
+
+/// Width of the timeline and buffer graphs, in pixels.
+const GRAPH_WIDTH: u32 = 800;
+
+/// Width of the object names to the left of the timeline.
+const LABEL_WIDTH: u32 = 200;
+
+/// Height of each object's row in the timeline.
+const ROW_HEIGHT: u32 = 14;
+
+/// Height of the buffer graph.
+const BUFFER_HEIGHT: u32 = 120;
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ccc; padding: 2px 8px; text-align: left; }
+td.n { text-align: right; font-variant-numeric: tabular-nums; }
+ul.tree { list-style: none; padding-left: 1.2em; }
+.kind { color: #888; }
+svg { background: #f8f8f8; border: 1px solid #ccc; }
+figure { display: inline-block; margin: 0 1em 1em 0; }
+figure img { max-width: 160px; image-rendering: pixelated; }
+";
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for group in data.chunks(3) {
+        let bits = group
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, &b)| bits | (b as u32) << (16 - i * 8));
+
+        for i in 0..4 {
+            out.push(match i <= group.len() {
+                true => ALPHABET[(bits >> (18 - i * 6)) as usize & 0x3F] as char,
+                false => '=',
+            });
+        }
+    }
+
+    out
+}
+
+/// A still as a BMP file. Stills are usually stored as a bitmap without the
+/// BMP file header, which is added back so that browsers can show it.
+fn still_to_bmp(data: &[u8]) -> Option<Vec<u8>> {
+    if data.starts_with(b"BM") {
+        return Some(data.to_vec());
+    }
+
+    let header_size = u32::from_le_bytes(data.get(0..4)?.try_into().ok()?);
+    if header_size != 40 {
+        return None;
+    }
+
+    let bit_count = u16::from_le_bytes(data.get(14..16)?.try_into().ok()?);
+    let colours = match u32::from_le_bytes(data.get(32..36)?.try_into().ok()?) {
+        0 if bit_count <= 8 => 1 << bit_count,
+        n => n,
+    };
+    let pixels = 14 + header_size + colours * 4;
+
+    let mut bmp = b"BM".to_vec();
+    bmp.extend((14 + data.len() as u32).to_le_bytes());
+    bmp.extend([0; 4]);
+    bmp.extend(pixels.to_le_bytes());
+    bmp.extend(data);
+
+    Some(bmp)
+}
+
+/// Lists the objects in `parent` and everything inside them. `ancestors`
+/// are the objects it's in, since an ID used twice could otherwise have
+/// itself as a child.
+fn write_tree(
+    out: &mut String,
+    objects: &[ObjectInfo],
+    children: &HashMap<Option<u32>, Vec<usize>>,
+    parent: Option<u32>,
+    ancestors: &mut Vec<u32>,
+) {
+    let Some(indices) = children.get(&parent) else {
+        return;
+    };
+
+    out.push_str("<ul class=\"tree\">\n");
+
+    for &i in indices {
+        let object = &objects[i];
+
+        let _ = write!(
+            out,
+            "<li>{} <span class=\"kind\">{} #{}</span>",
+            escape(&object.name),
+            object.kind,
+            object.id
+        );
+
+        if !ancestors.contains(&object.id) {
+            ancestors.push(object.id);
+            write_tree(out, objects, children, Some(object.id), ancestors);
+            ancestors.pop();
+        }
+
+        out.push_str("</li>\n");
+    }
+
+    out.push_str("</ul>\n");
+}
+
+impl Omni {
+    /// Writes a self-contained HTML page summarising the file: a table and
+    /// tree of its objects, a chart of when each object's chunks are
+    /// streamed, how full each buffer is and thumbnails of its stills. With
+    /// `previews`, the thumbnails are scaled down PNGs, and videos get one
+    /// of their first frame too.
+    ///
+    /// Stills and videos are read from `source`, if they weren't loaded.
+    pub fn report<R: Read + Seek>(
+        &self,
+        source: &mut R,
+        title: &str,
+        previews: bool,
+    ) -> std::io::Result<String> {
+        let objects = self.objects();
+        let stats = self.stats();
+        let timeline = self.timeline();
+
+        let mut out = String::new();
+        let title = escape(title);
+
+        let _ = writeln!(
+            out,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>{title}</title>\n<style>\n{STYLE}</style>\n</head>\n<body>\n\
+             <h1>{title}</h1>\n<p>Version {}, {} buffers of {}, {} objects</p>",
+            self.header.version,
+            self.header.buffer_count,
+            human_bytes(self.header.buffer_size.0 as f64),
+            objects.len()
+        );
+
+        // objects
+
+        let by_id = stats
+            .objects
+            .iter()
+            .map(|o| (o.id, o))
+            .collect::<HashMap<_, _>>();
+
+        out.push_str(
+            "<h2>Objects</h2>\n<table>\n<tr><th>ID</th><th>Type</th><th>Name</th>\
+             <th>File</th><th>Chunks</th><th>Bytes</th><th>Duration</th><th>Peak/s</th></tr>\n",
+        );
+
+        for object in &objects {
+            let _ = write!(
+                out,
+                "<tr><td class=\"n\">{}</td><td>{}</td><td>{}</td><td>{}</td>",
+                object.id,
+                object.kind,
+                escape(&object.name),
+                escape(object.file_name.as_deref().unwrap_or(""))
+            );
+
+            match by_id.get(&object.id) {
+                Some(s) => {
+                    let _ = writeln!(
+                        out,
+                        "<td class=\"n\">{}</td><td class=\"n\">{}</td>\
+                         <td class=\"n\">{:.3}s</td><td class=\"n\">{}</td></tr>",
+                        s.chunks,
+                        human_bytes(s.bytes as f64),
+                        s.duration() as f64 / 1000.0,
+                        human_bytes(s.peak_rate as f64)
+                    );
+                }
+                None => out.push_str("<td></td><td></td><td></td><td></td></tr>\n"),
+            }
+        }
+
+        for orphan in stats.objects.iter().filter(|o| o.kind.is_empty()) {
+            let _ = writeln!(
+                out,
+                "<tr><td class=\"n\">{}</td><td>orphaned</td><td></td><td></td>\
+                 <td class=\"n\">{}</td><td class=\"n\">{}</td><td></td><td></td></tr>",
+                orphan.id,
+                orphan.chunks,
+                human_bytes(orphan.bytes as f64)
+            );
+        }
+
+        out.push_str("</table>\n");
+
+        // hierarchy
+
+        let mut children = HashMap::<_, Vec<_>>::new();
+        for (i, object) in objects.iter().enumerate() {
+            children.entry(object.parent).or_default().push(i);
+        }
+
+        out.push_str("<h2>Hierarchy</h2>\n");
+        write_tree(&mut out, &objects, &children, None, &mut vec![]);
+
+        // timeline
+
+        let end = timeline.iter().map(|e| e.time).max().unwrap_or(0).max(1);
+        let rows = stats
+            .objects
+            .iter()
+            .enumerate()
+            .map(|(i, o)| (o.id, i as u32))
+            .collect::<HashMap<_, _>>();
+        let height = rows.len() as u32 * ROW_HEIGHT;
+
+        let _ = writeln!(
+            out,
+            "<h2>Timeline</h2>\n<p>Each mark is a chunk, from 0 to {:.3}s.</p>\n\
+             <svg width=\"{}\" height=\"{height}\">",
+            end as f64 / 1000.0,
+            LABEL_WIDTH + GRAPH_WIDTH
+        );
+
+        for object in &stats.objects {
+            let _ = writeln!(
+                out,
+                "<text x=\"4\" y=\"{}\" font-size=\"10\">{}</text>",
+                rows[&object.id] * ROW_HEIGHT + ROW_HEIGHT - 3,
+                escape(&match object.kind {
+                    "" => format!("{} (orphaned)", object.id),
+                    _ => format!("{} ({})", object.name, object.id),
+                })
+            );
+        }
+
+        for entry in &timeline {
+            let x = LABEL_WIDTH + (entry.time as u64 * GRAPH_WIDTH as u64 / end as u64) as u32;
+            let _ = writeln!(
+                out,
+                "<rect x=\"{x}\" y=\"{}\" width=\"2\" height=\"{}\" fill=\"{}\"><title>{} ms, {} bytes</title></rect>",
+                rows[&entry.object] * ROW_HEIGHT + 2,
+                ROW_HEIGHT - 4,
+                if entry.end { "#c33" } else { "#36c" },
+                entry.time,
+                entry.size
+            );
+        }
+
+        out.push_str("</svg>\n");
+
+        // buffers
+
+        if let Some(buffers) = stats.buffers.as_ref().filter(|b| !b.is_empty()) {
+            let width = (GRAPH_WIDTH / buffers.len() as u32).max(1);
+
+            let _ = writeln!(
+                out,
+                "<h2>Buffers</h2>\n<p>How much of each {} buffer is chunk data.</p>\n\
+                 <svg width=\"{}\" height=\"{BUFFER_HEIGHT}\">",
+                human_bytes(stats.buffer_size as f64),
+                width * buffers.len() as u32
+            );
+
+            for (i, &used) in buffers.iter().enumerate() {
+                let fill = used as f64 / stats.buffer_size.max(1) as f64;
+                let bar = (fill * BUFFER_HEIGHT as f64) as u32;
+
+                let _ = writeln!(
+                    out,
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{bar}\" fill=\"#6a6\">\
+                     <title>#{i}: {:.1}%</title></rect>",
+                    i as u32 * width,
+                    BUFFER_HEIGHT - bar,
+                    width.saturating_sub(1).max(1),
+                    fill * 100.0
+                );
+            }
+
+            out.push_str("</svg>\n");
+        }
+
+        // stills
+
+        let kinds: &[&str] = match previews {
+            true => &["bitmap", "video"],
+            false => &["bitmap"],
+        };
+        let stills = self
+            .extract(&ObjectFilter::all())
+            .into_iter()
+            .filter(|r| {
+                objects
+                    .iter()
+                    .any(|o| o.id == r.id && kinds.contains(&o.kind))
+            })
+            .collect::<Vec<_>>();
+
+        if !stills.is_empty() {
+            out.push_str(match previews {
+                true => "<h2>Previews</h2>\n",
+                false => "<h2>Stills</h2>\n",
+            });
+
+            for still in stills {
+                let data = still.read(source)?;
+                let image = match previews {
+                    true => image::thumbnail(&data).ok().map(|png| ("png", png)),
+                    false => still_to_bmp(&data).map(|bmp| ("bmp", bmp)),
+                };
+                let Some((format, image)) = image else {
+                    continue;
+                };
+
+                let _ = writeln!(
+                    out,
+                    "<figure><img src=\"data:image/{format};base64,{}\" alt=\"\">\
+                     <figcaption>{} ({})</figcaption></figure>",
+                    base64(&image),
+                    escape(&still.name),
+                    still.id
+                );
+            }
+        }
+
+        out.push_str("</body>\n</html>\n");
+
+        Ok(out)
+    }
+}
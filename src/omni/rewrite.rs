@@ -0,0 +1,199 @@
+use super::{
+    paths::replace_prefix,
+    riff::{with_version, MxOb, MxObType, RiffChunk},
+    CompileError, Omni,
+};
+use regex::Regex;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RenameError {
+    #[error("There's no object called \"{0}\" to rename")]
+    Missing(String),
+
+    #[error("\"{0}\" can't be renamed to \"{1}\", which is already used")]
+    Taken(String, String),
+
+    #[error(transparent)]
+    Compile(#[from] CompileError),
+}
+
+/// A change to make to the stored paths of files.
+#[derive(Debug, Clone)]
+pub enum PathRewrite {
+    /// Replaces a prefix, matched ignoring case and which way the slashes go
+    Prefix(String, String),
+    /// Replaces every match of a regex, with `$1`, `$name` and so on in the
+    /// replacement standing for its groups
+    Regex(Regex, String),
+}
+
+impl PathRewrite {
+    /// `path` with this change made, if it changes anything.
+    pub fn apply(&self, path: &str) -> Option<String> {
+        let new = match self {
+            Self::Prefix(from, to) => replace_prefix(path, from, to)?,
+            Self::Regex(regex, replacement) => regex.replace_all(path, replacement).into_owned(),
+        };
+
+        (new != path).then_some(new)
+    }
+}
+
+/// An object whose stored path was rewritten.
+#[derive(Debug, Clone)]
+pub struct PathChange {
+    pub id: u32,
+    pub name: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// Makes `edit` to an object and each of its children, updating the sizes
+/// of the chunks they're in.
+fn edit_object(
+    ob: &mut MxOb,
+    edit: &mut impl FnMut(&mut MxObType) -> Result<(), CompileError>,
+) -> Result<(), CompileError> {
+    edit(&mut ob.obj)?;
+
+    if let Some(list) = ob.obj.get_list_mut() {
+        edit_chunks(&mut list.subchunks, edit)?;
+        list.update_size()?;
+    }
+
+    Ok(ob.update_size()?)
+}
+
+fn edit_chunks(
+    chunks: &mut [RiffChunk],
+    edit: &mut impl FnMut(&mut MxObType) -> Result<(), CompileError>,
+) -> Result<(), CompileError> {
+    for chunk in chunks {
+        match chunk {
+            RiffChunk::MxSt(st) => {
+                edit_object(&mut st.obj, edit)?;
+                st.update_size()?;
+            }
+            RiffChunk::MxOb(ob) => edit_object(ob, edit)?,
+            RiffChunk::List(list) => {
+                edit_chunks(&mut list.subchunks, edit)?;
+                list.update_size()?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+impl Omni {
+    /// Makes each of `rules` in turn to the stored path of every object
+    /// that has one, returning the paths that changed.
+    ///
+    /// Objects whose paths change length change size, so
+    /// [`Omni::interleave`] should be called before writing the file.
+    pub fn rewrite_paths(
+        &mut self,
+        rules: &[PathRewrite],
+    ) -> Result<Vec<PathChange>, CompileError> {
+        with_version(self.header.version, || {
+            let mut changes = vec![];
+
+            edit_chunks(&mut self.streams.subchunks, &mut |obj| {
+                let Some(from) = obj.get_filename() else {
+                    return Ok(());
+                };
+
+                let to = rules
+                    .iter()
+                    .fold(from.clone(), |path, rule| rule.apply(&path).unwrap_or(path));
+
+                if to != from {
+                    obj.set_filename(&to)?;
+                    changes.push(PathChange {
+                        id: obj.get_id(),
+                        name: obj.get_name(),
+                        from,
+                        to,
+                    });
+                }
+
+                Ok(())
+            })?;
+            self.streams.update_size()?;
+
+            Ok(changes)
+        })
+    }
+
+    /// Renames the first object called `from` to `to`, returning its ID.
+    /// Objects refer to their children by containing them rather than by
+    /// name, so nothing else has to change.
+    ///
+    /// The object changes size, so [`Omni::interleave`] should be called
+    /// before writing the file.
+    pub fn rename_object(&mut self, from: &str, to: &str) -> Result<u32, RenameError> {
+        if self.objects().iter().any(|o| o.name == to) {
+            return Err(RenameError::Taken(from.into(), to.into()));
+        }
+
+        with_version(self.header.version, || {
+            let mut renamed = None;
+
+            edit_chunks(&mut self.streams.subchunks, &mut |obj| {
+                if renamed.is_none() && obj.get_name() == from {
+                    obj.set_name(to)?;
+                    renamed = Some(obj.get_id());
+                }
+
+                Ok(())
+            })?;
+            self.streams.update_size().map_err(CompileError::from)?;
+
+            renamed.ok_or_else(|| RenameError::Missing(from.into()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::omni::testing::{compile, decompile, wav};
+    use std::io::Cursor;
+
+    #[test]
+    fn renamed_objects_keep_their_place() {
+        let wav = wav();
+        let files = [("a.wav", wav.as_slice())];
+        let script = |name: &str| {
+            format!(
+                "defineSettings S {{ bufferSizeKB = 64; buffersNum = 2; }}
+                defineSound {name} {{ fileName = \"a.wav\"; }}
+                parallelAction P {{ {name}; }}
+                serialAction T Weave {{ P; }}"
+            )
+        };
+
+        let mut omni = Omni::parse_bytes(&compile(&script("A"), &files)).unwrap();
+        let id = omni.rename_object("A", "LongerName").unwrap();
+        assert_eq!(id, 2);
+
+        omni.interleave().unwrap();
+        let mut cursor = Cursor::new(vec![]);
+        omni.write(&mut cursor).unwrap();
+        let renamed = cursor.into_inner();
+
+        assert_eq!(renamed, compile(&script("LongerName"), &files));
+        assert!(decompile(&renamed).to_string().contains("\tLongerName;"));
+
+        assert!(matches!(
+            omni.rename_object("A", "B"),
+            Err(RenameError::Missing(_))
+        ));
+        assert!(matches!(
+            omni.rename_object("P", "T"),
+            Err(RenameError::Taken(..))
+        ));
+    }
+}
@@ -0,0 +1,163 @@
+use super::{
+    begin_chunk, end_chunk, padded, read_data, ChunkId, RiffChunkHeader, LIST_ID, MXCH_ID, MXHD_ID,
+    MXOB_ID, MXOF_ID, MXST_ID, RIFF_ID,
+};
+use crate::text::{ToBlock, ToBlockResult};
+use ::bytes::Bytes;
+use binrw::{BinRead, BinResult, BinWrite, Endian};
+use derivative::Derivative;
+use std::{
+    io::{Read, Seek, Write},
+    sync::{Arc, RwLock},
+};
+
+const PAD_ID: ChunkId = ChunkId { value: *b"pad " };
+
+/// Chunks that are always read as what they are, so one that can't be read
+/// is an error rather than a custom chunk.
+const BUILT_IN: &[ChunkId] = &[
+    RIFF_ID, LIST_ID, MXHD_ID, MXOF_ID, MXCH_ID, MXOB_ID, MXST_ID, PAD_ID,
+];
+
+static HANDLERS: RwLock<Vec<(ChunkId, Arc<dyn ChunkHandler>)>> = RwLock::new(vec![]);
+
+/// How chunks with an ID that isn't built in are read, decompiled and
+/// written, such as the extra data chunks that some SI variants have.
+///
+/// Chunks without a handler of their own are dealt with by [`RawHandler`].
+pub trait ChunkHandler: Send + Sync {
+    /// Checks the data of a chunk that's just been read. An error makes
+    /// reading fail, or, in lenient mode, skips the chunk with a warning.
+    fn parse(&self, chunk: &CustomChunk, endian: Endian) -> Result<(), String> {
+        let _ = (chunk, endian);
+        Ok(())
+    }
+
+    /// The block the chunk is decompiled to, along with any that go before
+    /// and after it. By default, there isn't one, so the chunk is left out
+    /// of the script.
+    fn to_block(&self, chunk: &CustomChunk, top_level: bool) -> ToBlockResult {
+        let _ = (chunk, top_level);
+        Ok((None, vec![], vec![]))
+    }
+
+    /// Writes what goes after the chunk's ID and size, which is the data as
+    /// it was read by default. The size is filled in afterwards, but files
+    /// are laid out with the one in the chunk's header, so the two should
+    /// match.
+    fn write(&self, chunk: &CustomChunk, writer: &mut dyn Write, endian: Endian) -> BinResult<()> {
+        let _ = endian;
+        Ok(writer.write_all(&chunk.data)?)
+    }
+}
+
+/// Keeps a chunk's data as it is, so that it's written back unchanged and
+/// left out when decompiling.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RawHandler;
+
+impl ChunkHandler for RawHandler {}
+
+/// Has chunks with `id` dealt with by `handler` from now on, in place of
+/// any handler it had. Built-in chunks (RIFF, LIST, MxHd, MxOf, MxCh, MxOb,
+/// MxSt and pad) are always read as they are, so giving one a handler does
+/// nothing and returns false.
+pub fn register_chunk_handler(id: ChunkId, handler: impl ChunkHandler + 'static) -> bool {
+    if BUILT_IN.contains(&id) {
+        return false;
+    }
+
+    let mut handlers = HANDLERS.write().unwrap_or_else(|e| e.into_inner());
+    handlers.retain(|(other, _)| *other != id);
+    handlers.push((id, Arc::new(handler)));
+    true
+}
+
+/// The handler for chunks with `id`.
+pub fn chunk_handler(id: ChunkId) -> Arc<dyn ChunkHandler> {
+    HANDLERS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .find(|(other, _)| *other == id)
+        .map_or_else(|| Arc::new(RawHandler) as _, |(_, handler)| handler.clone())
+}
+
+/// A chunk with an ID that isn't built in, kept as it was read and dealt
+/// with by the [`ChunkHandler`] for its ID.
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct CustomChunk {
+    pub id: ChunkId,
+    pub header: RiffChunkHeader,
+    #[derivative(Debug = "ignore")]
+    pub data: Bytes,
+}
+
+impl CustomChunk {
+    pub fn new(id: ChunkId, data: Bytes) -> Self {
+        Self {
+            id,
+            header: RiffChunkHeader {
+                size: padded(data.len() as u32),
+            },
+            data,
+        }
+    }
+}
+
+impl BinRead for CustomChunk {
+    type Args<'a> = ();
+
+    fn read_options<R: Read + Seek>(reader: &mut R, endian: Endian, _: ()) -> BinResult<Self> {
+        let pos = reader.stream_position()?;
+        let id = ChunkId::read_options(reader, endian, ())?;
+
+        if BUILT_IN.contains(&id) {
+            return Err(binrw::Error::AssertFail {
+                pos,
+                message: format!("\"{id}\" chunk isn't a custom one"),
+            });
+        }
+
+        let size = u32::read_options(reader, endian, ())?;
+        let data = read_data(reader, size as usize)?;
+        // like the built-in chunks, the pad byte is counted in the size
+        // that's kept, and skipped over by whatever reads the chunk
+        let chunk = Self {
+            id,
+            header: RiffChunkHeader { size: padded(size) },
+            data,
+        };
+
+        chunk_handler(id)
+            .parse(&chunk, endian)
+            .map_err(|message| binrw::Error::AssertFail {
+                pos,
+                message: format!("Invalid \"{id}\" chunk: {message}"),
+            })?;
+
+        Ok(chunk)
+    }
+}
+
+impl BinWrite for CustomChunk {
+    type Args<'a> = ();
+
+    fn write_options<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        endian: Endian,
+        _: (),
+    ) -> BinResult<()> {
+        let start = begin_chunk(writer, self.id)?;
+        chunk_handler(self.id).write(self, writer, endian)?;
+        end_chunk(writer, start, endian)
+    }
+}
+
+impl ToBlock for CustomChunk {
+    fn to_block(&self, top_level: bool) -> ToBlockResult {
+        chunk_handler(self.id).to_block(self, top_level)
+    }
+}
@@ -1,18 +1,38 @@
-use crate::text::{Block, BlockType::*, RValue, Statement::*, ToBlock};
+use crate::{
+    text::{Block, BlockType::*, RValue, Statement::*, ToBlock, ToBlockResult},
+    types::Encoding,
+};
 
-use self::{mxob::MxOb, mxst::MxSt};
-use binrw::{binrw, parser, BinRead, BinResult};
-use bytes::HumanBytes;
+use self::bytes::HumanBytes;
+pub use self::{
+    custom::{chunk_handler, register_chunk_handler, ChunkHandler, CustomChunk, RawHandler},
+    mxob::{MxOb, MxObType},
+    mxst::{MxSt, MXDA_ID},
+};
+use super::{
+    compile::{CompileError, Compiler, FromBlock, DEFAULT_BUFFER_COUNT, DEFAULT_BUFFER_SIZE_KB},
+    OmniParseError, ParseOptions, ParseWarning, ProgressHook,
+};
+use ::bytes::Bytes;
+use binrw::{binrw, parser, writer, BinRead, BinResult, BinWrite, Endian};
 use derivative::Derivative;
 use modular_bitfield::prelude::*;
 use std::{
-    cell::RefCell,
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::HashMap,
     fmt::{Debug, Display},
-    io::SeekFrom::{Current, Start},
+    io::{
+        Cursor, Read, Seek,
+        SeekFrom::{Current, Start},
+        Write,
+    },
     mem::size_of,
+    str::FromStr,
 };
 
 mod bytes;
+mod custom;
 mod mxob;
 mod mxst;
 
@@ -23,8 +43,169 @@ pub struct ChunkId {
 }
 
 pub const RIFF_ID: ChunkId = ChunkId { value: *b"RIFF" };
+pub const RIFX_ID: ChunkId = ChunkId { value: *b"RIFX" };
 pub const OMNI_ID: ChunkId = ChunkId { value: *b"OMNI" };
 pub const MXST_ID: ChunkId = ChunkId { value: *b"MxSt" };
+pub const LIST_ID: ChunkId = ChunkId { value: *b"LIST" };
+pub const MXHD_ID: ChunkId = ChunkId { value: *b"MxHd" };
+pub const MXOF_ID: ChunkId = ChunkId { value: *b"MxOf" };
+pub const MXCH_ID: ChunkId = ChunkId { value: *b"MxCh" };
+pub const MXOB_ID: ChunkId = ChunkId { value: *b"MxOb" };
+
+/// Longest Act/RAND selection list that will be read. Each entry picks one
+/// child, so anything near this is a corrupted count.
+const MAX_LIST_COUNT: u32 = 0x10000;
+
+/// Deepest nesting of chunk lists that will be read, so that a corrupted
+/// file can't recurse until the stack runs out.
+const MAX_DEPTH: u32 = 64;
+
+thread_local! {
+    static DEPTH: Cell<u32> = const { Cell::new(0) };
+    static LENIENT: Cell<bool> = const { Cell::new(false) };
+    static LAZY: Cell<bool> = const { Cell::new(false) };
+    static WARNINGS: RefCell<Vec<ParseWarning>> = const { RefCell::new(vec![]) };
+    static ENCODING: Cell<Encoding> = const { Cell::new(Encoding::Windows1252) };
+    static VERSION: Cell<OmniVersion> = const { Cell::new(OmniVersion::V2_2) };
+    static PROGRESS: RefCell<Option<ProgressHook>> = const { RefCell::new(None) };
+    static PROGRESS_TOTAL: Cell<u64> = const { Cell::new(0) };
+    static ORIGIN: Cell<u64> = const { Cell::new(0) };
+    static SHARED: RefCell<Option<Bytes>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with objects read and written in the layout of `version`.
+pub fn with_version<T>(version: OmniVersion, f: impl FnOnce() -> T) -> T {
+    let outer = VERSION.replace(version);
+    let rv = f();
+    VERSION.set(outer);
+    rv
+}
+
+/// Format version that objects are currently read and written in.
+pub fn version() -> OmniVersion {
+    VERSION.get()
+}
+
+/// Runs `f` with stored strings read and written in `encoding`.
+pub fn with_encoding<T>(encoding: Encoding, f: impl FnOnce() -> T) -> T {
+    let outer = ENCODING.replace(encoding);
+    let rv = f();
+    ENCODING.set(outer);
+    rv
+}
+
+/// Code page that stored strings are currently read and written in.
+pub fn encoding() -> Encoding {
+    ENCODING.get()
+}
+
+/// Runs `f` with chunks read according to `options`, returning whatever it
+/// returned along with the warnings for anything that had to be skipped.
+pub fn with_parse_options<T>(
+    options: &ParseOptions,
+    f: impl FnOnce() -> T,
+) -> (T, Vec<ParseWarning>) {
+    let outer_lenient = LENIENT.replace(options.lenient);
+    let outer_lazy = LAZY.replace(options.lazy);
+    let outer_warnings = WARNINGS.take();
+    // until a header says otherwise
+    let outer_version = VERSION.replace(OmniVersion::default());
+    let outer_progress = PROGRESS.replace(options.progress.clone());
+    let outer_total = PROGRESS_TOTAL.replace(0);
+    let outer_origin = ORIGIN.replace(0);
+
+    let rv = f();
+
+    PROGRESS.set(outer_progress);
+    PROGRESS_TOTAL.set(outer_total);
+    ORIGIN.set(outer_origin);
+    LENIENT.set(outer_lenient);
+    LAZY.set(outer_lazy);
+    VERSION.set(outer_version);
+    (rv, WARNINGS.replace(outer_warnings))
+}
+
+/// Runs `f` with chunk data taken as slices of `data` instead of being
+/// copied out of the stream, which has to be reading `data` from its start.
+pub fn with_shared<T>(data: Bytes, f: impl FnOnce() -> T) -> T {
+    let outer = SHARED.replace(Some(data));
+    let rv = f();
+    SHARED.set(outer);
+    rv
+}
+
+/// Reads `len` bytes of chunk data, as a slice of the buffer being parsed
+/// if there is one.
+fn read_data<R: Read + Seek>(reader: &mut R, len: usize) -> BinResult<Bytes> {
+    let pos = reader.stream_position()?;
+
+    let shared = SHARED.with_borrow(|shared| {
+        let shared = shared.as_ref()?;
+        let start = usize::try_from(pos).ok()?;
+        (start.checked_add(len)? <= shared.len()).then(|| shared.slice(start..start + len))
+    });
+
+    match shared {
+        Some(data) => {
+            reader.seek(Current(len as i64))?;
+            Ok(data)
+        }
+        None => {
+            let mut data = vec![0; len];
+            reader.read_exact(&mut data)?;
+            Ok(data.into())
+        }
+    }
+}
+
+#[parser(reader)]
+fn parse_data(len: u32) -> BinResult<Bytes> {
+    read_data(reader, len as usize)
+}
+
+#[writer(writer)]
+fn write_data(data: &Bytes) -> BinResult<()> {
+    writer.write_all(data)?;
+    Ok(())
+}
+
+/// Sets how long the file being parsed is, for progress updates.
+pub fn set_progress_total(total: u64) {
+    PROGRESS_TOTAL.set(total)
+}
+
+/// Sets where the file being parsed starts in the stream, which its buffers
+/// are counted from.
+pub fn set_origin(pos: u64) {
+    ORIGIN.set(pos)
+}
+
+/// Tells the progress hook (if there is one) that reading has got to `pos`,
+/// and whether it wants to stop.
+fn report_progress(pos: u64, object: &str) -> bool {
+    PROGRESS.with_borrow(|p| match p {
+        Some(progress) => {
+            progress.0.update(pos, PROGRESS_TOTAL.get(), object);
+            progress.0.cancelled()
+        }
+        None => false,
+    })
+}
+
+/// In lenient mode, records a problem and returns true so that reading can
+/// carry on; otherwise, returns false so that it can be treated as an error.
+pub fn warn(pos: u64, message: impl Display) -> bool {
+    if LENIENT.get() {
+        WARNINGS.with_borrow_mut(|w| {
+            w.push(ParseWarning {
+                pos,
+                message: message.to_string(),
+            })
+        });
+    }
+
+    LENIENT.get()
+}
 
 impl Display for ChunkId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -38,10 +219,17 @@ impl Debug for ChunkId {
     }
 }
 
+/// `size` rounded up to an even number. Chunks start on even boundaries,
+/// so one with an odd size is followed by a pad byte, which is counted in
+/// the sizes kept in [`RiffChunkHeader`]s but not in the ones written.
+pub const fn padded(size: u32) -> u32 {
+    size.saturating_add(1) & !1
+}
+
 #[binrw]
 #[derive(Debug, Clone)]
 pub struct RiffChunkHeader {
-    #[br(map(|x: u32| ((x + 1) & !1)))]
+    #[br(map(padded))]
     pub size: u32,
 }
 
@@ -53,19 +241,22 @@ pub struct DummyRiffChunk {
     pub hdr: RiffChunkHeader,
     #[br(if(hdr.size >= 4))]
     pub sub_type: Option<ChunkId>,
-    #[br(count(hdr.size - if sub_type.is_some() {4} else {0}))]
+    #[br(parse_with = parse_data, args(hdr.size - if sub_type.is_some() {4} else {0}))]
+    #[bw(write_with = write_data)]
     #[derivative(Debug = "ignore")]
-    pub data: Vec<u8>,
+    pub data: Bytes,
 }
 
 #[binrw]
 #[derive(Debug, Clone)]
 #[br(import(buf_size: i32))]
 pub struct Riff {
+    #[br(assert(header.size >= 4, "RIFF chunk too small ({:#X} bytes)", header.size))]
     pub header: RiffChunkHeader,
     pub riff_type: ChunkId,
     #[br(parse_with(read_chunks))]
     #[br(args(header.size - 4, buf_size))]
+    #[bw(write_with(write_chunks))]
     pub subchunks: Vec<RiffChunk>,
 }
 
@@ -74,19 +265,22 @@ pub struct Riff {
 pub struct ActListCount {
     #[br(temp)]
     #[bw(try_calc(values.len().try_into()))]
+    #[br(assert(count <= MAX_LIST_COUNT, "Act list too long ({} entries)", count))]
     count: u32,
     #[br(count(count))]
-    values: Vec<u16>,
+    pub values: Vec<u16>,
 }
+
 #[binrw]
 #[derive(Debug, Clone)]
 pub struct RandListCount {
-    rand_upper: u32,
+    pub rand_upper: u32,
     #[br(temp)]
     #[bw(try_calc(values.len().try_into()))]
+    #[br(assert(count <= MAX_LIST_COUNT, "RAND list too long ({} entries)", count))]
     count: u32,
     #[br(count(count))]
-    values: Vec<u16>,
+    pub values: Vec<u16>,
 }
 
 #[binrw]
@@ -95,14 +289,28 @@ pub enum ListCount {
     #[brw(magic(b"Act\0"))]
     Act(ActListCount),
     #[brw(magic(b"RAND"))]
-    Rand(u32, u32),
+    Rand(RandListCount),
     Count(u32),
 }
 
+impl ListCount {
+    pub fn size(&self) -> u32 {
+        (match self {
+            Self::Act(a) => {
+                size_of::<ChunkId>() + size_of::<u32>() + a.values.len() * size_of::<u16>()
+            }
+            Self::Rand(r) => {
+                size_of::<ChunkId>() + 2 * size_of::<u32>() + r.values.len() * size_of::<u16>()
+            }
+            Self::Count(_) => size_of::<u32>(),
+        }) as u32
+    }
+}
+
 #[binrw]
 #[derive(Debug, Clone)]
 pub struct MxChList {
-    list_count: ListCount,
+    pub list_count: ListCount,
 }
 
 #[binrw]
@@ -113,24 +321,107 @@ pub enum LISTType {
     Other(ChunkId),
 }
 
+impl LISTType {
+    pub fn size(&self) -> u32 {
+        size_of::<ChunkId>() as u32
+            + match self {
+                Self::MxCh(l) => l.list_count.size(),
+                Self::Other(_) => 0,
+            }
+    }
+}
+
 #[binrw]
 #[derive(Debug, Clone)]
 #[br(import(buf_size: i32))]
 pub struct List {
     pub header: RiffChunkHeader,
+    #[br(assert(header.size >= list_type.size(), "LIST chunk too small ({:#X} bytes)", header.size))]
     pub list_type: LISTType,
     #[br(parse_with(read_chunks))]
-    #[br(args(header.size - match &list_type { LISTType::MxCh(l) => { match l.list_count { ListCount::Act(_) => todo!(), ListCount::Rand(_, _) => 8, ListCount::Count(_) => 8 } }, LISTType::Other(_) => 4 }, buf_size))]
+    #[br(args(header.size - list_type.size(), buf_size))]
+    #[bw(write_with(write_chunks))]
     pub subchunks: Vec<RiffChunk>,
 }
 
+impl List {
+    pub fn new(list_type: LISTType, subchunks: Vec<RiffChunk>) -> BinResult<Self> {
+        let mut list = Self {
+            header: RiffChunkHeader { size: 0 },
+            list_type,
+            subchunks,
+        };
+        list.update_size()?;
+        Ok(list)
+    }
+
+    pub fn update_size(&mut self) -> BinResult<()> {
+        self.header.size = data_size(self)?;
+        Ok(())
+    }
+}
+
 #[binrw]
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct OmniVersion {
     pub hi: u16,
     pub lo: u16,
 }
 
+impl OmniVersion {
+    /// What LEGO Island uses, and the only version its engine will load
+    pub const V2_2: Self = Self { hi: 2, lo: 2 };
+
+    /// Versions whose object layouts are known. Before 2.2, media objects
+    /// don't store a sustain time.
+    pub const KNOWN: &'static [Self] = &[Self { hi: 2, lo: 1 }, Self::V2_2];
+
+    pub fn is_known(&self) -> bool {
+        Self::KNOWN.contains(self)
+    }
+
+    /// Whether media objects have a sustain time.
+    pub fn has_sustain_time(&self) -> bool {
+        *self >= Self::V2_2
+    }
+}
+
+impl Default for OmniVersion {
+    fn default() -> Self {
+        Self::V2_2
+    }
+}
+
+impl FromStr for OmniVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let version = s
+            .trim_start_matches(['v', 'V'])
+            .split_once('.')
+            .and_then(|(hi, lo)| {
+                Some(Self {
+                    hi: hi.parse().ok()?,
+                    lo: lo.parse().ok()?,
+                })
+            })
+            .ok_or_else(|| format!("Expected a version such as \"2.2\", found \"{s}\""))?;
+
+        if !version.is_known() {
+            let known = Self::KNOWN
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>();
+            return Err(format!(
+                "Unknown version {version} (known: {})",
+                known.join(", ")
+            ));
+        }
+
+        Ok(version)
+    }
+}
+
 impl Display for OmniVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "v{}.{}", self.hi, self.lo)
@@ -147,14 +438,20 @@ impl Debug for OmniVersion {
 #[derive(Debug, Clone)]
 pub struct MxHd {
     pub header: RiffChunkHeader,
+    // everything after the header is read in its layout
+    #[br(map(|v: OmniVersion| {
+        VERSION.set(v);
+        v
+    }))]
     pub version: OmniVersion,
+    #[br(assert(buffer_size.0 > 0, "Invalid buffer size {}", buffer_size.0))]
     pub buffer_size: HumanBytes<i32>,
     pub buffer_count: i32,
 }
 
 impl ToBlock for MxHd {
-    fn to_block(&self, _: bool) -> (Option<Block>, Vec<Block>, Vec<Block>) {
-        (
+    fn to_block(&self, _: bool) -> ToBlockResult {
+        Ok((
             Some(Block {
                 id: u32::MAX,
                 block_type: DefineSettings,
@@ -167,10 +464,77 @@ impl ToBlock for MxHd {
                     ),
                     Assignment("buffersNum".into(), RValue::Integer(self.buffer_count)),
                 ],
+                span: None,
+                statement_spans: vec![],
             }),
             vec![],
             vec![],
-        )
+        ))
+    }
+}
+
+impl FromBlock for MxHd {
+    fn from_block(block: &Block, compiler: &mut Compiler) -> Result<Self, CompileError> {
+        let mut buffer_size = None;
+        let mut buffer_count = None;
+
+        for statement in &block.statements {
+            match statement {
+                Assignment(name, RValue::Integer(i)) if name == "bufferSizeKB" => {
+                    buffer_size = Some(*i)
+                }
+                Assignment(name, RValue::Integer(i)) if name == "buffersNum" => {
+                    buffer_count = Some(*i)
+                }
+                Assignment(name, _) if name == "bufferSizeKB" || name == "buffersNum" => {
+                    return Err(CompileError::WrongType(block.name.clone(), name.clone()))
+                }
+                // settings have nowhere to keep them, so they're left out
+                Assignment(name, _) | Declaration(name) => compiler.tolerate(
+                    CompileError::UnknownField(block.name.clone(), name.clone()).at(block),
+                )?,
+            }
+        }
+
+        let options = compiler.options();
+        let buffer_size = options.buffer_size.or(buffer_size);
+        let buffer_count = options.buffer_count.or(buffer_count);
+
+        let buffer_size = match buffer_size {
+            Some(size) => size,
+            None => {
+                compiler.tolerate(
+                    CompileError::MissingField(block.name.clone(), "bufferSizeKB").at(block),
+                )?;
+                DEFAULT_BUFFER_SIZE_KB
+            }
+        };
+        let buffer_count = match buffer_count {
+            Some(count) => count,
+            None => {
+                compiler.tolerate(
+                    CompileError::MissingField(block.name.clone(), "buffersNum").at(block),
+                )?;
+                DEFAULT_BUFFER_COUNT
+            }
+        };
+        let options = compiler.options();
+
+        if buffer_size <= 0 || buffer_count <= 0 {
+            return Err(CompileError::BadBufferGeometry(buffer_size, buffer_count));
+        }
+        let buffer_bytes = buffer_size
+            .checked_mul(1024)
+            .ok_or(CompileError::BufferTooLarge(buffer_size))?;
+
+        Ok(Self {
+            header: RiffChunkHeader {
+                size: (size_of::<OmniVersion>() + 2 * size_of::<i32>()) as u32,
+            },
+            version: options.target_version,
+            buffer_size: HumanBytes(buffer_bytes),
+            buffer_count,
+        })
     }
 }
 
@@ -178,31 +542,56 @@ impl ToBlock for MxHd {
 #[derive(Derivative, Clone)]
 #[derivative(Debug)]
 pub struct MxOf {
+    #[br(assert(header.size >= 4, "MxOf chunk too small ({:#X} bytes)", header.size))]
     pub header: RiffChunkHeader,
     pub offset_count: u32,
     #[br(count((header.size as usize - 4)/size_of::<u32>()))]
     pub objects: Vec<u32>,
 }
 
+impl MxOf {
+    /// Builds an offset table indexed by object ID; entries for objects that
+    /// aren't stored at the top level are left as 0.
+    pub fn from_offsets(objects: Vec<u32>) -> Self {
+        Self {
+            header: RiffChunkHeader {
+                size: (size_of::<u32>() * (objects.len() + 1)) as u32,
+            },
+            offset_count: objects.iter().filter(|&&o| o != 0).count() as u32,
+            objects,
+        }
+    }
+}
+
 #[bitfield]
 #[binrw]
-#[br(map(Self::from_bytes))]
+#[br(map(|bits: u16| Self::from_bytes(bits.to_le_bytes())))]
+#[bw(map(Self::bits))]
 #[derive(Debug, Clone)]
 #[repr(u16)]
 pub struct MxChFlags {
     unk0: B1,
-    end: bool,
+    /// Marks the empty chunk that ends an object's data
+    pub end: bool,
     unk1: B2,
-    split: bool,
+    /// Marks a chunk that runs past the end of the buffer it starts in
+    pub split: bool,
     unk2: B3,
     unk3: B8,
 }
 
+impl MxChFlags {
+    /// The raw bits, including the ones without names.
+    pub fn bits(&self) -> u16 {
+        u16::from_le_bytes(self.clone().into_bytes())
+    }
+}
+
 #[binrw]
 #[derive(Derivative, Clone)]
 #[derivative(Debug)]
-#[brw(little)]
 pub struct MxCh {
+    #[br(assert(header.size >= 14, "MxCh chunk too small ({:#X} bytes)", header.size))]
     pub header: RiffChunkHeader,
     pub flags: MxChFlags,
     pub object: u32,
@@ -210,9 +599,122 @@ pub struct MxCh {
     #[br(temp)]
     #[bw(try_calc((data.len() + if !data.is_empty() { 2 * size_of::<u32>() } else { 0 }).try_into()))]
     size: u32,
-    #[br(count(header.size - 14))]
+    #[br(args_raw(header.size - 14))]
     #[derivative(Debug = "ignore")]
-    pub data: Vec<u8>,
+    pub data: Payload,
+}
+
+impl MxCh {
+    /// Size of the fields between the chunk header and the payload.
+    pub const HEADER_SIZE: usize = 14;
+
+    /// A chunk of `data`, padded to an even length the way the payloads in
+    /// existing files are, so that it reads back the same.
+    pub fn new(object: u32, time: u32, mut data: Vec<u8>) -> Self {
+        data.resize(padded(data.len() as u32) as usize, 0);
+
+        Self {
+            header: RiffChunkHeader {
+                size: (Self::HEADER_SIZE + data.len()) as u32,
+            },
+            flags: MxChFlags::new(),
+            object,
+            time,
+            data: Payload::Loaded(data.into()),
+        }
+    }
+
+    /// The empty chunk that comes after the last of an object's data.
+    pub fn end_of_stream(object: u32, time: u32) -> Self {
+        let mut chunk = Self::new(object, time, vec![]);
+        chunk.flags.set_end(true);
+        chunk
+    }
+}
+
+/// The data of an MxCh chunk. Lazily-parsed files only note where the data
+/// is, so that it can be read from the source when it's needed.
+#[derive(Debug, Clone)]
+pub enum Payload {
+    /// Data that was read in, which shares memory with the buffer it was
+    /// parsed from if there was one
+    Loaded(Bytes),
+    Lazy {
+        offset: u64,
+        len: u32,
+    },
+}
+
+impl Payload {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Loaded(data) => data.len(),
+            Self::Lazy { len, .. } => *len as usize,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Where the data is in the source stream, if it wasn't loaded.
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            Self::Loaded(_) => None,
+            Self::Lazy { offset, .. } => Some(*offset),
+        }
+    }
+
+    /// Gets the data, reading it from `source` (the stream the file was
+    /// parsed from) if it hasn't been loaded.
+    pub fn load<R: Read + Seek>(&self, source: &mut R) -> std::io::Result<Cow<'_, [u8]>> {
+        match self {
+            Self::Loaded(data) => Ok(Cow::Borrowed(data)),
+            Self::Lazy { offset, len } => {
+                let mut data = vec![0; *len as usize];
+                source.seek(Start(*offset))?;
+                source.read_exact(&mut data)?;
+                Ok(Cow::Owned(data))
+            }
+        }
+    }
+}
+
+impl BinRead for Payload {
+    type Args<'a> = u32;
+
+    fn read_options<R: Read + Seek>(
+        reader: &mut R,
+        _: Endian,
+        len: Self::Args<'_>,
+    ) -> BinResult<Self> {
+        if LAZY.get() {
+            let offset = reader.stream_position()?;
+            reader.seek(Current(len as i64))?;
+            Ok(Self::Lazy { offset, len })
+        } else {
+            Ok(Self::Loaded(read_data(reader, len as usize)?))
+        }
+    }
+}
+
+impl BinWrite for Payload {
+    type Args<'a> = ();
+
+    fn write_options<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        _: Endian,
+        _: Self::Args<'_>,
+    ) -> BinResult<()> {
+        match self {
+            Self::Loaded(data) => Ok(writer.write_all(data)?),
+            Self::Lazy { .. } => Err(binrw::Error::AssertFail {
+                pos: writer.stream_position()?,
+                message: "Can't write chunk data that hasn't been loaded".into(),
+            }),
+        }
+    }
 }
 
 #[binrw]
@@ -220,40 +722,69 @@ pub struct MxCh {
 #[derivative(Debug)]
 pub struct Pad {
     pub header: RiffChunkHeader,
-    #[br(count(header.size))]
+    #[br(parse_with = parse_data, args(header.size))]
+    #[bw(write_with = write_data)]
     #[derivative(Debug = "ignore")]
-    pub data: Vec<u8>,
+    pub data: Bytes,
+}
+
+impl Pad {
+    pub fn new(data: Bytes) -> Self {
+        Self {
+            header: RiffChunkHeader {
+                size: data.len() as u32,
+            },
+            data,
+        }
+    }
 }
 
 #[binrw]
 #[derive(Debug, Clone)]
-#[brw(little)]
 #[br(import_raw(buf_size: i32))]
 pub enum RiffChunk {
-    #[br(magic(b"RIFF"))]
-    Riff(#[br(args(buf_size))] Riff),
+    #[brw(magic(b"RIFF"))]
+    Riff(
+        #[br(args(buf_size))]
+        #[bw(write_with(write_sized))]
+        Riff,
+    ),
+
+    #[brw(magic(b"LIST"))]
+    List(
+        #[br(args(buf_size))]
+        #[bw(write_with(write_sized))]
+        List,
+    ),
 
-    #[br(magic(b"LIST"))]
-    List(#[br(args(buf_size))] List),
+    #[brw(magic(b"MxHd"))]
+    MxHd(#[bw(write_with(write_sized))] MxHd),
 
-    #[br(magic(b"MxHd"))]
-    MxHd(MxHd),
+    #[brw(magic(b"MxOf"))]
+    MxOf(#[bw(write_with(write_sized))] MxOf),
 
-    #[br(magic(b"MxOf"))]
-    MxOf(MxOf),
+    #[brw(magic(b"MxCh"))]
+    MxCh(#[bw(write_with(write_sized))] MxCh),
 
-    #[br(magic(b"MxCh"))]
-    MxCh(MxCh),
+    #[brw(magic(b"MxOb"))]
+    MxOb(
+        #[br(args(buf_size))]
+        #[bw(write_with(write_sized))]
+        Box<MxOb>,
+    ),
 
-    #[br(magic(b"MxOb"))]
-    MxOb(#[br(args(buf_size))] Box<MxOb>),
+    #[brw(magic(b"MxSt"))]
+    MxSt(
+        #[br(args(buf_size))]
+        #[bw(write_with(write_sized))]
+        Box<MxSt>,
+    ),
 
-    #[br(magic(b"MxSt"))]
-    MxSt(#[br(args(buf_size))] Box<MxSt>),
+    #[brw(magic(b"pad "))]
+    Pad(#[bw(write_with(write_sized))] Pad),
 
-    #[br(magic(b"pad "))]
-    Pad(Pad),
-    //Unknown(DummyRiffChunk),
+    /// Any other chunk, which reads its own ID
+    Custom(Box<CustomChunk>),
 }
 
 impl RiffChunk {
@@ -267,42 +798,152 @@ impl RiffChunk {
             Self::MxOb(x) => x.header.size,
             Self::MxSt(x) => x.header.size,
             Self::Pad(x) => x.header.size,
-            //RiffChunk::Unknown(x) => x.hdr.size,
+            Self::Custom(x) => x.header.size,
         }
     }
 
-    pub fn get_name(&self) -> String {
+    /// Name of the object this chunk holds, if it holds one.
+    pub fn get_name(&self) -> Option<String> {
         match self {
-            Self::Riff(x) => unreachable!(),
-            Self::List(x) => unreachable!(),
-            Self::MxHd(x) => unreachable!(),
-            Self::MxOf(x) => unreachable!(),
-            Self::MxCh(x) => unreachable!(),
-            Self::MxOb(x) => x.obj.get_name(),
-            Self::MxSt(x) => unreachable!(),
-            Self::Pad(x) => unreachable!(),
-            //RiffChunk::Unknown(x) => x.hdr.size,
+            Self::MxOb(x) => Some(x.obj.get_name()),
+            Self::MxSt(x) => Some(x.obj.obj.get_name()),
+            _ => None,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Riff(_) => "RIFF",
+            Self::List(_) => "LIST",
+            Self::MxHd(_) => "MxHd",
+            Self::MxOf(_) => "MxOf",
+            Self::MxCh(_) => "MxCh",
+            Self::MxOb(_) => "MxOb",
+            Self::MxSt(_) => "MxSt",
+            Self::Pad(_) => "pad",
+            Self::Custom(_) => "custom",
+        }
+    }
+
+    pub fn collect_ids(&self, ids: &mut Vec<u32>) {
+        match self {
+            Self::MxOb(x) => x.obj.collect_ids(ids),
+            Self::MxSt(x) => x.obj.obj.collect_ids(ids),
+            Self::List(x) => x.subchunks.iter().for_each(|c| c.collect_ids(ids)),
+            _ => {}
+        }
+    }
+
+    pub fn collect_offsets(&self, offsets: &mut HashMap<u32, u64>) {
+        match self {
+            Self::MxOb(x) => x.collect_offsets(offsets),
+            Self::MxSt(x) => x.obj.collect_offsets(offsets),
+            Self::List(x) => x.subchunks.iter().for_each(|c| c.collect_offsets(offsets)),
+            _ => {}
+        }
+    }
+
+    pub fn collect_flags(&self, flags: &mut HashMap<u32, u32>) {
+        match self {
+            Self::MxOb(x) => x.collect_flags(flags),
+            Self::MxSt(x) => x.obj.collect_flags(flags),
+            Self::List(x) => x.subchunks.iter().for_each(|c| c.collect_flags(flags)),
+            _ => {}
+        }
+    }
+
+    /// Renumbers the objects in this chunk, and the data chunks that belong
+    /// to them, according to `map`.
+    pub fn remap_ids(&mut self, map: &HashMap<u32, u32>) {
+        match self {
+            Self::MxOb(x) => x.obj.remap_ids(map),
+            Self::MxSt(x) => {
+                x.obj.obj.remap_ids(map);
+                x.list.subchunks.iter_mut().for_each(|c| c.remap_ids(map));
+            }
+            Self::List(x) => x.subchunks.iter_mut().for_each(|c| c.remap_ids(map)),
+            Self::MxCh(x) => {
+                if let Some(&new) = map.get(&x.object) {
+                    x.object = new;
+                }
+            }
+            _ => {}
         }
     }
 }
 
 impl ToBlock for RiffChunk {
-    fn to_block(&self, top_level: bool) -> (Option<Block>, Vec<Block>, Vec<Block>) {
+    fn to_block(&self, top_level: bool) -> ToBlockResult {
         match self {
-            Self::Riff(_) => todo!(),
-            Self::List(_) => todo!(),
             Self::MxHd(x) => x.to_block(top_level),
-            Self::MxOf(_) => todo!(),
-            Self::MxCh(_) => todo!(),
             Self::MxOb(x) => x.to_block(top_level),
             Self::MxSt(x) => x.to_block(top_level),
-            Self::Pad(_) => (None, vec![], vec![]),
+            Self::Pad(_) => Ok((None, vec![], vec![])),
+            Self::Custom(x) => x.to_block(top_level),
+            Self::Riff(_) | Self::List(_) | Self::MxOf(_) | Self::MxCh(_) => {
+                Err(OmniParseError::UnexpectedChunk(self.kind()))
+            }
         }
     }
 }
 
+/// Number of bytes `value` occupies when written, which is the same in
+/// either byte order.
+pub fn serialized_size<T>(value: &T) -> BinResult<u32>
+where
+    T: BinWrite,
+    for<'a> T::Args<'a>: Default,
+{
+    let mut cursor = Cursor::new(vec![]);
+    value.write_le(&mut cursor)?;
+    Ok(cursor.into_inner().len() as u32)
+}
+
+/// The size to keep in the [`RiffChunkHeader`] that `value` starts with: the
+/// number of bytes after it, [`padded`].
+pub fn data_size<T>(value: &T) -> BinResult<u32>
+where
+    T: BinWrite,
+    for<'a> T::Args<'a>: Default,
+{
+    Ok(padded(
+        serialized_size(value)? - size_of::<RiffChunkHeader>() as u32,
+    ))
+}
+
+/// Position of the chunk being read, for fields that come straight after
+/// its ID.
+#[parser(reader)]
+pub fn chunk_start() -> BinResult<u64> {
+    Ok(reader
+        .stream_position()?
+        .saturating_sub(size_of::<ChunkId>() as u64))
+}
+
 #[parser(reader, endian)]
-pub fn read_chunks(size: u32, mut buf_size: i32) -> BinResult<Vec<RiffChunk>> {
+pub fn read_chunks(size: u32, buf_size: i32) -> BinResult<Vec<RiffChunk>> {
+    let depth = DEPTH.get();
+
+    if depth >= MAX_DEPTH {
+        return Err(binrw::Error::AssertFail {
+            pos: reader.stream_position()?,
+            message: format!("Chunks nested more than {MAX_DEPTH} deep"),
+        });
+    }
+
+    DEPTH.set(depth + 1);
+    let rv = read_chunk_list(reader, endian, size, buf_size);
+    DEPTH.set(depth);
+
+    rv
+}
+
+fn read_chunk_list<R: Read + Seek>(
+    reader: &mut R,
+    endian: Endian,
+    size: u32,
+    mut buf_size: i32,
+) -> BinResult<Vec<RiffChunk>> {
     let mut rv = vec![];
 
     let max_pos = reader.stream_position()? + size as u64;
@@ -315,12 +956,37 @@ pub fn read_chunks(size: u32, mut buf_size: i32) -> BinResult<Vec<RiffChunk>> {
         //println!("\tchunk: {:X}", reader.stream_position()?);
         let before = reader.stream_position()?;
 
-        let pos_in_buffer = before as i32 % buf_size;
+        let pos_in_buffer = ((before - ORIGIN.get()) % buf_size as u64) as i32;
         if pos_in_buffer + 8 > buf_size {
             reader.seek(Current((buf_size - pos_in_buffer) as i64))?;
             continue;
         }
 
+        // a chunk can't run past the end of its parent, which keeps every
+        // count taken from a corrupted size within the input
+        let (id, chunk_size) = match <(ChunkId, u32)>::read_options(reader, endian, ()) {
+            Ok(header) => header,
+            Err(e) if e.is_eof() => {
+                warn(before, "File ends in the middle of a chunk header");
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        reader.seek(Start(before))?;
+
+        if before + 8 + chunk_size as u64 > max_pos {
+            let message = format!("Chunk of {chunk_size:#X} bytes runs past the end of its parent");
+
+            if warn(before, &message) {
+                break;
+            }
+
+            return Err(binrw::Error::AssertFail {
+                pos: before,
+                message,
+            });
+        }
+
         let chunk = RiffChunk::read_options(reader, endian, buf_size);
         /*if reader.stream_position()? % 2 != 0 && !packed {
             reader.seek(Current(1))?;
@@ -342,9 +1008,29 @@ pub fn read_chunks(size: u32, mut buf_size: i32) -> BinResult<Vec<RiffChunk>> {
                     buf_size = hd.buffer_size.0
                 }
 
+                let name = match &c {
+                    RiffChunk::MxOb(ob) => ob.obj.get_name(),
+                    RiffChunk::MxSt(st) => st.obj.obj.get_name(),
+                    _ => String::new(),
+                };
+
+                if report_progress(reader.stream_position()?, &name) {
+                    return Err(binrw::Error::AssertFail {
+                        pos: before,
+                        message: "Cancelled".into(),
+                    });
+                }
+
                 rv.push(c);
             }
-            Err(e) if e.is_eof() => break,
+            Err(e) if e.is_eof() => {
+                warn(before, "File ends in the middle of a chunk");
+                break;
+            }
+            // skip over the chunk, since its size has already been checked
+            Err(_) if warn(before, format!("Skipped unreadable \"{id}\" chunk")) => {
+                reader.seek(Start(before + 8 + padded(chunk_size) as u64))?;
+            }
             Err(e) => return Err(e),
         }
     }
@@ -359,3 +1045,83 @@ pub fn read_chunks(size: u32, mut buf_size: i32) -> BinResult<Vec<RiffChunk>> {
 
     Ok(rv)
 }
+
+#[writer(writer, endian)]
+pub fn write_chunks(chunks: &Vec<RiffChunk>) -> BinResult<()> {
+    for chunk in chunks {
+        chunk.write_options(writer, endian, ())?;
+    }
+
+    Ok(())
+}
+
+/// Writes a chunk body that starts with a [`RiffChunkHeader`], then
+/// back-patches the header with the number of bytes actually written, so
+/// a stale size in the tree can never end up in the file.
+pub fn write_sized<T, W>(value: &T, writer: &mut W, endian: Endian, _: ()) -> BinResult<()>
+where
+    T: for<'a> BinWrite<Args<'a> = ()>,
+    W: Write + Seek,
+{
+    let start = writer.stream_position()?;
+    value.write_options(writer, endian, ())?;
+    let end = writer.stream_position()?;
+
+    let size = end - start - size_of::<RiffChunkHeader>() as u64;
+    let Ok(patched) = u32::try_from(size) else {
+        return Err(binrw::Error::AssertFail {
+            pos: start,
+            message: format!("Chunk of {size:#X} bytes is too big for its header"),
+        });
+    };
+
+    writer.seek(Start(start))?;
+    patched.write_options(writer, endian, ())?;
+    writer.seek(Start(end))?;
+
+    write_pad(writer, size)
+}
+
+/// Writes the pad byte after a chunk with `size` bytes of data, if it needs
+/// one.
+fn write_pad<W: Write + Seek>(writer: &mut W, size: u64) -> BinResult<()> {
+    if !size.is_multiple_of(2) {
+        writer.write_all(&[0])?;
+    }
+
+    Ok(())
+}
+
+/// Writes a chunk ID followed by a placeholder size, returning the position
+/// of the chunk so that [`end_chunk`] can fill the size in afterwards.
+pub fn begin_chunk<W: Write + Seek>(writer: &mut W, id: ChunkId) -> BinResult<u64> {
+    let start = writer.stream_position()?;
+    writer.write_all(&id.value)?;
+    writer.write_all(&[0; 4])?;
+    Ok(start)
+}
+
+/// Back-patches the size of a chunk started with [`begin_chunk`], in
+/// `endian` order, and pads its data to an even length.
+pub fn end_chunk<W: Write + Seek>(writer: &mut W, start: u64, endian: Endian) -> BinResult<()> {
+    let end = writer.stream_position()?;
+    let size = end - start - (size_of::<ChunkId>() + size_of::<u32>()) as u64;
+
+    writer.seek(Start(start + size_of::<ChunkId>() as u64))?;
+    (size as u32).write_options(writer, endian, ())?;
+    writer.seek(Start(end))?;
+
+    write_pad(writer, size)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::omni::{testing::compile_with, CompileOptions};
+
+    #[test]
+    fn huge_buffers_are_errors() {
+        let script = "defineSettings S { bufferSizeKB = 3000000; buffersNum = 2; }";
+        let error = compile_with(script, &[], CompileOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("too large"), "{error}");
+    }
+}
@@ -1,15 +1,27 @@
 use std::{
+    collections::HashMap,
     fmt::{Debug, Display},
     io::Cursor,
-    mem::size_of,
 };
 
-use super::{read_chunks, List, RiffChunk};
+use super::{
+    chunk_start, data_size, read_chunks, write_sized, ActListCount, LISTType, List, ListCount,
+    MxChList, RandListCount, RiffChunk,
+};
 use crate::{
-    omni::riff::{HumanBytes, OmniVersion, RiffChunkHeader},
+    omni::{
+        compile::{CompileError, Compiler, FromBlock},
+        riff::{encoding, version, HumanBytes, OmniVersion, RiffChunkHeader},
+        OmniParseError,
+    },
     text::{
-        Block, BlockType::*, Definition, Duration, LoopingMethod, PaletteManagement, RValue,
-        Statement::*, ToBlock, Transparency,
+        encode_externals, extra_key, join_extra,
+        schema::{self, non_default, Defaults},
+        Argument, Block, BlockType,
+        BlockType::*,
+        Definition, Duration, Function, LoopingMethod, PaletteManagement, RValue, Statement,
+        Statement::*,
+        ToBlock, ToBlockResult, Transparency,
     },
     types::Vec3,
 };
@@ -20,9 +32,10 @@ use modular_bitfield::prelude::*;
 pub struct ExtraString(Option<NullString>);
 
 impl ExtraString {
+    /// Stored length, including the null terminator.
     pub fn len(&self) -> usize {
         match &self.0 {
-            Some(s) => s.len(),
+            Some(s) => s.len() + 1,
             None => 0,
         }
     }
@@ -32,10 +45,31 @@ impl ExtraString {
     }
 }
 
+impl TryFrom<Option<String>> for ExtraString {
+    type Error = CompileError;
+
+    fn try_from(value: Option<String>) -> Result<Self, Self::Error> {
+        value.as_deref().map(encode).transpose().map(Self)
+    }
+}
+
+/// A stored string, decoded from the current encoding.
+fn decode(s: &NullString) -> String {
+    encoding().decode(&s.0)
+}
+
+/// A string to store, encoded in the current encoding.
+fn encode(s: &str) -> Result<NullString, CompileError> {
+    encoding()
+        .encode(s)
+        .map(NullString)
+        .ok_or_else(|| CompileError::Encoding(s.into()))
+}
+
 impl Display for ExtraString {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self.0 {
-            Some(s) => write!(f, "{}", s),
+            Some(s) => write!(f, "{}", decode(s)),
             None => write!(f, ""),
         }
     }
@@ -87,6 +121,426 @@ impl BinWrite for ExtraString {
     }
 }
 
+/// Statements of an object block, gathered up before the object is built.
+#[derive(Default)]
+struct Fields {
+    handler_class: Option<String>,
+    file_name: Option<String>,
+    location: Option<Vec3>,
+    direction: Option<Vec3>,
+    up: Option<Vec3>,
+    start_time: Option<i32>,
+    duration: Option<i32>,
+    loop_count: Option<i32>,
+    looping_method: Option<LoopingMethod>,
+    palette_management: Option<PaletteManagement>,
+    transparency: Option<Transparency>,
+    volume: Option<i32>,
+    sustain_time: Option<i32>,
+    flags: Option<u32>,
+    flag_bits: Vec<(u32, bool)>,
+    extra: Option<String>,
+    selection_list: Option<Vec<u16>>,
+    random_count: Option<u32>,
+    children: Vec<String>,
+}
+
+impl Fields {
+    fn from_block(block: &Block, compiler: &mut Compiler) -> Result<Self, CompileError> {
+        let mut fields = Self::default();
+        let mut extra_entries = vec![];
+
+        for statement in &block.statements {
+            let (name, value) = match statement {
+                Declaration(child) => {
+                    fields.children.push(child.clone());
+                    continue;
+                }
+                Assignment(name, value) => (name.as_str(), value),
+            };
+
+            if let Some(key) = extra_key(name) {
+                let value = match value {
+                    RValue::String(s) => s.clone(),
+                    RValue::Integer(i) => i.to_string(),
+                    _ => return Err(CompileError::WrongType(block.name.clone(), name.into())),
+                };
+
+                extra_entries.push((key.to_string(), value));
+                continue;
+            }
+
+            match (name, value) {
+                ("handlerClass", RValue::String(s)) => fields.handler_class = Some(s.clone()),
+                ("fileName", RValue::String(s)) => fields.file_name = Some(s.clone()),
+                ("location", RValue::Vec3(v)) => fields.location = Some(*v),
+                ("direction", RValue::Vec3(v)) => fields.direction = Some(*v),
+                ("up", RValue::Vec3(v)) => fields.up = Some(*v),
+                ("startTime", RValue::Integer(i)) => fields.start_time = Some(*i),
+                ("duration", RValue::Integer(i)) => fields.duration = Some(*i),
+                ("duration", RValue::Definition(Definition::Duration(d))) => {
+                    fields.duration = Some(d.0)
+                }
+                ("loopCount", RValue::Integer(i)) => fields.loop_count = Some(*i),
+                ("loopingMethod", RValue::Definition(Definition::LoopingMethod(l))) => {
+                    fields.looping_method = Some(l.clone())
+                }
+                ("paletteManagement", RValue::Definition(Definition::PaletteManagement(p))) => {
+                    fields.palette_management = Some(p.clone())
+                }
+                // NONE is read as a looping method, since that's tried first
+                (
+                    "paletteManagement",
+                    RValue::Definition(Definition::LoopingMethod(LoopingMethod::None)),
+                ) => fields.palette_management = Some(PaletteManagement::None),
+                // no flag bit is known to stand for it, so it's left out
+                (
+                    "transparency",
+                    RValue::Definition(Definition::Transparency(Transparency::Fast)),
+                ) => compiler.tolerate(
+                    CompileError::UnknownValue(block.name.clone(), name.into(), value.to_string())
+                        .at(block),
+                )?,
+                ("transparency", RValue::Definition(Definition::Transparency(t))) => {
+                    fields.transparency = Some(t.clone())
+                }
+                ("volume", RValue::Integer(i)) => fields.volume = Some(*i),
+                ("volume", RValue::Volume(v)) => fields.volume = Some(v.level()),
+                ("sustainTime", RValue::Integer(i)) => fields.sustain_time = Some(*i),
+                ("flags", RValue::Bits(b)) => fields.flags = Some(*b),
+                ("flags", RValue::Integer(i)) => fields.flags = Some(*i as u32),
+                (_, RValue::Integer(i @ (0 | 1))) if flag_bit(name).is_some() => fields
+                    .flag_bits
+                    .push((flag_bit(name).unwrap_or_default(), *i == 1)),
+                ("extra" | "entityName", RValue::String(s)) => fields.extra = Some(s.clone()),
+                // already turned into the block's ID by Text::assign_ids
+                ("stream", RValue::Integer(_)) => {}
+                ("selectionList", RValue::Function(f)) if f.name == "list" => {
+                    fields.selection_list = Some(
+                        f.args
+                            .iter()
+                            .map(|a| match a {
+                                Argument::Integer(i) => u16::try_from(*i).ok(),
+                                Argument::String(s) => s.parse().ok(),
+                                _ => None,
+                            })
+                            .collect::<Option<_>>()
+                            .ok_or_else(|| {
+                                CompileError::WrongType(block.name.clone(), name.into())
+                            })?,
+                    )
+                }
+                ("randomCount", RValue::Integer(i)) => {
+                    fields.random_count = Some(u32::try_from(*i).map_err(|_| {
+                        CompileError::UnknownValue(block.name.clone(), name.into(), i.to_string())
+                    })?)
+                }
+                // left out, so the object gets the default
+                ("loopingMethod" | "paletteManagement" | "transparency", _) => compiler.tolerate(
+                    CompileError::UnknownValue(block.name.clone(), name.into(), value.to_string())
+                        .at(block),
+                )?,
+                _ if schema::field(name).is_some() => {
+                    return Err(CompileError::WrongType(block.name.clone(), name.into()))
+                }
+                // kept in the extra data, in case the engine knows it
+                _ => {
+                    compiler.tolerate(
+                        CompileError::UnknownField(block.name.clone(), name.into()).at(block),
+                    )?;
+
+                    let value = match value {
+                        RValue::String(s) => s.clone(),
+                        value => value.to_string(),
+                    };
+                    extra_entries.push((name.to_string(), value));
+                }
+            }
+        }
+
+        fields.extra = join_extra(fields.extra, &extra_entries);
+
+        Ok(fields)
+    }
+
+    /// The handler class given, or none, which leaves the engine to pick
+    /// one from the object's type.
+    fn handler_class(&self) -> Result<NullString, CompileError> {
+        encode(self.handler_class.as_deref().unwrap_or_default())
+    }
+
+    /// The fields every object starts with, taking anything not given
+    /// from `defaults`.
+    fn header(
+        &self,
+        block: &Block,
+        defaults: &Defaults,
+        extra: ExtraString,
+    ) -> Result<MxObHeader, CompileError> {
+        Ok(MxObHeader {
+            presenter: self.handler_class()?,
+            unk0: 0,
+            name: encode(&block.name)?,
+            id: block.id,
+            flags: self.flags(defaults),
+            start_time: self.start_time.unwrap_or(defaults.start_time),
+            duration: self.duration.unwrap_or(defaults.duration),
+            loops: self.loop_count.unwrap_or(defaults.loop_count),
+            location: self.location.unwrap_or(defaults.location),
+            direction: self.direction.unwrap_or(defaults.direction),
+            up: self.up.unwrap_or(defaults.up),
+            extra,
+        })
+    }
+
+    /// The file name to store. Without one, nothing is stored or read.
+    fn file_name(&self, block: &Block, compiler: &mut Compiler) -> Result<String, CompileError> {
+        match &self.file_name {
+            Some(f) => Ok(compiler.stored_name(f)),
+            None => {
+                compiler.tolerate(
+                    CompileError::MissingField(block.name.clone(), "fileName").at(block),
+                )?;
+                Ok(String::new())
+            }
+        }
+    }
+
+    fn flags(&self, defaults: &Defaults) -> MxObFlags {
+        let looping_method = self
+            .looping_method
+            .as_ref()
+            .unwrap_or(&defaults.looping_method);
+
+        let mut flags = named_flags(looping_method, self.transparency.as_ref()).bits()
+            | self.flags.unwrap_or(0);
+        for &(bit, set) in &self.flag_bits {
+            if set {
+                flags |= bit
+            } else {
+                flags &= !bit
+            }
+        }
+
+        MxObFlags::from_bytes(flags.to_le_bytes())
+    }
+
+    /// The extra data, with a reference added for each child in another
+    /// file.
+    fn extra(&self, compiler: &Compiler) -> Result<ExtraString, CompileError> {
+        let externals = self
+            .children
+            .iter()
+            .enumerate()
+            .filter_map(|(i, name)| Some((i, compiler.external(name)?)))
+            .collect::<Vec<_>>();
+
+        encode_externals(self.extra.as_deref(), &externals).try_into()
+    }
+
+    fn children(&self, block: &Block, compiler: &mut Compiler) -> Result<List, CompileError> {
+        let mut subchunks = vec![];
+
+        for name in &self.children {
+            // recorded in the extra data instead
+            if compiler.external(name).is_some() {
+                continue;
+            }
+
+            let child = compiler.lookup(name)?;
+
+            // weaves keep their own stream, wherever they're nested
+            subchunks.push(if child.is_weave {
+                RiffChunk::MxSt(Box::new(compiler.compile_stream(child)?))
+            } else {
+                RiffChunk::MxOb(Box::new(MxOb::from_block(child, compiler)?))
+            });
+        }
+
+        let list_count = match (&self.selection_list, self.random_count) {
+            (Some(values), None) => ListCount::Act(ActListCount {
+                values: values.clone(),
+            }),
+            (Some(values), Some(upper)) => ListCount::Rand(RandListCount {
+                rand_upper: upper,
+                values: values.clone(),
+            }),
+            (None, None) => ListCount::Count(subchunks.len() as u32),
+            // played as if there was no randomCount
+            (None, Some(_)) => {
+                compiler.tolerate(
+                    CompileError::MissingField(block.name.clone(), "selectionList").at(block),
+                )?;
+                ListCount::Count(subchunks.len() as u32)
+            }
+        };
+
+        Ok(List::new(
+            LISTType::MxCh(MxChList { list_count }),
+            subchunks,
+        )?)
+    }
+}
+
+/// The `handlerClass` statement for an object's presenter, unless it has
+/// none.
+fn handler_statement(presenter: &NullString) -> Option<Statement> {
+    let presenter = decode(presenter);
+
+    (!presenter.is_empty()).then(|| Assignment("handlerClass".into(), RValue::String(presenter)))
+}
+
+/// Statements for when an object starts and how long it plays, leaving out
+/// the ones at their defaults.
+fn timing_statements(
+    block_type: BlockType,
+    start_time: i32,
+    duration: i32,
+) -> impl Iterator<Item = Statement> {
+    [
+        non_default(block_type, "startTime", RValue::Integer(start_time)),
+        non_default(
+            block_type,
+            "duration",
+            RValue::Definition(Definition::Duration(Duration(duration))),
+        ),
+    ]
+    .into_iter()
+    .flatten()
+}
+
+/// The flags that a looping method and transparency stand for.
+fn named_flags(looping_method: &LoopingMethod, transparency: Option<&Transparency>) -> MxObFlags {
+    MxObFlags::new()
+        .with_loop_cache(matches!(looping_method, LoopingMethod::Cache))
+        .with_no_loop(matches!(looping_method, LoopingMethod::None))
+        .with_loop_stream(matches!(looping_method, LoopingMethod::Stream))
+        .with_transparent(matches!(transparency, Some(Transparency::Yes)))
+}
+
+/// The flag bits with names of their own, as the fields that set them.
+const FLAG_BITS: [(&str, u32); 4] = [
+    ("loopCache", 1 << 0),
+    ("noLoop", 1 << 1),
+    ("loopStream", 1 << 2),
+    ("transparent", 1 << 3),
+];
+
+fn flag_bit(name: &str) -> Option<u32> {
+    FLAG_BITS
+        .iter()
+        .find(|(field, _)| *field == name)
+        .map(|&(_, bit)| bit)
+}
+
+/// Statements for an object's flags: its looping method, its transparency
+/// if its type has one, a statement for each named bit that those don't
+/// account for, and `flags` for the bits without names, which are set when
+/// compiling.
+fn flag_statements(block_type: BlockType, flags: &MxObFlags) -> Vec<Statement> {
+    let mut statements = vec![];
+    let transparency = schema::fields(block_type).any(|f| f.name == "transparency");
+
+    let looping_method = if flags.loop_cache() {
+        LoopingMethod::Cache
+    } else if flags.loop_stream() {
+        LoopingMethod::Stream
+    } else {
+        LoopingMethod::None
+    };
+    let transparency = (transparency && flags.transparent()).then_some(Transparency::Yes);
+
+    statements.extend(non_default(
+        block_type,
+        "loopingMethod",
+        RValue::Definition(Definition::LoopingMethod(looping_method.clone())),
+    ));
+    if let Some(transparency) = &transparency {
+        statements.push(Assignment(
+            "transparency".into(),
+            RValue::Definition(Definition::Transparency(transparency.clone())),
+        ))
+    }
+
+    let named = named_flags(&looping_method, transparency.as_ref()).bits();
+    let other = flags.bits() ^ named;
+
+    let mut unnamed = other;
+    for (field, bit) in FLAG_BITS {
+        if other & bit != 0 {
+            statements.push(Assignment(
+                field.into(),
+                RValue::Integer((flags.bits() & bit != 0).into()),
+            ));
+            unnamed &= !bit;
+        }
+    }
+
+    if unnamed != 0 {
+        statements.push(Assignment("flags".into(), RValue::Bits(unnamed)))
+    }
+
+    statements
+}
+
+/// Statements describing how an action picks from its children, for lists
+/// that carry an `Act\0` selection list or a `RAND` count.
+fn selection_statements(list: &List) -> Vec<Statement> {
+    let LISTType::MxCh(MxChList { list_count }) = &list.list_type else {
+        return vec![];
+    };
+
+    let (rand_upper, values) = match list_count {
+        ListCount::Act(a) => (None, &a.values),
+        ListCount::Rand(r) => (Some(r.rand_upper), &r.values),
+        ListCount::Count(_) => return vec![],
+    };
+
+    let mut statements = vec![];
+
+    if let Some(upper) = rand_upper {
+        statements.push(Assignment(
+            "randomCount".into(),
+            RValue::Integer(upper as i32),
+        ))
+    }
+
+    statements.push(Assignment(
+        "selectionList".into(),
+        RValue::Function(Function {
+            name: "list".into(),
+            args: values
+                .iter()
+                .map(|&v| Argument::Integer(v.into()))
+                .collect(),
+        }),
+    ));
+
+    statements
+}
+
+/// The fields every kind of object starts with.
+#[binrw]
+#[derive(Debug, Clone)]
+pub struct MxObHeader {
+    presenter: NullString,
+    unk0: u32,
+    name: NullString,
+    id: u32,
+    flags: MxObFlags,
+    start_time: i32,
+    duration: i32,
+    loops: i32,
+    location: Vec3,
+    direction: Vec3,
+    up: Vec3,
+    #[br(temp)]
+    #[bw(try_calc(extra.len().try_into()))]
+    extra_size: u16,
+    #[br(count(extra_size as usize))]
+    extra: ExtraString,
+}
+
 #[bitfield]
 #[binrw]
 #[br(map(Self::from_bytes))]
@@ -101,7 +555,10 @@ pub struct MxFlcFlags {
 #[derive(Debug, Clone)]
 pub struct MxFlcVideo {
     flags: MxFlcFlags,
-    unk6: u32,
+    /// How long the last frame is held after the data runs out, in
+    /// milliseconds
+    #[brw(if(version().has_sustain_time()))]
+    sustain_time: i32,
 }
 
 #[bitfield]
@@ -118,7 +575,10 @@ pub struct MxSmkFlags {
 #[derive(Debug, Clone)]
 pub struct MxSmkVideo {
     flags: MxSmkFlags,
-    unk6: u32,
+    /// How long the last frame is held after the data runs out, in
+    /// milliseconds
+    #[brw(if(version().has_sustain_time()))]
+    sustain_time: i32,
 }
 
 #[binrw]
@@ -133,22 +593,7 @@ pub enum MxVideoFileType {
 #[binrw]
 #[derive(Debug, Clone)]
 pub struct MxVideo {
-    presenter: NullString,
-    unk0: u32,
-    name: NullString,
-    id: u32,
-    flags: MxObFlags,
-    start_time: i32,
-    duration: i32,
-    loops: i32,
-    location: Vec3,
-    direction: Vec3,
-    up: Vec3,
-    #[br(temp)]
-    #[bw(try_calc(extra.len().try_into()))]
-    extra_size: u16,
-    #[br(count(extra_size as usize))]
-    extra: ExtraString,
+    header: MxObHeader,
     filename: NullString,
     unk2: u32,
     unk3: u32,
@@ -157,72 +602,108 @@ pub struct MxVideo {
 }
 
 impl ToBlock for MxVideo {
-    fn to_block(&self, top_level: bool) -> (Option<Block>, Vec<Block>, Vec<Block>) {
+    fn to_block(&self, top_level: bool) -> ToBlockResult {
         let mut statements = vec![Assignment(
             "fileName".into(),
-            RValue::String(self.filename.to_string()),
+            RValue::String(decode(&self.filename)),
         )];
-        if self.presenter != "".into() {
-            statements.push(Assignment(
-                "handlerClass".into(),
-                RValue::String(self.presenter.to_string()),
-            ))
-        }
-        if self.location != Vec3::ZERO {
-            statements.push(Assignment("location".into(), RValue::Vec3(self.location)))
-        }
-        if self.direction != Vec3::Z {
-            statements.push(Assignment("direction".into(), RValue::Vec3(self.direction)))
-        }
-        if self.up != Vec3::Y {
-            statements.push(Assignment("up".into(), RValue::Vec3(self.up)))
-        }
-
-        match &self.filetype {
-            MxVideoFileType::Flc(f) => {
-                if !f.flags.has_palette_management() {
-                    statements.push(Assignment(
-                        "paletteManagement".into(),
-                        RValue::Definition(Definition::PaletteManagement(PaletteManagement::None)),
-                    ))
-                }
-            }
-            MxVideoFileType::Smk(s) => {
-                if !s.flags.has_palette_management() {
-                    statements.push(Assignment(
-                        "paletteManagement".into(),
-                        RValue::Definition(Definition::PaletteManagement(PaletteManagement::None)),
-                    ))
-                }
-            }
-        }
-
-        if self.duration != 0 {
+        statements.extend(handler_statement(&self.header.presenter));
+        statements.extend(non_default(
+            DefineAnim,
+            "location",
+            RValue::Vec3(self.header.location),
+        ));
+        statements.extend(non_default(
+            DefineAnim,
+            "direction",
+            RValue::Vec3(self.header.direction),
+        ));
+        statements.extend(non_default(DefineAnim, "up", RValue::Vec3(self.header.up)));
+        statements.extend(timing_statements(
+            DefineAnim,
+            self.header.start_time,
+            self.header.duration,
+        ));
+        statements.extend(non_default(
+            DefineAnim,
+            "loopCount",
+            RValue::Integer(self.header.loops),
+        ));
+
+        let (has_palette_management, sustain_time) = match &self.filetype {
+            MxVideoFileType::Flc(f) => (f.flags.has_palette_management(), f.sustain_time),
+            MxVideoFileType::Smk(s) => (s.flags.has_palette_management(), s.sustain_time),
+        };
+
+        if !has_palette_management {
             statements.push(Assignment(
-                "duration".into(),
-                RValue::Definition(Definition::Duration(Duration(self.duration))),
+                "paletteManagement".into(),
+                RValue::Definition(Definition::PaletteManagement(PaletteManagement::None)),
             ))
         }
-        if self.extra.is_some() {
+        statements.extend(non_default(
+            DefineAnim,
+            "sustainTime",
+            RValue::Integer(sustain_time),
+        ));
+
+        statements.extend(flag_statements(DefineAnim, &self.header.flags));
+        if self.header.extra.is_some() {
             statements.push(Assignment(
                 "extra".into(),
-                RValue::String(self.extra.to_string()),
+                RValue::String(self.header.extra.to_string()),
             ))
         }
 
-        statements.push(Assignment("stream".into(), RValue::Integer(self.id as i32)));
+        statements.push(Assignment(
+            "stream".into(),
+            RValue::Integer(self.header.id as i32),
+        ));
 
-        (
+        Ok((
             Some(Block {
-                id: self.id,
+                id: self.header.id,
                 block_type: DefineAnim,
-                name: self.name.to_string(),
+                name: decode(&self.header.name),
                 is_weave: top_level,
                 statements,
+                span: None,
+                statement_spans: vec![],
             }),
             vec![],
             vec![],
-        )
+        ))
+    }
+}
+
+impl FromBlock for MxVideo {
+    fn from_block(block: &Block, compiler: &mut Compiler) -> Result<Self, CompileError> {
+        let fields = Fields::from_block(block, compiler)?;
+        let defaults = Defaults::of(block.block_type);
+        let filename = fields.file_name(block, compiler)?;
+        compiler.load_resource(block, &filename)?;
+
+        let has_palette_management = fields.palette_management.is_none();
+        let filetype = match filename.rsplit('.').next().map(str::to_ascii_lowercase) {
+            Some(ext) if ext == "flc" => MxVideoFileType::Flc(MxFlcVideo {
+                flags: MxFlcFlags::new().with_has_palette_management(has_palette_management),
+                sustain_time: fields.sustain_time.unwrap_or(defaults.sustain_time),
+            }),
+            Some(ext) if ext == "smk" => MxVideoFileType::Smk(MxSmkVideo {
+                flags: MxSmkFlags::new().with_has_palette_management(has_palette_management),
+                sustain_time: fields.sustain_time.unwrap_or(defaults.sustain_time),
+            }),
+            _ => return Err(CompileError::UnknownFileType(block.name.clone(), filename)),
+        };
+
+        Ok(Self {
+            header: fields.header(block, &defaults, fields.extra.clone().try_into()?)?,
+            filename: encode(&filename)?,
+            unk2: 0,
+            unk3: 0,
+            unk4: 0,
+            filetype,
+        })
     }
 }
 
@@ -236,22 +717,7 @@ pub enum MxSoundFileType {
 #[binrw]
 #[derive(Debug, Clone)]
 pub struct MxSound {
-    presenter: NullString,
-    unk0: u32,
-    name: NullString,
-    id: u32,
-    flags: MxObFlags,
-    start_time: i32,
-    duration: i32,
-    loops: i32,
-    location: Vec3,
-    direction: Vec3,
-    up: Vec3,
-    #[br(temp)]
-    #[bw(try_calc(extra.len().try_into()))]
-    extra_size: u16,
-    #[br(count(extra_size as usize))]
-    extra: ExtraString,
+    header: MxObHeader,
     filename: NullString,
     unk2: u32,
     unk3: u32,
@@ -260,73 +726,102 @@ pub struct MxSound {
 }
 
 impl ToBlock for MxSound {
-    fn to_block(&self, top_level: bool) -> (Option<Block>, Vec<Block>, Vec<Block>) {
+    fn to_block(&self, top_level: bool) -> ToBlockResult {
         let mut statements = vec![Assignment(
             "fileName".into(),
-            RValue::String(self.filename.to_string()),
+            RValue::String(decode(&self.filename)),
         )];
-        if self.presenter != "".into() && self.presenter != "Lego3DWavePresenter".into() {
-            statements.push(Assignment(
-                "handlerClass".into(),
-                RValue::String(self.presenter.to_string()),
-            ))
-        }
-        if self.location != Vec3::ZERO {
-            statements.push(Assignment("location".into(), RValue::Vec3(self.location)))
-        }
-        if self.direction != Vec3::Z {
-            statements.push(Assignment("direction".into(), RValue::Vec3(self.direction)))
-        }
-        if self.up != Vec3::Y {
-            statements.push(Assignment("up".into(), RValue::Vec3(self.up)))
-        }
+        statements.extend(handler_statement(&self.header.presenter));
+        statements.extend(non_default(
+            DefineSound,
+            "location",
+            RValue::Vec3(self.header.location),
+        ));
+        statements.extend(non_default(
+            DefineSound,
+            "direction",
+            RValue::Vec3(self.header.direction),
+        ));
+        statements.extend(non_default(DefineSound, "up", RValue::Vec3(self.header.up)));
+        statements.extend(timing_statements(
+            DefineSound,
+            self.header.start_time,
+            self.header.duration,
+        ));
+        statements.extend(non_default(
+            DefineSound,
+            "loopCount",
+            RValue::Integer(self.header.loops),
+        ));
 
         let MxSoundFileType::Wav(wav) = &self.filetype;
-        if wav.volume != 0x4F {
-            statements.push(Assignment("volume".into(), RValue::Integer(wav.volume)))
-        }
-
-        if self.start_time != 0 {
-            statements.push(Assignment(
-                "startTime".into(),
-                RValue::Integer(self.start_time),
-            ))
-        }
-        if self.loops != 1 {
-            statements.push(Assignment("loopCount".into(), RValue::Integer(self.loops)))
-        }
-        if !self.flags.no_loop() {
-            statements.push(Assignment(
-                "loopingMethod".into(),
-                RValue::Definition(Definition::LoopingMethod(if self.flags.loop_cache() {
-                    LoopingMethod::Cache
-                } else if self.flags.loop_stream() {
-                    LoopingMethod::Stream
-                } else {
-                    unreachable!()
-                })),
-            ))
-        }
-        if self.extra.is_some() {
+        statements.extend(non_default(
+            DefineSound,
+            "volume",
+            RValue::Integer(wav.volume),
+        ));
+        statements.extend(non_default(
+            DefineSound,
+            "sustainTime",
+            RValue::Integer(wav.sustain_time),
+        ));
+
+        statements.extend(flag_statements(DefineSound, &self.header.flags));
+        if self.header.extra.is_some() {
             statements.push(Assignment(
                 "entityName".into(),
-                RValue::String(self.extra.to_string()),
+                RValue::String(self.header.extra.to_string()),
             ))
         }
 
-        statements.push(Assignment("stream".into(), RValue::Integer(self.id as i32)));
+        statements.push(Assignment(
+            "stream".into(),
+            RValue::Integer(self.header.id as i32),
+        ));
 
-        (
+        Ok((
             Some(Block {
-                id: self.id,
+                id: self.header.id,
                 block_type: DefineSound,
-                name: self.name.to_string(),
+                name: decode(&self.header.name),
                 is_weave: top_level,
                 statements,
+                span: None,
+                statement_spans: vec![],
             }),
             vec![],
             vec![],
-        )
+        ))
+    }
+}
+
+impl FromBlock for MxSound {
+    fn from_block(block: &Block, compiler: &mut Compiler) -> Result<Self, CompileError> {
+        let fields = Fields::from_block(block, compiler)?;
+        let defaults = Defaults::of(block.block_type);
+        let filename = fields.file_name(block, compiler)?;
+        let repeats = match fields.looping_method {
+            Some(LoopingMethod::Stream) => {
+                fields.loop_count.unwrap_or(defaults.loop_count).max(1) as u32
+            }
+            _ => 1,
+        };
+        let filename = compiler.load_sound(block, &filename, repeats)?;
+
+        let filetype = MxSoundFileType::Wav(MxWavObject {
+            palette_management: 0,
+            sustain_time: fields.sustain_time.unwrap_or(defaults.sustain_time),
+            volume: fields.volume.unwrap_or(defaults.volume),
+        });
+
+        Ok(Self {
+            header: fields.header(block, &defaults, fields.extra.clone().try_into()?)?,
+            filename: encode(&filename)?,
+            unk2: 0,
+            unk3: 0,
+            unk4: 0,
+            filetype,
+        })
     }
 }
 
@@ -334,68 +829,64 @@ impl ToBlock for MxSound {
 #[derive(Debug, Clone)]
 #[br(import(buf_size: i32))]
 pub struct MxWorld {
-    presenter: NullString,
-    unk0: u32,
-    name: NullString,
-    id: u32,
-    flags: MxObFlags,
-    start_time: i32,
-    duration: i32,
-    loops: i32,
-    location: Vec3,
-    direction: Vec3,
-    up: Vec3,
-    #[br(temp)]
-    #[bw(try_calc(extra.len().try_into()))]
-    extra_size: u16,
-    #[br(count(extra_size as usize))]
-    extra: ExtraString,
+    header: MxObHeader,
 
-    #[br(magic(b"LIST"))]
+    #[brw(magic(b"LIST"))]
     #[br(args(buf_size))]
+    #[bw(write_with(write_sized))]
     pub list: List,
 }
 
 impl ToBlock for MxWorld {
-    fn to_block(&self, top_level: bool) -> (Option<Block>, Vec<Block>, Vec<Block>) {
+    fn to_block(&self, top_level: bool) -> ToBlockResult {
         let mut statements = vec![];
-        if self.presenter != "".into() {
-            statements.push(Assignment(
-                "handlerClass".into(),
-                RValue::String(self.presenter.to_string()),
-            ))
-        }
-        if self.location != Vec3::ZERO {
-            statements.push(Assignment("location".into(), RValue::Vec3(self.location)))
-        }
-        if self.direction != Vec3::Z {
-            statements.push(Assignment("direction".into(), RValue::Vec3(self.direction)))
-        }
-        if self.up != Vec3::Y {
-            statements.push(Assignment("up".into(), RValue::Vec3(self.up)))
-        }
-        if self.loops != 1 {
-            statements.push(Assignment("loopCount".into(), RValue::Integer(self.loops)))
-        }
-        if !self.flags.no_loop() {
-            statements.push(Assignment(
-                "loopingMethod".into(),
-                RValue::Definition(Definition::LoopingMethod(if self.flags.loop_cache() {
-                    LoopingMethod::Cache
-                } else if self.flags.loop_stream() {
-                    LoopingMethod::Stream
-                } else {
-                    unreachable!()
-                })),
-            ))
-        }
+        statements.extend(handler_statement(&self.header.presenter));
+        statements.extend(non_default(
+            SerialAction,
+            "location",
+            RValue::Vec3(self.header.location),
+        ));
+        statements.extend(non_default(
+            SerialAction,
+            "direction",
+            RValue::Vec3(self.header.direction),
+        ));
+        statements.extend(non_default(
+            SerialAction,
+            "up",
+            RValue::Vec3(self.header.up),
+        ));
+        statements.extend(timing_statements(
+            SerialAction,
+            self.header.start_time,
+            self.header.duration,
+        ));
+        statements.extend(non_default(
+            SerialAction,
+            "loopCount",
+            RValue::Integer(self.header.loops),
+        ));
+        statements.extend(flag_statements(SerialAction, &self.header.flags));
+
+        statements.extend(selection_statements(&self.list));
 
         let mut blocks_before = vec![];
 
         for chunk in &self.list.subchunks {
-            statements.push(Declaration(chunk.get_name()));
+            match chunk {
+                RiffChunk::Pad(_) => continue,
+                // not children, but whatever their handler makes of them
+                // still goes before the object
+                RiffChunk::Custom(_) => {}
+                _ => {
+                    let name = chunk
+                        .get_name()
+                        .ok_or(OmniParseError::UnexpectedChunk(chunk.kind()))?;
+                    statements.push(Declaration(name));
+                }
+            }
 
-            let (block, before, after) = chunk.to_block(false);
+            let (block, before, after) = chunk.to_block(false)?;
             blocks_before.extend(before);
             if let Some(b) = block {
                 blocks_before.push(b);
@@ -403,26 +894,43 @@ impl ToBlock for MxWorld {
             blocks_before.extend(after);
         }
 
-        if self.extra.is_some() {
+        if self.header.extra.is_some() {
             statements.push(Assignment(
                 "extra".into(),
-                RValue::String(self.extra.to_string()),
+                RValue::String(self.header.extra.to_string()),
             ))
         }
 
-        statements.push(Assignment("stream".into(), RValue::Integer(self.id as i32)));
+        statements.push(Assignment(
+            "stream".into(),
+            RValue::Integer(self.header.id as i32),
+        ));
 
-        (
+        Ok((
             Some(Block {
-                id: self.id,
+                id: self.header.id,
                 block_type: SerialAction,
-                name: self.name.to_string(),
+                name: decode(&self.header.name),
                 is_weave: top_level,
                 statements,
+                span: None,
+                statement_spans: vec![],
             }),
             blocks_before,
             vec![],
-        )
+        ))
+    }
+}
+
+impl FromBlock for MxWorld {
+    fn from_block(block: &Block, compiler: &mut Compiler) -> Result<Self, CompileError> {
+        let fields = Fields::from_block(block, compiler)?;
+        let defaults = Defaults::of(block.block_type);
+
+        Ok(Self {
+            header: fields.header(block, &defaults, fields.extra(compiler)?)?,
+            list: fields.children(block, compiler)?,
+        })
     }
 }
 
@@ -430,68 +938,64 @@ impl ToBlock for MxWorld {
 #[derive(Debug, Clone)]
 #[br(import(buf_size: i32))]
 pub struct MxPresenter {
-    presenter: NullString,
-    unk0: u32,
-    name: NullString,
-    id: u32,
-    flags: MxObFlags,
-    start_time: i32,
-    duration: i32,
-    loops: i32,
-    location: Vec3,
-    direction: Vec3,
-    up: Vec3,
-    #[br(temp)]
-    #[bw(try_calc(extra.len().try_into()))]
-    extra_size: u16,
-    #[br(count(extra_size as usize))]
-    extra: ExtraString,
+    header: MxObHeader,
 
-    #[br(magic(b"LIST"))]
+    #[brw(magic(b"LIST"))]
     #[br(args(buf_size))]
+    #[bw(write_with(write_sized))]
     pub list: List,
 }
 
 impl ToBlock for MxPresenter {
-    fn to_block(&self, top_level: bool) -> (Option<Block>, Vec<Block>, Vec<Block>) {
+    fn to_block(&self, top_level: bool) -> ToBlockResult {
         let mut statements = vec![];
-        if self.presenter != "".into() {
-            statements.push(Assignment(
-                "handlerClass".into(),
-                RValue::String(self.presenter.to_string()),
-            ))
-        }
-        if self.location != Vec3::ZERO {
-            statements.push(Assignment("location".into(), RValue::Vec3(self.location)))
-        }
-        if self.direction != Vec3::Z {
-            statements.push(Assignment("direction".into(), RValue::Vec3(self.direction)))
-        }
-        if self.up != Vec3::Y {
-            statements.push(Assignment("up".into(), RValue::Vec3(self.up)))
-        }
-        if self.loops != 1 {
-            statements.push(Assignment("loopCount".into(), RValue::Integer(self.loops)))
-        }
-        if !self.flags.no_loop() {
-            statements.push(Assignment(
-                "loopingMethod".into(),
-                RValue::Definition(Definition::LoopingMethod(if self.flags.loop_cache() {
-                    LoopingMethod::Cache
-                } else if self.flags.loop_stream() {
-                    LoopingMethod::Stream
-                } else {
-                    unreachable!()
-                })),
-            ))
-        }
+        statements.extend(handler_statement(&self.header.presenter));
+        statements.extend(non_default(
+            ParallelAction,
+            "location",
+            RValue::Vec3(self.header.location),
+        ));
+        statements.extend(non_default(
+            ParallelAction,
+            "direction",
+            RValue::Vec3(self.header.direction),
+        ));
+        statements.extend(non_default(
+            ParallelAction,
+            "up",
+            RValue::Vec3(self.header.up),
+        ));
+        statements.extend(timing_statements(
+            ParallelAction,
+            self.header.start_time,
+            self.header.duration,
+        ));
+        statements.extend(non_default(
+            ParallelAction,
+            "loopCount",
+            RValue::Integer(self.header.loops),
+        ));
+        statements.extend(flag_statements(ParallelAction, &self.header.flags));
+
+        statements.extend(selection_statements(&self.list));
 
         let mut blocks_before = vec![];
 
         for chunk in &self.list.subchunks {
-            statements.push(Declaration(chunk.get_name()));
+            match chunk {
+                RiffChunk::Pad(_) => continue,
+                // not children, but whatever their handler makes of them
+                // still goes before the object
+                RiffChunk::Custom(_) => {}
+                _ => {
+                    let name = chunk
+                        .get_name()
+                        .ok_or(OmniParseError::UnexpectedChunk(chunk.kind()))?;
+                    statements.push(Declaration(name));
+                }
+            }
 
-            let (block, before, after) = chunk.to_block(false);
+            let (block, before, after) = chunk.to_block(false)?;
             blocks_before.extend(before);
             if let Some(b) = block {
                 blocks_before.push(b);
@@ -499,26 +1003,43 @@ impl ToBlock for MxPresenter {
             blocks_before.extend(after);
         }
 
-        if self.extra.is_some() {
+        if self.header.extra.is_some() {
             statements.push(Assignment(
                 "extra".into(),
-                RValue::String(self.extra.to_string()),
+                RValue::String(self.header.extra.to_string()),
             ))
         }
 
-        statements.push(Assignment("stream".into(), RValue::Integer(self.id as i32)));
+        statements.push(Assignment(
+            "stream".into(),
+            RValue::Integer(self.header.id as i32),
+        ));
 
-        (
+        Ok((
             Some(Block {
-                id: self.id,
+                id: self.header.id,
                 block_type: ParallelAction,
-                name: self.name.to_string(),
+                name: decode(&self.header.name),
                 is_weave: top_level,
                 statements,
+                span: None,
+                statement_spans: vec![],
             }),
             blocks_before,
             vec![],
-        )
+        ))
+    }
+}
+
+impl FromBlock for MxPresenter {
+    fn from_block(block: &Block, compiler: &mut Compiler) -> Result<Self, CompileError> {
+        let fields = Fields::from_block(block, compiler)?;
+        let defaults = Defaults::of(block.block_type);
+
+        Ok(Self {
+            header: fields.header(block, &defaults, fields.extra(compiler)?)?,
+            list: fields.children(block, compiler)?,
+        })
     }
 }
 
@@ -539,22 +1060,7 @@ pub enum MxEventFileType {
 #[binrw]
 #[derive(Debug, Clone)]
 pub struct MxEvent {
-    presenter: NullString,
-    unk0: u32,
-    name: NullString,
-    id: u32,
-    flags: MxObFlags,
-    start_time: i32,
-    duration: i32,
-    loops: i32,
-    location: Vec3,
-    direction: Vec3,
-    up: Vec3,
-    #[br(temp)]
-    #[bw(try_calc(extra.len().try_into()))]
-    extra_size: u16,
-    #[br(count(extra_size as usize))]
-    extra: ExtraString,
+    header: MxObHeader,
     filename: NullString,
     unk2: u32,
     unk3: u32,
@@ -563,73 +1069,86 @@ pub struct MxEvent {
 }
 
 impl ToBlock for MxEvent {
-    fn to_block(&self, top_level: bool) -> (Option<Block>, Vec<Block>, Vec<Block>) {
+    fn to_block(&self, top_level: bool) -> ToBlockResult {
         let mut statements = vec![Assignment(
             "fileName".into(),
-            RValue::String(
-                self.filename
-                    .to_string()
-                    .trim_end_matches(".evt")
-                    .to_string(),
-            ),
+            RValue::String(decode(&self.filename).trim_end_matches(".evt").to_string()),
         )];
-        if self.presenter != "".into() {
-            statements.push(Assignment(
-                "handlerClass".into(),
-                RValue::String(self.presenter.to_string()),
-            ))
-        }
-        if self.location != Vec3::ZERO {
-            statements.push(Assignment("location".into(), RValue::Vec3(self.location)))
-        }
-        if self.direction != Vec3::Z {
-            statements.push(Assignment("direction".into(), RValue::Vec3(self.direction)))
-        }
-        if self.up != Vec3::Y {
-            statements.push(Assignment("up".into(), RValue::Vec3(self.up)))
-        }
-        if self.extra.is_some() {
+        statements.extend(handler_statement(&self.header.presenter));
+        statements.extend(non_default(
+            DefineEvent,
+            "location",
+            RValue::Vec3(self.header.location),
+        ));
+        statements.extend(non_default(
+            DefineEvent,
+            "direction",
+            RValue::Vec3(self.header.direction),
+        ));
+        statements.extend(non_default(DefineEvent, "up", RValue::Vec3(self.header.up)));
+        statements.extend(timing_statements(
+            DefineEvent,
+            self.header.start_time,
+            self.header.duration,
+        ));
+        statements.extend(non_default(
+            DefineEvent,
+            "loopCount",
+            RValue::Integer(self.header.loops),
+        ));
+        statements.extend(flag_statements(DefineEvent, &self.header.flags));
+        if self.header.extra.is_some() {
             statements.push(Assignment(
                 "extra".into(),
-                RValue::String(self.extra.to_string()),
+                RValue::String(self.header.extra.to_string()),
             ))
         }
 
-        statements.push(Assignment("stream".into(), RValue::Integer(self.id as i32)));
+        statements.push(Assignment(
+            "stream".into(),
+            RValue::Integer(self.header.id as i32),
+        ));
 
-        (
+        Ok((
             Some(Block {
-                id: self.id,
+                id: self.header.id,
                 block_type: DefineEvent,
-                name: self.name.to_string(),
+                name: decode(&self.header.name),
                 is_weave: top_level,
                 statements,
+                span: None,
+                statement_spans: vec![],
             }),
             vec![],
             vec![],
-        )
+        ))
+    }
+}
+
+impl FromBlock for MxEvent {
+    fn from_block(block: &Block, compiler: &mut Compiler) -> Result<Self, CompileError> {
+        let fields = Fields::from_block(block, compiler)?;
+        let defaults = Defaults::of(block.block_type);
+        let filename = fields.file_name(block, compiler)? + ".evt";
+        compiler.load_resource(block, &filename)?;
+
+        let filetype = MxEventFileType::Evt(MxEvtEvent { unk5: 0, unk6: 0 });
+
+        Ok(Self {
+            header: fields.header(block, &defaults, fields.extra.clone().try_into()?)?,
+            filename: encode(&filename)?,
+            unk2: 0,
+            unk3: 0,
+            unk4: 0,
+            filetype,
+        })
     }
 }
 
 #[binrw]
 #[derive(Debug, Clone)]
 pub struct MxAnimation {
-    presenter: NullString,
-    unk0: u32,
-    name: NullString,
-    id: u32,
-    flags: MxObFlags,
-    start_time: i32,
-    duration: i32,
-    loops: i32,
-    location: Vec3,
-    direction: Vec3,
-    up: Vec3,
-    #[br(temp)]
-    #[bw(try_calc(extra.len().try_into()))]
-    extra_size: u16,
-    #[br(count(extra_size as usize))]
-    extra: ExtraString,
+    header: MxObHeader,
 }
 
 #[binrw]
@@ -642,22 +1161,7 @@ pub enum MxBitmapFileType {
 #[binrw]
 #[derive(Debug, Clone)]
 pub struct MxBitmap {
-    presenter: NullString,
-    unk0: u32,
-    name: NullString,
-    id: u32,
-    flags: MxObFlags,
-    start_time: i32,
-    duration: i32,
-    loops: i32,
-    location: Vec3,
-    direction: Vec3,
-    up: Vec3,
-    #[br(temp)]
-    #[bw(try_calc(extra.len().try_into()))]
-    extra_size: u16,
-    #[br(count(extra_size as usize))]
-    extra: ExtraString,
+    header: MxObHeader,
     filename: NullString,
     unk2: u32,
     unk3: u32,
@@ -666,32 +1170,33 @@ pub struct MxBitmap {
 }
 
 impl ToBlock for MxBitmap {
-    fn to_block(&self, top_level: bool) -> (Option<Block>, Vec<Block>, Vec<Block>) {
+    fn to_block(&self, top_level: bool) -> ToBlockResult {
         let mut statements = vec![Assignment(
             "fileName".into(),
-            RValue::String(self.filename.to_string()),
+            RValue::String(decode(&self.filename)),
         )];
-        if self.presenter != "".into() {
-            statements.push(Assignment(
-                "handlerClass".into(),
-                RValue::String(self.presenter.to_string()),
-            ))
-        }
-        if self.duration != 0 {
-            statements.push(Assignment(
-                "duration".into(),
-                RValue::Definition(Definition::Duration(Duration(self.duration))),
-            ))
-        }
-        if self.location != Vec3::ZERO {
-            statements.push(Assignment("location".into(), RValue::Vec3(self.location)))
-        }
-        if self.direction != Vec3::Z {
-            statements.push(Assignment("direction".into(), RValue::Vec3(self.direction)))
-        }
-        if self.up != Vec3::Y {
-            statements.push(Assignment("up".into(), RValue::Vec3(self.up)))
-        }
+        statements.extend(handler_statement(&self.header.presenter));
+        statements.extend(non_default(
+            DefineStill,
+            "location",
+            RValue::Vec3(self.header.location),
+        ));
+        statements.extend(non_default(
+            DefineStill,
+            "direction",
+            RValue::Vec3(self.header.direction),
+        ));
+        statements.extend(non_default(DefineStill, "up", RValue::Vec3(self.header.up)));
+        statements.extend(timing_statements(
+            DefineStill,
+            self.header.start_time,
+            self.header.duration,
+        ));
+        statements.extend(non_default(
+            DefineStill,
+            "loopCount",
+            RValue::Integer(self.header.loops),
+        ));
 
         let MxBitmapFileType::Stl(stl) = &self.filetype;
         if !stl.flags.has_palette_management() {
@@ -700,42 +1205,74 @@ impl ToBlock for MxBitmap {
                 RValue::Definition(Definition::PaletteManagement(PaletteManagement::None)),
             ))
         }
+        statements.extend(non_default(
+            DefineStill,
+            "sustainTime",
+            RValue::Integer(stl.sustain_time),
+        ));
 
-        if self.flags.transparent() {
-            statements.push(Assignment(
-                "transparency".into(),
-                RValue::Definition(Definition::Transparency(Transparency::Yes)),
-            ))
-        }
+        statements.extend(flag_statements(DefineStill, &self.header.flags));
 
-        if self.extra.is_some() {
+        if self.header.extra.is_some() {
             statements.push(Assignment(
                 "extra".into(),
-                RValue::String(self.extra.to_string()),
+                RValue::String(self.header.extra.to_string()),
             ))
         }
 
-        statements.push(Assignment("stream".into(), RValue::Integer(self.id as i32)));
+        statements.push(Assignment(
+            "stream".into(),
+            RValue::Integer(self.header.id as i32),
+        ));
 
-        (
+        Ok((
             Some(Block {
-                id: self.id,
+                id: self.header.id,
                 block_type: DefineStill,
-                name: self.name.to_string(),
+                name: decode(&self.header.name),
                 is_weave: top_level,
                 statements,
+                span: None,
+                statement_spans: vec![],
             }),
             vec![],
             vec![],
-        )
+        ))
+    }
+}
+
+impl FromBlock for MxBitmap {
+    fn from_block(block: &Block, compiler: &mut Compiler) -> Result<Self, CompileError> {
+        let fields = Fields::from_block(block, compiler)?;
+        let defaults = Defaults::of(block.block_type);
+        let filename = fields.file_name(block, compiler)?;
+        compiler.load_resource(block, &filename)?;
+
+        let filetype = MxBitmapFileType::Stl(MxStlObject {
+            flags: MxStlFlags::new()
+                .with_has_palette_management(fields.palette_management.is_none()),
+            sustain_time: fields.sustain_time.unwrap_or(defaults.sustain_time),
+        });
+
+        Ok(Self {
+            header: fields.header(block, &defaults, fields.extra.clone().try_into()?)?,
+            filename: encode(&filename)?,
+            unk2: 0,
+            unk3: 0,
+            unk4: 0,
+            filetype,
+        })
     }
 }
 
 #[binrw]
 #[derive(Debug, Clone)]
 pub struct MxWavObject {
-    unk5: u32,
-    unk6: u32,
+    /// Always 0, since sounds don't have a palette
+    palette_management: u32,
+    /// How long the sound is held after its data runs out, in milliseconds
+    #[brw(if(version().has_sustain_time()))]
+    sustain_time: i32,
     volume: i32,
 }
 
@@ -753,7 +1290,10 @@ pub struct MxStlFlags {
 #[derive(Debug, Clone)]
 pub struct MxStlObject {
     flags: MxStlFlags,
-    unk6: u32,
+    /// How long the last frame is held after the data runs out, in
+    /// milliseconds
+    #[brw(if(version().has_sustain_time()))]
+    sustain_time: i32,
 }
 
 #[binrw]
@@ -773,22 +1313,7 @@ pub enum MxObjectFileType {
 #[binrw]
 #[derive(Debug, Clone)]
 pub struct MxObject {
-    presenter: NullString,
-    unk0: u32,
-    name: NullString,
-    id: u32,
-    flags: MxObFlags,
-    start_time: i32,
-    duration: i32,
-    loops: i32,
-    location: Vec3,
-    direction: Vec3,
-    up: Vec3,
-    #[br(temp)]
-    #[bw(try_calc(extra.len().try_into()))]
-    extra_size: u16,
-    #[br(count(extra_size as usize))]
-    extra: ExtraString,
+    header: MxObHeader,
     filename: NullString,
     unk2: u32,
     unk3: u32,
@@ -797,52 +1322,83 @@ pub struct MxObject {
 }
 
 impl ToBlock for MxObject {
-    fn to_block(&self, top_level: bool) -> (Option<Block>, Vec<Block>, Vec<Block>) {
+    fn to_block(&self, top_level: bool) -> ToBlockResult {
         let mut statements = vec![Assignment(
             "fileName".into(),
-            RValue::String(self.filename.to_string()),
+            RValue::String(decode(&self.filename)),
         )];
-        if self.presenter != "".into() {
-            statements.push(Assignment(
-                "handlerClass".into(),
-                RValue::String(self.presenter.to_string()),
-            ))
-        }
-        if self.location != Vec3::ZERO {
-            statements.push(Assignment("location".into(), RValue::Vec3(self.location)))
-        }
-        if self.direction != Vec3::Z {
-            statements.push(Assignment("direction".into(), RValue::Vec3(self.direction)))
-        }
-        if self.up != Vec3::Y {
-            statements.push(Assignment("up".into(), RValue::Vec3(self.up)))
-        }
-        if self.duration != 0 {
-            statements.push(Assignment(
-                "duration".into(),
-                RValue::Definition(Definition::Duration(Duration(self.duration))),
-            ))
-        }
-        if self.extra.is_some() {
+        statements.extend(handler_statement(&self.header.presenter));
+        statements.extend(non_default(
+            DefineObject,
+            "location",
+            RValue::Vec3(self.header.location),
+        ));
+        statements.extend(non_default(
+            DefineObject,
+            "direction",
+            RValue::Vec3(self.header.direction),
+        ));
+        statements.extend(non_default(
+            DefineObject,
+            "up",
+            RValue::Vec3(self.header.up),
+        ));
+        statements.extend(timing_statements(
+            DefineObject,
+            self.header.start_time,
+            self.header.duration,
+        ));
+        statements.extend(non_default(
+            DefineObject,
+            "loopCount",
+            RValue::Integer(self.header.loops),
+        ));
+        statements.extend(flag_statements(DefineObject, &self.header.flags));
+        if self.header.extra.is_some() {
             statements.push(Assignment(
                 "extra".into(),
-                RValue::String(self.extra.to_string()),
+                RValue::String(self.header.extra.to_string()),
             ))
         }
 
-        statements.push(Assignment("stream".into(), RValue::Integer(self.id as i32)));
+        statements.push(Assignment(
+            "stream".into(),
+            RValue::Integer(self.header.id as i32),
+        ));
 
-        (
+        Ok((
             Some(Block {
-                id: self.id,
+                id: self.header.id,
                 block_type: DefineObject,
-                name: self.name.to_string(),
+                name: decode(&self.header.name),
                 is_weave: top_level,
                 statements,
+                span: None,
+                statement_spans: vec![],
             }),
             vec![],
             vec![],
-        )
+        ))
+    }
+}
+
+impl FromBlock for MxObject {
+    fn from_block(block: &Block, compiler: &mut Compiler) -> Result<Self, CompileError> {
+        let fields = Fields::from_block(block, compiler)?;
+        let defaults = Defaults::of(block.block_type);
+        let filename = fields.file_name(block, compiler)?;
+        compiler.load_resource(block, &filename)?;
+
+        let filetype = MxObjectFileType::Obj(MxObjObject { unk5: 0, unk6: 0 });
+
+        Ok(Self {
+            header: fields.header(block, &defaults, fields.extra.clone().try_into()?)?,
+            filename: encode(&filename)?,
+            unk2: 0,
+            unk3: 0,
+            unk4: 0,
+            filetype,
+        })
     }
 }
 
@@ -869,38 +1425,189 @@ pub enum MxObType {
 }
 
 impl ToBlock for MxObType {
-    fn to_block(&self, top_level: bool) -> (Option<Block>, Vec<Block>, Vec<Block>) {
+    fn to_block(&self, top_level: bool) -> ToBlockResult {
         match self {
             Self::Video(x) => x.to_block(top_level),
             Self::Sound(x) => x.to_block(top_level),
             Self::World(x) => x.to_block(top_level),
             Self::Presenter(x) => x.to_block(top_level),
             Self::Event(x) => x.to_block(top_level),
-            Self::Animation(_) => todo!(),
+            Self::Animation(x) => Err(OmniParseError::UnsupportedObject(
+                decode(&x.header.name),
+                self.kind(),
+            )),
             Self::Bitmap(x) => x.to_block(top_level),
             Self::Object(x) => x.to_block(top_level),
         }
     }
 }
 
+impl FromBlock for MxObType {
+    fn from_block(block: &Block, compiler: &mut Compiler) -> Result<Self, CompileError> {
+        Ok(match block.block_type {
+            DefineSettings => {
+                return Err(CompileError::NotAnObject(
+                    block.name.clone(),
+                    block.block_type,
+                ))
+            }
+            DefineObject => Self::Object(MxObject::from_block(block, compiler)?),
+            DefineSound => Self::Sound(MxSound::from_block(block, compiler)?),
+            DefineEvent => Self::Event(MxEvent::from_block(block, compiler)?),
+            DefineAnim => Self::Video(MxVideo::from_block(block, compiler)?),
+            ParallelAction => Self::Presenter(MxPresenter::from_block(block, compiler)?),
+            DefineStill => Self::Bitmap(MxBitmap::from_block(block, compiler)?),
+            SerialAction => Self::World(MxWorld::from_block(block, compiler)?),
+        })
+    }
+}
+
 impl MxObType {
+    fn header(&self) -> &MxObHeader {
+        match self {
+            MxObType::Video(x) => &x.header,
+            MxObType::Sound(x) => &x.header,
+            MxObType::World(x) => &x.header,
+            MxObType::Presenter(x) => &x.header,
+            MxObType::Event(x) => &x.header,
+            MxObType::Animation(x) => &x.header,
+            MxObType::Bitmap(x) => &x.header,
+            MxObType::Object(x) => &x.header,
+        }
+    }
+
+    fn header_mut(&mut self) -> &mut MxObHeader {
+        match self {
+            MxObType::Video(x) => &mut x.header,
+            MxObType::Sound(x) => &mut x.header,
+            MxObType::World(x) => &mut x.header,
+            MxObType::Presenter(x) => &mut x.header,
+            MxObType::Event(x) => &mut x.header,
+            MxObType::Animation(x) => &mut x.header,
+            MxObType::Bitmap(x) => &mut x.header,
+            MxObType::Object(x) => &mut x.header,
+        }
+    }
+
     pub fn get_name(&self) -> String {
+        decode(&self.header().name)
+    }
+
+    pub fn get_id(&self) -> u32 {
+        self.header().id
+    }
+
+    /// The object's flags, as they're stored.
+    pub fn flag_word(&self) -> u32 {
+        self.header().flags.bits()
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MxObType::Video(_) => "video",
+            MxObType::Sound(_) => "sound",
+            MxObType::World(_) => "world",
+            MxObType::Presenter(_) => "presenter",
+            MxObType::Event(_) => "event",
+            MxObType::Animation(_) => "animation",
+            MxObType::Bitmap(_) => "bitmap",
+            MxObType::Object(_) => "object",
+        }
+    }
+
+    pub fn get_filename(&self) -> Option<String> {
+        match self {
+            MxObType::Video(x) => Some(decode(&x.filename)),
+            MxObType::Sound(x) => Some(decode(&x.filename)),
+            MxObType::Event(x) => Some(decode(&x.filename)),
+            MxObType::Bitmap(x) => Some(decode(&x.filename)),
+            MxObType::Object(x) => Some(decode(&x.filename)),
+            MxObType::World(_) | MxObType::Presenter(_) | MxObType::Animation(_) => None,
+        }
+    }
+
+    pub fn set_name(&mut self, name: &str) -> Result<(), CompileError> {
+        self.header_mut().name = encode(name)?;
+        Ok(())
+    }
+
+    /// Replaces the stored file name, for objects that have one. Returns
+    /// whether it did.
+    pub fn set_filename(&mut self, filename: &str) -> Result<bool, CompileError> {
+        let field = match self {
+            MxObType::Video(x) => &mut x.filename,
+            MxObType::Sound(x) => &mut x.filename,
+            MxObType::Event(x) => &mut x.filename,
+            MxObType::Bitmap(x) => &mut x.filename,
+            MxObType::Object(x) => &mut x.filename,
+            MxObType::World(_) | MxObType::Presenter(_) | MxObType::Animation(_) => {
+                return Ok(false)
+            }
+        };
+
+        *field = encode(filename)?;
+        Ok(true)
+    }
+
+    /// How many times a sound that loops by streaming is played, which is
+    /// how many times its samples are stored. Anything else is stored once.
+    pub fn streamed_loops(&self) -> u32 {
+        match self {
+            MxObType::Sound(x) if x.header.flags.loop_stream() => x.header.loops.max(1) as u32,
+            _ => 1,
+        }
+    }
+
+    pub fn get_list(&self) -> Option<&List> {
+        match self {
+            MxObType::World(x) => Some(&x.list),
+            MxObType::Presenter(x) => Some(&x.list),
+            _ => None,
+        }
+    }
+
+    pub fn get_list_mut(&mut self) -> Option<&mut List> {
         match self {
-            MxObType::Video(x) => x.name.to_string(),
-            MxObType::Sound(x) => x.name.to_string(),
-            MxObType::World(x) => x.name.to_string(),
-            MxObType::Presenter(x) => x.name.to_string(),
-            MxObType::Event(x) => x.name.to_string(),
-            MxObType::Animation(x) => x.name.to_string(),
-            MxObType::Bitmap(x) => x.name.to_string(),
-            MxObType::Object(x) => x.name.to_string(),
+            MxObType::World(x) => Some(&mut x.list),
+            MxObType::Presenter(x) => Some(&mut x.list),
+            _ => None,
+        }
+    }
+
+    pub fn get_children(&self) -> &[RiffChunk] {
+        match self.get_list() {
+            Some(list) => &list.subchunks,
+            None => &[],
+        }
+    }
+
+    pub fn collect_ids(&self, ids: &mut Vec<u32>) {
+        ids.push(self.get_id());
+        for child in self.get_children() {
+            child.collect_ids(ids);
+        }
+    }
+
+    /// Gives this object and its children the new IDs in `map`; IDs that
+    /// aren't in it are left alone.
+    pub fn remap_ids(&mut self, map: &HashMap<u32, u32>) {
+        let id = &mut self.header_mut().id;
+        if let Some(&new) = map.get(id) {
+            *id = new;
+        }
+
+        if let Some(list) = self.get_list_mut() {
+            for child in &mut list.subchunks {
+                child.remap_ids(map);
+            }
         }
     }
 }
 
 #[bitfield]
 #[binrw]
-#[br(map(Self::from_bytes))]
+#[br(map(|bits: u32| Self::from_bytes(bits.to_le_bytes())))]
+#[bw(map(Self::bits))]
 #[derive(Debug, Clone)]
 pub struct MxObFlags {
     loop_cache: bool,
@@ -913,10 +1620,22 @@ pub struct MxObFlags {
     unk3: B24,
 }
 
+impl MxObFlags {
+    /// The raw bits, including the ones without names.
+    pub fn bits(&self) -> u32 {
+        u32::from_le_bytes(self.clone().into_bytes())
+    }
+}
+
 #[binrw]
 #[derive(Debug, Clone)]
 #[br(import(buf_size: i32))]
 pub struct MxOb {
+    /// Where the chunk starts in the file it was read from, or 0 if it was
+    /// built in memory
+    #[br(parse_with(chunk_start))]
+    #[bw(ignore)]
+    pub offset: u64,
     pub header: RiffChunkHeader,
     #[br(pad_size_to(header.size))]
     #[br(args(buf_size))]
@@ -924,7 +1643,290 @@ pub struct MxOb {
 }
 
 impl ToBlock for MxOb {
-    fn to_block(&self, top_level: bool) -> (Option<Block>, Vec<Block>, Vec<Block>) {
+    fn to_block(&self, top_level: bool) -> ToBlockResult {
         self.obj.to_block(top_level)
     }
 }
+
+impl MxOb {
+    pub fn new(obj: MxObType) -> BinResult<Self> {
+        let mut ob = Self {
+            offset: 0,
+            header: RiffChunkHeader { size: 0 },
+            obj,
+        };
+        ob.update_size()?;
+        Ok(ob)
+    }
+
+    pub fn collect_offsets(&self, offsets: &mut HashMap<u32, u64>) {
+        offsets.insert(self.obj.get_id(), self.offset);
+        for child in self.obj.get_children() {
+            child.collect_offsets(offsets);
+        }
+    }
+
+    pub fn collect_flags(&self, flags: &mut HashMap<u32, u32>) {
+        flags.insert(self.obj.get_id(), self.obj.flag_word());
+        for child in self.obj.get_children() {
+            child.collect_flags(flags);
+        }
+    }
+
+    pub fn update_size(&mut self) -> BinResult<()> {
+        self.header.size = 0;
+        self.header.size = data_size(self)?;
+        Ok(())
+    }
+}
+
+impl FromBlock for MxOb {
+    fn from_block(block: &Block, compiler: &mut Compiler) -> Result<Self, CompileError> {
+        let obj = MxObType::from_block(block, compiler).map_err(|e| e.at(block))?;
+        Ok(Self::new(obj)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        omni::{
+            testing::{compile, compile_with, decompile, round_trip, wav},
+            CompileOptions,
+        },
+        text::FormatOptions,
+    };
+
+    const SETTINGS: &str = "defineSettings S { bufferSizeKB = 64; buffersNum = 2; }\n";
+
+    #[test]
+    fn animations_and_stills_keep_their_looping() {
+        let files = [
+            ("a.flc", b"flc".as_slice()),
+            ("b.smk", b"smk".as_slice()),
+            ("c.stl", b"stl".as_slice()),
+        ];
+
+        let file = compile(
+            &format!(
+                "{SETTINGS}defineAnim A {{ fileName = \"a.flc\"; loopCount = 4; loopingMethod = CACHE; }}
+                defineAnim B {{ fileName = \"b.smk\"; loopCount = 2; loopingMethod = STREAM; }}
+                defineStill C {{ fileName = \"c.stl\"; loopCount = 3; loopingMethod = CACHE; }}
+                serialAction T Weave {{ A; B; C; }}"
+            ),
+            &files,
+        );
+
+        let script = round_trip(&file, &files);
+        for looping in [
+            "loopCount = 4;",
+            "loopCount = 2;",
+            "loopCount = 3;",
+            "loopingMethod = CACHE;",
+            "loopingMethod = STREAM;",
+        ] {
+            assert!(script.contains(looping), "no {looping} in {script}");
+        }
+    }
+
+    #[test]
+    fn sounds_without_a_handler_class_keep_an_empty_one() {
+        let wav = wav();
+        let files = [("a.wav", wav.as_slice())];
+
+        let plain = compile(
+            &format!("{SETTINGS}defineSound A {{ fileName = \"a.wav\"; }}"),
+            &files,
+        );
+        let script = round_trip(&plain, &files);
+        assert!(!script.contains("handlerClass"), "{script}");
+
+        let positioned = compile(
+            &format!(
+                "{SETTINGS}defineSound A {{ fileName = \"a.wav\"; handlerClass = \"Lego3DWavePresenter\"; }}"
+            ),
+            &files,
+        );
+        assert_ne!(plain, positioned);
+        let script = round_trip(&positioned, &files);
+        assert!(
+            script.contains("handlerClass = \"Lego3DWavePresenter\";"),
+            "{script}"
+        );
+    }
+
+    #[test]
+    fn timing_is_kept_for_every_type() {
+        let wav = wav();
+        let files = [
+            ("a.wav", wav.as_slice()),
+            ("e.evt", b"event".as_slice()),
+            ("o.obj", b"object".as_slice()),
+        ];
+
+        let file = compile(
+            &format!(
+                "{SETTINGS}defineSound A {{ fileName = \"a.wav\"; startTime = 100; duration = 2500; }}
+                defineEvent E {{ fileName = \"e\"; startTime = 20; duration = 40; }}
+                defineObject O {{ fileName = \"o.obj\"; startTime = 30; duration = INDEFINITE; }}
+                parallelAction P {{ startTime = 50; duration = 9000; loopCount = 3; A; E; O; }}
+                serialAction T Weave {{ P; startTime = 7; duration = 1234; }}"
+            ),
+            &files,
+        );
+
+        let script = round_trip(&file, &files);
+        for timing in [
+            "startTime = 100;",
+            "duration = 2500;",
+            "startTime = 20;",
+            "duration = 40;",
+            "startTime = 30;",
+            "duration = INDEFINITE;",
+            "startTime = 50;",
+            "loopCount = 3;",
+            "startTime = 7;",
+            "duration = 1234;",
+        ] {
+            assert!(script.contains(timing), "no {timing} in {script}");
+        }
+    }
+
+    #[test]
+    fn placement_is_kept_for_every_type() {
+        let wav = wav();
+        let files = [
+            ("a.wav", wav.as_slice()),
+            ("b.flc", b"flc".as_slice()),
+            ("c.stl", b"stl".as_slice()),
+            ("e.evt", b"event".as_slice()),
+            ("o.obj", b"object".as_slice()),
+        ];
+
+        let placement = "location = (1, 2, 3); direction = (0, 0, 1); up = (0, 1, 0);";
+        let file = compile(
+            &format!(
+                "{SETTINGS}defineSound A {{ fileName = \"a.wav\"; {placement} extra = \"sound\"; }}
+                defineAnim B {{ fileName = \"b.flc\"; {placement} extra = \"anim\"; }}
+                defineStill C {{ fileName = \"c.stl\"; {placement} extra = \"still\"; }}
+                defineEvent E {{ fileName = \"e\"; {placement} extra = \"event\"; }}
+                defineObject O {{ fileName = \"o.obj\"; {placement} extra = \"object\"; }}
+                parallelAction P {{ {placement} extra = \"presenter\"; A; B; C; E; O; }}
+                serialAction T Weave {{ P; {placement} extra = \"world\"; }}"
+            ),
+            &files,
+        );
+
+        let script = round_trip(&file, &files);
+        assert_eq!(
+            script.matches("location = (1, 2, 3);").count(),
+            7,
+            "{script}"
+        );
+        for extra in [
+            "sound",
+            "anim",
+            "still",
+            "event",
+            "object",
+            "presenter",
+            "world",
+        ] {
+            assert!(script.contains(&format!("\"{extra}\";")), "{script}");
+        }
+    }
+
+    #[test]
+    fn flags_are_kept_bit_by_bit() {
+        let files = [
+            ("e.evt", b"event".as_slice()),
+            ("o.obj", b"object".as_slice()),
+        ];
+        let script = |flags: &str| {
+            format!(
+                "{SETTINGS}defineEvent E {{ fileName = \"e\"; loopingMethod = CACHE; loopStream = 1; {flags} }}
+                defineObject O {{ fileName = \"o.obj\"; loopingMethod = NONE; noLoop = 0; transparent = 1; }}
+                serialAction T Weave {{ E; O; }}"
+            )
+        };
+
+        let file = compile(&script("flags = 0x300;"), &files);
+        assert_eq!(file, compile(&script("flags = 768;"), &files));
+
+        let script = round_trip(&file, &files);
+        for statement in [
+            "loopStream = 1;",
+            "flags = 0x300;",
+            "noLoop = 0;",
+            "transparent = 1;",
+        ] {
+            assert!(script.contains(statement), "no {statement} in {script}");
+        }
+    }
+
+    #[test]
+    fn named_bits_in_flags_are_set() {
+        let files = [("e.evt", b"event".as_slice())];
+        let script = |flags: &str| {
+            format!(
+                "{SETTINGS}defineEvent E {{ fileName = \"e\"; loopingMethod = CACHE; {flags} }}
+                serialAction T Weave {{ E; }}"
+            )
+        };
+
+        let plain = compile(&script(""), &files);
+        assert_eq!(compile(&script("flags = 0x1;"), &files), plain);
+        assert_eq!(
+            compile(&script("flags = 0x5; loopStream = 0;"), &files),
+            plain
+        );
+    }
+
+    #[test]
+    fn the_bit_after_transparent_has_no_name() {
+        let files = [("c.stl", b"stl".as_slice())];
+        let script = |fields: &str| {
+            format!(
+                "{SETTINGS}defineStill C {{ fileName = \"c.stl\"; {fields} }}
+                serialAction T Weave {{ C; }}"
+            )
+        };
+
+        let file = compile(&script("transparency = YES; flags = 0x10;"), &files);
+        let decompiled = round_trip(&file, &files);
+        assert!(decompiled.contains("transparency = YES;"), "{decompiled}");
+        assert!(decompiled.contains("flags = 0x10;"), "{decompiled}");
+
+        let annotated = decompile(&file).format(&FormatOptions {
+            annotate_unknown: true,
+            ..Default::default()
+        });
+        assert!(annotated.contains("// flags 0x"), "{annotated}");
+
+        // FAST can't be stored, so it's left out, or refused with --strict
+        let fast = compile(&script("transparency = FAST;"), &files);
+        assert_eq!(fast, compile(&script(""), &files));
+        let strict = CompileOptions {
+            strict: true,
+            ..Default::default()
+        };
+        assert!(compile_with(&script("transparency = FAST;"), &files, strict).is_err());
+    }
+
+    #[test]
+    fn negative_random_counts_are_errors() {
+        let files = [("e.evt", b"event".as_slice())];
+        let script = |count: i32| {
+            format!(
+                "{SETTINGS}defineEvent E {{ fileName = \"e\"; }}
+                serialAction T Weave {{ randomCount = {count}; selectionList = list(0); E; }}"
+            )
+        };
+
+        let decompiled = round_trip(&compile(&script(3), &files), &files);
+        assert!(decompiled.contains("randomCount = 3;"), "{decompiled}");
+
+        let error = compile_with(&script(-1), &files, CompileOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("can't be -1"), "{error}");
+    }
+}
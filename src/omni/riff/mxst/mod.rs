@@ -1,31 +1,53 @@
-use std::mem::size_of;
-
 use crate::{
     omni::riff::{HumanBytes, OmniVersion, RiffChunkHeader},
-    text::{Block, BlockType::*, ToBlock},
+    text::{BlockType::*, ToBlock, ToBlockResult},
 };
-use binrw::binrw;
+use binrw::{binrw, BinResult};
 
 use super::{
+    data_size,
     mxob::{MxOb, MxObType::*},
-    read_chunks, List, RiffChunk,
+    read_chunks, write_sized, ChunkId, LISTType, List, RiffChunk,
 };
 
+pub const MXDA_ID: ChunkId = ChunkId { value: *b"MxDa" };
+
 #[binrw]
 #[derive(Debug, Clone)]
 #[br(import(buf_size: i32))]
 pub struct MxSt {
     pub header: RiffChunkHeader,
-    #[br(magic(b"MxOb"))]
+    #[brw(magic(b"MxOb"))]
     #[br(args(buf_size))]
+    #[bw(write_with(write_sized))]
     pub obj: MxOb,
-    #[br(magic(b"LIST"))]
+    #[brw(magic(b"LIST"))]
     #[br(args(buf_size))]
+    #[bw(write_with(write_sized))]
     pub list: List,
 }
 
+impl MxSt {
+    pub fn new(obj: MxOb, chunks: Vec<RiffChunk>) -> BinResult<Self> {
+        let mut st = Self {
+            header: RiffChunkHeader { size: 0 },
+            obj,
+            list: List::new(LISTType::Other(MXDA_ID), chunks)?,
+        };
+        st.update_size()?;
+        Ok(st)
+    }
+
+    pub fn update_size(&mut self) -> BinResult<()> {
+        self.list.update_size()?;
+        self.header.size = data_size(self)?;
+        Ok(())
+    }
+}
+
 impl ToBlock for MxSt {
-    fn to_block(&self, top_level: bool) -> (Option<Block>, Vec<Block>, Vec<Block>) {
-        self.obj.to_block(top_level)
+    fn to_block(&self, _: bool) -> ToBlockResult {
+        // anything with its own stream is a weave, however deeply it's nested
+        self.obj.to_block(true)
     }
 }
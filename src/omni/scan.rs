@@ -0,0 +1,67 @@
+use super::riff::{OMNI_ID, RIFF_ID, RIFX_ID};
+use std::io::{Read, Seek, SeekFrom};
+
+/// How much of the source is searched at a time.
+const BLOCK_SIZE: usize = 0x10000;
+
+/// Length of the signature searched for: `RIFF` (or `RIFX`, for
+/// big-endian files), the chunk size, then `OMNI`.
+const SIGNATURE_LEN: usize = 12;
+
+/// An Omni file found inside another file by [`scan`].
+#[derive(Debug, Clone)]
+pub struct Embedded {
+    /// Where its RIFF chunk starts
+    pub offset: u64,
+    /// Length its RIFF chunk claims to have, including the chunk header;
+    /// it can run past the end of the source if it's been cut off
+    pub len: u64,
+}
+
+/// Searches anything (an EXE, a disc image, a memory dump...) for the start
+/// of an Omni file, listing every candidate in order. Nothing is checked
+/// past the signature, so a match can turn out not to parse.
+pub fn scan<R: Read + Seek>(source: &mut R) -> std::io::Result<Vec<Embedded>> {
+    source.seek(SeekFrom::Start(0))?;
+
+    let mut found = vec![];
+    let mut buf = vec![];
+    // offset of the start of `buf` in the source
+    let mut base = 0u64;
+
+    loop {
+        let kept = buf.len();
+        buf.resize(kept + BLOCK_SIZE, 0);
+        let read = source.read(&mut buf[kept..])?;
+        buf.truncate(kept + read);
+
+        for (i, window) in buf.windows(SIGNATURE_LEN).enumerate() {
+            if window[8..] != OMNI_ID.value {
+                continue;
+            }
+
+            let size = window[4..8].try_into().unwrap();
+            let size = match &window[..4] {
+                id if id == RIFF_ID.value => u32::from_le_bytes(size),
+                id if id == RIFX_ID.value => u32::from_be_bytes(size),
+                _ => continue,
+            };
+
+            found.push(Embedded {
+                offset: base + i as u64,
+                len: size as u64 + 8,
+            });
+        }
+
+        if read == 0 {
+            break;
+        }
+
+        // keep the end, in case a signature is split across blocks
+        let keep = buf.len().min(SIGNATURE_LEN - 1);
+        base += (buf.len() - keep) as u64;
+        buf.drain(..buf.len() - keep);
+    }
+
+    Ok(found)
+}
@@ -0,0 +1,232 @@
+use super::Omni;
+use crate::diagnostics::{Diagnostic, Location, Severity};
+use human_bytes::human_bytes;
+use std::{collections::BTreeMap, fmt::Display};
+
+/// Length of the window that peak rates are measured over, in milliseconds.
+const RATE_WINDOW: u32 = 1000;
+
+/// Roughly how many buffers the engine reads in a second: one each time its
+/// disk streaming thread wakes up, which is every 100 ms or so.
+pub const BUFFER_READS_PER_SECOND: u64 = 10;
+
+/// How an object's data is spread over time.
+#[derive(Debug, Clone)]
+pub struct ObjectStats {
+    pub id: u32,
+    pub name: String,
+    pub kind: &'static str,
+    /// Total size of the object's chunk data
+    pub bytes: u64,
+    pub chunks: usize,
+    pub first_time: u32,
+    pub last_time: u32,
+    /// Most bytes streamed in any one second
+    pub peak_rate: u64,
+}
+
+impl ObjectStats {
+    /// Time from the first chunk to the last, in milliseconds.
+    pub fn duration(&self) -> u32 {
+        self.last_time - self.first_time
+    }
+
+    /// Bytes per second over the object's duration, if it has one.
+    pub fn average_rate(&self) -> Option<f64> {
+        match self.duration() {
+            0 => None,
+            d => Some(self.bytes as f64 * 1000.0 / d as f64),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Stats {
+    /// Every object with data, by ID
+    pub objects: Vec<ObjectStats>,
+    pub buffer_size: u64,
+    /// Bytes of chunk data in each buffer, as read from the file or as
+    /// writing it would lay it out
+    pub buffers: Option<Vec<u64>>,
+}
+
+/// An object that's streamed faster than the buffers can be filled.
+#[derive(Debug, Clone)]
+pub struct RateWarning {
+    pub id: u32,
+    pub name: String,
+    pub kind: &'static str,
+    /// Most bytes the object streams in a second
+    pub needed: u64,
+    /// Most bytes the buffers can bring in a second
+    pub available: u64,
+}
+
+impl RateWarning {
+    /// What's wrong and how to fix it, without saying which object it is.
+    fn problem(&self) -> String {
+        let fix = match self.kind {
+            "sound" => "lower its sample rate, bit depth or channels",
+            "video" => "lower its frame rate or size",
+            _ => "make it smaller",
+        };
+
+        format!(
+            "needs {}/s but the buffer layout delivers {}/s; increase bufferSizeKB or {fix}",
+            human_bytes(self.needed as f64),
+            human_bytes(self.available as f64)
+        )
+    }
+}
+
+impl Display for RateWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} \"{}\" ({}) {}",
+            self.kind,
+            self.name,
+            self.id,
+            self.problem()
+        )
+    }
+}
+
+impl From<RateWarning> for Diagnostic {
+    fn from(warning: RateWarning) -> Self {
+        let message = format!("{} {}", warning.kind, warning.problem());
+        Diagnostic::new(
+            Severity::Warning,
+            Some(Location::Object(warning.id, warning.name)),
+            message,
+        )
+    }
+}
+
+/// Most bytes in any window of [`RATE_WINDOW`], from `(time, size)` pairs
+/// sorted by time.
+fn peak_rate(chunks: &[(u32, u64)]) -> u64 {
+    let mut peak = 0;
+    let mut sum = 0;
+    let mut start = 0;
+
+    for &(time, size) in chunks {
+        sum += size;
+
+        while chunks[start].0 + RATE_WINDOW <= time {
+            sum -= chunks[start].1;
+            start += 1;
+        }
+
+        peak = peak.max(sum);
+    }
+
+    peak
+}
+
+impl Stats {
+    /// Most bytes the buffers can bring in a second, going by
+    /// [`BUFFER_READS_PER_SECOND`].
+    pub fn available_rate(&self) -> u64 {
+        self.buffer_size * BUFFER_READS_PER_SECOND
+    }
+
+    /// Objects streamed over time that need their data faster than the
+    /// buffers can bring it in. Anything needed all at once is loaded
+    /// before it starts, so it's left out.
+    pub fn rate_warnings(&self) -> Vec<RateWarning> {
+        let available = self.available_rate();
+
+        self.objects
+            .iter()
+            .filter(|o| o.duration() > 0 && o.peak_rate > available)
+            .map(|o| RateWarning {
+                id: o.id,
+                name: o.name.clone(),
+                kind: o.kind,
+                needed: o.peak_rate,
+                available,
+            })
+            .collect()
+    }
+}
+
+impl Omni {
+    pub fn stats(&self) -> Stats {
+        let timeline = self.timeline();
+        let buffer_size = self.header.buffer_size.0 as u64;
+
+        let mut by_object = BTreeMap::<u32, Vec<_>>::new();
+
+        for entry in &timeline {
+            by_object.entry(entry.object).or_default().push(entry);
+        }
+
+        let objects = by_object
+            .into_iter()
+            .map(|(id, entries)| {
+                let mut chunks = entries
+                    .iter()
+                    .map(|e| (e.time, e.size as u64))
+                    .collect::<Vec<_>>();
+                chunks.sort_by_key(|&(time, _)| time);
+
+                ObjectStats {
+                    id,
+                    name: entries[0].name.clone(),
+                    kind: entries[0].kind,
+                    bytes: chunks.iter().map(|&(_, size)| size).sum(),
+                    chunks: chunks.len(),
+                    first_time: chunks[0].0,
+                    last_time: chunks[chunks.len() - 1].0,
+                    peak_rate: peak_rate(&chunks),
+                }
+            })
+            .collect();
+
+        // files read lazily remember where their chunk data was, and
+        // anything else is where writing it would put it
+        let ranges = timeline
+            .iter()
+            .map(|entry| Some((entry.data.offset()?, entry.size as u64)))
+            .collect::<Option<Vec<_>>>()
+            .or_else(|| {
+                let placements = self.placements().ok()?;
+                Some(
+                    placements
+                        .iter()
+                        .map(|p| (p.data_offset, p.data_len))
+                        .collect(),
+                )
+            });
+
+        let buffers = ranges.filter(|_| buffer_size != 0).map(|ranges| {
+            let mut buffers = vec![];
+
+            for (mut start, size) in ranges {
+                let end = start + size;
+
+                // data can run on into the next buffer if it's too big for one
+                while start < end {
+                    let index = (start / buffer_size) as usize;
+                    let buffer_end = (index as u64 + 1) * buffer_size;
+
+                    if buffers.len() <= index {
+                        buffers.resize(index + 1, 0);
+                    }
+                    buffers[index] += end.min(buffer_end) - start;
+
+                    start = buffer_end;
+                }
+            }
+
+            buffers
+        });
+
+        Stats {
+            objects,
+            buffer_size,
+            buffers,
+        }
+    }
+}
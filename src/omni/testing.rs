@@ -0,0 +1,83 @@
+//! Compiling and decompiling scripts in tests, with their resources written
+//! to a folder of their own.
+
+use super::{CompileOptions, Omni};
+use crate::{audio::Pcm, diagnostics::Diagnostics, text::Text};
+use std::{
+    fs::{create_dir_all, remove_dir_all, write},
+    io::Cursor,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static FOLDERS: AtomicUsize = AtomicUsize::new(0);
+
+/// A short mono 8-bit sound, as a WAV file.
+pub fn wav() -> Vec<u8> {
+    Pcm {
+        channels: 1,
+        sample_rate: 11025,
+        bits: 8,
+        samples: (0..2000).map(|i| (i % 50) - 25).collect(),
+    }
+    .to_wav()
+}
+
+/// Compiles `script` with `options`, with `files` in the resource folder.
+pub fn compile_with(
+    script: &str,
+    files: &[(&str, &[u8])],
+    options: CompileOptions,
+) -> anyhow::Result<Vec<u8>> {
+    let folder = std::env::temp_dir().join(format!(
+        "gwydd-test-{}-{}",
+        std::process::id(),
+        FOLDERS.fetch_add(1, Ordering::Relaxed)
+    ));
+    create_dir_all(&folder)?;
+    for (name, data) in files {
+        write(folder.join(name), data)?;
+    }
+
+    let compiled = (|| {
+        let mut text = Text::parse_str(script)?;
+        text.assign_ids()?;
+
+        let omni = Omni::from_text(
+            &text,
+            &CompileOptions {
+                resources: Some(folder.clone()),
+                ..options
+            },
+        )?;
+
+        let mut cursor = Cursor::new(vec![]);
+        omni.write(&mut cursor)?;
+        Ok(cursor.into_inner())
+    })();
+
+    remove_dir_all(&folder)?;
+    compiled
+}
+
+/// Compiles `script`, with `files` in the resource folder.
+pub fn compile(script: &str, files: &[(&str, &[u8])]) -> Vec<u8> {
+    compile_with(script, files, CompileOptions::default()).unwrap()
+}
+
+/// Decompiles a compiled file.
+pub fn decompile(file: &[u8]) -> Text {
+    Text::from_omni(&Omni::parse_bytes(file).unwrap(), &Diagnostics::new()).unwrap()
+}
+
+/// Decompiles a compiled file, then compiles the script that makes with
+/// `files`, checking that it comes out the same. Returns the script.
+pub fn round_trip(file: &[u8], files: &[(&str, &[u8])]) -> String {
+    let script = decompile(file).to_string();
+
+    assert!(
+        compile(&script, files) == file,
+        "compiling the decompiled script changed the file:\n{script}"
+    );
+
+    script
+}
@@ -0,0 +1,85 @@
+use super::{
+    riff::{MxObType, Payload, RiffChunk},
+    Omni,
+};
+use std::collections::HashMap;
+
+/// One MxCh chunk, in the order it's stored.
+#[derive(Debug, Clone)]
+pub struct TimelineEntry<'a> {
+    pub object: u32,
+    /// Name of the object the chunk belongs to, or empty if there's no
+    /// object with its ID
+    pub name: String,
+    /// Type of the object the chunk belongs to (see [`TimelineEntry::name`])
+    pub kind: &'static str,
+    /// Time in milliseconds from the start of the object
+    pub time: u32,
+    pub size: u32,
+    pub flags: u16,
+    /// Whether it's the empty chunk that ends the object's data
+    pub end: bool,
+    /// Whether it runs past the end of the buffer it starts in
+    pub split: bool,
+    pub data: &'a Payload,
+}
+
+fn walk<'a>(
+    chunk: &'a RiffChunk,
+    objects: &mut HashMap<u32, &'a MxObType>,
+    entries: &mut Vec<TimelineEntry<'a>>,
+) {
+    match chunk {
+        RiffChunk::MxCh(x) => entries.push(TimelineEntry {
+            object: x.object,
+            name: String::new(),
+            kind: "",
+            time: x.time,
+            size: x.data.len() as u32,
+            flags: x.flags.bits(),
+            end: x.flags.end(),
+            split: x.flags.split(),
+            data: &x.data,
+        }),
+        RiffChunk::MxOb(x) => {
+            objects.insert(x.obj.get_id(), &x.obj);
+            for child in x.obj.get_children() {
+                walk(child, objects, entries);
+            }
+        }
+        RiffChunk::MxSt(x) => {
+            objects.insert(x.obj.obj.get_id(), &x.obj.obj);
+            for child in x.obj.obj.get_children().iter().chain(&x.list.subchunks) {
+                walk(child, objects, entries);
+            }
+        }
+        RiffChunk::List(x) => {
+            for child in &x.subchunks {
+                walk(child, objects, entries);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Omni {
+    /// Lists every MxCh chunk in file order, which is the order they're
+    /// streamed in.
+    pub fn timeline(&self) -> Vec<TimelineEntry<'_>> {
+        let mut objects = HashMap::new();
+        let mut entries = vec![];
+
+        for chunk in &self.streams.subchunks {
+            walk(chunk, &mut objects, &mut entries);
+        }
+
+        for entry in &mut entries {
+            if let Some(obj) = objects.get(&entry.object) {
+                entry.name = obj.get_name();
+                entry.kind = obj.kind();
+            }
+        }
+
+        entries
+    }
+}
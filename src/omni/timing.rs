@@ -0,0 +1,174 @@
+//! Working out when each part of a stored file is played, so that its
+//! chunks can be given the times the engine streams them at.
+
+const WAV_PCM: u16 = 1;
+
+/// Size of the format the engine stores at the start of a sound.
+const ENGINE_WAV_HEADER: usize = 24;
+
+const FLI_MAGIC: u16 = 0xAF11;
+const FLC_HEADER: usize = 128;
+const FLC_FRAME: u16 = 0xF1FA;
+
+/// Offset of the frame sizes in a Smacker header.
+const SMK_FRAME_SIZES: usize = 0x68;
+
+/// Set in a Smacker file's flags when it has a ring frame after the others.
+const SMK_RING_FRAME: u32 = 1;
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Where the samples of a WAV file are.
+#[derive(Debug, Clone, Copy)]
+pub struct WavLayout {
+    pub data_start: usize,
+    /// Size of the samples, if the header gives it
+    pub data_len: Option<usize>,
+    pub byte_rate: u32,
+}
+
+impl WavLayout {
+    /// Reads a WAV file's header, or the engine's own form of it: a
+    /// PCMWAVEFORMAT, then the size of the data and some flags.
+    pub fn of(data: &[u8]) -> Option<Self> {
+        if !(data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WAVE")) {
+            if u16_at(data, 0) != Some(WAV_PCM) || data.len() < ENGINE_WAV_HEADER {
+                return None;
+            }
+
+            return Some(Self {
+                data_start: ENGINE_WAV_HEADER,
+                data_len: None,
+                byte_rate: u32_at(data, 8).filter(|&r| r > 0)?,
+            });
+        }
+
+        let mut byte_rate = None;
+        let mut at = 12;
+
+        while let (Some(id), Some(size)) = (data.get(at..at + 4), u32_at(data, at + 4)) {
+            match id {
+                b"fmt " => byte_rate = u32_at(data, at + 16),
+                b"data" => {
+                    return Some(Self {
+                        data_start: at + 8,
+                        data_len: Some(size as usize),
+                        byte_rate: byte_rate.filter(|&r| r > 0)?,
+                    })
+                }
+                _ => {}
+            }
+
+            // chunks are padded to an even size
+            at += 8 + size as usize + (size as usize & 1);
+        }
+
+        None
+    }
+}
+
+/// When each byte of a sound or animation is played.
+#[derive(Debug, Clone)]
+pub enum MediaClock {
+    /// Samples start at `data_start` and are played at `byte_rate`.
+    Wav { data_start: usize, byte_rate: u32 },
+    /// Each frame starts at one of `starts` and lasts `frame_time` ms.
+    Frames { starts: Vec<usize>, frame_time: f64 },
+}
+
+impl MediaClock {
+    /// Reads the timing of a WAV file, in either form.
+    pub fn wav(data: &[u8]) -> Option<Self> {
+        let wav = WavLayout::of(data)?;
+
+        Some(Self::Wav {
+            data_start: wav.data_start,
+            byte_rate: wav.byte_rate,
+        })
+    }
+
+    /// Reads the timing of an FLI or FLC file.
+    pub fn flc(data: &[u8]) -> Option<Self> {
+        // FLI files give the speed in 1/70ths of a second, FLC files in ms
+        let frame_time = match u16_at(data, 4)? {
+            FLI_MAGIC => u16_at(data, 16)? as f64 * 1000.0 / 70.0,
+            _ => u32_at(data, 16)? as f64,
+        };
+
+        let mut starts = vec![];
+        let mut at = FLC_HEADER;
+
+        while let (Some(size), Some(kind)) = (u32_at(data, at), u16_at(data, at + 4)) {
+            // prefix chunks hold settings, not frames
+            if kind == FLC_FRAME {
+                starts.push(at);
+            }
+
+            if size < 6 {
+                break;
+            }
+            at += size as usize;
+        }
+
+        Some(Self::Frames { starts, frame_time })
+    }
+
+    /// Reads the timing of a Smacker file.
+    pub fn smk(data: &[u8]) -> Option<Self> {
+        let frames = u32_at(data, 12)? as usize;
+        let rate = u32_at(data, 16)? as i32;
+        let flags = u32_at(data, 20)?;
+        let trees_size = u32_at(data, 52)? as usize;
+
+        // positive rates are in ms, negative ones in 10µs units
+        let frame_time = match rate {
+            0 => 100.0,
+            r if r > 0 => r as f64,
+            r => -(r as f64) / 100.0,
+        };
+
+        let count = frames + (flags & SMK_RING_FRAME) as usize;
+
+        // frame sizes, then a type byte for each frame, then the trees
+        let mut at = SMK_FRAME_SIZES + count * 5 + trees_size;
+        let mut starts = Vec::with_capacity(frames);
+
+        // the ring frame goes back to the start, so it isn't given a time
+        for i in 0..frames {
+            starts.push(at);
+            // the bottom two bits of each size are flags
+            at += (u32_at(data, SMK_FRAME_SIZES + i * 4)? & !3) as usize;
+        }
+
+        Some(Self::Frames { starts, frame_time })
+    }
+
+    /// Time in ms from the start of playback that the byte at `offset` is
+    /// needed. Headers are needed straight away.
+    pub fn time_at(&self, offset: usize) -> u32 {
+        match self {
+            Self::Wav {
+                data_start,
+                byte_rate,
+            } => (offset.saturating_sub(*data_start) as u64 * 1000 / *byte_rate as u64) as u32,
+            Self::Frames { starts, frame_time } => {
+                let frame = starts.partition_point(|&s| s <= offset).saturating_sub(1);
+                (frame as f64 * frame_time) as u32
+            }
+        }
+    }
+
+    /// How long the whole file plays for, given its size.
+    pub fn duration(&self, len: usize) -> u32 {
+        match self {
+            Self::Wav { .. } => self.time_at(len),
+            Self::Frames { starts, frame_time } => (starts.len() as f64 * frame_time) as u32,
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use super::{schema::defaults, Statement, Text};
+
+impl Text {
+    /// Spells out every value that's been left at its default, so that
+    /// nothing about an object is implicit.
+    pub fn add_defaults(&mut self) {
+        for block in &mut self.blocks {
+            let missing = defaults(block.block_type)
+                .into_iter()
+                .filter(|(name, _)| {
+                    !block
+                        .statements
+                        .iter()
+                        .any(|s| matches!(s, Statement::Assignment(n, _) if n == name))
+                })
+                .map(|(name, value)| Statement::Assignment(name.into(), value))
+                .collect::<Vec<_>>();
+
+            // keep the stream ID last, where decompiling puts it
+            let at = block
+                .statements
+                .iter()
+                .position(|s| matches!(s, Statement::Assignment(n, _) if n == "stream"))
+                .unwrap_or(block.statements.len());
+
+            block.statements.splice(at..at, missing);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use super::{Block, BlockType, RValue, Statement, Text};
+use std::fmt::Write;
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// What the engine calls the object a block compiles to.
+fn object_type(block_type: BlockType) -> &'static str {
+    match block_type {
+        BlockType::DefineSettings => "Settings",
+        BlockType::DefineObject => "Object",
+        BlockType::DefineSound => "Sound",
+        BlockType::DefineEvent => "Event",
+        BlockType::DefineAnim => "Video",
+        BlockType::ParallelAction => "Presenter",
+        BlockType::DefineStill => "Bitmap",
+        BlockType::SerialAction => "World",
+    }
+}
+
+fn label(block: &Block) -> String {
+    let mut label = format!(
+        "{}\\n{} {} ({})",
+        escape(&block.name),
+        block.block_type,
+        block.id,
+        object_type(block.block_type)
+    );
+
+    for statement in &block.statements {
+        if let Statement::Assignment(name, value @ RValue::Definition(_)) = statement {
+            if name == "loopingMethod" {
+                write!(label, "\\nloopingMethod = {value}").unwrap();
+            }
+        }
+    }
+
+    label
+}
+
+impl Text {
+    /// Renders the blocks and the declarations between them as a Graphviz
+    /// graph. Weaves have a double border, and the children of serial
+    /// actions are numbered in the order they play.
+    pub fn to_dot(&self) -> String {
+        let mut dot = "digraph {\n\trankdir = LR;\n\tnode [shape = box];\n\n".to_string();
+
+        for block in &self.blocks {
+            writeln!(
+                dot,
+                "\t\"{}\" [label = \"{}\"{}];",
+                escape(&block.name),
+                label(block),
+                if block.is_weave {
+                    ", peripheries = 2"
+                } else {
+                    ""
+                }
+            )
+            .unwrap();
+        }
+
+        dot.push('\n');
+
+        for block in &self.blocks {
+            let children = block.statements.iter().filter_map(|s| match s {
+                Statement::Declaration(name) => Some(name),
+                _ => None,
+            });
+
+            for (i, child) in children.enumerate() {
+                let edge_label = match block.block_type {
+                    BlockType::SerialAction => format!(" [label = \"{}\"]", i + 1),
+                    _ => String::new(),
+                };
+
+                writeln!(
+                    dot,
+                    "\t\"{}\" -> \"{}\"{edge_label};",
+                    escape(&block.name),
+                    escape(child)
+                )
+                .unwrap();
+            }
+        }
+
+        dot + "}\n"
+    }
+}
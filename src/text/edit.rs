@@ -0,0 +1,142 @@
+use super::{Block, BlockType, RValue, Statement, Text};
+use anyhow::{anyhow, Result};
+
+impl Block {
+    /// Where new statements go: before the stream ID, which decompiling
+    /// always puts last.
+    fn insert_at(&self) -> usize {
+        self.statements
+            .iter()
+            .position(|s| matches!(s, Statement::Assignment(n, _) if n == "stream"))
+            .unwrap_or(self.statements.len())
+    }
+
+    /// The value a field is given, if it's given one.
+    pub fn get(&self, field: &str) -> Option<&RValue> {
+        self.statements.iter().find_map(|s| match s {
+            Statement::Assignment(name, value) if name == field => Some(value),
+            _ => None,
+        })
+    }
+
+    /// Gives a field a value, in place of the one it had, or after the other
+    /// fields if it didn't have one.
+    pub fn set(&mut self, field: &str, value: RValue) {
+        let existing = self.statements.iter_mut().find_map(|s| match s {
+            Statement::Assignment(name, value) if name == field => Some(value),
+            _ => None,
+        });
+
+        match existing {
+            Some(existing) => *existing = value,
+            None => self.add_statement(Statement::Assignment(field.into(), value)),
+        }
+    }
+
+    /// Takes a field out, returning the value it had. The object gets the
+    /// field's default instead.
+    pub fn remove(&mut self, field: &str) -> Option<RValue> {
+        let index = self
+            .statements
+            .iter()
+            .position(|s| matches!(s, Statement::Assignment(n, _) if n == field))?;
+
+        if self.statement_spans.len() == self.statements.len() {
+            self.statement_spans.remove(index);
+        }
+
+        let Statement::Assignment(_, value) = self.statements.remove(index) else {
+            return None;
+        };
+        Some(value)
+    }
+
+    /// Adds a statement after the others (but before the stream ID). Since
+    /// children play in the order they're declared, a declaration adds a
+    /// child that plays last.
+    pub fn add_statement(&mut self, statement: Statement) {
+        let at = self.insert_at();
+
+        // a new statement has no span of its own, so it's given the block's
+        match &self.span {
+            Some(span) if self.statement_spans.len() == self.statements.len() => {
+                self.statement_spans.insert(at, span.clone())
+            }
+            _ => self.statement_spans.clear(),
+        }
+
+        self.statements.insert(at, statement);
+    }
+
+    /// Names of the blocks this one declares, in order.
+    pub fn children(&self) -> impl Iterator<Item = &str> {
+        self.statements.iter().filter_map(|s| match s {
+            Statement::Declaration(name) => Some(name.as_str()),
+            Statement::Assignment(..) => None,
+        })
+    }
+}
+
+impl Text {
+    pub fn settings_mut(&mut self) -> &mut Block {
+        &mut self.settings
+    }
+
+    /// The blocks, to change in place. Use [`Text::rename`] to rename one,
+    /// so that declarations of it are renamed too.
+    pub fn blocks_mut(&mut self) -> impl Iterator<Item = &mut Block> {
+        self.blocks.iter_mut()
+    }
+
+    pub fn find_block(&self, name: &str) -> Option<&Block> {
+        self.blocks.iter().find(|b| b.name == name)
+    }
+
+    pub fn find_block_mut(&mut self, name: &str) -> Option<&mut Block> {
+        self.blocks.iter_mut().find(|b| b.name == name)
+    }
+
+    /// Adds a block after the others. It isn't given an ID until
+    /// [`Text::assign_ids`] is called, and nothing plays it unless it's a
+    /// weave or another block declares it.
+    pub fn add_block(&mut self, block: Block) -> Result<()> {
+        if block.block_type == BlockType::DefineSettings {
+            return Err(anyhow!("A script only has one settings block"));
+        }
+
+        if self.find_block(&block.name).is_some() || self.external(&block.name).is_some() {
+            return Err(anyhow!("Block \"{}\" already exists", block.name));
+        }
+
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    /// Takes a block out, along with every declaration of it, so that the
+    /// rest still compiles. The blocks it declared are left in, even if
+    /// nothing else declares them.
+    pub fn remove_block(&mut self, name: &str) -> Result<Block> {
+        let index = self
+            .blocks
+            .iter()
+            .position(|b| b.name == name)
+            .ok_or_else(|| anyhow!("No block named \"{name}\""))?;
+
+        let block = self.blocks.remove(index);
+
+        for parent in &mut self.blocks {
+            while let Some(index) = parent
+                .statements
+                .iter()
+                .position(|s| matches!(s, Statement::Declaration(child) if child == name))
+            {
+                if parent.statement_spans.len() == parent.statements.len() {
+                    parent.statement_spans.remove(index);
+                }
+                parent.statements.remove(index);
+            }
+        }
+
+        Ok(block)
+    }
+}
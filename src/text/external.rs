@@ -0,0 +1,156 @@
+use super::{quote, RValue, Statement, Text};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// An object in another Omni file, which actions can declare as a child
+/// without it being compiled into this one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct External {
+    /// Omni file the object is in, as the game refers to it
+    pub file: String,
+    pub name: String,
+}
+
+impl Display for External {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "external {} {};", quote(&self.file), self.name)
+    }
+}
+
+/// Start of an entry in an action's extra data that records a child in
+/// another file, followed by `FILE:NAME:INDEX`.
+const PREFIX: &str = "External:";
+
+/// Separates the entries from each other and from the rest of the extra
+/// data.
+pub(super) const SEPARATOR: char = ';';
+
+/// Appends references to `externals`, each with its position among the
+/// action's children, to an action's extra data.
+pub(crate) fn encode_externals(
+    extra: Option<&str>,
+    externals: &[(usize, &External)],
+) -> Option<String> {
+    let entries = externals
+        .iter()
+        .map(|(index, e)| format!("{PREFIX}{}:{}:{index}", e.file, e.name));
+
+    let all = extra.into_iter().map(str::to_string).chain(entries);
+    let joined = all.collect::<Vec<_>>().join(&SEPARATOR.to_string());
+
+    (!joined.is_empty()).then_some(joined)
+}
+
+fn decode_entry(entry: &str) -> Option<(usize, External)> {
+    // the file name comes first, since it's the one that could have a `:`
+    let mut parts = entry.strip_prefix(PREFIX)?.rsplitn(3, ':');
+    let index = parts.next()?.parse().ok()?;
+    let name = parts.next()?;
+    let file = parts.next()?;
+
+    Some((
+        index,
+        External {
+            file: file.into(),
+            name: name.into(),
+        },
+    ))
+}
+
+/// Splits an action's extra data back into what was there to begin with
+/// and the references added by [`encode_externals`].
+fn decode_externals(extra: &str) -> (Option<String>, Vec<(usize, External)>) {
+    let mut rest = vec![];
+    let mut externals = vec![];
+
+    for entry in extra.split(SEPARATOR) {
+        match decode_entry(entry) {
+            Some(external) => externals.push(external),
+            None => rest.push(entry),
+        }
+    }
+
+    let rest = rest.join(&SEPARATOR.to_string());
+
+    (
+        (!rest.is_empty() || externals.is_empty()).then_some(rest),
+        externals,
+    )
+}
+
+impl Text {
+    pub fn externals(&self) -> &[External] {
+        &self.externals
+    }
+
+    pub fn external(&self, name: &str) -> Option<&External> {
+        self.externals.iter().find(|e| e.name == name)
+    }
+
+    /// Turns the references to other files that compiling left in actions'
+    /// extra data back into declarations.
+    pub(super) fn restore_externals(&mut self) {
+        for block in &mut self.blocks {
+            let Some(at) = block.statements.iter().position(
+                |s| matches!(s, Statement::Assignment(n, RValue::String(_)) if n == "extra"),
+            ) else {
+                continue;
+            };
+
+            let Statement::Assignment(_, RValue::String(extra)) = &block.statements[at] else {
+                continue;
+            };
+
+            let (rest, mut found) = decode_externals(extra);
+
+            if found.is_empty() {
+                continue;
+            }
+
+            match rest {
+                Some(rest) => {
+                    block.statements[at] =
+                        Statement::Assignment("extra".into(), RValue::String(rest))
+                }
+                None => {
+                    block.statements.remove(at);
+                }
+            }
+
+            // in order, so that each index counts the ones put back before it
+            found.sort_by_key(|(index, _)| *index);
+
+            for (index, external) in found {
+                let declarations = block
+                    .statements
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, s)| matches!(s, Statement::Declaration(_)))
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>();
+
+                let pos = match (declarations.get(index), declarations.last()) {
+                    (Some(&i), _) => i,
+                    (None, Some(&last)) => last + 1,
+                    // actions list their children before their extra data
+                    (None, None) => block
+                        .statements
+                        .iter()
+                        .position(|s| match s {
+                            Statement::Assignment(n, _) => n == "extra" || n == "stream",
+                            _ => false,
+                        })
+                        .unwrap_or(block.statements.len()),
+                };
+
+                block
+                    .statements
+                    .insert(pos, Statement::Declaration(external.name.clone()));
+
+                if !self.externals.contains(&external) {
+                    self.externals.push(external);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use super::{external::SEPARATOR, RValue, Statement, Text};
+
+/// Start of the name of a field that sets one key of the extra data, such
+/// as `extra.Action`.
+const PREFIX: &str = "extra.";
+
+/// The key set by a field of the form `extra.KEY`, if it's one.
+pub(crate) fn extra_key(field: &str) -> Option<&str> {
+    field.strip_prefix(PREFIX)
+}
+
+/// Puts the keys given with `extra.KEY` fields back into the form they're
+/// stored in, after any extra data given as a whole.
+pub(crate) fn join_extra(extra: Option<String>, entries: &[(String, String)]) -> Option<String> {
+    if entries.is_empty() {
+        return extra;
+    }
+
+    let entries = entries.iter().map(|(key, value)| format!("{key}:{value}"));
+
+    Some(
+        extra
+            .into_iter()
+            .chain(entries)
+            .collect::<Vec<_>>()
+            .join(&SEPARATOR.to_string()),
+    )
+}
+
+fn is_key(key: &str) -> bool {
+    let mut chars = key.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits extra data made up only of `KEY:VALUE` entries into its keys and
+/// values, as long as joining them back up gives exactly the same string.
+fn parse_extra(extra: &str) -> Option<Vec<(&str, &str)>> {
+    extra
+        .split(SEPARATOR)
+        .map(|entry| entry.split_once(':').filter(|(key, _)| is_key(key)))
+        .collect()
+}
+
+impl Text {
+    /// Turns extra data that's a list of keys and values into an `extra.KEY`
+    /// field for each, which compile back to the same bytes.
+    pub(super) fn structure_extra(&mut self) {
+        for block in &mut self.blocks {
+            let Some(at) = block.statements.iter().position(
+                |s| matches!(s, Statement::Assignment(n, RValue::String(_)) if n == "extra"),
+            ) else {
+                continue;
+            };
+
+            let Statement::Assignment(_, RValue::String(extra)) = &block.statements[at] else {
+                continue;
+            };
+
+            let Some(entries) = parse_extra(extra) else {
+                continue;
+            };
+
+            let fields = entries
+                .into_iter()
+                .map(|(key, value)| {
+                    Statement::Assignment(format!("{PREFIX}{key}"), RValue::String(value.into()))
+                })
+                .collect::<Vec<_>>();
+
+            block.statements.splice(at..=at, fields);
+        }
+    }
+}
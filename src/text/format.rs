@@ -0,0 +1,352 @@
+use super::{Block, BlockType, Statement, Text};
+use crate::{omni::safe_file_name, types::ObjectFilter};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+/// What statements are indented with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indent {
+    Tab,
+    Spaces(usize),
+}
+
+/// Parses `tab` or a number of spaces.
+impl FromStr for Indent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tab" | "tabs" => Ok(Self::Tab),
+            _ => s
+                .parse()
+                .map(Self::Spaces)
+                .map_err(|_| format!("Expected \"tab\" or a number of spaces, found \"{s}\"")),
+        }
+    }
+}
+
+/// Where a block's declarations go relative to its assignments. Statements
+/// of the same kind always keep their order, since the order of an action's
+/// children is the order they play in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatementOrder {
+    Keep,
+    DeclarationsFirst,
+    DeclarationsLast,
+}
+
+impl FromStr for StatementOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Self::Keep),
+            "declarations-first" => Ok(Self::DeclarationsFirst),
+            "declarations-last" => Ok(Self::DeclarationsLast),
+            _ => Err(format!(
+                "Expected \"keep\", \"declarations-first\" or \"declarations-last\", found \"{s}\""
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    pub indent: Indent,
+    pub order: StatementOrder,
+    /// End lines with CRLF instead of LF
+    pub crlf: bool,
+    /// Put a comment before each object with its ID and where it was in
+    /// the file it was decompiled from
+    pub annotate: bool,
+    /// Put a comment before each decompiled object whose flags don't all
+    /// have a name with the flags as they were stored
+    pub annotate_unknown: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            indent: Indent::Tab,
+            order: StatementOrder::Keep,
+            crlf: false,
+            annotate: false,
+            annotate_unknown: false,
+        }
+    }
+}
+
+impl Block {
+    /// Writes the block out, without a blank line after it.
+    pub fn format(&self, options: &FormatOptions) -> String {
+        let newline = if options.crlf { "\r\n" } else { "\n" };
+        let indent = match options.indent {
+            Indent::Tab => "\t".to_string(),
+            Indent::Spaces(n) => " ".repeat(n),
+        };
+
+        let mut statements = self.statements.iter().collect::<Vec<_>>();
+        let is_declaration = |s: &&Statement| matches!(s, Statement::Declaration(_));
+
+        // stable, so each kind keeps its own order
+        match options.order {
+            StatementOrder::Keep => {}
+            StatementOrder::DeclarationsFirst => statements.sort_by_key(|s| !is_declaration(s)),
+            StatementOrder::DeclarationsLast => statements.sort_by_key(is_declaration),
+        }
+
+        let mut rv = format!(
+            "{} {}{} {{{newline}",
+            self.block_type,
+            self.name,
+            if self.is_weave { " Weave" } else { "" }
+        );
+
+        for statement in statements {
+            rv += &format!("{indent}{statement};{newline}");
+        }
+
+        rv + "}" + newline
+    }
+}
+
+impl Text {
+    /// Writes a block out, after a comment about where it came from if the
+    /// options ask for one.
+    fn format_block(&self, block: &Block, options: &FormatOptions) -> String {
+        let newline = if options.crlf { "\r\n" } else { "\n" };
+
+        let mut annotation = if !options.annotate || block.block_type == BlockType::DefineSettings {
+            String::new()
+        } else {
+            match self.offsets.get(&block.id) {
+                Some(&offset) if offset != 0 => {
+                    format!("// ID {}, offset {offset:#X}{newline}", block.id)
+                }
+                _ => format!("// ID {}{newline}", block.id),
+            }
+        };
+
+        // the bits without names are left in `flags`
+        if options.annotate_unknown && block.get("flags").is_some() {
+            if let Some(flags) = self.flags.get(&block.id) {
+                annotation += &format!("// flags {flags:#010X}{newline}");
+            }
+        }
+
+        annotation + &block.format(options)
+    }
+
+    /// Writes the script out laid out according to `options`, with a blank
+    /// line between blocks.
+    pub fn format(&self, options: &FormatOptions) -> String {
+        let newline = if options.crlf { "\r\n" } else { "\n" };
+
+        std::iter::once(self.settings.format(options))
+            .chain(self.format_externals(options))
+            .chain(self.blocks.iter().map(|b| self.format_block(b, options)))
+            .collect::<Vec<_>>()
+            .join(newline)
+    }
+
+    /// Writes out the `external` lines, if there are any.
+    fn format_externals(&self, options: &FormatOptions) -> Option<String> {
+        let newline = if options.crlf { "\r\n" } else { "\n" };
+
+        (!self.externals.is_empty()).then(|| {
+            self.externals
+                .iter()
+                .map(|e| format!("{e}{newline}"))
+                .collect()
+        })
+    }
+}
+
+impl Text {
+    /// Writes out only the blocks picked by `filter` and the blocks they
+    /// declare, without the settings, to be pasted into another script.
+    /// Each block that isn't declared by another one in the snippet comes
+    /// after a comment naming the blocks it was declared in.
+    pub fn format_snippet(&self, filter: &ObjectFilter, options: &FormatOptions) -> String {
+        let newline = if options.crlf { "\r\n" } else { "\n" };
+        let keep = self.picked_with_children(filter);
+
+        // a block declared more than once goes with the first declaration,
+        // as when splitting
+        let mut parents = HashMap::new();
+        for block in &self.blocks {
+            for statement in &block.statements {
+                if let Statement::Declaration(child) = statement {
+                    parents.entry(child.as_str()).or_insert(block);
+                }
+            }
+        }
+
+        self.blocks
+            .iter()
+            .filter(|b| keep.contains(&b.name))
+            .map(|block| {
+                let mut chain = vec![];
+                let mut seen = HashSet::from([block.name.as_str()]);
+                let mut name = block.name.as_str();
+
+                while let Some(parent) = parents.get(name) {
+                    if keep.contains(&parent.name) || !seen.insert(&parent.name) {
+                        break;
+                    }
+
+                    chain.push(format!("{} {}", parent.block_type, parent.name));
+                    name = &parent.name;
+                }
+
+                let comment = match chain.is_empty() {
+                    true => String::new(),
+                    false => {
+                        chain.reverse();
+                        format!("// in {}{newline}", chain.join(" > "))
+                    }
+                };
+
+                comment + &self.format_block(block, options)
+            })
+            .collect::<Vec<_>>()
+            .join(newline)
+    }
+}
+
+/// A script split up by [`Text::format_split`].
+#[derive(Debug, Clone)]
+pub struct SplitText {
+    /// The settings, an `#include` for each file, and any blocks that don't
+    /// belong to a weave
+    pub master: String,
+    /// File names (the weaves' names, made safe to write and told apart
+    /// ignoring case, with `.ss` on the end) and contents
+    pub files: Vec<(String, String)>,
+}
+
+impl Text {
+    /// Writes each top-level weave into a file of its own, along with the
+    /// blocks it declares, the way Weaver projects were usually organised.
+    /// The master file's `#include`s have `include_dir` put in front of the
+    /// file names.
+    pub fn format_split(&self, options: &FormatOptions, include_dir: &str) -> SplitText {
+        let newline = if options.crlf { "\r\n" } else { "\n" };
+
+        let declared = self
+            .blocks
+            .iter()
+            .flat_map(|b| &b.statements)
+            .filter_map(|s| match s {
+                Statement::Declaration(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        // a block declared by more than one weave goes with the first, so
+        // that it's only defined once
+        let mut placed = HashSet::new();
+        let mut used = HashSet::new();
+        let mut files = vec![];
+
+        for weave in &self.blocks {
+            if !weave.is_weave || declared.contains(weave.name.as_str()) {
+                continue;
+            }
+
+            let mut members = HashSet::new();
+            let mut pending = vec![weave.name.as_str()];
+
+            while let Some(name) = pending.pop() {
+                if placed.contains(name) || !members.insert(name) {
+                    continue;
+                }
+
+                if let Some(block) = self.blocks.iter().find(|b| b.name == name) {
+                    pending.extend(block.statements.iter().filter_map(|s| match s {
+                        Statement::Declaration(child) => Some(child.as_str()),
+                        _ => None,
+                    }));
+                }
+            }
+
+            // the blocks keep the order they had in the whole script
+            let contents = self
+                .blocks
+                .iter()
+                .filter(|b| members.contains(b.name.as_str()))
+                .map(|b| self.format_block(b, options))
+                .collect::<Vec<_>>()
+                .join(newline);
+
+            placed.extend(members);
+
+            // weaves whose names only differ in case would write over each
+            // other on some systems
+            let safe_name = safe_file_name(&weave.name);
+            let file_name = (1..)
+                .map(|i| match i {
+                    1 => format!("{safe_name}.ss"),
+                    _ => format!("{safe_name}_{i}.ss"),
+                })
+                .find(|f| used.insert(f.to_lowercase()))
+                .unwrap_or_default();
+            files.push((file_name, contents));
+        }
+
+        let includes = files
+            .iter()
+            .map(|(name, _)| format!("#include \"{include_dir}{name}\"{newline}"))
+            .collect::<String>();
+
+        let master = std::iter::once(self.settings.format(options))
+            .chain(self.format_externals(options))
+            .chain(std::iter::once(includes))
+            .chain(
+                self.blocks
+                    .iter()
+                    .filter(|b| !placed.contains(b.name.as_str()))
+                    .map(|b| self.format_block(b, options)),
+            )
+            .collect::<Vec<_>>()
+            .join(newline);
+
+        SplitText { master, files }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_files_are_safe_and_distinct() {
+        let mut text = Text::parse_str(
+            "defineSettings S { bufferSizeKB = 64; buffersNum = 2; }
+            defineSound A { fileName = \"a.wav\"; }
+            defineSound B { fileName = \"b.wav\"; }
+            defineSound C { fileName = \"c.wav\"; }
+            serialAction Ab Weave { A; }
+            serialAction aB Weave { B; }
+            serialAction Up Weave { C; }",
+        )
+        .unwrap();
+
+        // names read from an Omni file can be anything
+        if let Some(up) = text.blocks.iter_mut().find(|b| b.name == "Up") {
+            up.name = "..\\..\\up".into();
+        }
+
+        let split = text.format_split(&FormatOptions::default(), "weaves/");
+        let names = split
+            .files
+            .iter()
+            .map(|(n, _)| n.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, ["Ab.ss", "aB_2.ss", ".._.._up.ss"]);
+        assert!(split.master.contains("#include \"weaves/aB_2.ss\""));
+    }
+}
@@ -0,0 +1,83 @@
+use super::BlockType::{self, *};
+
+/// A presenter class the engine knows how to create, as named by an
+/// object's `handlerClass`.
+#[derive(Debug, Clone, Copy)]
+pub struct HandlerClass {
+    pub name: &'static str,
+    /// Types of block it can present
+    pub block_types: &'static [BlockType],
+    /// Extensions of the files it can present, or empty for any
+    pub file_types: &'static [&'static str],
+}
+
+const fn class(
+    name: &'static str,
+    block_types: &'static [BlockType],
+    file_types: &'static [&'static str],
+) -> HandlerClass {
+    HandlerClass {
+        name,
+        block_types,
+        file_types,
+    }
+}
+
+const ACTIONS: &[BlockType] = &[SerialAction, ParallelAction];
+
+/// Compiling converts FLAC and Ogg sounds to WAV, unless told not to.
+const SOUND_FILES: &[&str] = &["wav", "flac", "ogg", "oga"];
+
+/// Presenter classes the engine registers. Giving an object one that can't
+/// present it makes the engine crash when the object starts.
+pub const HANDLER_CLASSES: &[HandlerClass] = &[
+    class("MxWavePresenter", &[DefineSound], SOUND_FILES),
+    class("Lego3DWavePresenter", &[DefineSound], SOUND_FILES),
+    class("LegoLoadCacheSoundPresenter", &[DefineSound], SOUND_FILES),
+    class("MxFlcPresenter", &[DefineAnim], &["flc"]),
+    class("MxLoopingFlcPresenter", &[DefineAnim], &["flc"]),
+    class("LegoFlcTexturePresenter", &[DefineAnim], &["flc"]),
+    class("MxSmkPresenter", &[DefineAnim], &["smk"]),
+    class("MxLoopingSmkPresenter", &[DefineAnim], &["smk"]),
+    class("MxStillPresenter", &[DefineStill], &["stl"]),
+    class("LegoPalettePresenter", &[DefineStill], &["stl"]),
+    class("MxEventPresenter", &[DefineEvent], &[]),
+    class("LegoModelPresenter", &[DefineObject], &[]),
+    class("LegoPartPresenter", &[DefineObject], &[]),
+    class("LegoTexturePresenter", &[DefineObject], &[]),
+    class("LegoPathPresenter", &[DefineObject], &[]),
+    class("LegoPhonemePresenter", &[DefineObject], &[]),
+    class("LegoAnimPresenter", &[DefineObject], &[]),
+    class("LegoLoopingAnimPresenter", &[DefineObject], &[]),
+    class("LegoLocomotionAnimPresenter", &[DefineObject], &[]),
+    class("LegoHideAnimPresenter", &[DefineObject], &[]),
+    class("LegoCarBuildAnimPresenter", &[DefineObject], &[]),
+    class("MxCompositePresenter", ACTIONS, &[]),
+    class("MxCompositeMediaPresenter", ACTIONS, &[]),
+    class("MxControlPresenter", ACTIONS, &[]),
+    class("LegoWorldPresenter", ACTIONS, &[]),
+    class("LegoEntityPresenter", ACTIONS, &[]),
+    class("LegoActorPresenter", ACTIONS, &[]),
+    class("LegoActionControlPresenter", ACTIONS, &[]),
+    class("LegoAnimMMPresenter", ACTIONS, &[]),
+];
+
+impl HandlerClass {
+    pub fn find(name: &str) -> Option<&'static Self> {
+        HANDLER_CLASSES.iter().find(|c| c.name == name)
+    }
+
+    /// Whether it can present a block of this type, using a file with this
+    /// stored name.
+    pub fn accepts(&self, block_type: BlockType, file_name: Option<&str>) -> bool {
+        let extension = file_name
+            .and_then(|f| f.rsplit_once('.'))
+            .map(|(_, ext)| ext.to_ascii_lowercase());
+
+        self.block_types.contains(&block_type)
+            && match extension {
+                Some(ext) if !self.file_types.is_empty() => self.file_types.contains(&ext.as_str()),
+                _ => true,
+            }
+    }
+}
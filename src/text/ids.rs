@@ -0,0 +1,133 @@
+use super::{Block, RValue, Statement, Text};
+use anyhow::{anyhow, Result};
+use std::collections::{HashMap, HashSet};
+
+fn stream(block: &Block) -> Result<Option<u32>> {
+    let mut stream = None;
+
+    for statement in &block.statements {
+        if let Statement::Assignment(name, RValue::Integer(id)) = statement {
+            if name == "stream" {
+                let id = (*id)
+                    .try_into()
+                    .map_err(|_| anyhow!("Invalid stream ID {id} in block \"{}\"", block.name))?;
+                stream = Some(id);
+            }
+        }
+    }
+
+    Ok(stream)
+}
+
+struct Allocator<'a> {
+    blocks: &'a [Block],
+    by_name: HashMap<&'a str, usize>,
+    externals: HashSet<&'a str>,
+    reserved: HashSet<u32>,
+    ids: Vec<Option<u32>>,
+    next_id: u32,
+}
+
+impl Allocator<'_> {
+    fn next_free(&mut self) -> u32 {
+        while self.reserved.contains(&self.next_id) {
+            self.next_id += 1;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Numbers a block before its children, the way the engine's own
+    /// files are laid out.
+    fn visit(&mut self, index: usize) -> Result<()> {
+        if self.ids[index].is_some() {
+            return Ok(());
+        }
+
+        self.ids[index] = Some(match stream(&self.blocks[index])? {
+            Some(id) => id,
+            None => self.next_free(),
+        });
+
+        for statement in &self.blocks[index].statements {
+            if let Statement::Declaration(name) = statement {
+                // numbered in their own file
+                if self.externals.contains(name.as_str()) {
+                    continue;
+                }
+
+                let Some(&child) = self.by_name.get(name.as_str()) else {
+                    return Err(anyhow!(
+                        "Block \"{name}\" is declared by \"{}\" but never defined",
+                        self.blocks[index].name
+                    ));
+                };
+                self.visit(child)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Text {
+    /// Gives every block an object ID. Blocks with a `stream` keep it, and
+    /// the rest are numbered in order from each top-level weave down
+    /// through its children, skipping IDs that are already taken.
+    pub fn assign_ids(&mut self) -> Result<()> {
+        let mut reserved = HashSet::new();
+
+        for block in &self.blocks {
+            if let Some(id) = stream(block)? {
+                if !reserved.insert(id) {
+                    return Err(anyhow!("Stream ID {id} is used more than once"));
+                }
+            }
+        }
+
+        let declared = self
+            .blocks
+            .iter()
+            .flat_map(|b| &b.statements)
+            .filter_map(|s| match s {
+                Statement::Declaration(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        let mut allocator = Allocator {
+            blocks: &self.blocks,
+            by_name: self
+                .blocks
+                .iter()
+                .enumerate()
+                .map(|(i, b)| (b.name.as_str(), i))
+                .collect(),
+            externals: self.externals.iter().map(|e| e.name.as_str()).collect(),
+            reserved,
+            ids: vec![None; self.blocks.len()],
+            next_id: 0,
+        };
+
+        let roots = (0..self.blocks.len()).filter(|&i| {
+            self.blocks[i].is_weave && !declared.contains(self.blocks[i].name.as_str())
+        });
+
+        // anything not reachable from a weave is numbered afterwards
+        for index in roots.chain(0..self.blocks.len()) {
+            allocator.visit(index)?;
+        }
+
+        let ids = allocator.ids;
+
+        for (block, id) in self.blocks.iter_mut().zip(ids) {
+            if let Some(id) = id {
+                block.id = id;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,352 @@
+use super::{
+    extra_key,
+    schema::{self, Defaults},
+    Block, BlockType, HandlerClass, RValue, Span, Statement, Text,
+};
+use crate::{
+    diagnostics::{Diagnostic, Location, Severity},
+    types::Vec3,
+};
+use std::{collections::HashSet, fmt::Display};
+
+/// A problem found by [`Text::lint`].
+#[derive(Debug, Clone)]
+pub struct Lint {
+    /// Short name of the check that found it, such as `undefined-block`
+    pub rule: &'static str,
+    pub severity: Severity,
+    /// Name of the block it's in
+    pub block: String,
+    pub message: String,
+    /// Where in the script it is, if the script was parsed
+    pub span: Option<Span>,
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(span) = &self.span {
+            write!(f, "{span}: ")?;
+        }
+
+        write!(
+            f,
+            "{}[{}] {}: {}",
+            self.severity, self.rule, self.block, self.message
+        )
+    }
+}
+
+impl From<&Lint> for Diagnostic {
+    fn from(lint: &Lint) -> Self {
+        Diagnostic::new(
+            lint.severity,
+            lint.span.clone().map(Location::Script),
+            format_args!("[{}] {}: {}", lint.rule, lint.block, lint.message),
+        )
+    }
+}
+
+/// Loudest volume the engine plays sounds at, which is also the default.
+const MAX_VOLUME: i32 = 0x4F;
+
+/// Most degrees a 3D sound's up can be from right angles to its direction.
+const MAX_3D_SKEW: f64 = 10.0;
+
+/// Whether a block is a sound that's played positioned in 3D.
+fn is_3d_sound(block: &Block) -> bool {
+    block.block_type == BlockType::DefineSound
+        && matches!(block.get("handlerClass"), Some(RValue::String(class)) if class == "Lego3DWavePresenter")
+}
+
+fn lint_handler_class(
+    block: &Block,
+    class: &str,
+    lint: &mut impl FnMut(&'static str, Severity, Option<&str>, String),
+) {
+    // left for the engine to pick
+    if class.is_empty() {
+        return;
+    }
+
+    let Some(known) = HandlerClass::find(class) else {
+        lint(
+            "handler-class",
+            Severity::Warning,
+            Some("handlerClass"),
+            format!("\"{class}\" isn't a handler class the engine knows"),
+        );
+        return;
+    };
+
+    let file_name = block.statements.iter().find_map(|s| match s {
+        Statement::Assignment(n, RValue::String(f)) if n == "fileName" => Some(f.as_str()),
+        _ => None,
+    });
+
+    if !known.accepts(block.block_type, file_name) {
+        let what = match file_name {
+            Some(f) => format!("{} block using \"{f}\"", block.block_type),
+            None => format!("{} block", block.block_type),
+        };
+
+        lint(
+            "handler-class",
+            Severity::Warning,
+            Some("handlerClass"),
+            format!("{class} can't present a {what}, which crashes the engine"),
+        );
+    }
+}
+
+fn lint_fields(block: &Block, lints: &mut Vec<Lint>) {
+    // lints about a field point at where it's given a value
+    let mut lint = |rule, severity, field: Option<&str>, message| {
+        lints.push(Lint {
+            rule,
+            severity,
+            block: block.name.clone(),
+            message,
+            span: match field {
+                Some(field) => block.field_span(field),
+                None => block.span.as_ref(),
+            }
+            .cloned(),
+        })
+    };
+
+    let assigned = block
+        .statements
+        .iter()
+        .filter_map(|s| match s {
+            Statement::Assignment(name, value) => Some((name.as_str(), value)),
+            Statement::Declaration(_) => None,
+        })
+        .collect::<Vec<_>>();
+
+    for field in schema::FIELDS
+        .iter()
+        .filter(|f| f.required_by.contains(&block.block_type))
+        .map(|f| f.name)
+    {
+        if !assigned.iter().any(|&(name, _)| name == field) {
+            lint(
+                "missing-field",
+                Severity::Error,
+                None,
+                format!("Missing required field \"{field}\""),
+            );
+        }
+    }
+
+    for &(name, value) in &assigned {
+        let is_settings = block.block_type == BlockType::DefineSettings;
+
+        // extra data is kept as text, so its keys can be given numbers too
+        let (used, accepted) = match (schema::field(name), extra_key(name)) {
+            (Some(field), _) => (
+                field.used_by.contains(&block.block_type),
+                field.value.accepts(value),
+            ),
+            (None, Some(_)) => (
+                !is_settings,
+                matches!(value, RValue::String(_) | RValue::Integer(_)),
+            ),
+            (None, None) => {
+                lint(
+                    "unknown-field",
+                    Severity::Error,
+                    Some(name),
+                    format!("Unknown field \"{name}\""),
+                );
+                continue;
+            }
+        };
+
+        if !used {
+            lint(
+                "unused-field",
+                Severity::Warning,
+                Some(name),
+                format!("\"{name}\" does nothing in a {} block", block.block_type),
+            );
+        } else if !accepted {
+            lint(
+                "wrong-type",
+                Severity::Error,
+                Some(name),
+                format!("\"{name}\" can't be {value}"),
+            );
+        }
+
+        if let ("handlerClass", RValue::String(class)) = (name, value) {
+            lint_handler_class(block, class, &mut lint);
+        }
+
+        let level = match (name, value) {
+            ("volume", RValue::Integer(level)) => Some(*level),
+            ("volume", RValue::Volume(volume)) => Some(volume.level()),
+            _ => None,
+        };
+        if let Some(level) = level.filter(|l| !(0..=MAX_VOLUME).contains(l)) {
+            let volume = match value {
+                RValue::Volume(_) => format!("{value} (level {level})"),
+                _ => value.to_string(),
+            };
+
+            lint(
+                "volume-range",
+                Severity::Warning,
+                Some("volume"),
+                format!("Volume {volume} is outside 0 to {MAX_VOLUME}"),
+            );
+        }
+    }
+
+    let vector = |field| {
+        assigned.iter().find_map(|&(name, value)| match value {
+            RValue::Vec3(v) if name == field => Some(*v),
+            _ => None,
+        })
+    };
+
+    let (direction, up) = (vector("direction"), vector("up"));
+    let direction_given = direction.is_some();
+
+    if direction.is_some() || up.is_some() {
+        let (direction, up) = (direction.unwrap_or(Vec3::Z), up.unwrap_or(Vec3::Y));
+
+        if Vec3::orthonormal(direction, up).is_none() {
+            lint(
+                "orientation",
+                Severity::Warning,
+                Some(if direction_given { "direction" } else { "up" }),
+                format!("Direction {direction} and up {up} don't give an orientation"),
+            );
+        }
+    }
+
+    // a 3D sound that's placed or pointed nonsensically is silent in game
+    if is_3d_sound(block) {
+        if let Some(location) =
+            vector("location").filter(|l| !l.to_array().iter().all(|c| c.is_finite()))
+        {
+            lint(
+                "sound-3d",
+                Severity::Warning,
+                Some("location"),
+                format!("Location {location} isn't somewhere the sound can be"),
+            );
+        }
+
+        let defaults = Defaults::of(block.block_type);
+        let (direction, up) = (
+            direction.unwrap_or(defaults.direction),
+            up.unwrap_or(defaults.up),
+        );
+
+        // ones that don't give an orientation at all are linted above
+        if let (Some(_), Some(d), Some(u)) = (
+            Vec3::orthonormal(direction, up),
+            direction.normalized(),
+            up.normalized(),
+        ) {
+            let angle = d.dot(u).clamp(-1.0, 1.0).acos().to_degrees();
+
+            if (angle - 90.0).abs() > MAX_3D_SKEW {
+                lint(
+                    "sound-3d",
+                    Severity::Warning,
+                    Some(if direction_given { "direction" } else { "up" }),
+                    format!(
+                        "Up {up} is {angle:.0}° from direction {direction}, rather than at right angles"
+                    ),
+                );
+            }
+        }
+    }
+}
+
+impl Text {
+    /// Checks the script for mistakes that would stop it compiling, or that
+    /// probably aren't what was meant, most severe first.
+    pub fn lint(&self) -> Vec<Lint> {
+        let mut lints = vec![];
+        let mut names = HashSet::new();
+
+        for block in std::iter::once(&self.settings).chain(&self.blocks) {
+            if block.block_type != BlockType::DefineSettings && !names.insert(block.name.as_str()) {
+                lints.push(Lint {
+                    rule: "duplicate-block",
+                    severity: Severity::Error,
+                    block: block.name.clone(),
+                    message: format!("Block \"{}\" is defined more than once", block.name),
+                    span: block.span.clone(),
+                });
+            }
+
+            lint_fields(block, &mut lints);
+        }
+
+        for block in &self.blocks {
+            for statement in &block.statements {
+                let Statement::Declaration(child) = statement else {
+                    continue;
+                };
+
+                if !names.contains(child.as_str()) && self.external(child).is_none() {
+                    lints.push(Lint {
+                        rule: "undefined-block",
+                        severity: Severity::Error,
+                        block: block.name.clone(),
+                        message: format!("Block \"{child}\" is declared but never defined"),
+                        span: block.declaration_span(child).cloned(),
+                    });
+                }
+            }
+        }
+
+        for block in self.unused() {
+            lints.push(Lint {
+                rule: "unused-block",
+                severity: Severity::Warning,
+                block: block.name.clone(),
+                message: format!(
+                    "Block \"{}\" isn't a weave or declared by any other block",
+                    block.name
+                ),
+                span: block.span.clone(),
+            });
+        }
+
+        // stable, so lints of the same severity stay in script order
+        lints.sort_by_key(|l| std::cmp::Reverse(l.severity));
+
+        lints
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::text::Text;
+
+    fn volume_warnings(volume: &str) -> usize {
+        let script = format!(
+            "defineSettings S {{ bufferSizeKB = 64; buffersNum = 2; }}
+            defineSound A {{ fileName = \"a.wav\"; volume = {volume}; }}"
+        );
+
+        let text = Text::parse_str(&script).unwrap();
+        let lints = text.lint();
+        lints.iter().filter(|l| l.rule == "volume-range").count()
+    }
+
+    #[test]
+    fn volumes_louder_than_the_default_are_out_of_range() {
+        assert_eq!(volume_warnings("79"), 0);
+        assert_eq!(volume_warnings("100%"), 0);
+        assert_eq!(volume_warnings("80"), 1);
+        assert_eq!(volume_warnings("100"), 1);
+        assert_eq!(volume_warnings("3dB"), 1);
+        assert_eq!(volume_warnings("-1"), 1);
+    }
+}
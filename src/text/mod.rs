@@ -1,16 +1,50 @@
-use crate::{omni::Omni, types::Vec3};
+use crate::{
+    diagnostics::{Diagnostics, Location},
+    omni::{Omni, OmniParseError},
+    types::{ObjectFilter, Vec3},
+};
 use anyhow::{anyhow, Result};
 use chumsky::Parser;
+use preprocessor::PreprocessWarning;
+use serde::{Deserialize, Serialize};
+use span::Locator;
 use std::{
-    cmp::Ordering,
-    collections::{BTreeMap, HashMap},
+    collections::{HashMap, HashSet},
     fmt::Display,
+    path::{Path, PathBuf},
 };
-
+use thiserror::Error;
+
+mod defaults;
+mod dot;
+mod edit;
+mod external;
+mod extra;
+mod format;
+mod handlers;
+mod ids;
+mod lint;
+mod order;
 mod parser;
-mod preprocessor;
-
-#[derive(Debug, Clone)]
+pub mod preprocessor;
+mod query;
+pub mod schema;
+mod span;
+mod volume;
+
+pub use crate::diagnostics::Severity;
+pub(crate) use external::encode_externals;
+pub use external::External;
+pub(crate) use extra::{extra_key, join_extra};
+pub use format::{FormatOptions, Indent, SplitText, StatementOrder};
+pub use handlers::{HandlerClass, HANDLER_CLASSES};
+pub use lint::Lint;
+pub use query::{Query, QueryMatch};
+pub use span::Span;
+pub use volume::{Volume, VolumeUnit};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
 pub enum LoopingMethod {
     Cache,
     None,
@@ -31,7 +65,7 @@ impl Display for LoopingMethod {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Duration(pub i32);
 
 impl Display for Duration {
@@ -43,7 +77,8 @@ impl Display for Duration {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
 pub enum PaletteManagement {
     None,
 }
@@ -60,7 +95,8 @@ impl Display for PaletteManagement {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
 pub enum Transparency {
     Yes,
     Fast,
@@ -73,13 +109,14 @@ impl Display for Transparency {
             "{}",
             match self {
                 Self::Yes => "YES",
-                Self::Fast => "FAST", // unknown purpose/encoding
+                Self::Fast => "FAST",
             }
         )
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub enum Definition {
     LoopingMethod(LoopingMethod),
     Duration(Duration),
@@ -98,10 +135,53 @@ impl Display for Definition {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Quotes a string for a script, escaping whatever would be read back
+/// differently. Backslashes are only doubled where they'd start an escape,
+/// so that stored paths stay readable.
+pub(crate) fn quote(s: &str) -> String {
+    let mut rv = String::from('"');
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => rv += "\\\"",
+            '\\' if matches!(chars.peek(), None | Some('"' | '\\')) => rv += "\\\\",
+            c => rv.push(c),
+        }
+    }
+
+    rv.push('"');
+    rv
+}
+
+/// An argument to a [`Function`], which keeps the type it was written with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Argument {
+    String(String),
+    Integer(i32),
+    Float(f64),
+    Vec3(Vec3),
+    Ident(String),
+}
+
+impl Display for Argument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::String(s) => write!(f, "{}", quote(s)),
+            Self::Integer(i) => write!(f, "{i}"),
+            // always with a decimal point, so that it's read back as a float
+            Self::Float(x) => write!(f, "{x:?}"),
+            Self::Vec3(v) => write!(f, "{v}"),
+            Self::Ident(i) => write!(f, "{i}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Function {
     pub name: String,
-    pub args: Vec<String>,
+    pub args: Vec<Argument>,
 }
 
 impl Display for Function {
@@ -112,17 +192,22 @@ impl Display for Function {
             self.name,
             self.args
                 .iter()
-                .map(|a| a.to_string())
+                .map(Argument::to_string)
                 .collect::<Vec<_>>()
                 .join(", ")
         )
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub enum RValue {
     String(String),
     Integer(i32),
+    /// Flag bits, written in hex
+    Bits(u32),
+    /// A volume as a percentage or in decibels
+    Volume(Volume),
     Vec3(Vec3),
     Definition(Definition),
     Function(Function),
@@ -131,8 +216,10 @@ pub enum RValue {
 impl Display for RValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::String(s) => write!(f, "\"{s}\""),
+            Self::String(s) => write!(f, "{}", quote(s)),
             Self::Integer(i) => write!(f, "{i}"),
+            Self::Bits(b) => write!(f, "{b:#X}"),
+            Self::Volume(v) => write!(f, "{v}"),
             Self::Vec3(v) => write!(f, "{v}"),
             Self::Definition(d) => write!(f, "{d}"),
             Self::Function(fun) => write!(f, "{fun}"),
@@ -140,7 +227,8 @@ impl Display for RValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub enum Statement {
     Assignment(String, RValue),
     Declaration(String),
@@ -155,7 +243,8 @@ impl Display for Statement {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub enum BlockType {
     DefineSettings,
     DefineObject,
@@ -186,13 +275,20 @@ impl Display for BlockType {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Block {
     pub id: u32,
     pub block_type: BlockType,
     pub name: String,
     pub is_weave: bool,
     pub statements: Vec<Statement>,
+    /// Where the block was in the script, if it was parsed from one
+    #[serde(skip)]
+    pub span: Option<Span>,
+    /// Where each statement was in the script, if it was parsed from one
+    #[serde(skip)]
+    pub statement_spans: Vec<Span>,
 }
 
 impl Display for Block {
@@ -211,264 +307,367 @@ impl Display for Block {
     }
 }
 
-#[derive(Debug)]
-pub struct Tree<T> {
-    elem: T,
-    left: Option<Box<Tree<T>>>,
-    right: Option<Box<Tree<T>>>,
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Text {
+    settings: Block,
+    blocks: Vec<Block>,
+    /// Objects in other files that blocks refer to
+    externals: Vec<External>,
+    /// Where each object was in the file this was decompiled from, by ID
+    #[serde(skip)]
+    offsets: HashMap<u32, u64>,
+    /// Each object's flags in the file this was decompiled from, by ID
+    #[serde(skip)]
+    flags: HashMap<u32, u32>,
+    /// Files the script was read from, starting with the one given to
+    /// [`Text::parse_file`] and followed by everything it included
+    #[serde(skip)]
+    sources: Vec<PathBuf>,
+    /// Problems the preprocessor found reading the script
+    #[serde(skip)]
+    warnings: Vec<PreprocessWarning>,
+    /// Whether the script had comments or directives
+    #[serde(skip)]
+    stripped: bool,
 }
 
-impl<T: Clone> Tree<T> {
-    pub fn new(elem: T) -> Self {
-        Self {
-            elem,
-            left: None,
-            right: None,
-        }
+impl Display for Text {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.collect())
     }
+}
 
-    pub fn add(elem: T) -> Box<Self> {
-        Box::new(Self::new(elem))
-    }
+/// A converted block, along with any blocks that have to come before and
+/// after it.
+pub type ToBlockResult =
+    std::result::Result<(Option<Block>, Vec<Block>, Vec<Block>), OmniParseError>;
 
-    pub fn insert_before(&mut self, elem: T) -> &mut Self {
-        let insert = self.left.is_some();
-        let left = self.left.get_or_insert(Self::add(elem.clone()));
-        if insert {
-            left.insert_before(elem)
-        } else {
-            left
-        }
-    }
+pub trait ToBlock {
+    fn to_block(&self, top_level: bool) -> ToBlockResult;
+}
 
-    pub fn insert_after(&mut self, elem: T) -> &mut Self {
-        let insert = self.right.is_some();
-        let right = self.right.get_or_insert(Self::add(elem.clone()));
-        if insert {
-            right.insert_after(elem)
-        } else {
-            right
-        }
-    }
+/// A script that doesn't follow the grammar, with what the parser made of
+/// it.
+#[derive(Debug, Error)]
+#[error("Parse error(s): {0}")]
+pub struct SyntaxError(String);
 
-    pub fn insert_just_before(&mut self, elem: T) -> &mut Self {
-        let insert = self.left.is_some();
-        let left = self.left.get_or_insert(Self::add(elem.clone()));
-        if insert {
-            left.insert_after(elem)
-        } else {
-            left
-        }
+impl Text {
+    pub fn parse(file: &str) -> Result<Self> {
+        Self::parse_str(file)
     }
 
-    pub fn insert_just_after(&mut self, elem: T) -> &mut Self {
-        let insert = self.right.is_some();
-        let right = self.right.get_or_insert(Self::add(elem.clone()));
-        if insert {
-            right.insert_before(elem)
-        } else {
-            right
-        }
-    }
+    /// Parses a script without printing anything along the way. Malformed
+    /// input comes back as an error rather than a panic.
+    pub fn parse_str(file: &str) -> Result<Self> {
+        let mut pp = preprocessor::Preprocessor::new();
 
-    pub fn traverse<F: FnMut(&T)>(&self, f: &mut F) {
-        if let Some(l) = &self.left {
-            l.traverse(f);
-        }
-        f(&self.elem);
-        if let Some(r) = &self.right {
-            r.traverse(f)
-        }
+        let file = pp.preprocess(file)?;
+
+        Self::parse_preprocessed(&file, &pp)
     }
-}
 
-impl<T: Clone + Display> Tree<T> {
-    pub fn collect(&self) -> impl Display {
-        let mut rv = String::new();
+    /// Parses a script from a file, following its `#include`s relative to
+    /// the file they're in.
+    pub fn parse_file(path: impl AsRef<Path>) -> Result<Self> {
+        let mut pp = preprocessor::Preprocessor::new();
 
-        self.traverse(&mut |e: &T| rv += &e.to_string());
+        let file = pp.preprocess_file(path.as_ref())?;
 
-        rv
+        let mut text = Self::parse_preprocessed(&file, &pp)?;
+        text.sources = pp.sources().to_vec();
+
+        Ok(text)
     }
-}
 
-#[derive(Debug)]
-pub struct Text {
-    settings: Block,
-    blocks: BTreeMap<SortingId, Block>,
-}
+    /// Parses the blocks in a script that doesn't have to have a settings
+    /// block, such as one written with [`Text::format_snippet`]. A settings
+    /// block is left out if it has one.
+    pub fn parse_snippet_file(path: impl AsRef<Path>) -> Result<Vec<Block>> {
+        let mut pp = preprocessor::Preprocessor::new();
 
-impl Display for Text {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.collect())
+        let file = pp.preprocess_file(path.as_ref())?;
+
+        // the parser insists on settings, so it's given some at the end,
+        // where they don't move any line numbers
+        let file = format!("{file}\ndefineSettings Snippet {{}}\n");
+        let text = Self::parse_preprocessed(&file, &pp)?;
+
+        Ok(text
+            .blocks
+            .into_iter()
+            .filter(|b| b.block_type != BlockType::DefineSettings)
+            .collect())
     }
-}
 
-pub trait ToBlock {
-    fn to_block(&self, top_level: bool) -> (Option<Block>, Vec<Block>, Vec<Block>);
-}
+    /// Parses the output of `pp`, pointing spans and errors at where they
+    /// were in the files it read.
+    fn parse_preprocessed(file: &str, pp: &preprocessor::Preprocessor) -> Result<Self> {
+        let (text, errs) = Self::parser().parse(file).into_output_errors();
+        let locator = Locator::new(file, pp);
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct SortingId {
-    block_type: BlockType,
-    id: u32,
-    offset: u32,
-    index: usize,
-    parent_id: u32,
-    parent_offset: u32,
-    parent_index: usize,
-}
+        let Some(mut text) = text else {
+            let errs = errs
+                .iter()
+                .map(|e| format!("{}: {e}", locator.locate(e.span().start)))
+                .collect::<Vec<_>>();
 
-impl PartialOrd for SortingId {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
-    }
-}
+            return Err(SyntaxError(errs.join("; ")).into());
+        };
 
-impl Ord for SortingId {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        return self.id.cmp(&other.id);
+        locator.locate_text(&mut text);
+        text.warnings = pp.warnings().to_vec();
+        text.stripped = pp.stripped();
 
-        if self.parent_id == other.id {
-            return Ordering::Less;
-        }
+        Ok(text)
+    }
 
-        if self.id == other.parent_id {
-            return Ordering::Greater;
-        }
+    /// Turns the spans [`Text::parser`] leaves, which are byte offsets into
+    /// `preprocessed`, into where they were in the files `preprocessor`
+    /// read. The `parse` functions do this themselves.
+    pub fn locate(&mut self, preprocessed: &str, preprocessor: &preprocessor::Preprocessor) {
+        Locator::new(preprocessed, preprocessor).locate_text(self);
+    }
 
-        if self.offset != 0 && other.offset != 0 {
-            return self.index.cmp(&other.index);
-        }
+    /// Decompiles `omni`, noting any objects with flags that have no name
+    /// in `diagnostics`.
+    pub fn from_omni(omni: &Omni, diagnostics: &Diagnostics) -> Result<Self> {
+        let (Some(settings), _, _) = omni.header.to_block(true)? else {
+            return Err(anyhow!("Couldn't convert the header to a settings block"));
+        };
 
-        if self.offset == 0 && other.offset != 0 {
-            return self.parent_id.cmp(&other.id);
-        }
+        let mut blocks = vec![];
 
-        if self.offset != 0 && other.offset == 0 {
-            return self.id.cmp(&other.parent_id);
+        for chunk in &omni.streams.subchunks {
+            let (block, blocks_before, blocks_after) = chunk.to_block(true)?;
+            blocks.extend(blocks_before);
+            blocks.extend(block);
+            blocks.extend(blocks_after);
         }
 
-        if self.parent_id != other.parent_id {
-            //self.parent_id.cmp(&other.parent_id)
-            return self.parent_offset.cmp(&other.parent_offset);
+        let blocks = order::order_blocks(blocks, &omni.offsets.objects);
+
+        let mut text = Self {
+            settings,
+            blocks,
+            externals: vec![],
+            offsets: omni.object_offsets(),
+            flags: omni.object_flags(),
+            sources: vec![],
+            warnings: vec![],
+            stripped: false,
+        };
+        text.restore_externals();
+        text.structure_extra();
+
+        for block in &text.blocks {
+            if let Some(RValue::Bits(flags)) = block.get("flags") {
+                diagnostics.note(
+                    Some(Location::Object(block.id, block.name.clone())),
+                    format_args!("Unknown flags {flags:#X} kept in `flags`"),
+                );
+            }
         }
 
-        self.index.cmp(&other.index)
+        Ok(text)
     }
-}
 
-impl SortingId {
-    pub fn from_id_index(
-        block_type: BlockType,
-        id: u32,
-        offsets: &[u32],
-        index: usize,
-        parent_id: u32,
-        parent_index: usize,
-    ) -> Self {
+    pub fn new(settings: Block, blocks: Vec<Block>) -> Self {
         Self {
-            block_type,
-            id,
-            offset: *offsets.get(id as usize).unwrap_or(&0),
-            index,
-            parent_id,
-            parent_offset: *offsets.get(parent_id as usize).unwrap_or(&0),
-            parent_index,
+            settings,
+            blocks,
+            externals: vec![],
+            offsets: HashMap::new(),
+            flags: HashMap::new(),
+            sources: vec![],
+            warnings: vec![],
+            stripped: false,
         }
     }
-}
 
-impl Text {
-    pub fn parse(file: &str) -> Result<Self> {
-        let mut pp = preprocessor::Preprocessor::new();
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
 
-        let file = pp.preprocess(file)?;
+    /// Whether the script had comments or preprocessor directives, which
+    /// aren't kept when it's written back out.
+    pub fn stripped(&self) -> bool {
+        self.stripped
+    }
 
-        println!("{file}");
+    /// Problems the preprocessor found that didn't stop the script parsing,
+    /// such as macros being redefined.
+    pub fn preprocess_warnings(&self) -> &[PreprocessWarning] {
+        &self.warnings
+    }
 
-        let (text, errs) = Self::parser().parse(&file).into_output_errors();
+    pub fn settings(&self) -> &Block {
+        &self.settings
+    }
 
-        text.ok_or(anyhow!("Parse error(s): {errs:?}"))
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.iter()
     }
 
-    pub fn from_omni(omni: &Omni) -> Result<Self> {
-        let (Some(settings), _, _) = omni.header.to_block(true) else {
-            unreachable!()
-        };
+    /// Renames a block, along with every declaration that refers to it.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        if self.blocks.iter().any(|b| b.name == to) {
+            return Err(anyhow!("Block \"{to}\" already exists"));
+        }
 
-        //let mut blocks = Tree::new(settings);
-        let mut blocks = BTreeMap::new();
+        let Some(block) = self.blocks.iter_mut().find(|b| b.name == from) else {
+            return Err(anyhow!("No block named \"{from}\""));
+        };
+        block.name = to.into();
 
-        for (index, chunk) in omni.streams.subchunks.iter().enumerate() {
-            let (block, blocks_before, blocks_after) = chunk.to_block(true);
-            println!("{:?}", block);
-            if let Some(b) = block {
-                /*let cur = blocks.insert_after(b);
-                for block in blocks_before {
-                    cur.insert_just_before(block);
+        for statement in self.blocks.iter_mut().flat_map(|b| &mut b.statements) {
+            if let Statement::Declaration(name) = statement {
+                if name == from {
+                    *name = to.into();
                 }
-                for block in blocks_after {
-                    cur.insert_just_after(block);
-                }*/
-
-                let sorting_id = SortingId::from_id_index(
-                    b.block_type,
-                    b.id,
-                    &omni.offsets.objects,
-                    index,
-                    b.id,
-                    index,
-                );
+            }
+        }
 
-                let parent_id = b.id;
-                println!("{:?}", sorting_id);
-                println!("inserting: {:?}", blocks.insert(sorting_id, b));
-                for (index_before, block_before) in blocks_before.into_iter().enumerate() {
-                    println!("\tsub: {:?}", block_before);
-                    let sorting_id_before = SortingId::from_id_index(
-                        block_before.block_type,
-                        block_before.id,
-                        &omni.offsets.objects,
-                        index_before,
-                        parent_id,
-                        index,
-                    );
-                    println!("\tsub: {:?}", sorting_id_before);
-                    println!(
-                        "\tinserting sub: {:?}",
-                        blocks.insert(sorting_id_before, block_before)
-                    );
-                }
-                for (index_after, block_after) in blocks_after.into_iter().enumerate() {
-                    let sorting_id_after = SortingId::from_id_index(
-                        block_after.block_type,
-                        block_after.id,
-                        &omni.offsets.objects,
-                        index_after,
-                        parent_id,
-                        index,
-                    );
-                    println!(
-                        "\tinserting sub: {:?}",
-                        blocks.insert(sorting_id_after, block_after)
-                    );
+        Ok(())
+    }
+
+    /// Blocks that aren't weaves and aren't declared by any other block, so
+    /// nothing plays them.
+    pub fn unused(&self) -> Vec<&Block> {
+        let declared = self
+            .blocks
+            .iter()
+            .flat_map(|b| &b.statements)
+            .filter_map(|s| match s {
+                Statement::Declaration(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        self.blocks
+            .iter()
+            .filter(|b| !b.is_weave && !declared.contains(b.name.as_str()))
+            .collect()
+    }
+
+    /// Puts each of `blocks` in place of the block with the same name,
+    /// which keeps its ID and whether it's a weave. Returns the IDs of the
+    /// blocks replaced.
+    pub fn replace_blocks(&mut self, blocks: Vec<Block>) -> Result<Vec<u32>> {
+        let mut ids = vec![];
+
+        for block in blocks {
+            let Some(old) = self.blocks.iter_mut().find(|b| b.name == block.name) else {
+                return Err(anyhow!("No block named \"{}\"", block.name));
+            };
+
+            old.block_type = block.block_type;
+            old.statements = block.statements;
+            ids.push(old.id);
+        }
+
+        Ok(ids)
+    }
+
+    /// Drops every block that isn't picked by `filter` or declared by a
+    /// block that is, so that the rest still compiles.
+    pub fn select(&mut self, filter: &ObjectFilter) {
+        let keep = self.picked_with_children(filter);
+        self.blocks.retain(|b| keep.contains(&b.name));
+    }
+
+    /// Names of the blocks picked by `filter` and every block they declare,
+    /// however deep.
+    fn picked_with_children(&self, filter: &ObjectFilter) -> HashSet<String> {
+        let mut keep = HashSet::new();
+        let mut pending = self
+            .blocks
+            .iter()
+            .filter(|b| filter.matches(b.id, &b.name))
+            .map(|b| b.name.clone())
+            .collect::<Vec<_>>();
+
+        while let Some(name) = pending.pop() {
+            if !keep.insert(name.clone()) {
+                continue;
+            }
+
+            let Some(block) = self.blocks.iter().find(|b| b.name == name) else {
+                continue;
+            };
+
+            for statement in &block.statements {
+                if let Statement::Declaration(child) = statement {
+                    pending.push(child.clone());
                 }
             }
         }
 
-        println!("{:#?}", blocks);
-
-        Ok(Self { settings, blocks })
+        keep
     }
 
     pub fn collect(&self) -> impl Display {
         let mut rv = self.settings.to_string();
 
-        for block in self.blocks.values() {
+        for external in &self.externals {
+            rv += &format!("{external}\n");
+        }
+
+        if !self.externals.is_empty() {
+            rv += "\n";
+        }
+
+        for block in &self.blocks {
             rv += &block.to_string();
         }
 
         rv
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_scripts_are_errors() {
+        for script in [
+            "",
+            "defineSettings",
+            "defineSettings S { bufferSizeKB = ; }",
+            "defineSettings S { } defineSound A {",
+            "defineSettings S { } /* never ends",
+            "#include",
+            "#bogus x",
+            "\"",
+        ] {
+            assert!(Text::parse(script).is_err(), "{script:?} parsed");
+        }
+    }
+
+    #[test]
+    fn parse_matches_parse_str() {
+        let script = "defineSettings S { bufferSizeKB = 64; buffersNum = 2; }
+            defineSound A { fileName = \"\\\\a.wav\"; volume = 79; }";
+
+        let parsed = Text::parse(script).unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            Text::parse_str(script).unwrap().to_string()
+        );
+        assert_eq!(parsed.blocks().count(), 1);
+    }
+
+    #[test]
+    fn windows_paths_keep_their_backslashes() {
+        let script = r#"defineSettings S { bufferSizeKB = 64; buffersNum = 2; }
+            defineSound A { fileName = "C:\new\test.wav"; extra = "\s16.wav"; }
+            defineSound B { fileName = "say \"hi\" \\"; }"#;
+
+        let text = Text::parse(script).unwrap().to_string();
+        assert!(text.contains(r#""C:\new\test.wav""#), "{text}");
+        assert!(text.contains(r#""\s16.wav""#), "{text}");
+        assert!(text.contains(r#""say \"hi\" \\""#), "{text}");
+        assert_eq!(Text::parse(&text).unwrap().to_string(), text);
+    }
+}
@@ -0,0 +1,85 @@
+use super::{Block, Statement};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Visit {
+    New,
+    InProgress,
+    Done,
+}
+
+struct Orderer<'a> {
+    blocks: &'a [Block],
+    by_name: HashMap<&'a str, usize>,
+    state: Vec<Visit>,
+    order: Vec<usize>,
+}
+
+impl Orderer<'_> {
+    fn visit(&mut self, index: usize) {
+        if self.state[index] != Visit::New {
+            // either already placed, or a cycle back to a block being placed
+            return;
+        }
+
+        self.state[index] = Visit::InProgress;
+
+        for statement in &self.blocks[index].statements {
+            if let Statement::Declaration(name) = statement {
+                if let Some(&child) = self.by_name.get(name.as_str()) {
+                    self.visit(child);
+                }
+            }
+        }
+
+        self.state[index] = Visit::Done;
+        self.order.push(index);
+    }
+}
+
+/// Orders blocks so that every block comes after the blocks it declares.
+/// Top-level blocks are taken in the order they're stored in the file
+/// (according to the MxOf table), and anything not reachable from them goes
+/// last, in ID order.
+pub fn order_blocks(blocks: Vec<Block>, offsets: &[u32]) -> Vec<Block> {
+    let offset = |id: u32| {
+        offsets
+            .get(id as usize)
+            .copied()
+            .filter(|&o| o != 0)
+            .unwrap_or(u32::MAX)
+    };
+
+    let mut roots = (0..blocks.len())
+        .filter(|&i| blocks[i].is_weave)
+        .collect::<Vec<_>>();
+    roots.sort_by_key(|&i| (offset(blocks[i].id), blocks[i].id));
+
+    let mut rest = (0..blocks.len())
+        .filter(|&i| !blocks[i].is_weave)
+        .collect::<Vec<_>>();
+    rest.sort_by_key(|&i| blocks[i].id);
+
+    let mut orderer = Orderer {
+        blocks: &blocks,
+        by_name: blocks
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (b.name.as_str(), i))
+            .collect(),
+        state: vec![Visit::New; blocks.len()],
+        order: vec![],
+    };
+
+    for index in roots.into_iter().chain(rest) {
+        orderer.visit(index);
+    }
+
+    let order = orderer.order;
+    let mut blocks = blocks.into_iter().map(Some).collect::<Vec<_>>();
+
+    order
+        .into_iter()
+        .filter_map(|index| blocks[index].take())
+        .collect()
+}
@@ -1,12 +1,10 @@
-use std::{cmp::Ordering, collections::BTreeMap};
-
 use chumsky::{extra::ParserExtra, input::SliceInput, prelude::*};
 
 use crate::types::Vec3;
 
 use super::{
-    Block, BlockType, Definition, Duration, Function, LoopingMethod, PaletteManagement, RValue,
-    SortingId, Statement, Text, Transparency,
+    Argument, Block, BlockType, Definition, Duration, External, Function, LoopingMethod,
+    PaletteManagement, RValue, Span, Statement, Text, Transparency, Volume,
 };
 
 #[must_use]
@@ -16,9 +14,13 @@ pub fn ident<
     C: text::Char,
     E: ParserExtra<'a, I>,
 >() -> impl Parser<'a, I, &'a C::Str, E> + Copy {
+    let continues = |c: C| c.is_ident_continue() || c.to_char().is_alphanumeric();
+
     any()
         .try_map(|c: C, span| {
-            if c.is_ident_start() || /* need underscores at the start to be allowed */ c.to_char() == '_' {
+            // need underscores at the start to be allowed, and letters
+            // from localised names
+            if c.is_ident_start() || c.to_char() == '_' || c.to_char().is_alphabetic() {
                 Ok(c)
             } else {
                 Err(chumsky::error::Error::expected_found(
@@ -28,7 +30,7 @@ pub fn ident<
                 ))
             }
         })
-        .then(select! { c if (c as C).is_ident_continue() => () }.repeated())
+        .then(select! { c if continues(c) => () }.repeated())
         .to_slice()
 }
 
@@ -37,7 +39,20 @@ fn integer<'a>() -> impl Parser<'a, &'a str, i32, extra::Err<Rich<'a, char>>> {
         .or_not()
         .then(text::int(10))
         .to_slice()
-        .map(|num: &str| num.parse().unwrap())
+        .try_map(|num: &str, span| {
+            num.parse()
+                .map_err(|e| Rich::custom(span, format!("Invalid integer: {e}")))
+        })
+}
+
+/// A hex number, like `0x1F00`.
+fn bits<'a>() -> impl Parser<'a, &'a str, u32, extra::Err<Rich<'a, char>>> {
+    just("0x")
+        .ignore_then(text::digits(16).to_slice())
+        .try_map(|s: &str, span| {
+            u32::from_str_radix(s, 16)
+                .map_err(|e| Rich::custom(span, format!("Invalid number: {e}")))
+        })
 }
 
 fn float<'a>() -> impl Parser<'a, &'a str, f64, extra::Err<Rich<'a, char>>> {
@@ -50,31 +65,55 @@ fn float<'a>() -> impl Parser<'a, &'a str, f64, extra::Err<Rich<'a, char>>> {
         .then(one_of("+-").or_not())
         .then(digits);
 
-    integer()
+    // the whole part can be bigger than an integer
+    just('-')
+        .or_not()
+        .then(text::int(10))
         .then(frac.or_not())
         .then(exp.or_not())
         .to_slice()
-        .map(|s: &str| s.parse().unwrap())
+        .try_map(|s: &str, span| {
+            s.parse()
+                .map_err(|e| Rich::custom(span, format!("Invalid number: {e}")))
+        })
         .boxed()
 }
 
 fn string<'a>() -> impl Parser<'a, &'a str, String, extra::Err<Rich<'a, char>>> {
-    none_of("\"")
+    // any other backslash is kept as it is, since stored paths are full of
+    // them, and things like `\new` and `\s16.wav` have to stay as written
+    let escape = just('\\').ignore_then(one_of("\"\\"));
+
+    escape
+        .or(none_of("\""))
         .repeated()
-        .to_slice()
+        .collect::<String>()
         .delimited_by(just('"'), just('"'))
-        .map(str::to_string)
+}
+
+/// A component of a [`Vec3`]. Components that aren't finite are written as
+/// their bits.
+fn component<'a>() -> impl Parser<'a, &'a str, f64, extra::Err<Rich<'a, char>>> {
+    let bits = just("0x")
+        .ignore_then(text::digits(16).exactly(16).to_slice())
+        .try_map(|s: &str, span| {
+            u64::from_str_radix(s, 16)
+                .map(f64::from_bits)
+                .map_err(|e| Rich::custom(span, format!("Invalid number: {e}")))
+        });
+
+    bits.or(float())
 }
 
 impl Vec3 {
     fn parser<'a>() -> impl Parser<'a, &'a str, Self, extra::Err<Rich<'a, char>>> {
         let separator = just(',').padded();
 
-        float()
+        component()
             .then_ignore(separator)
-            .then(float())
+            .then(component())
             .then_ignore(separator)
-            .then(float())
+            .then(component())
             .delimited_by(just('(').padded(), just(')'))
             .map(|((x, y), z)| Vec3::new(x, y, z))
     }
@@ -108,6 +147,15 @@ impl Transparency {
     }
 }
 
+impl Volume {
+    fn parser<'a>() -> impl Parser<'a, &'a str, Self, extra::Err<Rich<'a, char>>> {
+        choice((
+            float().then_ignore(just('%')).map(Self::Percent),
+            float().then_ignore(just("dB")).map(Self::Decibels),
+        ))
+    }
+}
+
 impl Definition {
     fn parser<'a>() -> impl Parser<'a, &'a str, Self, extra::Err<Rich<'a, char>>> {
         choice((
@@ -119,32 +167,40 @@ impl Definition {
     }
 }
 
+impl Argument {
+    fn parser<'a>() -> impl Parser<'a, &'a str, Self, extra::Err<Rich<'a, char>>> {
+        // integers are floats without a fraction or exponent
+        let number = float().to_slice().try_map(|s: &str, span| {
+            if s.contains(['.', 'e', 'E']) {
+                s.parse()
+                    .map(Self::Float)
+                    .map_err(|e| Rich::custom(span, format!("Invalid number: {e}")))
+            } else {
+                s.parse()
+                    .map(Self::Integer)
+                    .map_err(|e| Rich::custom(span, format!("Invalid integer: {e}")))
+            }
+        });
+
+        choice((
+            string().map(Self::String),
+            Vec3::parser().map(Self::Vec3),
+            number,
+            ident().map(|i: &str| Self::Ident(i.to_string())),
+        ))
+    }
+}
+
 impl Function {
     fn parser<'a>() -> impl Parser<'a, &'a str, Self, extra::Err<Rich<'a, char>>> {
         ident()
             .padded()
             .then(
-                string()
+                Argument::parser()
                     .padded()
-                    .or_not()
-                    .then(
-                        just(',')
-                            .padded()
-                            .ignored()
-                            .then(string().padded())
-                            .map(|(_, v)| v)
-                            .repeated()
-                            .collect::<Vec<_>>(),
-                    )
-                    .delimited_by(just('('), just(')'))
-                    .map(|(first, rest)| {
-                        let mut args = match first {
-                            Some(f) => vec![f],
-                            None => vec![],
-                        };
-                        args.extend(rest);
-                        args
-                    }),
+                    .separated_by(just(','))
+                    .collect::<Vec<_>>()
+                    .delimited_by(just('('), just(')')),
             )
             .map(|(name, args)| Function {
                 name: name.to_string(),
@@ -157,6 +213,9 @@ impl RValue {
     fn parser<'a>() -> impl Parser<'a, &'a str, Self, extra::Err<Rich<'a, char>>> {
         choice((
             string().map(Self::String),
+            // before integers, which the number would be read as otherwise
+            Volume::parser().map(Self::Volume),
+            bits().map(Self::Bits),
             integer().map(Self::Integer),
             Vec3::parser().map(Self::Vec3),
             Definition::parser().map(Self::Definition),
@@ -166,7 +225,10 @@ impl RValue {
 }
 
 fn assignment<'a>() -> impl Parser<'a, &'a str, Statement, extra::Err<Rich<'a, char>>> {
-    ident()
+    // `extra.KEY` sets one key of the extra data
+    let field = ident().then(just('.').then(ident()).or_not()).to_slice();
+
+    field
         .padded()
         .then_ignore(just('=').padded())
         .then(RValue::parser().padded())
@@ -205,53 +267,98 @@ impl BlockType {
 
 impl Block {
     fn parser<'a>() -> impl Parser<'a, &'a str, Self, extra::Err<Rich<'a, char>>> {
+        // spans are where things start, after any whitespace
+        let statement = text::whitespace()
+            .ignore_then(Statement::parser().map_with(|s, e| (s, Span::offset(e.span().start))))
+            .padded();
+
         BlockType::parser()
+            .map_with(|t, e| (t, Span::offset(e.span().start)))
             .padded()
             .then(ident().padded())
             .then(just("Weave").padded().or_not())
             .then(
-                Statement::parser()
-                    .padded()
+                statement
                     .repeated()
                     .collect::<Vec<_>>()
                     .delimited_by(just('{').padded(), just('}')),
             )
-            .map(|(((t, n), w), s)| Block {
-                id: 0,
-                block_type: t,
-                name: n.to_string(),
-                is_weave: w.is_some(),
-                statements: s,
+            .map(|((((t, span), n), w), s)| {
+                let (statements, statement_spans) = s.into_iter().unzip();
+
+                Block {
+                    id: 0,
+                    block_type: t,
+                    name: n.to_string(),
+                    is_weave: w.is_some(),
+                    statements,
+                    span: Some(span),
+                    statement_spans,
+                }
+            })
+    }
+}
+
+impl External {
+    fn parser<'a>() -> impl Parser<'a, &'a str, Self, extra::Err<Rich<'a, char>>> {
+        just("external")
+            .padded()
+            .ignore_then(string().padded())
+            .then(ident().padded())
+            .then_ignore(just(';'))
+            .map(|(file, name)| External {
+                file,
+                name: name.to_string(),
             })
     }
 }
 
+enum Item {
+    Block(Block),
+    External(External),
+}
+
 impl Text {
     pub fn parser<'a>() -> impl Parser<'a, &'a str, Self, extra::Err<Rich<'a, char>>> {
-        Block::parser()
-            .padded()
-            .repeated()
-            .collect::<Vec<_>>()
-            .map(|mut blocks| {
-                blocks.sort_by(|a, _| {
-                    if matches!(a.block_type, BlockType::DefineSettings) {
-                        Ordering::Greater
-                    } else {
-                        Ordering::Less
-                    }
-                });
-                let settings = blocks.pop().unwrap();
-                Self {
-                    settings,
-                    blocks: BTreeMap::from_iter(blocks.into_iter().enumerate().map(
-                        |(index, elem)| {
-                            (
-                                SortingId::from_id_index(elem.block_type, 0, &[], index, 0, 0),
-                                elem,
-                            )
-                        },
-                    )),
+        choice((
+            External::parser().map(Item::External),
+            Block::parser().map(Item::Block),
+        ))
+        .padded()
+        .repeated()
+        .collect::<Vec<_>>()
+        .try_map(|items, span| {
+            let mut blocks = vec![];
+            let mut externals = vec![];
+
+            for item in items {
+                match item {
+                    Item::Block(b) => blocks.push(b),
+                    Item::External(e) => externals.push(e),
                 }
-            })
+            }
+
+            let Some(index) = blocks
+                .iter()
+                .position(|b| matches!(b.block_type, BlockType::DefineSettings))
+            else {
+                return Err(Rich::custom(span, "No defineSettings block"));
+            };
+
+            if let Some(e) = externals
+                .iter()
+                .find(|e| blocks.iter().any(|b| b.name == e.name))
+            {
+                return Err(Rich::custom(
+                    span,
+                    format!("\"{}\" is both external and defined here", e.name),
+                ));
+            }
+
+            let settings = blocks.remove(index);
+            let mut text = Self::new(settings, blocks);
+            text.externals = externals;
+            Ok(text)
+        })
     }
 }
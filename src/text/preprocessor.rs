@@ -1,7 +1,16 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
 
 use thiserror::Error;
 
+/// Deepest that included files can include others, so that a file that
+/// includes itself doesn't go on forever.
+const MAX_INCLUDE_DEPTH: usize = 32;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PreprocessorState {
     Expecting,
@@ -30,28 +39,319 @@ pub enum PreprocessError {
 
     #[error("Too many parameters for {0:?} directive at ({1}:{2})")]
     TooManyParameters(Directive, usize, usize),
+
+    #[error("Expected a line number for #line at ({1}:{2}), found \"{0}\"")]
+    BadLineNumber(String, usize, usize),
+
+    #[error("Can't include \"{0}\" from a script that wasn't read from a file")]
+    NoIncludeDir(String),
+
+    #[error("Couldn't include \"{0}\": {1}")]
+    Include(PathBuf, std::io::Error),
+
+    #[error("Includes nested more than {MAX_INCLUDE_DEPTH} deep at \"{0}\"")]
+    IncludeDepth(PathBuf),
+
+    #[error("In \"{0}\": {1}")]
+    InFile(PathBuf, Box<PreprocessError>),
+}
+
+/// Where a directive was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Site {
+    /// File it was in, if the script was read from one
+    pub file: Option<PathBuf>,
+    /// Line and column, both from 0
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Site {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        write!(f, "{}:{}", self.line + 1, self.column + 1)
+    }
+}
+
+/// Something in a script that preprocesses, but probably isn't what was
+/// meant.
+#[derive(Debug, Clone)]
+pub enum PreprocessWarning {
+    /// A macro was defined again with a different value, which replaces the
+    /// old one from there on
+    Redefined {
+        name: String,
+        at: Site,
+        previous: Site,
+    },
+    /// `#undef` of a macro that isn't defined
+    NotDefined { name: String, at: Site },
+}
+
+impl PreprocessWarning {
+    pub fn site(&self) -> &Site {
+        match self {
+            Self::Redefined { at, .. } | Self::NotDefined { at, .. } => at,
+        }
+    }
+
+    /// What's wrong, without where.
+    pub fn message(&self) -> String {
+        match self {
+            Self::Redefined { name, previous, .. } => {
+                format!("\"{name}\" redefined (it was defined at {previous})")
+            }
+            Self::NotDefined { name, .. } => format!("#undef of \"{name}\", which isn't defined"),
+        }
+    }
+}
+
+impl Display for PreprocessWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.site(), self.message())
+    }
+}
+
+/// A macro's value and where it was defined.
+#[derive(Debug, Clone)]
+struct Macro {
+    value: String,
+    site: Site,
+}
+
+impl PreprocessError {
+    /// Line and column (both from 0) of the problem in the file it's in.
+    pub fn position(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::UnexpectedToken(_, line, column)
+            | Self::UnknownDirective(_, line, column)
+            | Self::NoParams(_, line, column)
+            | Self::TooManyParameters(_, line, column)
+            | Self::BadLineNumber(_, line, column) => Some((*line, *column)),
+            Self::InFile(_, e) => e.position(),
+            Self::UnexpectedEndState(_)
+            | Self::NoIncludeDir(_)
+            | Self::Include(..)
+            | Self::IncludeDepth(_) => None,
+        }
+    }
 }
 
 pub struct Preprocessor {
-    definitions: HashMap<String, String>,
+    definitions: HashMap<String, Macro>,
+    /// Files currently being preprocessed, innermost last; includes are
+    /// found relative to the last one
+    files: Vec<PathBuf>,
+    /// Every file read so far
+    sources: Vec<PathBuf>,
+    /// Every file lines of the output came from: those read, and those
+    /// named by `#line`
+    origins: Vec<PathBuf>,
+    /// Whether `#include`s are read in, rather than left out
+    includes: bool,
+    /// Where each line of the output came from: the index of its file in
+    /// `origins` (if it's from a file) and its line there
+    lines: Vec<(Option<usize>, usize)>,
+    warnings: Vec<PreprocessWarning>,
+    /// Whether any comments or directives have been taken out
+    stripped: bool,
 }
 
 #[derive(Debug)]
 pub enum Directive {
     Define,
+    Undef,
     Include,
+    Line,
 }
 
 impl Preprocessor {
     pub fn new() -> Self {
         Self {
             definitions: HashMap::new(),
+            files: vec![],
+            sources: vec![],
+            origins: vec![],
+            includes: true,
+            lines: vec![],
+            warnings: vec![],
+            stripped: false,
+        }
+    }
+
+    /// Leaves `#include`s out instead of reading the files they name, so
+    /// that each line of the output is the same line of the script.
+    pub fn skip_includes(&mut self) {
+        self.includes = false;
+    }
+
+    /// Every file that's been read, in the order they were first included.
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+
+    /// Problems found so far that didn't stop preprocessing.
+    pub fn warnings(&self) -> &[PreprocessWarning] {
+        &self.warnings
+    }
+
+    /// Whether any comments or directives have been taken out, so that
+    /// writing the script back out from what's left would lose them.
+    pub fn stripped(&self) -> bool {
+        self.stripped
+    }
+
+    /// The value of a macro, and where it was defined.
+    pub fn definition(&self, name: &str) -> Option<(&str, &Site)> {
+        self.definitions
+            .get(name)
+            .map(|m| (m.value.as_str(), &m.site))
+    }
+
+    fn site(&self, line: usize, column: usize) -> Site {
+        Site {
+            file: self.files.last().cloned(),
+            line,
+            column,
+        }
+    }
+
+    fn define(&mut self, name: &str, value: &str, site: Site) {
+        let previous = self.definitions.insert(
+            name.into(),
+            Macro {
+                value: value.into(),
+                site: site.clone(),
+            },
+        );
+
+        // the same definition again, as from a file included twice, is
+        // harmless
+        if let Some(previous) = previous.filter(|p| p.value != value) {
+            self.warnings.push(PreprocessWarning::Redefined {
+                name: name.into(),
+                at: site,
+                previous: previous.site,
+            });
+        }
+    }
+
+    fn undefine(&mut self, name: &str, site: Site) {
+        if self.definitions.remove(name).is_none() {
+            self.warnings.push(PreprocessWarning::NotDefined {
+                name: name.into(),
+                at: site,
+            });
+        }
+    }
+
+    /// The file (if it was read from one) and line, from 0, that a line of
+    /// the output came from.
+    pub fn locate(&self, line: usize) -> (Option<&Path>, usize) {
+        let (source, line) = match self.lines.get(line) {
+            Some(&found) => found,
+            // a last line without a newline is in the outermost file
+            None => (
+                (!self.origins.is_empty()).then_some(0),
+                self.lines.last().map_or(0, |&(_, l)| l + 1) + line - self.lines.len(),
+            ),
+        };
+
+        (source.map(|i| self.origins[i].as_path()), line)
+    }
+
+    /// Every file that lines of the output can have come from: the files
+    /// read, and any named by `#line`.
+    pub fn origins(&self) -> &[PathBuf] {
+        &self.origins
+    }
+
+    fn origin(&mut self, path: &Path) -> usize {
+        match self.origins.iter().position(|o| o == path) {
+            Some(index) => index,
+            None => {
+                self.origins.push(path.into());
+                self.origins.len() - 1
+            }
         }
     }
 
+    /// `preprocessed` (the output of preprocessing), with a `#line` before
+    /// each line that doesn't follow on from the one before it in the same
+    /// file, so that preprocessing it again gives the same locations.
+    pub fn mark_lines(&self, preprocessed: &str) -> String {
+        let mut rv = String::new();
+        let mut expected = None;
+
+        for (index, line) in preprocessed.split_inclusive('\n').enumerate() {
+            let (file, number) = self.locate(index);
+
+            if expected != Some((file, number)) {
+                match file {
+                    Some(file) => rv += &format!("#line {} \"{}\"\n", number + 1, file.display()),
+                    None => rv += &format!("#line {}\n", number + 1),
+                }
+            }
+
+            rv += line;
+            expected = Some((file, number + 1));
+        }
+
+        rv
+    }
+
+    /// Reads and preprocesses a file, along with anything it includes.
+    pub fn preprocess_file(&mut self, path: &Path) -> Result<String, PreprocessError> {
+        if self.files.len() >= MAX_INCLUDE_DEPTH {
+            return Err(PreprocessError::IncludeDepth(path.into()));
+        }
+
+        let file = read_to_string(path).map_err(|e| PreprocessError::Include(path.into(), e))?;
+
+        if !self.sources.iter().any(|s| s == path) {
+            self.sources.push(path.into());
+        }
+        self.origin(path);
+
+        self.files.push(path.into());
+        let rv = self.preprocess(&file);
+        self.files.pop();
+
+        rv.map_err(|e| match e {
+            // only the innermost file is worth naming
+            PreprocessError::InFile(..) => e,
+            e => PreprocessError::InFile(path.into(), Box::new(e)),
+        })
+    }
+
+    fn include(&mut self, parameter: &str) -> Result<String, PreprocessError> {
+        let name = parameter
+            .trim_start_matches(['"', '<'])
+            .trim_end_matches(['"', '>']);
+
+        let Some(file) = self.files.last() else {
+            return Err(PreprocessError::NoIncludeDir(name.into()));
+        };
+
+        let path = file
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(name.replace('\\', "/"));
+
+        self.preprocess_file(&path)
+    }
+
     pub fn preprocess(&mut self, file: &str) -> Result<String, PreprocessError> {
         let mut rv = String::new();
 
+        let mut source = self
+            .files
+            .last()
+            .and_then(|f| self.origins.iter().position(|o| o == f));
+
+        // the state to go back to after a comment
         let mut previous_state = PreprocessorState::Expecting;
         let mut state = PreprocessorState::Expecting;
 
@@ -60,6 +360,10 @@ impl Preprocessor {
         let mut line = 0;
         let mut column = 0;
 
+        // the line the output is said to be from, which `#line` can change
+        let mut origin_line = 0;
+        let mut next_line = None;
+
         let chars = file.chars().collect::<Vec<_>>();
 
         let mut directive_buf = String::new();
@@ -76,7 +380,9 @@ impl Preprocessor {
         ) -> Result<Directive, PreprocessError> {
             match directive_buf {
                 "define" => Ok(Directive::Define),
+                "undef" => Ok(Directive::Undef),
                 "include" => Ok(Directive::Include),
+                "line" => Ok(Directive::Line),
                 _ => Err(PreprocessError::UnknownDirective(
                     directive_buf.to_string(),
                     directive_line,
@@ -85,6 +391,19 @@ impl Preprocessor {
             }
         }
 
+        /// Whether the `/` at `index` starts a comment, rather than being
+        /// part of a parameter.
+        fn starts_comment(chars: &[char], index: usize) -> bool {
+            matches!(chars.get(index + 1), Some('/' | '*'))
+        }
+
+        fn push_parameter(directive_parameter_buf: &mut Vec<String>, c: char) {
+            match directive_parameter_buf.last_mut() {
+                Some(parameter) => parameter.push(c),
+                None => directive_parameter_buf.push(c.into()),
+            }
+        }
+
         'preprocess_loop: while index < chars.len() {
             let c = chars[index];
 
@@ -92,17 +411,20 @@ impl Preprocessor {
                 match state {
                     PreprocessorState::Expecting => match c {
                         '/' => {
+                            self.stripped = true;
                             previous_state = state;
                             state = PreprocessorState::Slash;
                         }
                         '#' => {
-                            previous_state = state;
+                            self.stripped = true;
                             state = PreprocessorState::Directive;
                             directive_buf = String::new();
                             directive_line = line;
                             directive_column = column;
                         }
                         '\n' => {
+                            self.lines.push((source, origin_line));
+                            origin_line += 1;
                             column = 0;
                             line += 1;
                             index += 1;
@@ -116,7 +438,7 @@ impl Preprocessor {
                                 if index + len < chars.len()
                                     && &String::from_iter(&chars[index..index + len]) == k
                                 {
-                                    rv += v;
+                                    rv += &v.value;
                                     index += len;
                                     continue 'preprocess_loop;
                                 }
@@ -134,8 +456,15 @@ impl Preprocessor {
                         _ => return Err(PreprocessError::UnexpectedToken(c, line, column)),
                     },
                     PreprocessorState::SkipLine => match c {
+                        // the directive the comment is in ends here
+                        '\n' if previous_state == PreprocessorState::DirectiveParameter => {
+                            state = previous_state;
+                            continue;
+                        }
                         '\n' => {
                             state = previous_state;
+                            self.lines.push((source, origin_line));
+                            origin_line += 1;
                             column = 0;
                             line += 1;
                             index += 1;
@@ -148,6 +477,17 @@ impl Preprocessor {
                         '*' => {
                             state = PreprocessorState::EndComment;
                         }
+                        // kept so that lines after the comment stay where
+                        // they were
+                        '\n' => {
+                            self.lines.push((source, origin_line));
+                            origin_line += 1;
+                            column = 0;
+                            line += 1;
+                            index += 1;
+                            rv.push(c);
+                            continue;
+                        }
                         _ => {}
                     },
                     PreprocessorState::EndComment => match c {
@@ -157,6 +497,16 @@ impl Preprocessor {
                         _ => return Err(PreprocessError::UnexpectedToken(c, line, column)),
                     },
                     PreprocessorState::Directive => match c {
+                        '/' if starts_comment(&chars, index) => {
+                            directive = parse_directive_buf(
+                                &directive_buf,
+                                directive_line,
+                                directive_column,
+                            )?;
+                            directive_parameter_buf = vec![String::new()];
+                            previous_state = PreprocessorState::DirectiveParameter;
+                            state = PreprocessorState::Slash;
+                        }
                         ' ' | '\t' => {
                             directive = parse_directive_buf(
                                 &directive_buf,
@@ -182,27 +532,92 @@ impl Preprocessor {
                         }
                     },
                     PreprocessorState::DirectiveParameter => match c {
+                        // a comment separates parameters, like whitespace
+                        '/' if starts_comment(&chars, index) => {
+                            if directive_parameter_buf
+                                .last()
+                                .is_some_and(|p| !p.is_empty())
+                            {
+                                directive_parameter_buf.push(String::new());
+                            }
+                            previous_state = state;
+                            state = PreprocessorState::Slash;
+                        }
                         '"' | '<' => {
                             directive_parameter_delimiter = c;
                             state = PreprocessorState::DirectiveString;
-                            directive_parameter_buf.last_mut().unwrap().push(c);
+                            push_parameter(&mut directive_parameter_buf, c);
                         }
                         '\n' => {
-                            if directive_parameter_buf.last().unwrap().is_empty() {
+                            if directive_parameter_buf.last().is_some_and(String::is_empty) {
                                 directive_parameter_buf.pop();
                             }
 
                             match directive {
                                 Directive::Define => match directive_parameter_buf.len() {
+                                    1 | 2 => {
+                                        let site = self.site(directive_line, directive_column);
+                                        self.define(
+                                            &directive_parameter_buf[0],
+                                            directive_parameter_buf.get(1).map_or("", |v| v),
+                                            site,
+                                        );
+                                    }
+                                    0 => {
+                                        return Err(PreprocessError::NoParams(
+                                            directive,
+                                            directive_line,
+                                            directive_column,
+                                        ))
+                                    }
+                                    _ => {
+                                        return Err(PreprocessError::TooManyParameters(
+                                            directive,
+                                            directive_line,
+                                            directive_column,
+                                        ))
+                                    }
+                                },
+                                Directive::Undef => match directive_parameter_buf.len() {
                                     1 => {
-                                        self.definitions
-                                            .insert(directive_parameter_buf[0].clone(), "".into());
+                                        let site = self.site(directive_line, directive_column);
+                                        self.undefine(&directive_parameter_buf[0], site);
                                     }
-                                    2 => {
-                                        self.definitions.insert(
-                                            directive_parameter_buf[0].clone(),
-                                            directive_parameter_buf[1].clone(),
-                                        );
+                                    0 => {
+                                        return Err(PreprocessError::NoParams(
+                                            directive,
+                                            directive_line,
+                                            directive_column,
+                                        ))
+                                    }
+                                    _ => {
+                                        return Err(PreprocessError::TooManyParameters(
+                                            directive,
+                                            directive_line,
+                                            directive_column,
+                                        ))
+                                    }
+                                },
+                                Directive::Line => match directive_parameter_buf.len() {
+                                    1 | 2 => {
+                                        let number = &directive_parameter_buf[0];
+                                        let number = number
+                                            .parse::<usize>()
+                                            .ok()
+                                            .filter(|&n| n > 0)
+                                            .ok_or_else(|| {
+                                                PreprocessError::BadLineNumber(
+                                                    number.clone(),
+                                                    directive_line,
+                                                    directive_column,
+                                                )
+                                            })?;
+
+                                        let file = directive_parameter_buf.get(1).map(|f| {
+                                            self.origin(Path::new(f.trim_matches(['"', '<', '>'])))
+                                        });
+
+                                        next_line = Some((number - 1, file));
                                     }
                                     0 => {
                                         return Err(PreprocessError::NoParams(
@@ -220,8 +635,10 @@ impl Preprocessor {
                                     }
                                 },
                                 Directive::Include => match directive_parameter_buf.len() {
+                                    1 if !self.includes => {}
                                     1 => {
-                                        println!("include {}", directive_parameter_buf[0])
+                                        let included = self.include(&directive_parameter_buf[0])?;
+                                        rv += &included;
                                     }
                                     0 => {
                                         return Err(PreprocessError::NoParams(
@@ -239,26 +656,42 @@ impl Preprocessor {
                                     }
                                 },
                             }
-                            state = previous_state;
+                            state = PreprocessorState::Expecting;
+                            self.lines.push((source, origin_line));
+                            origin_line += 1;
+
+                            // a #line says where the lines after it are from
+                            if let Some((number, file)) = next_line.take() {
+                                origin_line = number;
+                                source = file.or(source);
+                            }
+                            column = 0;
+                            line += 1;
+                            index += 1;
+                            rv.push(c);
+                            continue;
                         }
                         ' ' | '\t' => {
-                            if !directive_parameter_buf.last().unwrap().is_empty() {
+                            if directive_parameter_buf
+                                .last()
+                                .is_some_and(|p| !p.is_empty())
+                            {
                                 directive_parameter_buf.push(String::new());
                             }
                         }
                         _ => {
-                            directive_parameter_buf.last_mut().unwrap().push(c);
+                            push_parameter(&mut directive_parameter_buf, c);
                         }
                     },
                     PreprocessorState::DirectiveString => match c {
                         _ if c == directive_parameter_delimiter => {
-                            directive_parameter_buf.last_mut().unwrap().push(c);
+                            push_parameter(&mut directive_parameter_buf, c);
                             state = PreprocessorState::DirectiveParameter;
                             directive_parameter_buf.push(String::new());
                         }
                         '\n' => return Err(PreprocessError::UnexpectedToken(c, line, column)),
                         _ => {
-                            directive_parameter_buf.last_mut().unwrap().push(c);
+                            push_parameter(&mut directive_parameter_buf, c);
                         }
                     },
                 }
@@ -269,7 +702,16 @@ impl Preprocessor {
         }
 
         match state {
-            PreprocessorState::Expecting | PreprocessorState::SkipLine => Ok(rv),
+            PreprocessorState::Expecting | PreprocessorState::SkipLine => {
+                // an included file's last line gets a newline of its own, so
+                // that it isn't taken to be the #include's line
+                if self.files.len() > 1 && !rv.is_empty() && !rv.ends_with('\n') {
+                    self.lines.push((source, origin_line));
+                    rv.push('\n');
+                }
+
+                Ok(rv)
+            }
             PreprocessorState::Slash
             | PreprocessorState::SkipComment
             | PreprocessorState::EndComment
@@ -279,3 +721,196 @@ impl Preprocessor {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stripped(script: &str) -> bool {
+        let mut pp = Preprocessor::new();
+        pp.preprocess(script).unwrap();
+        pp.stripped()
+    }
+
+    fn preprocess(script: &str) -> (String, Preprocessor) {
+        let mut pp = Preprocessor::new();
+        let preprocessed = pp.preprocess(script).unwrap();
+        (preprocessed, pp)
+    }
+
+    #[test]
+    fn notes_what_formatting_would_lose() {
+        assert!(!stripped("defineSound A { fileName = \"a.wav\"; }\n"));
+        assert!(stripped("defineSound A { } // sound\n"));
+        assert!(stripped("/* sound */ defineSound A { }\n"));
+        assert!(stripped(
+            "#define VOLUME 79\ndefineSound A { volume = VOLUME; }\n"
+        ));
+    }
+
+    #[test]
+    fn undef_stops_substitution() {
+        let (preprocessed, pp) = preprocess("#define V 1\nA V;\n#undef V\nB V;\n");
+
+        assert_eq!(preprocessed, "\nA 1;\n\nB V;\n");
+        assert!(pp.definition("V").is_none());
+        assert!(pp.warnings().is_empty());
+    }
+
+    #[test]
+    fn undef_of_nothing_warns() {
+        let (_, pp) = preprocess("\n  #undef V\n");
+
+        match pp.warnings() {
+            [PreprocessWarning::NotDefined { name, at }] => {
+                assert_eq!(name, "V");
+                assert_eq!((at.line, at.column), (1, 2));
+            }
+            warnings => panic!("{warnings:?}"),
+        }
+    }
+
+    #[test]
+    fn redefinitions_warn_with_both_sites() {
+        let (_, pp) = preprocess("#define V 1\n#define V 1\n\n#define V 2\n");
+
+        match pp.warnings() {
+            [warning @ PreprocessWarning::Redefined { name, at, previous }] => {
+                assert_eq!(name, "V");
+                assert_eq!(at.line, 3);
+                assert_eq!(previous.line, 1);
+                assert_eq!(
+                    warning.to_string(),
+                    "4:1: \"V\" redefined (it was defined at 2:1)"
+                );
+            }
+            warnings => panic!("{warnings:?}"),
+        }
+
+        let (value, site) = pp.definition("V").unwrap();
+        assert_eq!(value, "2");
+        assert_eq!(site.line, 3);
+    }
+
+    #[test]
+    fn definitions_know_their_file() {
+        let folder = std::env::temp_dir().join(format!("gwydd-pp-{}-defs", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let script = folder.join("a.ss");
+        std::fs::write(folder.join("b.ss"), "#define V 1\n").unwrap();
+        std::fs::write(&script, "#include \"b.ss\"\n#define V 2\n").unwrap();
+
+        let mut pp = Preprocessor::new();
+        pp.preprocess_file(&script).unwrap();
+        std::fs::remove_dir_all(&folder).unwrap();
+
+        let (_, site) = pp.definition("V").unwrap();
+        assert_eq!(site.file.as_deref(), Some(script.as_path()));
+        match pp.warnings() {
+            [PreprocessWarning::Redefined { previous, .. }] => {
+                assert_eq!(previous.file, Some(folder.join("b.ss")));
+            }
+            warnings => panic!("{warnings:?}"),
+        }
+    }
+
+    #[test]
+    fn line_directives_move_later_lines() {
+        let (_, pp) = preprocess("A;\n#line 10 \"other.ss\"\nB;\nC;\n#line 3\nD;\n");
+
+        assert_eq!(pp.locate(0), (None, 0));
+        assert_eq!(pp.locate(2), (Some(Path::new("other.ss")), 9));
+        assert_eq!(pp.locate(3), (Some(Path::new("other.ss")), 10));
+        assert_eq!(pp.locate(5), (Some(Path::new("other.ss")), 2));
+    }
+
+    #[test]
+    fn bad_line_numbers_are_errors() {
+        for script in [
+            "#line 0\n",
+            "#line ten\n",
+            "#line\n",
+            "#line 1 \"a\" \"b\"\n",
+        ] {
+            assert!(Preprocessor::new().preprocess(script).is_err(), "{script}");
+        }
+    }
+
+    #[test]
+    fn lines_are_located_across_includes() {
+        let folder = std::env::temp_dir().join(format!("gwydd-pp-{}-lines", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let (script, included) = (folder.join("a.ss"), folder.join("b.ss"));
+        std::fs::write(&included, "B;\nC;").unwrap();
+        std::fs::write(&script, "A;\n#include \"b.ss\"\nD;\n").unwrap();
+
+        let mut pp = Preprocessor::new();
+        let preprocessed = pp.preprocess_file(&script).unwrap();
+        std::fs::remove_dir_all(&folder).unwrap();
+
+        // the #include's own line is left empty
+        assert_eq!(preprocessed, "A;\nB;\nC;\n\nD;\n");
+        let located = (0..5).map(|l| pp.locate(l)).collect::<Vec<_>>();
+        assert_eq!(
+            located,
+            [
+                (Some(script.as_path()), 0),
+                (Some(included.as_path()), 0),
+                (Some(included.as_path()), 1),
+                (Some(script.as_path()), 1),
+                (Some(script.as_path()), 2),
+            ]
+        );
+
+        // the marked output says the same about each line when it's read
+        // again, although the markers are lines of their own
+        let marked = pp.mark_lines(&preprocessed);
+        let (again, marked_pp) = preprocess(&marked);
+        for (index, line) in again.lines().enumerate() {
+            if let Some(at) = preprocessed
+                .lines()
+                .position(|l| l == line && !l.is_empty())
+            {
+                assert_eq!(marked_pp.locate(index), pp.locate(at), "{marked}");
+            }
+        }
+    }
+
+    #[test]
+    fn comments_in_directives_are_stripped() {
+        for script in [
+            "#define V 1 // volume\n",
+            "#define V 1// volume\n",
+            "#define V /* volume */ 1\n",
+            "#define /* volume */ V 1\n",
+            "#define/* volume */V 1\n",
+            "#define V 1 /* volume */\n",
+        ] {
+            let (preprocessed, pp) = preprocess(script);
+            assert_eq!(pp.definition("V").map(|(v, _)| v), Some("1"), "{script}");
+            assert_eq!(preprocessed, "\n", "{script}");
+        }
+
+        let (_, pp) = preprocess("#undef V // gone\n");
+        assert!(matches!(
+            pp.warnings(),
+            [PreprocessWarning::NotDefined { .. }]
+        ));
+    }
+
+    #[test]
+    fn block_comments_can_run_past_directives() {
+        let (preprocessed, pp) = preprocess("#define V 1 /* volume\nstill a comment */\nA V;\n");
+
+        assert_eq!(pp.definition("V").map(|(v, _)| v), Some("1"));
+        assert_eq!(preprocessed, "\n\nA 1;\n");
+        assert_eq!(pp.locate(2), (None, 2));
+    }
+
+    #[test]
+    fn slashes_in_directive_strings_are_kept() {
+        let (_, pp) = preprocess("#line 5 \"a//b/*c*/.ss\" // where\nA;\n");
+
+        assert_eq!(pp.locate(1), (Some(Path::new("a//b/*c*/.ss")), 4));
+    }
+}
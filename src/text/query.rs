@@ -0,0 +1,220 @@
+use super::{Block, BlockType, RValue, Statement, Text};
+use crate::types::wildcard_match;
+use std::str::FromStr;
+
+use BlockType::*;
+
+const BLOCK_TYPES: [BlockType; 8] = [
+    DefineSettings,
+    DefineObject,
+    DefineSound,
+    DefineEvent,
+    DefineAnim,
+    ParallelAction,
+    DefineStill,
+    SerialAction,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    /// `=`, the whole value
+    Equals,
+    /// `!=`
+    NotEquals,
+    /// `~=`, a case-insensitive pattern with `*` and `?`
+    Matches,
+}
+
+#[derive(Debug, Clone)]
+struct Condition {
+    field: String,
+    /// Left out to only check that the field is there
+    test: Option<(Op, String)>,
+}
+
+/// Picks out blocks, or one of their fields, with a selector such as
+/// `defineSound[name~="*laugh*"].fileName`: a block type (or `*` for any),
+/// then any number of `[field]`, `[field=value]`, `[field!=value]` or
+/// `[field~=pattern]` conditions, then the field to give back. Blocks give
+/// back their name if no field is given.
+///
+/// Besides the fields a block assigns, `name`, `id` and `type` are the
+/// block's own, and `children` is each block it declares.
+#[derive(Debug, Clone)]
+pub struct Query {
+    block_type: Option<BlockType>,
+    conditions: Vec<Condition>,
+    field: Option<String>,
+}
+
+/// A value picked out by a [`Query`].
+#[derive(Debug, Clone)]
+pub struct QueryMatch {
+    /// Name of the block it's from
+    pub block: String,
+    pub value: String,
+}
+
+fn is_field_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Splits a field name off the front of `s`.
+fn field(s: &str) -> Result<(&str, &str), String> {
+    let end = s.find(|c| !is_field_char(c)).unwrap_or(s.len());
+
+    match s.split_at(end) {
+        ("", _) => Err(format!("Expected a field name at \"{s}\"")),
+        split => Ok(split),
+    }
+}
+
+/// Splits a condition's value off the front of `s`, up to the closing `]`.
+fn value(s: &str) -> Result<(String, &str), String> {
+    if let Some(quoted) = s.strip_prefix('"') {
+        let end = quoted
+            .find('"')
+            .ok_or_else(|| format!("Unclosed quote at \"{s}\""))?;
+
+        let rest = quoted[end + 1..].trim_start();
+        return Ok((quoted[..end].into(), rest));
+    }
+
+    let end = s.find(']').unwrap_or(s.len());
+    Ok((s[..end].trim().into(), &s[end..]))
+}
+
+fn condition(s: &str) -> Result<(Condition, &str), String> {
+    let (field, rest) = field(s.trim_start())?;
+    let rest = rest.trim_start();
+
+    let op = [
+        ("!=", Op::NotEquals),
+        ("~=", Op::Matches),
+        ("=", Op::Equals),
+    ]
+    .into_iter()
+    .find_map(|(token, op)| rest.strip_prefix(token).map(|rest| (op, rest)));
+
+    let (test, rest) = match op {
+        Some((op, rest)) => {
+            let (value, rest) = value(rest.trim_start())?;
+            (Some((op, value)), rest)
+        }
+        None => (None, rest),
+    };
+
+    let rest = rest
+        .strip_prefix(']')
+        .ok_or_else(|| format!("Expected \"]\" at \"{rest}\""))?;
+
+    Ok((
+        Condition {
+            field: field.into(),
+            test,
+        },
+        rest,
+    ))
+}
+
+impl FromStr for Query {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let end = s.find(['[', '.']).unwrap_or(s.len());
+        let (name, mut rest) = s.split_at(end);
+
+        let block_type = match name {
+            "" | "*" => None,
+            _ => Some(
+                *BLOCK_TYPES
+                    .iter()
+                    .find(|t| t.to_string().eq_ignore_ascii_case(name))
+                    .ok_or_else(|| format!("Unknown block type \"{name}\""))?,
+            ),
+        };
+
+        let mut conditions = vec![];
+
+        while let Some(inner) = rest.strip_prefix('[') {
+            let (condition, after) = condition(inner)?;
+            conditions.push(condition);
+            rest = after;
+        }
+
+        let field = match rest.strip_prefix('.') {
+            Some(rest) => match field(rest)? {
+                (field, "") => Some(field.into()),
+                (_, extra) => return Err(format!("Unexpected \"{extra}\" at end of query")),
+            },
+            None if rest.is_empty() => None,
+            None => return Err(format!("Unexpected \"{rest}\" in query")),
+        };
+
+        Ok(Self {
+            block_type,
+            conditions,
+            field,
+        })
+    }
+}
+
+/// Every value `field` has in `block`, as it'd be written in a script but
+/// with strings unquoted.
+fn values(block: &Block, field: &str) -> Vec<String> {
+    match field {
+        "name" => return vec![block.name.clone()],
+        "id" => return vec![block.id.to_string()],
+        "type" => return vec![block.block_type.to_string()],
+        _ => {}
+    }
+
+    block
+        .statements
+        .iter()
+        .filter_map(|s| match s {
+            Statement::Assignment(name, RValue::String(value)) if name == field => {
+                Some(value.clone())
+            }
+            Statement::Assignment(name, value) if name == field => Some(value.to_string()),
+            Statement::Declaration(child) if field == "children" => Some(child.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+impl Condition {
+    fn matches(&self, block: &Block) -> bool {
+        let values = values(block, &self.field);
+
+        match &self.test {
+            None => !values.is_empty(),
+            Some((Op::Equals, want)) => values.iter().any(|v| v == want),
+            Some((Op::NotEquals, want)) => !values.iter().any(|v| v == want),
+            Some((Op::Matches, pattern)) => values.iter().any(|v| wildcard_match(pattern, v)),
+        }
+    }
+}
+
+impl Text {
+    /// Finds what `query` picks out of the script, in script order.
+    pub fn query(&self, query: &Query) -> Vec<QueryMatch> {
+        std::iter::once(&self.settings)
+            .chain(&self.blocks)
+            .filter(|b| query.block_type.is_none_or(|t| b.block_type == t))
+            .filter(|b| query.conditions.iter().all(|c| c.matches(b)))
+            .flat_map(|b| {
+                let values = match &query.field {
+                    Some(field) => values(b, field),
+                    None => vec![b.name.clone()],
+                };
+
+                values.into_iter().map(|value| QueryMatch {
+                    block: b.name.clone(),
+                    value,
+                })
+            })
+            .collect()
+    }
+}
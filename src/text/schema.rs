@@ -0,0 +1,362 @@
+use super::{BlockType, Definition, Duration, LoopingMethod, RValue, Statement};
+use crate::types::Vec3;
+
+use BlockType::*;
+
+/// Every type of block that's an object.
+pub const OBJECTS: &[BlockType] = &[
+    DefineObject,
+    DefineSound,
+    DefineEvent,
+    DefineAnim,
+    DefineStill,
+    SerialAction,
+    ParallelAction,
+];
+
+/// Types of block whose objects play data of their own.
+const MEDIA: &[BlockType] = &[
+    DefineObject,
+    DefineSound,
+    DefineEvent,
+    DefineAnim,
+    DefineStill,
+];
+
+const ACTIONS: &[BlockType] = &[SerialAction, ParallelAction];
+
+pub const DEFAULT_LOCATION: Vec3 = Vec3::ZERO;
+pub const DEFAULT_DIRECTION: Vec3 = Vec3::Z;
+pub const DEFAULT_UP: Vec3 = Vec3::Y;
+pub const DEFAULT_LOOP_COUNT: i32 = 1;
+pub const DEFAULT_VOLUME: i32 = 0x4F;
+
+/// Values that an object gets for the fields its block leaves out, which
+/// are what decompiling leaves out in turn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Defaults {
+    pub location: Vec3,
+    pub direction: Vec3,
+    pub up: Vec3,
+    pub start_time: i32,
+    pub duration: i32,
+    pub loop_count: i32,
+    pub looping_method: LoopingMethod,
+    pub sustain_time: i32,
+    pub volume: i32,
+}
+
+impl Defaults {
+    /// The defaults for a type of object, which are the same for every type
+    /// at the moment.
+    pub fn of(_: BlockType) -> Self {
+        Self {
+            location: DEFAULT_LOCATION,
+            direction: DEFAULT_DIRECTION,
+            up: DEFAULT_UP,
+            start_time: 0,
+            duration: 0,
+            loop_count: DEFAULT_LOOP_COUNT,
+            looping_method: LoopingMethod::None,
+            sustain_time: 0,
+            volume: DEFAULT_VOLUME,
+        }
+    }
+
+    /// The default of a field, as it would be written in a script.
+    fn value(&self, field: &str) -> Option<RValue> {
+        Some(match field {
+            "location" => RValue::Vec3(self.location),
+            "direction" => RValue::Vec3(self.direction),
+            "up" => RValue::Vec3(self.up),
+            "volume" => RValue::Integer(self.volume),
+            "duration" => RValue::Definition(Definition::Duration(Duration(self.duration))),
+            "sustainTime" => RValue::Integer(self.sustain_time),
+            "startTime" => RValue::Integer(self.start_time),
+            "loopCount" => RValue::Integer(self.loop_count),
+            "loopingMethod" => {
+                RValue::Definition(Definition::LoopingMethod(self.looping_method.clone()))
+            }
+            _ => return None,
+        })
+    }
+}
+
+/// The kind of value a field takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    String,
+    Integer,
+    /// A level, or a percentage or decibels relative to the default
+    Volume,
+    Vec3,
+    /// `CACHE`, `STREAM` or `NONE`
+    LoopingMethod,
+    /// A number of milliseconds, or `INDEFINITE`
+    Duration,
+    /// `NONE`
+    PaletteManagement,
+    /// `YES` or `FAST`
+    Transparency,
+    /// `list(...)`
+    List,
+    /// A hex number of flag bits, or an integer
+    Bits,
+}
+
+impl ValueType {
+    pub fn accepts(self, value: &RValue) -> bool {
+        match (self, value) {
+            (Self::String, RValue::String(_))
+            | (Self::Integer, RValue::Integer(_))
+            | (Self::Volume, RValue::Integer(_))
+            | (Self::Volume, RValue::Volume(_))
+            | (Self::Vec3, RValue::Vec3(_))
+            | (Self::LoopingMethod, RValue::Definition(Definition::LoopingMethod(_)))
+            | (Self::Duration, RValue::Integer(_))
+            | (Self::Bits, RValue::Bits(_))
+            | (Self::Bits, RValue::Integer(_))
+            | (Self::Duration, RValue::Definition(Definition::Duration(_)))
+            | (Self::PaletteManagement, RValue::Definition(Definition::PaletteManagement(_)))
+            | (Self::Transparency, RValue::Definition(Definition::Transparency(_))) => true,
+            // NONE is read as a looping method, since that's tried first
+            (
+                Self::PaletteManagement,
+                RValue::Definition(Definition::LoopingMethod(LoopingMethod::None)),
+            ) => true,
+            (Self::List, RValue::Function(f)) => f.name == "list",
+            _ => false,
+        }
+    }
+}
+
+/// A field that blocks can give a value.
+#[derive(Debug)]
+pub struct Field {
+    pub name: &'static str,
+    pub value: ValueType,
+    /// Types of block it does something in
+    pub used_by: &'static [BlockType],
+    /// Types of block that can't be compiled without it
+    pub required_by: &'static [BlockType],
+    pub description: &'static str,
+}
+
+impl Field {
+    /// The value an object of `block_type` gets when its block leaves this
+    /// field out, if it's one that can be written down.
+    pub fn default(&self, block_type: BlockType) -> Option<RValue> {
+        if !self.used_by.contains(&block_type) {
+            return None;
+        }
+
+        Defaults::of(block_type).value(self.name)
+    }
+}
+
+/// Every field, in the order that defaults are spelled out in.
+pub const FIELDS: &[Field] = &[
+    Field {
+        name: "bufferSizeKB",
+        value: ValueType::Integer,
+        used_by: &[DefineSettings],
+        required_by: &[DefineSettings],
+        description: "Size of each streaming buffer, in KB",
+    },
+    Field {
+        name: "buffersNum",
+        value: ValueType::Integer,
+        used_by: &[DefineSettings],
+        required_by: &[DefineSettings],
+        description: "Number of streaming buffers",
+    },
+    Field {
+        name: "fileName",
+        value: ValueType::String,
+        used_by: MEDIA,
+        required_by: MEDIA,
+        description: "Name the object's data is stored under, which is looked up in the resource folder when compiling",
+    },
+    Field {
+        name: "handlerClass",
+        value: ValueType::String,
+        used_by: OBJECTS,
+        required_by: &[],
+        description: "Presenter class that plays the object, instead of the usual one for its type",
+    },
+    Field {
+        name: "location",
+        value: ValueType::Vec3,
+        used_by: OBJECTS,
+        required_by: &[],
+        description: "Where the object is, as (x, y, z)",
+    },
+    Field {
+        name: "direction",
+        value: ValueType::Vec3,
+        used_by: OBJECTS,
+        required_by: &[],
+        description: "Which way the object faces, as (x, y, z)",
+    },
+    Field {
+        name: "up",
+        value: ValueType::Vec3,
+        used_by: OBJECTS,
+        required_by: &[],
+        description: "Which way is up for the object, as (x, y, z)",
+    },
+    Field {
+        name: "volume",
+        value: ValueType::Volume,
+        used_by: &[DefineSound],
+        required_by: &[],
+        description: "How loud the sound is: a level, where the default is 79, or a percentage or decibels relative to that (`50%`, `-6dB`)",
+    },
+    Field {
+        name: "duration",
+        value: ValueType::Duration,
+        used_by: OBJECTS,
+        required_by: &[],
+        description: "How long the object plays for, in milliseconds, or `INDEFINITE`",
+    },
+    Field {
+        name: "paletteManagement",
+        value: ValueType::PaletteManagement,
+        used_by: &[DefineAnim, DefineStill],
+        required_by: &[],
+        description: "How the object's palette is handled",
+    },
+    Field {
+        name: "sustainTime",
+        value: ValueType::Integer,
+        used_by: &[DefineSound, DefineAnim, DefineStill],
+        required_by: &[],
+        description: "How long the object is held after its data runs out, in milliseconds",
+    },
+    Field {
+        name: "startTime",
+        value: ValueType::Integer,
+        used_by: OBJECTS,
+        required_by: &[],
+        description: "How long after its parent starts the object starts, in milliseconds",
+    },
+    Field {
+        name: "loopCount",
+        value: ValueType::Integer,
+        used_by: OBJECTS,
+        required_by: &[],
+        description: "How many times the object plays",
+    },
+    Field {
+        name: "loopingMethod",
+        value: ValueType::LoopingMethod,
+        used_by: OBJECTS,
+        required_by: &[],
+        description: "How the object loops: `CACHE` keeps its data in memory, `STREAM` reads it again, and `NONE` doesn't loop",
+    },
+    Field {
+        name: "transparency",
+        value: ValueType::Transparency,
+        used_by: &[DefineAnim, DefineStill],
+        required_by: &[],
+        description: "Whether the image has transparent parts (`YES`). `FAST` has no known flag bit, so it isn't stored",
+    },
+    Field {
+        name: "flags",
+        value: ValueType::Bits,
+        used_by: OBJECTS,
+        required_by: &[],
+        description: "Object flag bits that have no name, which are set when compiling",
+    },
+    Field {
+        name: "loopCache",
+        value: ValueType::Integer,
+        used_by: OBJECTS,
+        required_by: &[],
+        description: "Sets (`1`) or clears (`0`) the flag bit for `loopingMethod = CACHE`, whatever the looping method",
+    },
+    Field {
+        name: "noLoop",
+        value: ValueType::Integer,
+        used_by: OBJECTS,
+        required_by: &[],
+        description: "Sets (`1`) or clears (`0`) the flag bit for `loopingMethod = NONE`, whatever the looping method",
+    },
+    Field {
+        name: "loopStream",
+        value: ValueType::Integer,
+        used_by: OBJECTS,
+        required_by: &[],
+        description: "Sets (`1`) or clears (`0`) the flag bit for `loopingMethod = STREAM`, whatever the looping method",
+    },
+    Field {
+        name: "transparent",
+        value: ValueType::Integer,
+        used_by: OBJECTS,
+        required_by: &[],
+        description: "Sets (`1`) or clears (`0`) the flag bit for `transparency = YES`, whatever the transparency",
+    },
+    Field {
+        name: "randomCount",
+        value: ValueType::Integer,
+        used_by: ACTIONS,
+        required_by: &[],
+        description: "Upper bound of the random number that's looked up in `selectionList`",
+    },
+    Field {
+        name: "selectionList",
+        value: ValueType::List,
+        used_by: ACTIONS,
+        required_by: &[],
+        description: "Values that pick which child plays, as `list(\"...\", ...)`",
+    },
+    Field {
+        name: "extra",
+        value: ValueType::String,
+        used_by: OBJECTS,
+        required_by: &[],
+        description: "Extra data handed to the object's presenter",
+    },
+    Field {
+        name: "entityName",
+        value: ValueType::String,
+        used_by: OBJECTS,
+        required_by: &[],
+        description: "Another name for `extra`",
+    },
+    Field {
+        name: "stream",
+        value: ValueType::Integer,
+        used_by: OBJECTS,
+        required_by: &[],
+        description: "Object ID, which is also the stream's ID if the object is a weave",
+    },
+];
+
+/// The field with a name, if there's one.
+pub fn field(name: &str) -> Option<&'static Field> {
+    FIELDS.iter().find(|f| f.name == name)
+}
+
+/// The fields that do something in a type of block.
+pub fn fields(block_type: BlockType) -> impl Iterator<Item = &'static Field> {
+    FIELDS
+        .iter()
+        .filter(move |f| f.used_by.contains(&block_type))
+}
+
+/// Every field that an object of `block_type` gets a default for, with the
+/// value it gets.
+pub fn defaults(block_type: BlockType) -> Vec<(&'static str, RValue)> {
+    fields(block_type)
+        .filter_map(|f| Some((f.name, f.default(block_type)?)))
+        .collect()
+}
+
+/// An assignment of `value` to `field`, unless it's what a block of
+/// `block_type` gets anyway.
+pub(crate) fn non_default(block_type: BlockType, field: &str, value: RValue) -> Option<Statement> {
+    let default = self::field(field).and_then(|f| f.default(block_type));
+
+    (default.as_ref() != Some(&value)).then(|| Statement::Assignment(field.into(), value))
+}
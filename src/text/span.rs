@@ -0,0 +1,110 @@
+use super::{preprocessor::Preprocessor, Block, Statement, Text};
+use std::{fmt::Display, path::Path, sync::Arc};
+
+/// Where something was in the script it was parsed from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    /// File it was in, if the script was read from one
+    pub file: Option<Arc<Path>>,
+    /// Line and column, both from 0
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        write!(f, "{}:{}", self.line + 1, self.column + 1)
+    }
+}
+
+impl Span {
+    /// Stands in for a span while parsing, until [`Locator::locate`] turns
+    /// the byte offset into a line and column.
+    pub(super) fn offset(offset: usize) -> Self {
+        Self {
+            file: None,
+            line: 0,
+            column: offset,
+        }
+    }
+}
+
+/// Turns byte offsets in preprocessed scripts into where they were in the
+/// files they came from.
+pub(super) struct Locator<'a> {
+    preprocessor: &'a Preprocessor,
+    /// Byte offset each line of the preprocessed script starts at
+    line_starts: Vec<usize>,
+    files: Vec<Arc<Path>>,
+}
+
+impl<'a> Locator<'a> {
+    pub fn new(preprocessed: &str, preprocessor: &'a Preprocessor) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(preprocessed.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Self {
+            preprocessor,
+            line_starts,
+            files: preprocessor
+                .origins()
+                .iter()
+                .map(|p| Arc::from(p.as_path()))
+                .collect(),
+        }
+    }
+
+    pub fn locate(&self, offset: usize) -> Span {
+        let line = self.line_starts.partition_point(|&s| s <= offset) - 1;
+        let (file, source_line) = self.preprocessor.locate(line);
+
+        Span {
+            file: file.and_then(|f| self.files.iter().find(|a| a.as_ref() == f).cloned()),
+            line: source_line,
+            column: offset - self.line_starts[line],
+        }
+    }
+
+    /// Turns the byte offsets the parser left in `text`'s spans into lines
+    /// and columns.
+    pub fn locate_text(&self, text: &mut Text) {
+        for block in std::iter::once(&mut text.settings).chain(&mut text.blocks) {
+            for span in block.span.iter_mut().chain(&mut block.statement_spans) {
+                *span = self.locate(span.column);
+            }
+        }
+    }
+}
+
+impl Block {
+    /// Where the statement at `index` was, if the block was parsed and its
+    /// statements haven't changed since.
+    pub fn statement_span(&self, index: usize) -> Option<&Span> {
+        (self.statement_spans.len() == self.statements.len())
+            .then(|| self.statement_spans.get(index))
+            .flatten()
+    }
+
+    /// Where `field` was given a value, or failing that where the block
+    /// was.
+    pub fn field_span(&self, field: &str) -> Option<&Span> {
+        self.statements
+            .iter()
+            .position(|s| matches!(s, Statement::Assignment(n, _) if n == field))
+            .and_then(|i| self.statement_span(i))
+            .or(self.span.as_ref())
+    }
+
+    /// Where `child` was declared, or failing that where the block was.
+    pub fn declaration_span(&self, child: &str) -> Option<&Span> {
+        self.statements
+            .iter()
+            .position(|s| matches!(s, Statement::Declaration(n) if n == child))
+            .and_then(|i| self.statement_span(i))
+            .or(self.span.as_ref())
+    }
+}
@@ -0,0 +1,93 @@
+use super::{schema::DEFAULT_VOLUME, RValue, Statement, Text};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, str::FromStr};
+
+/// A volume written as a percentage or in decibels. Both are relative to
+/// the default volume, so `100%` and `0dB` are both 0x4F.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Volume {
+    Percent(f64),
+    Decibels(f64),
+}
+
+impl Display for Volume {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Percent(p) => write!(f, "{p}%"),
+            Self::Decibels(db) => write!(f, "{db}dB"),
+        }
+    }
+}
+
+impl Volume {
+    /// The nearest volume level the engine has to this.
+    pub fn level(self) -> i32 {
+        let scale = match self {
+            Self::Percent(p) => p / 100.0,
+            Self::Decibels(db) => 10f64.powf(db / 20.0),
+        };
+
+        (scale * DEFAULT_VOLUME as f64).round() as i32
+    }
+
+    /// A volume level written in `unit`, rounded to as few decimal places
+    /// as still give back the same level. Silence can't be written in
+    /// decibels, so it stays a level.
+    pub fn from_level(level: i32, unit: VolumeUnit) -> RValue {
+        let (exact, volume): (f64, fn(f64) -> Self) = match unit {
+            VolumeUnit::Level => return RValue::Integer(level),
+            VolumeUnit::Percent => (level as f64 * 100.0 / DEFAULT_VOLUME as f64, Self::Percent),
+            VolumeUnit::Decibels if level > 0 => (
+                20.0 * (level as f64 / DEFAULT_VOLUME as f64).log10(),
+                Self::Decibels,
+            ),
+            VolumeUnit::Decibels => return RValue::Integer(level),
+        };
+
+        (0..=3)
+            .map(|places| volume(format!("{exact:.places$}").parse().unwrap_or(exact)))
+            .find(|v| v.level() == level)
+            .map_or(RValue::Integer(level), RValue::Volume)
+    }
+}
+
+/// What volumes are written in when decompiling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VolumeUnit {
+    /// The engine's own levels, as they're stored
+    #[default]
+    Level,
+    Percent,
+    Decibels,
+}
+
+impl FromStr for VolumeUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "level" => Ok(Self::Level),
+            "percent" => Ok(Self::Percent),
+            "db" | "dB" => Ok(Self::Decibels),
+            _ => Err(format!(
+                "Expected \"level\", \"percent\" or \"db\", found \"{s}\""
+            )),
+        }
+    }
+}
+
+impl Text {
+    /// Rewrites every volume that's given as a level in `unit` instead.
+    pub fn set_volume_unit(&mut self, unit: VolumeUnit) {
+        for block in &mut self.blocks {
+            for statement in &mut block.statements {
+                if let Statement::Assignment(name, value) = statement {
+                    if let ("volume", RValue::Integer(level)) = (name.as_str(), &*value) {
+                        *value = Volume::from_level(*level, unit);
+                    }
+                }
+            }
+        }
+    }
+}
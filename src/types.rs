@@ -1,18 +1,49 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
 
 use binrw::binrw;
+use serde::{Deserialize, Serialize};
 
+/// A location or orientation, stored as three doubles.
+///
+/// Two vectors are only equal if their components have the same bits, so
+/// that telling whether one was left at its default never mixes up `0` and
+/// `-0`; use [`Vec3::approx_eq`] to compare values worked out by hand.
 #[binrw]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Vec3 {
     x: f64,
     y: f64,
     z: f64,
 }
 
+impl PartialEq for Vec3 {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_bits() == other.to_bits()
+    }
+}
+
+/// Writes a component so that reading it back gives the same bits: the
+/// shortest decimal that does, in scientific notation if it's very large or
+/// small, or the bits themselves in hex if it isn't finite.
+fn fmt_component(f: &mut std::fmt::Formatter<'_>, x: f64) -> std::fmt::Result {
+    if !x.is_finite() {
+        write!(f, "0x{:016X}", x.to_bits())
+    } else if x != 0.0 && !(1e-5..1e16).contains(&x.abs()) {
+        write!(f, "{x:e}")
+    } else {
+        write!(f, "{x}")
+    }
+}
+
 impl Display for Vec3 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "({}, {}, {})", self.x, self.y, self.z)
+        write!(f, "(")?;
+        fmt_component(f, self.x)?;
+        write!(f, ", ")?;
+        fmt_component(f, self.y)?;
+        write!(f, ", ")?;
+        fmt_component(f, self.z)?;
+        write!(f, ")")
     }
 }
 
@@ -23,7 +54,260 @@ impl Vec3 {
     pub const Y: Self = Vec3::new(0.0, 1.0, 0.0);
     pub const Z: Self = Vec3::new(0.0, 0.0, 1.0);
 
+    /// How far apart components can be for [`Vec3::approx_eq`].
+    pub const EPSILON: f64 = 1e-6;
+
     pub const fn new(x: f64, y: f64, z: f64) -> Self {
         Self { x, y, z }
     }
+
+    pub fn from_bits(bits: [u64; 3]) -> Self {
+        Self::new(
+            f64::from_bits(bits[0]),
+            f64::from_bits(bits[1]),
+            f64::from_bits(bits[2]),
+        )
+    }
+
+    pub fn to_bits(self) -> [u64; 3] {
+        [self.x.to_bits(), self.y.to_bits(), self.z.to_bits()]
+    }
+
+    pub fn x(self) -> f64 {
+        self.x
+    }
+
+    pub fn y(self) -> f64 {
+        self.y
+    }
+
+    pub fn z(self) -> f64 {
+        self.z
+    }
+
+    /// Whether every component is within [`Vec3::EPSILON`] of `other`'s.
+    pub fn approx_eq(self, other: Self) -> bool {
+        (self - other)
+            .to_array()
+            .iter()
+            .all(|d| d.abs() <= Self::EPSILON)
+    }
+
+    pub fn to_array(self) -> [f64; 3] {
+        [self.x, self.y, self.z]
+    }
+
+    pub fn dot(self, other: Self) -> f64 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(self, other: Self) -> Self {
+        Self::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    pub fn length(self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn scale(self, by: f64) -> Self {
+        Self::new(self.x * by, self.y * by, self.z * by)
+    }
+
+    /// The vector scaled to a length of 1, or `None` if it's too short to
+    /// have a direction.
+    pub fn normalized(self) -> Option<Self> {
+        let length = self.length();
+        (length > Self::EPSILON).then(|| self.scale(1.0 / length))
+    }
+
+    /// Whether the vector is within [`Vec3::EPSILON`] of a length of 1.
+    pub fn is_normalized(self) -> bool {
+        (self.length() - 1.0).abs() <= Self::EPSILON
+    }
+
+    /// The vector rotated `angle` radians about `axis`, anticlockwise when
+    /// looking back along it.
+    pub fn rotated(self, axis: Self, angle: f64) -> Self {
+        let Some(axis) = axis.normalized() else {
+            return self;
+        };
+        let (sin, cos) = angle.sin_cos();
+
+        // Rodrigues' rotation formula
+        self.scale(cos) + axis.cross(self).scale(sin) + axis.scale(axis.dot(self) * (1.0 - cos))
+    }
+
+    /// `direction` and `up` made unit length and at right angles to each
+    /// other, keeping `direction` where it points and `up` in the same
+    /// plane, as the engine expects. `None` if either has no length or they
+    /// point the same way.
+    pub fn orthonormal(direction: Self, up: Self) -> Option<(Self, Self)> {
+        let direction = direction.normalized()?;
+        let right = up.cross(direction).normalized()?;
+
+        Some((direction, direction.cross(right)))
+    }
+}
+
+impl std::ops::Add for Vec3 {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl std::ops::Sub for Vec3 {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+impl std::ops::Neg for Vec3 {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::new(-self.x, -self.y, -self.z)
+    }
+}
+
+/// A set of objects picked out by ID or by name, from a comma-separated
+/// list such as `12,Act*,?ound`. Name patterns are case-insensitive and
+/// support `*` and `?`.
+#[derive(Debug, Clone, Default)]
+pub struct ObjectFilter {
+    ids: Vec<u32>,
+    patterns: Vec<String>,
+}
+
+impl FromStr for ObjectFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = Self::default();
+
+        for item in s.split(',').map(str::trim) {
+            if item.is_empty() {
+                return Err(format!("Empty object in \"{s}\""));
+            }
+
+            match item.parse() {
+                Ok(id) => filter.ids.push(id),
+                Err(_) => filter.patterns.push(item.to_ascii_lowercase()),
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+impl ObjectFilter {
+    pub fn all() -> Self {
+        Self {
+            ids: vec![],
+            patterns: vec!["*".into()],
+        }
+    }
+
+    pub fn matches(&self, id: u32, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+
+        self.ids.contains(&id)
+            || self
+                .patterns
+                .iter()
+                .any(|p| glob_match(p.as_bytes(), name.as_bytes()))
+    }
+}
+
+/// Whether `name` matches `pattern`, ignoring case. `*` matches any run of
+/// characters and `?` matches any one character.
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    glob_match(
+        pattern.to_ascii_lowercase().as_bytes(),
+        name.to_ascii_lowercase().as_bytes(),
+    )
+}
+
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => {
+            glob_match(rest, name) || !name.is_empty() && glob_match(pattern, &name[1..])
+        }
+        (Some((&p, rest)), Some((&n, name))) => (p == b'?' || p == n) && glob_match(rest, name),
+        _ => false,
+    }
+}
+
+/// Code page of the names, file names and extra data stored in Omni files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// What the original game's files use
+    #[default]
+    Windows1252,
+    Utf8,
+}
+
+/// Characters for bytes 0x80 to 0x9F in Windows-1252. The five bytes it
+/// leaves undefined stand for the control characters with the same value,
+/// so that every byte decodes to something.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+impl Encoding {
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            Self::Windows1252 => bytes
+                .iter()
+                .map(|&b| match b {
+                    0x80..=0x9F => WINDOWS_1252_HIGH[b as usize - 0x80],
+                    _ => b as char,
+                })
+                .collect(),
+            Self::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+        }
+    }
+
+    /// The bytes for `s`, or `None` if it has characters the code page
+    /// doesn't.
+    pub fn encode(&self, s: &str) -> Option<Vec<u8>> {
+        match self {
+            Self::Windows1252 => s
+                .chars()
+                .map(|c| match c as u32 {
+                    0x00..=0x7F | 0xA0..=0xFF => Some(c as u8),
+                    _ => WINDOWS_1252_HIGH
+                        .iter()
+                        .position(|&h| h == c)
+                        .map(|i| i as u8 + 0x80),
+                })
+                .collect(),
+            Self::Utf8 => Some(s.as_bytes().to_vec()),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "windows-1252" | "cp1252" | "latin1" => Ok(Self::Windows1252),
+            "utf-8" | "utf8" => Ok(Self::Utf8),
+            _ => Err(format!(
+                "Expected \"windows-1252\" or \"utf-8\", found \"{s}\""
+            )),
+        }
+    }
 }